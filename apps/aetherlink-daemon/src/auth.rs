@@ -0,0 +1,86 @@
+use std::{fs, io::Write, path::Path};
+
+use aetherlink_core::constant_time_eq;
+use anyhow::{Context, Result};
+use rand::RngCore;
+
+/// Random bytes behind the hex token written to the token file.
+pub const IPC_TOKEN_BYTES: usize = 32;
+
+pub fn generate_ipc_token() -> String {
+    let mut bytes = [0_u8; IPC_TOKEN_BYTES];
+    rand::rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Replaces the token file with `token`, readable only by the daemon's user. The old
+/// file is removed first so a file with looser permissions is never reused.
+pub fn write_token_file(path: &Path, token: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("create token file parent failed: {}", parent.display()))?;
+    }
+    match fs::remove_file(path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("remove stale token file failed: {}", path.display()));
+        }
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("create token file failed: {}", path.display()))?;
+    file.write_all(token.as_bytes())
+        .with_context(|| format!("write token file failed: {}", path.display()))?;
+    Ok(())
+}
+
+/// Compares in time independent of where the inputs differ. Lengths are not secret,
+/// and an empty expected token never matches.
+pub fn verify_ipc_token(provided: &[u8], expected: &[u8]) -> bool {
+    !expected.is_empty() && constant_time_eq(provided, expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_must_match_exactly() {
+        let token = generate_ipc_token();
+        assert_eq!(token.len(), IPC_TOKEN_BYTES * 2);
+        assert!(verify_ipc_token(token.as_bytes(), token.as_bytes()));
+
+        let mut flipped = token.clone().into_bytes();
+        flipped[IPC_TOKEN_BYTES] ^= 0x01;
+        assert!(!verify_ipc_token(&flipped, token.as_bytes()));
+        assert!(!verify_ipc_token(&token.as_bytes()[1..], token.as_bytes()));
+        assert!(!verify_ipc_token(b"", token.as_bytes()));
+        assert!(!verify_ipc_token(b"", b""));
+        assert_ne!(generate_ipc_token(), token);
+    }
+
+    #[test]
+    fn token_file_is_private_and_replaced() {
+        let path =
+            std::env::temp_dir().join(format!("aetherlink-daemon-token-{}", crate::unix_ms()));
+        write_token_file(&path, "first").unwrap();
+        write_token_file(&path, "second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let _ = fs::remove_file(path);
+    }
+}
@@ -1,34 +1,66 @@
 #![forbid(unsafe_code)]
 
+mod auth;
+mod node_admin;
+mod outbox;
+#[cfg(windows)]
+mod pipe;
+mod recording_pump;
+mod subscription;
+mod supervisor;
+mod trust_watch;
+
 use std::{
     collections::{BTreeSet, HashMap, HashSet},
     env, fs,
-    path::PathBuf,
+    io::BufWriter,
+    path::{Path, PathBuf},
     process::Stdio,
     sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use aetherlink_core::TrustedPeerRecord;
+use aetherlink_core::{TrustStoreFile, TrustedPeers};
+use aetherlink_input::normalize_input_event;
+use aetherlink_media::{RecordingWriter, VideoCodec, VideoProfile};
+use aetherlink_proto::is_ipc_ping;
 use aetherlink_proto::v1::{
-    ConnectSessionResponse, DaemonEvent, DaemonRequest, DaemonResponse, DiscoverDevicesResponse,
-    DiscoveredDevice, ErrorEvent, GenericAck, GetSessionStatsResponse, IpcEnvelope,
-    PairDeviceResponse, SessionStateEvent, SessionStats, daemon_event, daemon_request,
-    daemon_response, ipc_envelope,
+    AddTargetRequest, ConnectSessionResponse, DaemonErrorCode, DaemonEvent, DaemonRequest,
+    DaemonResponse, DaemonStatusResponse, DiscoverDevicesResponse, DiscoveredDevice, ErrorEvent,
+    GenericAck, GetSessionStatsResponse, InputEvent, IpcEnvelope, LookupDevicesRequest,
+    ManagedNodeState, NodeAdminRequest, NodeClipboardSyncRequest, NodeSendClipboardRequest,
+    NodeSendInputRequest, PairDeviceResponse, SendInputRequest, SessionStateEvent, SessionStats,
+    SetPeerRevokedRequest, TrustPeerRequest, UpdateNetworkRequest, daemon_event, daemon_request,
+    daemon_response, ipc_envelope, node_admin_request, node_admin_response,
 };
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow};
 use clap::Parser;
+use multiaddr::Multiaddr;
 use prost::Message;
-use serde::{Deserialize, Serialize};
 use tokio::{
     io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     process::{Child, Command},
-    sync::Mutex,
+    sync::{Mutex, broadcast, mpsc},
 };
 use tracing::{info, warn};
 
+use crate::outbox::{
+    CLIENT_OUTBOX_CAPACITY, ClientOutbox, EventDelivery, MAX_CONSECUTIVE_DROPPED_EVENTS,
+    run_client_writer,
+};
+use crate::{
+    auth::{generate_ipc_token, verify_ipc_token, write_token_file},
+    node_admin::{NodeAdminEndpoint, send_node_admin_request},
+    recording_pump::run_recording_pump,
+    subscription::{EVENT_BUS_CAPACITY, publish, stream_subscription},
+    supervisor::{PendingRestart, RestartPolicy, run_supervisor},
+    trust_watch::run_trust_store_watch,
+};
+
+#[cfg(windows)]
+use crate::pipe::PipeListener as IpcListener;
 #[cfg(windows)]
-use tokio::net::{TcpListener as IpcListener, TcpStream as IpcStream};
+use tokio::net::windows::named_pipe::NamedPipeServer as IpcStream;
 #[cfg(unix)]
 use tokio::net::{UnixListener as IpcListener, UnixStream as IpcStream};
 
@@ -39,7 +71,7 @@ use tokio::net::{UnixListener as IpcListener, UnixStream as IpcStream};
     about = "AetherLink local IPC daemon"
 )]
 struct Args {
-    #[arg(long, help = "IPC endpoint (unix path on Unix, host:port on Windows)")]
+    #[arg(long, help = "IPC endpoint (unix path on Unix, named pipe on Windows)")]
     socket_path: Option<String>,
 
     #[arg(long, help = "Path to persisted local identity key file")]
@@ -61,31 +93,80 @@ struct Args {
         help = "Managed node binary path"
     )]
     node_binary: String,
-}
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-struct TrustStoreFileV1 {
-    version: u32,
-    peers: Vec<TrustedPeerRecord>,
+    #[arg(
+        long,
+        help = "Admin IPC endpoint handed to the managed node (default: next to the daemon socket)"
+    )]
+    node_admin_socket: Option<String>,
+
+    #[arg(
+        long,
+        help = "File the IPC auth token is written to (default: daemon.token in the data dir)"
+    )]
+    token_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value_t = true,
+        action = clap::ArgAction::Set,
+        help = "Restart the managed node with backoff when it crashes"
+    )]
+    auto_restart: bool,
+
+    #[arg(
+        long,
+        default_value_t = 30_000,
+        help = "Deadline for answering a single IPC request (milliseconds)"
+    )]
+    request_timeout_ms: u64,
 }
 
+/// How long a new IPC client has to present the auth token.
+const IPC_AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+/// Pause after a failed accept, so a persistent failure does not spin.
+const IPC_ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+/// Bytes an auth frame may carry beyond the token, for a trailing newline and the like.
+const IPC_AUTH_FRAME_SLACK: usize = 2;
+/// How long the managed node gets to close sessions after SIGTERM before it is killed.
+const NODE_STOP_GRACE_MS: u64 = 5_000;
+/// Device codes are peer id strings, well under this.
+const MAX_DEVICE_CODE_LEN: usize = 128;
+
 #[derive(Debug, Clone)]
 struct DaemonState {
     node_binary: String,
     listen_multiaddr: String,
     bootstrap_multiaddrs: Vec<String>,
+    relay_multiaddrs: Vec<String>,
     trust_on_first_use: bool,
     identity_file: PathBuf,
     trust_store_file: PathBuf,
-    connect_device_codes: BTreeSet<String>,
     paired_devices: HashSet<String>,
     session_stats: HashMap<String, SessionStats>,
+    node_admin: NodeAdminEndpoint,
+    sessions: HashMap<String, SessionInfo>,
+}
+
+/// A session the daemon asked the managed node to establish. One node serves every
+/// target, so sessions come and go without restarting it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SessionInfo {
+    device_code: String,
+    state: String,
 }
 
 #[derive(Debug)]
 struct Runtime {
     config: DaemonState,
     child: Option<Child>,
+    node_started_unix_ms: Option<u64>,
+    last_error: Option<String>,
+    restart_policy: RestartPolicy,
+    /// A crash the supervisor will restart from.
+    pending_restart: Option<PendingRestart>,
+    node_exit_reported: bool,
+    recordings: HashMap<String, RecordingWriter<BufWriter<fs::File>>>,
 }
 
 #[tokio::main]
@@ -98,25 +179,33 @@ async fn main() -> Result<()> {
 
     let args = Args::parse();
     let socket_path = args.socket_path.unwrap_or_else(default_socket_path);
-    #[cfg(unix)]
-    if std::path::Path::new(&socket_path).exists() {
-        fs::remove_file(&socket_path)
-            .with_context(|| format!("remove stale daemon socket failed: {}", socket_path))?;
-    }
-    #[cfg(unix)]
-    if let Some(parent) = std::path::Path::new(&socket_path).parent() {
-        fs::create_dir_all(parent).with_context(|| {
-            format!("create socket parent failed: {}", parent.to_string_lossy())
-        })?;
-    }
-    let listener = bind_listener(&socket_path).await?;
+    let token_file = args
+        .token_file
+        .unwrap_or_else(|| default_data_dir().join("daemon.token"));
+    let token: Arc<str> = generate_ipc_token().into();
+    write_token_file(&token_file, &token)?;
+    info!("IPC auth token written to {}", token_file.display());
+    // Accepting on a named pipe swaps in the next pipe instance.
+    #[cfg_attr(unix, allow(unused_mut))]
+    let mut listener = bind_listener(&socket_path).await.map_err(|err| {
+        let code = classify_spawn_error(&err);
+        err.context(format!("daemon cannot listen ({})", code.as_str_name()))
+    })?;
     info!("daemon listening on {}", socket_path);
+    let node_admin = NodeAdminEndpoint {
+        address: args
+            .node_admin_socket
+            .unwrap_or_else(|| default_node_admin_endpoint(&socket_path)),
+        token: token.clone(),
+        token_file,
+    };
 
     let runtime = Arc::new(Mutex::new(Runtime {
         config: DaemonState {
             node_binary: args.node_binary,
             listen_multiaddr: args.default_listen,
             bootstrap_multiaddrs: Vec::new(),
+            relay_multiaddrs: Vec::new(),
             trust_on_first_use: false,
             identity_file: args
                 .identity_file
@@ -124,62 +213,186 @@ async fn main() -> Result<()> {
             trust_store_file: args
                 .trust_store_file
                 .unwrap_or_else(|| default_data_dir().join("trusted_peers.json")),
-            connect_device_codes: BTreeSet::new(),
             paired_devices: HashSet::new(),
             session_stats: HashMap::new(),
+            node_admin,
+            sessions: HashMap::new(),
         },
         child: None,
+        node_started_unix_ms: None,
+        last_error: None,
+        restart_policy: RestartPolicy::default(),
+        pending_restart: None,
+        node_exit_reported: false,
+        recordings: HashMap::new(),
     }));
 
+    let request_timeout = Duration::from_millis(args.request_timeout_ms.max(1));
+    let (event_bus, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+    tokio::spawn(run_supervisor(
+        runtime.clone(),
+        event_bus.clone(),
+        args.auto_restart,
+    ));
+    tokio::spawn(run_trust_store_watch(runtime.clone(), event_bus.clone()));
+    tokio::spawn(run_recording_pump(runtime.clone()));
     loop {
-        let (stream, _) = listener
-            .accept()
-            .await
-            .context("accept IPC connection failed")?;
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                // Usually transient (a client gone before accept, or out of file
+                // descriptors); the daemon keeps serving the clients it has.
+                warn!("accept IPC connection failed: {err}");
+                tokio::time::sleep(IPC_ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
         let runtime = runtime.clone();
+        let event_bus = event_bus.clone();
+        let token = token.clone();
         tokio::spawn(async move {
-            if let Err(err) = handle_client(stream, runtime).await {
+            if let Err(err) =
+                handle_client(stream, runtime, event_bus, &token, request_timeout).await
+            {
                 warn!("client session ended with error: {err}");
             }
         });
     }
 }
 
-async fn handle_client(mut stream: IpcStream, runtime: Arc<Mutex<Runtime>>) -> Result<()> {
-    let mut seq: u64 = 1;
-    while let Some(payload) = read_frame(&mut stream).await? {
-        let envelope = IpcEnvelope::decode(payload.as_slice()).context("decode IPC envelope")?;
-        let Some(ipc_envelope::Payload::Request(request)) = envelope.payload else {
-            continue;
-        };
-        let request_id = if envelope.request_id.is_empty() {
-            format!("ipc-{}", unix_ms())
-        } else {
-            envelope.request_id
-        };
-        let (response, events) = process_request(request, runtime.clone()).await;
+async fn handle_client(
+    stream: IpcStream,
+    runtime: Arc<Mutex<Runtime>>,
+    event_bus: broadcast::Sender<DaemonEvent>,
+    token: &str,
+    request_timeout: Duration,
+) -> Result<()> {
+    let (mut reader, writer) = tokio::io::split(stream);
+    authenticate_client(&mut reader, token).await?;
+    let (tx, rx) = mpsc::channel(CLIENT_OUTBOX_CAPACITY);
+    let writer_task = tokio::spawn(run_client_writer(writer, rx));
+    let mut outbox = ClientOutbox::new(tx, MAX_CONSECUTIVE_DROPPED_EVENTS);
 
-        let response_envelope = IpcEnvelope {
-            seq,
-            request_id: request_id.clone(),
-            payload: Some(ipc_envelope::Payload::Response(response)),
-        };
-        seq = seq.saturating_add(1);
-        write_frame(&mut stream, &response_envelope.encode_to_vec()).await?;
-
-        for event in events {
-            let event_envelope = IpcEnvelope {
-                seq,
-                request_id: request_id.clone(),
-                payload: Some(ipc_envelope::Payload::Event(event)),
+    let served = async {
+        while let Some(payload) = read_frame(&mut reader).await? {
+            let envelope =
+                IpcEnvelope::decode(payload.as_slice()).context("decode IPC envelope")?;
+            if is_ipc_ping(&envelope) {
+                outbox.send_pong(&envelope).await?;
+                continue;
+            }
+            let Some(ipc_envelope::Payload::Request(request)) = envelope.payload else {
+                continue;
             };
-            seq = seq.saturating_add(1);
-            write_frame(&mut stream, &event_envelope.encode_to_vec()).await?;
+            let request_id = if envelope.request_id.is_empty() {
+                format!("ipc-{}", unix_ms())
+            } else {
+                envelope.request_id
+            };
+            // Subscribe before the request runs so its own events reach the stream.
+            let subscription = match &request.payload {
+                Some(daemon_request::Payload::Subscribe(subscribe)) => {
+                    Some((subscribe.clone(), event_bus.subscribe()))
+                }
+                _ => None,
+            };
+            let (response, events) =
+                with_request_deadline(request_timeout, process_request(request, runtime.clone()))
+                    .await;
+            outbox.send_response(&request_id, response).await?;
+            if subscription.is_some() {
+                return Ok(subscription.map(|(subscribe, rx)| (subscribe, rx, request_id)));
+            }
+            for event in events {
+                publish(&event_bus, event.clone());
+                match outbox.push_event(&request_id, event) {
+                    EventDelivery::Queued => {}
+                    EventDelivery::Dropped => {
+                        warn!("client is not keeping up, dropped event for {request_id}");
+                    }
+                    EventDelivery::Disconnect => {
+                        return Err(anyhow!(
+                            "client stopped reading, {} events dropped",
+                            outbox.dropped_total()
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+    .await;
+
+    let result = match served {
+        Ok(Some((subscribe, events, request_id))) => {
+            info!(
+                "client {request_id} subscribed to {:?}",
+                subscribe.event_kinds
+            );
+            stream_subscription(reader, &mut outbox, events, subscribe, &request_id).await
         }
+        Ok(None) => Ok(()),
+        Err(err) => Err(err),
+    };
+    drop(outbox);
+    match writer_task.await {
+        Ok(Ok(())) => result,
+        Ok(Err(err)) => result.and(Err(err)),
+        Err(err) => result.and(Err(err).context("client writer task failed")),
+    }
+}
+
+/// The first frame on every connection must be the token from the token file.
+async fn authenticate_client<R>(reader: &mut R, token: &str) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    // Bounded by the token so an unauthenticated client cannot make us allocate a
+    // frame length of its choosing.
+    let max_len = token.len() + IPC_AUTH_FRAME_SLACK;
+    let presented = tokio::time::timeout(IPC_AUTH_TIMEOUT, read_frame_limited(reader, max_len))
+        .await
+        .context("IPC client did not authenticate in time")??
+        .ok_or_else(|| anyhow!("IPC client closed before authenticating"))?;
+    if !verify_ipc_token(presented.trim_ascii(), token.as_bytes()) {
+        return Err(anyhow!("IPC client presented an invalid auth token"));
     }
     Ok(())
 }
 
+/// Answers with an error response and a `request_timeout` event when `handler` misses
+/// `deadline`. Dropping the handler releases the runtime lock; a node it spawned is
+/// already in `Runtime::child`, so the supervisor and `stop_daemon` still manage it.
+async fn with_request_deadline<F>(
+    deadline: Duration,
+    handler: F,
+) -> (DaemonResponse, Vec<DaemonEvent>)
+where
+    F: Future<Output = (DaemonResponse, Vec<DaemonEvent>)>,
+{
+    match tokio::time::timeout(deadline, handler).await {
+        Ok(result) => result,
+        Err(_) => {
+            let detail = format!("request timed out after {}ms", deadline.as_millis());
+            warn!("{detail}");
+            (
+                DaemonResponse {
+                    payload: None,
+                    error: detail.clone(),
+                    error_code: DaemonErrorCode::Timeout as i32,
+                },
+                vec![DaemonEvent {
+                    payload: Some(daemon_event::Payload::Error(ErrorEvent {
+                        code: "request_timeout".to_string(),
+                        detail,
+                        error_code: DaemonErrorCode::Timeout as i32,
+                    })),
+                }],
+            )
+        }
+    }
+}
+
 async fn process_request(
     request: DaemonRequest,
     runtime: Arc<Mutex<Runtime>>,
@@ -191,12 +404,14 @@ async fn process_request(
                     ok: false,
                     detail: "missing daemon request payload".to_string(),
                 })),
+                error: String::new(),
+                error_code: DaemonErrorCode::InvalidArgument as i32,
             },
             vec![],
         );
     };
 
-    match payload {
+    let (response, events) = match payload {
         daemon_request::Payload::StartDaemon(start) => {
             let mut guard = runtime.lock().await;
             if !start.listen_multiaddr.trim().is_empty() {
@@ -207,6 +422,7 @@ async fn process_request(
             }
             guard.config.bootstrap_multiaddrs = start.bootstrap_multiaddrs;
             guard.config.trust_on_first_use = start.trust_on_first_use;
+            guard.restart_policy.reset();
             let result = restart_managed_node(&mut guard).await;
             match result {
                 Ok(()) => (
@@ -215,6 +431,8 @@ async fn process_request(
                             ok: true,
                             detail: "managed node started".to_string(),
                         })),
+                        error: String::new(),
+                        error_code: DaemonErrorCode::Unspecified as i32,
                     },
                     vec![DaemonEvent {
                         payload: Some(daemon_event::Payload::SessionState(SessionStateEvent {
@@ -224,20 +442,26 @@ async fn process_request(
                         })),
                     }],
                 ),
-                Err(err) => (
-                    DaemonResponse {
-                        payload: Some(daemon_response::Payload::StartDaemon(GenericAck {
-                            ok: false,
-                            detail: err.to_string(),
-                        })),
-                    },
-                    vec![DaemonEvent {
-                        payload: Some(daemon_event::Payload::Error(ErrorEvent {
-                            code: "start_daemon_failed".to_string(),
-                            detail: err.to_string(),
-                        })),
-                    }],
-                ),
+                Err(err) => {
+                    let error_code = classify_spawn_error(&err) as i32;
+                    (
+                        DaemonResponse {
+                            payload: Some(daemon_response::Payload::StartDaemon(GenericAck {
+                                ok: false,
+                                detail: err.to_string(),
+                            })),
+                            error: String::new(),
+                            error_code,
+                        },
+                        vec![DaemonEvent {
+                            payload: Some(daemon_event::Payload::Error(ErrorEvent {
+                                code: "start_daemon_failed".to_string(),
+                                detail: err.to_string(),
+                                error_code,
+                            })),
+                        }],
+                    )
+                }
             }
         }
         daemon_request::Payload::StopDaemon(_) => {
@@ -250,121 +474,250 @@ async fn process_request(
                             ok: true,
                             detail: "managed node stopped".to_string(),
                         })),
+                        error: String::new(),
+                        error_code: DaemonErrorCode::Unspecified as i32,
                     },
                     vec![],
                 ),
-                Err(err) => (
-                    DaemonResponse {
-                        payload: Some(daemon_response::Payload::StopDaemon(GenericAck {
-                            ok: false,
-                            detail: err.to_string(),
-                        })),
-                    },
-                    vec![DaemonEvent {
-                        payload: Some(daemon_event::Payload::Error(ErrorEvent {
-                            code: "stop_daemon_failed".to_string(),
-                            detail: err.to_string(),
-                        })),
-                    }],
-                ),
+                Err(err) => {
+                    let error_code = classify_spawn_error(&err) as i32;
+                    (
+                        DaemonResponse {
+                            payload: Some(daemon_response::Payload::StopDaemon(GenericAck {
+                                ok: false,
+                                detail: err.to_string(),
+                            })),
+                            error: String::new(),
+                            error_code,
+                        },
+                        vec![DaemonEvent {
+                            payload: Some(daemon_event::Payload::Error(ErrorEvent {
+                                code: "stop_daemon_failed".to_string(),
+                                detail: err.to_string(),
+                                error_code,
+                            })),
+                        }],
+                    )
+                }
             }
         }
-        daemon_request::Payload::DiscoverDevices(_) => {
-            let guard = runtime.lock().await;
-            let devices = discover_devices_from_trust_store(
-                &guard.config.trust_store_file,
-                &guard.config.paired_devices,
-            );
+        daemon_request::Payload::DiscoverDevices(req) => {
+            let (endpoint, known) = {
+                let guard = runtime.lock().await;
+                (
+                    guard.config.node_admin.clone(),
+                    discover_devices_from_trust_store(
+                        &guard.config.trust_store_file,
+                        &guard.config.paired_devices,
+                    ),
+                )
+            };
+            // The DHT lookup can take seconds; the runtime lock is not held across it.
+            let mut events = Vec::new();
+            let live = if req.device_codes.is_empty() {
+                Vec::new()
+            } else {
+                let request = NodeAdminRequest {
+                    payload: Some(node_admin_request::Payload::LookupDevices(
+                        LookupDevicesRequest {
+                            device_codes: req.device_codes,
+                        },
+                    )),
+                };
+                match send_node_admin_request(&endpoint, request).await {
+                    Ok(response) => match response.payload {
+                        Some(node_admin_response::Payload::LookupDevices(lookup)) => lookup.devices,
+                        _ => Vec::new(),
+                    },
+                    Err(err) => {
+                        warn!("live device discovery failed: {err:#}");
+                        events.push(DaemonEvent {
+                            payload: Some(daemon_event::Payload::Error(ErrorEvent {
+                                code: "live_discovery_failed".to_string(),
+                                detail: format!("{err:#}"),
+                                error_code: DaemonErrorCode::NodeUnreachable as i32,
+                            })),
+                        });
+                        Vec::new()
+                    }
+                }
+            };
+            let devices = merge_discovered(known, live);
             let response = DaemonResponse {
                 payload: Some(daemon_response::Payload::DiscoverDevices(
                     DiscoverDevicesResponse {
                         devices: devices.clone(),
                     },
                 )),
+                error: String::new(),
+                error_code: DaemonErrorCode::Unspecified as i32,
             };
             let event = DaemonEvent {
                 payload: Some(daemon_event::Payload::DiscoveryUpdate(
                     aetherlink_proto::v1::DiscoveryUpdateEvent { devices },
                 )),
             };
-            (response, vec![event])
+            events.insert(0, event);
+            (response, events)
         }
         daemon_request::Payload::PairDevice(pair) => {
-            let mut guard = runtime.lock().await;
-            if pair.approved {
-                guard.config.paired_devices.insert(pair.device_code.clone());
-            } else {
-                guard.config.paired_devices.remove(&pair.device_code);
-            }
+            let (paired, detail) = match validate_device_code(&pair.device_code) {
+                Ok(device_code) => {
+                    let mut guard = runtime.lock().await;
+                    if pair.approved {
+                        guard.config.paired_devices.insert(device_code.clone());
+                    } else {
+                        guard.config.paired_devices.remove(&device_code);
+                    }
+                    // Unpairing revokes trust so the node refuses the device at auth;
+                    // pairing again lifts any earlier revocation.
+                    let revocation =
+                        set_device_revoked(&mut guard, &device_code, !pair.approved).await;
+                    let (paired, detail) = match (pair.approved, revocation) {
+                        (true, Ok(_)) => (true, format!("device {device_code} marked as paired")),
+                        (true, Err(err)) => (
+                            true,
+                            format!(
+                                "device {device_code} marked as paired, but clearing its revocation failed: {err:#}"
+                            ),
+                        ),
+                        (false, Ok(_)) => (
+                            false,
+                            format!("device {device_code} removed from paired set and revoked"),
+                        ),
+                        (false, Err(err)) => (
+                            false,
+                            format!(
+                                "device {device_code} removed from paired set, but revoking trust failed: {err:#}"
+                            ),
+                        ),
+                    };
+                    let peer_id = pair.peer_id.trim();
+                    if !pair.approved || peer_id.is_empty() {
+                        (paired, detail)
+                    } else {
+                        // The identity the user approved is what the node will accept
+                        // for this code; another key under it still needs its own pairing.
+                        match trust_device_identity(&mut guard, &device_code, peer_id).await {
+                            Ok(trusted) => (paired, format!("{detail}; {trusted}")),
+                            Err(err) => (
+                                paired,
+                                format!("{detail}, but trusting peer {peer_id} failed: {err:#}"),
+                            ),
+                        }
+                    }
+                }
+                Err(detail) => (false, detail),
+            };
             (
                 DaemonResponse {
                     payload: Some(daemon_response::Payload::PairDevice(PairDeviceResponse {
-                        paired: pair.approved,
-                        detail: if pair.approved {
-                            format!("device {} marked as paired", pair.device_code)
-                        } else {
-                            format!("device {} removed from paired set", pair.device_code)
-                        },
+                        paired,
+                        detail,
                     })),
+                    error: String::new(),
+                    error_code: DaemonErrorCode::Unspecified as i32,
                 },
                 vec![],
             )
         }
         daemon_request::Payload::ConnectSession(connect) => {
-            let mut guard = runtime.lock().await;
-            guard
-                .config
-                .connect_device_codes
-                .insert(connect.device_code.clone());
-            let restart = restart_managed_node(&mut guard).await;
-            let session_id = format!("session-{}-{}", connect.device_code, unix_ms());
-            match restart {
-                Ok(()) => (
+            let connected = match validate_device_code(&connect.device_code) {
+                Ok(device_code) => {
+                    let (pending, state) = {
+                        let mut guard = runtime.lock().await;
+                        let pending = PendingSession::open(&runtime, &mut guard, &device_code);
+                        let state = guard.config.sessions[&pending.session_id].state.clone();
+                        (pending, state)
+                    };
+                    let connected = connect_target(&runtime, &device_code).await;
+                    let session_id = pending.session_id.clone();
+                    if connected.is_ok() {
+                        pending.keep();
+                    }
+                    connected.map(|detail| (session_id, device_code, state, detail))
+                }
+                Err(detail) => {
+                    Err(CodedError::new(DaemonErrorCode::InvalidArgument, detail).into())
+                }
+            };
+            match connected {
+                Ok((session_id, device_code, state, detail)) => (
                     DaemonResponse {
                         payload: Some(daemon_response::Payload::ConnectSession(
                             ConnectSessionResponse {
                                 session_id: session_id.clone(),
                                 accepted: true,
-                                detail: "managed node restarted with connect target".to_string(),
+                                detail,
                             },
                         )),
+                        error: String::new(),
+                        error_code: DaemonErrorCode::Unspecified as i32,
                     },
                     vec![DaemonEvent {
                         payload: Some(daemon_event::Payload::SessionState(SessionStateEvent {
                             session_id,
-                            state: "connecting".to_string(),
-                            detail: format!("target={}", connect.device_code),
+                            state,
+                            detail: format!("target={device_code}"),
                         })),
                     }],
                 ),
-                Err(err) => (
-                    DaemonResponse {
-                        payload: Some(daemon_response::Payload::ConnectSession(
-                            ConnectSessionResponse {
-                                session_id: String::new(),
-                                accepted: false,
+                Err(err) => {
+                    let error_code = classify_spawn_error(&err) as i32;
+                    (
+                        DaemonResponse {
+                            payload: Some(daemon_response::Payload::ConnectSession(
+                                ConnectSessionResponse {
+                                    session_id: String::new(),
+                                    accepted: false,
+                                    detail: err.to_string(),
+                                },
+                            )),
+                            error: String::new(),
+                            error_code,
+                        },
+                        vec![DaemonEvent {
+                            payload: Some(daemon_event::Payload::Error(ErrorEvent {
+                                code: "connect_session_failed".to_string(),
                                 detail: err.to_string(),
-                            },
-                        )),
-                    },
-                    vec![DaemonEvent {
-                        payload: Some(daemon_event::Payload::Error(ErrorEvent {
-                            code: "connect_session_failed".to_string(),
-                            detail: err.to_string(),
-                        })),
-                    }],
-                ),
+                                error_code,
+                            })),
+                        }],
+                    )
+                }
             }
         }
-        daemon_request::Payload::SendInput(_) => (
-            DaemonResponse {
-                payload: Some(daemon_response::Payload::SendInput(GenericAck {
-                    ok: true,
-                    detail: "input command accepted and forwarded to data plane queue".to_string(),
-                })),
-            },
-            vec![],
-        ),
+        daemon_request::Payload::SendInput(req) => {
+            let result = async {
+                // The runtime lock is not held across the node admin round trip.
+                let (node_admin, device_code, event) = {
+                    let guard = runtime.lock().await;
+                    let event = validate_send_input(&guard.config, &req)?;
+                    let device_code = session_device_code(&guard.config, &event.session_id)?;
+                    (guard.config.node_admin.clone(), device_code, event)
+                };
+                let request = NodeAdminRequest {
+                    payload: Some(node_admin_request::Payload::SendInput(
+                        NodeSendInputRequest {
+                            device_code,
+                            event: Some(event),
+                        },
+                    )),
+                };
+                send_node_admin_request(&node_admin, request).await?;
+                Ok("input event forwarded to managed node".to_string())
+            }
+            .await;
+            let error_code = error_code_of(&result);
+            (
+                DaemonResponse {
+                    payload: Some(daemon_response::Payload::SendInput(ack(result))),
+                    error: String::new(),
+                    error_code,
+                },
+                vec![],
+            )
+        }
         daemon_request::Payload::StartFileTransfer(req) => (
             DaemonResponse {
                 payload: Some(daemon_response::Payload::StartFileTransfer(GenericAck {
@@ -374,34 +727,98 @@ async fn process_request(
                         req.source_path, req.session_id
                     ),
                 })),
+                error: String::new(),
+                error_code: DaemonErrorCode::Unspecified as i32,
             },
             vec![],
         ),
-        daemon_request::Payload::SetClipboardSync(req) => (
-            DaemonResponse {
-                payload: Some(daemon_response::Payload::SetClipboardSync(GenericAck {
-                    ok: true,
-                    detail: format!(
-                        "clipboard sync {} for session {}",
-                        if req.enabled { "enabled" } else { "disabled" },
-                        req.session_id
-                    ),
-                })),
-            },
-            vec![],
-        ),
-        daemon_request::Payload::StartRecording(req) => (
-            DaemonResponse {
-                payload: Some(daemon_response::Payload::StartRecording(GenericAck {
-                    ok: true,
-                    detail: format!(
-                        "recording started for session {} output={}",
-                        req.session_id, req.output_path
-                    ),
-                })),
-            },
-            vec![],
-        ),
+        daemon_request::Payload::SetClipboardSync(req) => {
+            let result = async {
+                let (node_admin, device_code) =
+                    node_admin_for_session(&runtime, &req.session_id).await?;
+                let request = NodeAdminRequest {
+                    payload: Some(node_admin_request::Payload::SetClipboardSync(
+                        NodeClipboardSyncRequest {
+                            device_code,
+                            enabled: req.enabled,
+                        },
+                    )),
+                };
+                send_node_admin_request(&node_admin, request).await?;
+                Ok::<_, anyhow::Error>(format!(
+                    "clipboard sync {} for session {}",
+                    if req.enabled { "enabled" } else { "disabled" },
+                    req.session_id
+                ))
+            }
+            .await;
+            let error_code = error_code_of(&result);
+            (
+                DaemonResponse {
+                    payload: Some(daemon_response::Payload::SetClipboardSync(ack(result))),
+                    error: String::new(),
+                    error_code,
+                },
+                vec![],
+            )
+        }
+        daemon_request::Payload::SendClipboard(req) => {
+            let result = async {
+                let bytes = req.data.len();
+                let (node_admin, device_code) =
+                    node_admin_for_session(&runtime, &req.session_id).await?;
+                let request = NodeAdminRequest {
+                    payload: Some(node_admin_request::Payload::SendClipboard(
+                        NodeSendClipboardRequest {
+                            device_code,
+                            mime: req.mime,
+                            data: req.data,
+                        },
+                    )),
+                };
+                send_node_admin_request(&node_admin, request).await?;
+                Ok::<_, anyhow::Error>(format!(
+                    "sent {bytes} clipboard bytes to session {}",
+                    req.session_id
+                ))
+            }
+            .await;
+            let error_code = error_code_of(&result);
+            (
+                DaemonResponse {
+                    payload: Some(daemon_response::Payload::SendClipboard(ack(result))),
+                    error: String::new(),
+                    error_code,
+                },
+                vec![],
+            )
+        }
+        daemon_request::Payload::StartRecording(req) => {
+            let mut guard = runtime.lock().await;
+            let result = start_recording(&mut guard, &req.session_id, &req.output_path);
+            let error_code = error_code_of(&result);
+            (
+                DaemonResponse {
+                    payload: Some(daemon_response::Payload::StartRecording(ack(result))),
+                    error: String::new(),
+                    error_code,
+                },
+                vec![],
+            )
+        }
+        daemon_request::Payload::StopRecording(req) => {
+            let mut guard = runtime.lock().await;
+            let result = stop_recording(&mut guard, &req.session_id);
+            let error_code = error_code_of(&result);
+            (
+                DaemonResponse {
+                    payload: Some(daemon_response::Payload::StopRecording(ack(result))),
+                    error: String::new(),
+                    error_code,
+                },
+                vec![],
+            )
+        }
         daemon_request::Payload::GetSessionStats(req) => {
             let guard = runtime.lock().await;
             let stats = guard
@@ -424,67 +841,667 @@ async fn process_request(
                     payload: Some(daemon_response::Payload::GetSessionStats(
                         GetSessionStatsResponse { stats: Some(stats) },
                     )),
+                    error: String::new(),
+                    error_code: DaemonErrorCode::Unspecified as i32,
+                },
+                vec![],
+            )
+        }
+        daemon_request::Payload::Subscribe(_) => (
+            DaemonResponse {
+                payload: Some(daemon_response::Payload::Subscribe(GenericAck {
+                    ok: true,
+                    detail: "subscribed; events follow until the connection closes".to_string(),
+                })),
+                error: String::new(),
+                error_code: DaemonErrorCode::Unspecified as i32,
+            },
+            vec![],
+        ),
+        daemon_request::Payload::UpdateNetwork(update) => {
+            let result = async {
+                let (bootstrap, relays) = match (
+                    parse_multiaddrs(&update.bootstrap_multiaddrs),
+                    parse_relay_multiaddrs(&update.relay_multiaddrs),
+                ) {
+                    (Ok(bootstrap), Ok(relays)) => (bootstrap, relays),
+                    (bootstrap, relays) => {
+                        let invalid: Vec<String> = [bootstrap.err(), relays.err()]
+                            .into_iter()
+                            .flatten()
+                            .flatten()
+                            .collect();
+                        return Err(CodedError::new(
+                            DaemonErrorCode::InvalidArgument,
+                            format!("invalid multiaddrs: {}", invalid.join(", ")),
+                        )
+                        .into());
+                    }
+                };
+                let update = UpdateNetworkRequest {
+                    bootstrap_multiaddrs: bootstrap.iter().map(ToString::to_string).collect(),
+                    relay_multiaddrs: relays.iter().map(ToString::to_string).collect(),
+                };
+                // The runtime lock is not held across the node admin round trip.
+                let node_admin = {
+                    let mut guard = runtime.lock().await;
+                    guard.config.bootstrap_multiaddrs = update.bootstrap_multiaddrs.clone();
+                    guard.config.relay_multiaddrs = update.relay_multiaddrs.clone();
+                    if guard.child.is_none() {
+                        return Ok(
+                            "network saved, applied when the managed node starts".to_string()
+                        );
+                    }
+                    guard.config.node_admin.clone()
+                };
+                let request = NodeAdminRequest {
+                    payload: Some(node_admin_request::Payload::UpdateNetwork(update)),
+                };
+                send_node_admin_request(&node_admin, request).await?;
+                Ok::<_, anyhow::Error>(format!(
+                    "network updated on managed node: {} bootstrap, {} relay",
+                    bootstrap.len(),
+                    relays.len()
+                ))
+            }
+            .await;
+            let error_code = error_code_of(&result);
+            (
+                DaemonResponse {
+                    payload: Some(daemon_response::Payload::UpdateNetwork(ack(result))),
+                    error: String::new(),
+                    error_code,
+                },
+                vec![],
+            )
+        }
+        daemon_request::Payload::GetDaemonStatus(_) => {
+            let mut guard = runtime.lock().await;
+            (
+                DaemonResponse {
+                    payload: Some(daemon_response::Payload::GetDaemonStatus(daemon_status(
+                        &mut guard,
+                    ))),
+                    error: String::new(),
+                    error_code: DaemonErrorCode::Unspecified as i32,
                 },
                 vec![],
             )
         }
+    };
+    let last_error = events.iter().rev().find_map(|event| match &event.payload {
+        Some(daemon_event::Payload::Error(error)) => {
+            Some(format!("{}: {}", error.code, error.detail))
+        }
+        _ => None,
+    });
+    if last_error.is_some() {
+        runtime.lock().await.last_error = last_error;
     }
+    (response, events)
 }
 
-async fn restart_managed_node(runtime: &mut Runtime) -> Result<()> {
-    stop_managed_node(runtime).await?;
-    let mut cmd = Command::new(runtime.config.node_binary.clone());
-    cmd.arg("--listen")
-        .arg(runtime.config.listen_multiaddr.clone())
-        .arg("--identity-file")
-        .arg(runtime.config.identity_file.as_os_str())
-        .arg("--trust-store-file")
-        .arg(runtime.config.trust_store_file.as_os_str())
-        .arg("--trust-on-first-use")
-        .arg(if runtime.config.trust_on_first_use {
-            "true"
-        } else {
-            "false"
-        });
-    for addr in &runtime.config.bootstrap_multiaddrs {
-        cmd.arg("--bootstrap").arg(addr);
+/// Snapshot of the managed node. Takes `&mut` because polling the child for an exit
+/// status (`try_wait`) needs it; an exit noticed here becomes the last error.
+fn daemon_status(runtime: &mut Runtime) -> DaemonStatusResponse {
+    let (node_state, node_exit_code) = match runtime.child.as_mut().map(Child::try_wait) {
+        None if runtime.node_started_unix_ms.is_none() => (ManagedNodeState::NeverStarted, 0),
+        None => (ManagedNodeState::Stopped, 0),
+        Some(Ok(None)) => (ManagedNodeState::Running, 0),
+        Some(Ok(Some(status))) => {
+            runtime.last_error = Some(format!("managed node exited: {status}"));
+            (ManagedNodeState::Exited, status.code().unwrap_or(-1))
+        }
+        Some(Err(err)) => {
+            runtime.last_error = Some(format!("poll managed node failed: {err}"));
+            (ManagedNodeState::Exited, -1)
+        }
+    };
+    let node_uptime_ms = match (node_state, runtime.node_started_unix_ms) {
+        (ManagedNodeState::Running, Some(started)) => unix_ms().saturating_sub(started),
+        _ => 0,
+    };
+    DaemonStatusResponse {
+        node_state: node_state as i32,
+        node_uptime_ms,
+        node_exit_code,
+        active_sessions: runtime.config.sessions.len() as u32,
+        last_error: runtime.last_error.clone().unwrap_or_default(),
     }
-    for device_code in &runtime.config.connect_device_codes {
-        cmd.arg("--connect-device-code").arg(device_code);
+}
+
+/// A failure whose class is already known, kept in the `anyhow` chain so that
+/// `classify_spawn_error` reports it instead of guessing from the cause.
+#[derive(Debug, PartialEq, Eq)]
+struct CodedError {
+    code: DaemonErrorCode,
+    detail: String,
+}
+
+impl CodedError {
+    fn new(code: DaemonErrorCode, detail: impl Into<String>) -> Self {
+        Self {
+            code,
+            detail: detail.into(),
+        }
     }
-    if !runtime.config.connect_device_codes.is_empty() {
-        cmd.arg("--auto-request");
+}
+
+impl std::fmt::Display for CodedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.detail)
     }
-    cmd.stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null());
+}
 
-    let child = cmd.spawn().with_context(|| {
-        format!(
-            "failed to spawn managed node binary '{}'",
-            runtime.config.node_binary
-        )
-    })?;
-    runtime.child = Some(child);
-    Ok(())
+impl std::error::Error for CodedError {}
+
+/// Classifies a failure from spawning, binding or talking to the managed node by the
+/// first `CodedError` or I/O error in its chain.
+fn classify_spawn_error(err: &anyhow::Error) -> DaemonErrorCode {
+    use std::io::ErrorKind;
+
+    for cause in err.chain() {
+        if let Some(coded) = cause.downcast_ref::<CodedError>() {
+            return coded.code;
+        }
+        if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+            return match io.kind() {
+                ErrorKind::PermissionDenied => DaemonErrorCode::PermissionDenied,
+                ErrorKind::AddrInUse | ErrorKind::AddrNotAvailable => DaemonErrorCode::BindFailed,
+                ErrorKind::TimedOut => DaemonErrorCode::Timeout,
+                ErrorKind::ConnectionRefused
+                | ErrorKind::ConnectionReset
+                | ErrorKind::BrokenPipe
+                | ErrorKind::UnexpectedEof => DaemonErrorCode::NodeUnreachable,
+                ErrorKind::InvalidInput => DaemonErrorCode::InvalidArgument,
+                _ => DaemonErrorCode::Internal,
+            };
+        }
+    }
+    DaemonErrorCode::Internal
 }
 
-async fn stop_managed_node(runtime: &mut Runtime) -> Result<()> {
-    if let Some(child) = runtime.child.as_mut() {
-        child.kill().await.context("kill managed node failed")?;
+fn error_code_of<T>(result: &Result<T>) -> i32 {
+    match result {
+        Ok(_) => DaemonErrorCode::Unspecified as i32,
+        Err(err) => classify_spawn_error(err) as i32,
     }
-    runtime.child = None;
-    Ok(())
 }
 
-fn discover_devices_from_trust_store(
-    trust_store_file: &std::path::Path,
-    paired_devices: &HashSet<String>,
+fn ack(result: Result<String>) -> GenericAck {
+    match result {
+        Ok(detail) => GenericAck { ok: true, detail },
+        Err(err) => GenericAck {
+            ok: false,
+            detail: format!("{err:#}"),
+        },
+    }
+}
+
+/// Trims `code` and rejects it when empty, longer than `MAX_DEVICE_CODE_LEN` bytes, or
+/// containing whitespace or control characters.
+fn validate_device_code(code: &str) -> Result<String, String> {
+    let code = code.trim();
+    if code.is_empty() {
+        return Err("device code is empty".to_string());
+    }
+    if code.len() > MAX_DEVICE_CODE_LEN {
+        return Err(format!(
+            "device code is {} bytes, longer than {MAX_DEVICE_CODE_LEN}",
+            code.len()
+        ));
+    }
+    if code.chars().any(|ch| ch.is_whitespace() || ch.is_control()) {
+        return Err(format!(
+            "device code {code:?} contains whitespace or control characters"
+        ));
+    }
+    Ok(code.to_string())
+}
+
+/// Checks that the input targets a known session and normalizes cleanly, returning the
+/// normalized event to forward to the node.
+fn validate_send_input(
+    state: &DaemonState,
+    request: &SendInputRequest,
+) -> Result<InputEvent, CodedError> {
+    let event = request
+        .event
+        .as_ref()
+        .ok_or_else(|| CodedError::new(DaemonErrorCode::InvalidArgument, "missing input event"))?;
+    if !state.sessions.contains_key(&event.session_id) {
+        return Err(unknown_session(&event.session_id));
+    }
+    let command = normalize_input_event(event).map_err(|err| {
+        CodedError::new(
+            DaemonErrorCode::InvalidArgument,
+            format!("invalid input event: {err}"),
+        )
+    })?;
+    Ok(InputEvent {
+        payload: Some(command.into()),
+        ..event.clone()
+    })
+}
+
+fn unknown_session(session_id: &str) -> CodedError {
+    CodedError::new(
+        DaemonErrorCode::SessionNotFound,
+        format!("unknown session {session_id}"),
+    )
+}
+
+/// The device code behind a daemon session id; the node only knows device codes.
+fn session_device_code(state: &DaemonState, session_id: &str) -> Result<String> {
+    state
+        .sessions
+        .get(session_id)
+        .map(|session| session.device_code.clone())
+        .ok_or_else(|| unknown_session(session_id).into())
+}
+
+/// The node admin endpoint and the device code behind `session_id`, read under the
+/// runtime lock and returned so the admin round trip runs without holding it.
+async fn node_admin_for_session(
+    runtime: &Mutex<Runtime>,
+    session_id: &str,
+) -> Result<(NodeAdminEndpoint, String)> {
+    let guard = runtime.lock().await;
+    Ok((
+        guard.config.node_admin.clone(),
+        session_device_code(&guard.config, session_id)?,
+    ))
+}
+
+/// Creates `output_path`, refusing to overwrite an existing file, and writes the
+/// recording header for the session. Frames are appended by the recording pump as
+/// the node reassembles them. Until the data plane reports the negotiated stream,
+/// the header carries the default profile and H.264.
+fn start_recording(runtime: &mut Runtime, session_id: &str, output_path: &str) -> Result<String> {
+    if !runtime.config.sessions.contains_key(session_id) {
+        return Err(unknown_session(session_id).into());
+    }
+    if runtime.recordings.contains_key(session_id) {
+        return Err(anyhow!("session {session_id} is already recording"));
+    }
+    if output_path.trim().is_empty() {
+        return Err(anyhow!("recording output path is empty"));
+    }
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(output_path)
+        .with_context(|| format!("create recording file failed: {output_path}"))?;
+    let mut writer = RecordingWriter::new(BufWriter::new(file));
+    writer
+        .start(&VideoProfile::default(), VideoCodec::H264)
+        .context("write recording header failed")?;
+    runtime.recordings.insert(session_id.to_string(), writer);
+    Ok(format!(
+        "recording started for session {session_id} output={output_path}"
+    ))
+}
+
+fn stop_recording(runtime: &mut Runtime, session_id: &str) -> Result<String> {
+    let mut writer = runtime
+        .recordings
+        .remove(session_id)
+        .ok_or_else(|| anyhow!("session {session_id} is not recording"))?;
+    writer.stop().context("finish recording failed")?;
+    Ok(format!(
+        "recording stopped for session {session_id}: {} frames, {} bytes",
+        writer.frames_written(),
+        writer.bytes_written()
+    ))
+}
+
+/// Returns the session id for `device_code`, creating the session on first use. Ids
+/// are derived from the device code so reconnecting to a device keeps its id.
+fn upsert_session(state: &mut DaemonState, device_code: &str) -> String {
+    let session_id = format!("session-{device_code}");
+    state
+        .sessions
+        .entry(session_id.clone())
+        .or_insert_with(|| SessionInfo {
+            device_code: device_code.to_string(),
+            state: "connecting".to_string(),
+        });
+    session_id
+}
+
+/// A session `ConnectSession` created for a new device. It is removed again when
+/// dropped before `keep`, so a connect that fails or is cancelled by the request
+/// deadline leaves no session behind. A session that already existed is left alone.
+struct PendingSession {
+    runtime: Arc<Mutex<Runtime>>,
+    session_id: String,
+    created: bool,
+}
+
+impl PendingSession {
+    /// Opens the session in `state`, the locked contents of `runtime`.
+    fn open(runtime: &Arc<Mutex<Runtime>>, state: &mut Runtime, device_code: &str) -> Self {
+        let before = state.config.sessions.len();
+        let session_id = upsert_session(&mut state.config, device_code);
+        let created = state.config.sessions.len() > before;
+        Self {
+            runtime: runtime.clone(),
+            session_id,
+            created,
+        }
+    }
+
+    fn keep(mut self) {
+        self.created = false;
+    }
+}
+
+impl Drop for PendingSession {
+    fn drop(&mut self) {
+        if !self.created {
+            return;
+        }
+        let session_id = std::mem::take(&mut self.session_id);
+        match self.runtime.try_lock() {
+            Ok(mut guard) => {
+                guard.config.sessions.remove(&session_id);
+            }
+            Err(_) => {
+                let runtime = self.runtime.clone();
+                tokio::spawn(async move {
+                    runtime.lock().await.config.sessions.remove(&session_id);
+                });
+            }
+        }
+    }
+}
+
+/// Hands `device_code` to the running node over its admin socket, starting (or, if
+/// the admin call fails, restarting) the node with every known target otherwise.
+/// The runtime lock is not held across the node admin round trip. A restart holds it
+/// like `StartDaemon` does, so the old child is never out of the runtime's hands.
+async fn connect_target(runtime: &Mutex<Runtime>, device_code: &str) -> Result<String> {
+    let running_admin = {
+        let mut guard = runtime.lock().await;
+        let running = guard
+            .child
+            .as_mut()
+            .is_some_and(|child| matches!(child.try_wait(), Ok(None)));
+        running.then(|| guard.config.node_admin.clone())
+    };
+    if let Some(node_admin) = running_admin {
+        let request = NodeAdminRequest {
+            payload: Some(node_admin_request::Payload::AddTarget(AddTargetRequest {
+                device_code: device_code.to_string(),
+            })),
+        };
+        match send_node_admin_request(&node_admin, request).await {
+            Ok(_) => return Ok("connect target added to running node".to_string()),
+            Err(err) => warn!("add target via node admin failed, restarting node: {err:#}"),
+        }
+    }
+    restart_managed_node(&mut *runtime.lock().await).await?;
+    Ok("managed node started with connect targets".to_string())
+}
+
+/// Revokes trust in `device_code` (or lifts the revocation) through the running node,
+/// or straight in the trust store file when no node is running to own it.
+async fn set_device_revoked(
+    runtime: &mut Runtime,
+    device_code: &str,
+    revoked: bool,
+) -> Result<String> {
+    let running = runtime
+        .child
+        .as_mut()
+        .is_some_and(|child| matches!(child.try_wait(), Ok(None)));
+    if !running {
+        return set_revoked_in_trust_store(&runtime.config.trust_store_file, device_code, revoked);
+    }
+    let request = NodeAdminRequest {
+        payload: Some(node_admin_request::Payload::SetPeerRevoked(
+            SetPeerRevokedRequest {
+                device_code: device_code.to_string(),
+                revoked,
+            },
+        )),
+    };
+    let response = send_node_admin_request(&runtime.config.node_admin, request).await?;
+    match response.payload {
+        Some(node_admin_response::Payload::SetPeerRevoked(ack)) => Ok(ack.detail),
+        _ => Err(anyhow!("node admin reply was not a revocation ack")),
+    }
+}
+
+fn set_revoked_in_trust_store(path: &Path, device_code: &str, revoked: bool) -> Result<String> {
+    let Some(mut trusted) = load_trust_store(path, revoked)? else {
+        return Ok("no trust store yet".to_string());
+    };
+    if revoked {
+        trusted.revoke(device_code);
+    } else if !trusted.clear_revocation(device_code) {
+        return Ok(format!("device {device_code} was not revoked"));
+    }
+    save_trust_store(path, &trusted)?;
+    Ok(format!("trust store updated for device {device_code}"))
+}
+
+/// Trusts the approved `peer_id` under `device_code` through the running node, or
+/// straight in the trust store file when no node is running to own it.
+async fn trust_device_identity(
+    runtime: &mut Runtime,
+    device_code: &str,
+    peer_id: &str,
+) -> Result<String> {
+    let running = runtime
+        .child
+        .as_mut()
+        .is_some_and(|child| matches!(child.try_wait(), Ok(None)));
+    if !running {
+        return trust_identity_in_trust_store(
+            &runtime.config.trust_store_file,
+            device_code,
+            peer_id,
+        );
+    }
+    let request = NodeAdminRequest {
+        payload: Some(node_admin_request::Payload::TrustPeer(TrustPeerRequest {
+            device_code: device_code.to_string(),
+            peer_id: peer_id.to_string(),
+        })),
+    };
+    let response = send_node_admin_request(&runtime.config.node_admin, request).await?;
+    match response.payload {
+        Some(node_admin_response::Payload::TrustPeer(ack)) => Ok(ack.detail),
+        _ => Err(anyhow!("node admin reply was not a trust ack")),
+    }
+}
+
+fn trust_identity_in_trust_store(path: &Path, device_code: &str, peer_id: &str) -> Result<String> {
+    let mut trusted = load_trust_store(path, true)?.unwrap_or_default();
+    let added = trusted
+        .approve_peer_id(device_code, peer_id, unix_ms() as i64)
+        .with_context(|| format!("cannot trust peer {peer_id}"))?;
+    if !added {
+        return Ok(format!(
+            "peer {peer_id} was already trusted as device {device_code}"
+        ));
+    }
+    save_trust_store(path, &trusted)?;
+    Ok(format!("peer {peer_id} trusted as device {device_code}"))
+}
+
+/// The trust store at `path`; a missing file is an empty store when `create` is set
+/// and `None` otherwise.
+fn load_trust_store(path: &Path, create: bool) -> Result<Option<TrustedPeers>> {
+    let parsed = match fs::read(path) {
+        Ok(data) => serde_json::from_slice::<TrustStoreFile>(&data)
+            .with_context(|| format!("parse trust store failed: {}", path.display()))?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound && !create => return Ok(None),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => TrustStoreFile::default(),
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("read trust store failed: {}", path.display()));
+        }
+    };
+    TrustedPeers::from_store(parsed)
+        .context("decode trust store failed")
+        .map(Some)
+}
+
+fn save_trust_store(path: &Path, trusted: &TrustedPeers) -> Result<()> {
+    let payload = trusted.to_store();
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, serde_json::to_vec_pretty(&payload)?)
+        .with_context(|| format!("write trust store failed: {}", tmp_path.display()))?;
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("replace trust store failed: {}", path.display()))
+}
+
+async fn restart_managed_node(runtime: &mut Runtime) -> Result<()> {
+    stop_managed_node(runtime).await?;
+    let mut cmd = Command::new(runtime.config.node_binary.clone());
+    cmd.arg("--listen")
+        .arg(runtime.config.listen_multiaddr.clone())
+        .arg("--identity-file")
+        .arg(runtime.config.identity_file.as_os_str())
+        .arg("--trust-store-file")
+        .arg(runtime.config.trust_store_file.as_os_str())
+        .arg("--admin-socket")
+        .arg(&runtime.config.node_admin.address)
+        .arg("--admin-token-file")
+        .arg(runtime.config.node_admin.token_file.as_os_str())
+        .arg("--trust-on-first-use")
+        .arg(if runtime.config.trust_on_first_use {
+            "true"
+        } else {
+            "false"
+        });
+    for addr in &runtime.config.bootstrap_multiaddrs {
+        cmd.arg("--bootstrap").arg(addr);
+    }
+    for addr in &runtime.config.relay_multiaddrs {
+        cmd.arg("--relay").arg(addr);
+    }
+    let targets: BTreeSet<&str> = runtime
+        .config
+        .sessions
+        .values()
+        .map(|session| session.device_code.as_str())
+        .collect();
+    for device_code in &targets {
+        cmd.arg("--connect-device-code").arg(device_code);
+    }
+    if !targets.is_empty() {
+        cmd.arg("--auto-request");
+    }
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    // No await between spawning and recording the child: a request cancelled by its
+    // deadline must never leave an untracked node behind.
+    let child = cmd.spawn().map_err(|err| {
+        let detail = format!(
+            "failed to spawn managed node binary '{}'",
+            runtime.config.node_binary
+        );
+        if err.kind() == std::io::ErrorKind::NotFound {
+            CodedError::new(
+                DaemonErrorCode::NodeBinaryNotFound,
+                format!("{detail}: {err}"),
+            )
+            .into()
+        } else {
+            anyhow::Error::new(err).context(detail)
+        }
+    })?;
+    runtime.child = Some(child);
+    runtime.node_started_unix_ms = Some(unix_ms());
+    runtime.node_exit_reported = false;
+    runtime.pending_restart = None;
+    Ok(())
+}
+
+async fn stop_managed_node(runtime: &mut Runtime) -> Result<()> {
+    if let Some(child) = runtime.child.as_mut() {
+        terminate_child(child, NODE_STOP_GRACE_MS).await?;
+    }
+    runtime.child = None;
+    Ok(())
+}
+
+/// On Unix, sends SIGTERM so the child can shut down cleanly and kills it if it has
+/// not exited after `grace_ms`. Elsewhere the child is killed right away.
+#[cfg_attr(not(unix), allow(unused_variables))]
+async fn terminate_child(child: &mut Child, grace_ms: u64) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        use nix::{
+            sys::signal::{Signal, kill},
+            unistd::Pid,
+        };
+        let pid = i32::try_from(pid).context("child pid out of range")?;
+        match kill(Pid::from_raw(pid), Signal::SIGTERM) {
+            Ok(()) => {
+                let grace = Duration::from_millis(grace_ms);
+                if let Ok(status) = tokio::time::timeout(grace, child.wait()).await {
+                    status.context("wait for managed node exit failed")?;
+                    return Ok(());
+                }
+                warn!("managed node still running {grace_ms}ms after SIGTERM, killing it");
+            }
+            Err(err) => warn!("send SIGTERM to managed node failed: {err}"),
+        }
+    }
+    child.kill().await.context("kill managed node failed")
+}
+
+/// Parses every entry, returning all of them or, if any fail, just the invalid ones.
+fn parse_multiaddrs(raw: &[String]) -> Result<Vec<Multiaddr>, Vec<String>> {
+    let mut valid = Vec::with_capacity(raw.len());
+    let mut invalid = Vec::new();
+    for entry in raw {
+        match entry.trim().parse::<Multiaddr>() {
+            Ok(addr) => valid.push(addr),
+            Err(_) => invalid.push(entry.clone()),
+        }
+    }
+    if invalid.is_empty() {
+        Ok(valid)
+    } else {
+        Err(invalid)
+    }
+}
+
+/// `parse_multiaddrs` for relays, which must also name the relay with `/p2p/<peer_id>`
+/// so a reservation can be made on it.
+fn parse_relay_multiaddrs(raw: &[String]) -> Result<Vec<Multiaddr>, Vec<String>> {
+    let parsed = parse_multiaddrs(raw)?;
+    let invalid: Vec<String> = parsed
+        .iter()
+        .filter(|addr| {
+            !addr
+                .iter()
+                .any(|protocol| matches!(protocol, multiaddr::Protocol::P2p(_)))
+        })
+        .map(|addr| format!("{addr} (missing /p2p/<peer_id>)"))
+        .collect();
+    if invalid.is_empty() {
+        Ok(parsed)
+    } else {
+        Err(invalid)
+    }
+}
+
+fn discover_devices_from_trust_store(
+    trust_store_file: &std::path::Path,
+    paired_devices: &HashSet<String>,
 ) -> Vec<DiscoveredDevice> {
     let Ok(data) = fs::read(trust_store_file) else {
         return Vec::new();
     };
-    let Ok(parsed) = serde_json::from_slice::<TrustStoreFileV1>(&data) else {
+    let Ok(parsed) = serde_json::from_slice::<TrustStoreFile>(&data) else {
         return Vec::new();
     };
     parsed
@@ -495,11 +1512,56 @@ fn discover_devices_from_trust_store(
             peer_id: peer.peer_id,
             last_seen_unix_ms: peer.last_seen_unix_ms.max(0) as u64,
             trusted: paired_devices.contains(&peer.device_code),
+            addrs: Vec::new(),
+            pairing_code: aetherlink_core::pairing_code_from_hex(&peer.identity_pubkey_hex)
+                .unwrap_or_default(),
         })
         .collect()
 }
 
+/// Folds live DHT results into the trust-store view, one entry per device code. The
+/// live entry supplies addresses and a fresher peer id; whether a device is trusted
+/// is only ever decided by the trust store.
+fn merge_discovered(
+    known: Vec<DiscoveredDevice>,
+    live: Vec<DiscoveredDevice>,
+) -> Vec<DiscoveredDevice> {
+    let mut merged: HashMap<String, DiscoveredDevice> = known
+        .into_iter()
+        .map(|device| (device.device_code.clone(), device))
+        .collect();
+    for device in live {
+        match merged.get_mut(&device.device_code) {
+            Some(existing) => {
+                if !device.peer_id.is_empty() {
+                    existing.peer_id = device.peer_id;
+                }
+                if !device.pairing_code.is_empty() {
+                    existing.pairing_code = device.pairing_code;
+                }
+                existing.addrs = device.addrs;
+                existing.last_seen_unix_ms =
+                    existing.last_seen_unix_ms.max(device.last_seen_unix_ms);
+            }
+            None => {
+                merged.insert(device.device_code.clone(), device);
+            }
+        }
+    }
+    let mut devices: Vec<_> = merged.into_values().collect();
+    devices.sort_by(|a, b| a.device_code.cmp(&b.device_code));
+    devices
+}
+
 async fn read_frame<S>(stream: &mut S) -> Result<Option<Vec<u8>>>
+where
+    S: AsyncRead + Unpin,
+{
+    read_frame_limited(stream, usize::MAX).await
+}
+
+/// `read_frame` that refuses, before allocating, a frame longer than `max_len`.
+async fn read_frame_limited<S>(stream: &mut S, max_len: usize) -> Result<Option<Vec<u8>>>
 where
     S: AsyncRead + Unpin,
 {
@@ -513,6 +1575,9 @@ where
     if len == 0 {
         return Ok(None);
     }
+    if len > max_len {
+        return Err(anyhow!("IPC frame of {len} bytes exceeds {max_len}"));
+    }
     let mut payload = vec![0_u8; len];
     stream
         .read_exact(&mut payload)
@@ -545,6 +1610,18 @@ fn default_data_dir() -> PathBuf {
     PathBuf::from(".aetherlink")
 }
 
+fn default_node_admin_endpoint(daemon_endpoint: &str) -> String {
+    #[cfg(unix)]
+    {
+        format!("{daemon_endpoint}.node")
+    }
+    #[cfg(windows)]
+    {
+        let _ = daemon_endpoint;
+        "127.0.0.1:59322".to_string()
+    }
+}
+
 fn default_socket_path() -> String {
     #[cfg(unix)]
     {
@@ -552,21 +1629,57 @@ fn default_socket_path() -> String {
     }
     #[cfg(windows)]
     {
-        "127.0.0.1:59321".to_string()
+        r"\\.\pipe\aetherlink-daemon".to_string()
     }
 }
 
+/// Binds the socket readable and writable by the daemon's user only. Missing parent
+/// directories are created `0o700`; existing ones (such as `/tmp`) are left alone.
 #[cfg(unix)]
 async fn bind_listener(socket_path: &str) -> Result<IpcListener> {
-    IpcListener::bind(socket_path)
-        .with_context(|| format!("bind daemon socket failed: {socket_path}"))
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let path = std::path::Path::new(socket_path);
+    if path.exists() {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(CodedError::new(
+                DaemonErrorCode::AlreadyRunning,
+                format!("another daemon is listening on {socket_path}"),
+            )
+            .into());
+        }
+        fs::remove_file(path)
+            .with_context(|| format!("remove stale daemon socket failed: {socket_path}"))?;
+    }
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(parent)
+            .with_context(|| format!("create socket parent failed: {}", parent.display()))?;
+    }
+    let listener = IpcListener::bind(socket_path)
+        .with_context(|| format!("bind daemon socket failed: {socket_path}"))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("set daemon socket mode failed: {socket_path}"))?;
+    Ok(listener)
 }
 
 #[cfg(windows)]
 async fn bind_listener(socket_path: &str) -> Result<IpcListener> {
-    IpcListener::bind(socket_path)
-        .await
-        .with_context(|| format!("bind daemon socket failed: {socket_path}"))
+    match IpcListener::bind(socket_path) {
+        Ok(listener) => Ok(listener),
+        // The first instance of a pipe name is refused when another process owns it.
+        Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => Err(CodedError::new(
+            DaemonErrorCode::AlreadyRunning,
+            format!("another daemon is listening on {socket_path}"),
+        )
+        .into()),
+        Err(err) => Err(err).with_context(|| format!("bind daemon socket failed: {socket_path}")),
+    }
 }
 
 fn unix_ms() -> u64 {
@@ -579,12 +1692,13 @@ fn unix_ms() -> u64 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use aetherlink_core::TrustedPeerRecord;
 
     #[test]
     fn discovers_devices_from_trust_store_file() {
         let tmp_path =
             std::env::temp_dir().join(format!("aetherlink-daemon-test-{}.json", unix_ms()));
-        let payload = TrustStoreFileV1 {
+        let payload = TrustStoreFile {
             version: 1,
             peers: vec![TrustedPeerRecord {
                 device_code: "device-a".to_string(),
@@ -593,6 +1707,7 @@ mod tests {
                 first_seen_unix_ms: 1,
                 last_seen_unix_ms: 2,
             }],
+            revoked: Vec::new(),
         };
         fs::write(&tmp_path, serde_json::to_vec(&payload).unwrap()).unwrap();
 
@@ -606,4 +1721,703 @@ mod tests {
 
         let _ = fs::remove_file(tmp_path);
     }
+
+    #[test]
+    fn spawn_errors_map_to_codes() {
+        use std::io::{Error, ErrorKind};
+
+        let io = |kind: ErrorKind| {
+            anyhow::Error::new(Error::from(kind)).context("failed to spawn managed node binary")
+        };
+        // Only the spawn call knows a missing file is the node binary.
+        assert_eq!(
+            classify_spawn_error(&io(ErrorKind::NotFound)),
+            DaemonErrorCode::Internal
+        );
+        assert_eq!(
+            classify_spawn_error(&io(ErrorKind::PermissionDenied)),
+            DaemonErrorCode::PermissionDenied
+        );
+        assert_eq!(
+            classify_spawn_error(&io(ErrorKind::AddrInUse)),
+            DaemonErrorCode::BindFailed
+        );
+        assert_eq!(
+            classify_spawn_error(&io(ErrorKind::ConnectionRefused)),
+            DaemonErrorCode::NodeUnreachable
+        );
+        assert_eq!(
+            classify_spawn_error(&io(ErrorKind::TimedOut)),
+            DaemonErrorCode::Timeout
+        );
+        assert_eq!(
+            classify_spawn_error(&io(ErrorKind::OutOfMemory)),
+            DaemonErrorCode::Internal
+        );
+        assert_eq!(
+            classify_spawn_error(&anyhow!("no io cause")),
+            DaemonErrorCode::Internal
+        );
+        let coded = anyhow::Error::new(CodedError::new(
+            DaemonErrorCode::InvalidArgument,
+            "device code is empty",
+        ))
+        .context("connect session failed");
+        assert_eq!(
+            classify_spawn_error(&coded),
+            DaemonErrorCode::InvalidArgument
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_node_binary_is_reported_with_code() {
+        let runtime = Arc::new(Mutex::new(test_runtime()));
+        let request = DaemonRequest {
+            payload: Some(daemon_request::Payload::StartDaemon(
+                aetherlink_proto::v1::DaemonStartRequest {
+                    node_binary: format!("/nonexistent/aetherlink-node-{}", unix_ms()),
+                    listen_multiaddr: String::new(),
+                    bootstrap_multiaddrs: Vec::new(),
+                    trust_on_first_use: false,
+                },
+            )),
+        };
+        let (response, events) = process_request(request, runtime).await;
+        assert_eq!(
+            response.error_code,
+            DaemonErrorCode::NodeBinaryNotFound as i32
+        );
+        assert!(matches!(
+            &events[..],
+            [DaemonEvent { payload: Some(daemon_event::Payload::Error(error)) }]
+                if error.error_code == DaemonErrorCode::NodeBinaryNotFound as i32
+        ));
+    }
+
+    #[test]
+    fn device_codes_are_trimmed_and_validated() {
+        assert_eq!(
+            validate_device_code("  12D3KooWabc\n"),
+            Ok("12D3KooWabc".to_string())
+        );
+        assert_eq!(
+            validate_device_code(""),
+            Err("device code is empty".to_string())
+        );
+        assert_eq!(
+            validate_device_code(" \t\n"),
+            Err("device code is empty".to_string())
+        );
+        assert!(
+            validate_device_code("dev ice")
+                .unwrap_err()
+                .contains("whitespace")
+        );
+        assert!(validate_device_code("dev\u{7}ice").is_err());
+        assert!(validate_device_code(&"a".repeat(MAX_DEVICE_CODE_LEN)).is_ok());
+        assert!(
+            validate_device_code(&"a".repeat(MAX_DEVICE_CODE_LEN + 1))
+                .unwrap_err()
+                .contains("longer than")
+        );
+    }
+
+    #[tokio::test]
+    async fn pair_device_rejects_blank_code() {
+        let runtime = Arc::new(Mutex::new(test_runtime()));
+        let request = DaemonRequest {
+            payload: Some(daemon_request::Payload::PairDevice(
+                aetherlink_proto::v1::PairDeviceRequest {
+                    device_code: "   ".to_string(),
+                    approved: true,
+                    peer_id: String::new(),
+                },
+            )),
+        };
+        let (response, _) = process_request(request, runtime.clone()).await;
+        let Some(daemon_response::Payload::PairDevice(pair)) = response.payload else {
+            panic!("unexpected response");
+        };
+        assert!(!pair.paired);
+        assert_eq!(pair.detail, "device code is empty");
+        assert!(runtime.lock().await.config.paired_devices.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unpairing_revokes_the_device_in_the_trust_store() {
+        let record = TrustedPeerRecord {
+            device_code: "device-a".to_string(),
+            peer_id: "12D3KooWAHoEkEqnKzM5PXFygh2movVBCSX3k8tDsT2cneU68Gyt".to_string(),
+            identity_pubkey_hex: format!("08011220{}", "07".repeat(32)),
+            first_seen_unix_ms: 1,
+            last_seen_unix_ms: 2,
+        };
+        let tmp_path =
+            std::env::temp_dir().join(format!("aetherlink-daemon-revoke-{}.json", unix_ms()));
+        let payload = TrustStoreFile {
+            version: 1,
+            peers: vec![record],
+            revoked: Vec::new(),
+        };
+        fs::write(&tmp_path, serde_json::to_vec(&payload).unwrap()).unwrap();
+        let mut state = test_runtime();
+        state.config.trust_store_file = tmp_path.clone();
+        state.config.paired_devices.insert("device-a".to_string());
+        let runtime = Arc::new(Mutex::new(state));
+
+        let pair = |approved| DaemonRequest {
+            payload: Some(daemon_request::Payload::PairDevice(
+                aetherlink_proto::v1::PairDeviceRequest {
+                    device_code: "device-a".to_string(),
+                    approved,
+                    peer_id: String::new(),
+                },
+            )),
+        };
+        let (response, _) = process_request(pair(false), runtime.clone()).await;
+        let Some(daemon_response::Payload::PairDevice(unpaired)) = response.payload else {
+            panic!("unexpected response");
+        };
+        assert!(!unpaired.paired);
+        assert!(unpaired.detail.ends_with("revoked"), "{}", unpaired.detail);
+        let saved: TrustStoreFile = serde_json::from_slice(&fs::read(&tmp_path).unwrap()).unwrap();
+        assert!(saved.peers.is_empty());
+        assert_eq!(saved.revoked, vec!["device-a".to_string()]);
+        assert!(discover_devices_from_trust_store(&tmp_path, &HashSet::new()).is_empty());
+
+        process_request(pair(true), runtime.clone()).await;
+        let saved: TrustStoreFile = serde_json::from_slice(&fs::read(&tmp_path).unwrap()).unwrap();
+        assert!(saved.revoked.is_empty());
+        let _ = fs::remove_file(tmp_path);
+    }
+
+    #[tokio::test]
+    async fn approving_with_a_peer_id_trusts_exactly_that_identity() {
+        let peer_id = libp2p::identity::Keypair::generate_ed25519()
+            .public()
+            .to_peer_id()
+            .to_string();
+        let tmp_path =
+            std::env::temp_dir().join(format!("aetherlink-daemon-approve-{}.json", unix_ms()));
+        let mut state = test_runtime();
+        state.config.trust_store_file = tmp_path.clone();
+        let runtime = Arc::new(Mutex::new(state));
+
+        let pair = |peer_id: &str| DaemonRequest {
+            payload: Some(daemon_request::Payload::PairDevice(
+                aetherlink_proto::v1::PairDeviceRequest {
+                    device_code: "device-a".to_string(),
+                    approved: true,
+                    peer_id: peer_id.to_string(),
+                },
+            )),
+        };
+        let (response, _) = process_request(pair(&peer_id), runtime.clone()).await;
+        let Some(daemon_response::Payload::PairDevice(paired)) = response.payload else {
+            panic!("unexpected response");
+        };
+        assert!(paired.paired);
+        assert!(
+            paired.detail.ends_with("trusted as device device-a"),
+            "{}",
+            paired.detail
+        );
+        let saved: TrustStoreFile = serde_json::from_slice(&fs::read(&tmp_path).unwrap()).unwrap();
+        assert_eq!(saved.peers.len(), 1);
+        assert_eq!(saved.peers[0].device_code, "device-a");
+        assert_eq!(saved.peers[0].peer_id, peer_id);
+
+        let (response, _) = process_request(pair("not-a-peer-id"), runtime.clone()).await;
+        let Some(daemon_response::Payload::PairDevice(refused)) = response.payload else {
+            panic!("unexpected response");
+        };
+        assert!(refused.detail.contains("failed"), "{}", refused.detail);
+        let saved: TrustStoreFile = serde_json::from_slice(&fs::read(&tmp_path).unwrap()).unwrap();
+        assert_eq!(saved.peers.len(), 1);
+        let _ = fs::remove_file(tmp_path);
+    }
+
+    #[tokio::test]
+    async fn auth_frame_longer_than_the_token_is_refused_unread() {
+        let token = "ab".repeat(32);
+        let (mut client, mut server) = tokio::io::duplex(64);
+        // Only the length prefix is sent; a frame this long must not be awaited.
+        client.write_all(&u32::MAX.to_be_bytes()).await.unwrap();
+        let err = authenticate_client(&mut server, &token).await.unwrap_err();
+        assert!(format!("{err:#}").contains("exceeds"), "{err:#}");
+
+        let (mut client, mut server) = tokio::io::duplex(256);
+        write_frame(&mut client, format!("{token}\n").as_bytes())
+            .await
+            .unwrap();
+        authenticate_client(&mut server, &token).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn slow_request_yields_timeout_response() {
+        let slow = async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            (DaemonResponse::default(), Vec::new())
+        };
+        let (response, events) = with_request_deadline(Duration::from_millis(20), slow).await;
+        assert!(response.payload.is_none());
+        assert_eq!(response.error, "request timed out after 20ms");
+        assert!(matches!(
+            &events[..],
+            [DaemonEvent { payload: Some(daemon_event::Payload::Error(error)) }]
+                if error.code == "request_timeout"
+        ));
+
+        let runtime = Arc::new(Mutex::new(test_runtime()));
+        let request = DaemonRequest {
+            payload: Some(daemon_request::Payload::GetDaemonStatus(
+                aetherlink_proto::v1::GetDaemonStatusRequest {},
+            )),
+        };
+        let (response, _) =
+            with_request_deadline(Duration::from_secs(5), process_request(request, runtime)).await;
+        assert!(response.error.is_empty());
+        assert!(response.payload.is_some());
+    }
+
+    #[test]
+    fn parse_multiaddrs_reports_every_invalid_entry() {
+        let raw = vec![
+            " /ip4/203.0.113.1/udp/4001/quic-v1 ".to_string(),
+            "/dns4/relay.example.com/tcp/4001".to_string(),
+        ];
+        let parsed = parse_multiaddrs(&raw).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].to_string(), "/ip4/203.0.113.1/udp/4001/quic-v1");
+
+        let raw = vec![
+            "/ip4/203.0.113.1/udp/4001/quic-v1".to_string(),
+            "203.0.113.2:4001".to_string(),
+            "/ip4/999.0.0.1/tcp/1".to_string(),
+        ];
+        assert_eq!(
+            parse_multiaddrs(&raw),
+            Err(vec![
+                "203.0.113.2:4001".to_string(),
+                "/ip4/999.0.0.1/tcp/1".to_string()
+            ])
+        );
+        assert_eq!(parse_multiaddrs(&[]), Ok(Vec::new()));
+
+        let relay = "/ip4/203.0.113.1/udp/4001/quic-v1/p2p/12D3KooWAHoEkEqnKzM5PXFygh2movVBCSX3k8tDsT2cneU68Gyt";
+        assert_eq!(
+            parse_relay_multiaddrs(&[relay.to_string()]).unwrap().len(),
+            1
+        );
+        assert_eq!(
+            parse_relay_multiaddrs(&[
+                relay.to_string(),
+                "/ip4/203.0.113.2/udp/4001/quic-v1".to_string()
+            ]),
+            Err(vec![
+                "/ip4/203.0.113.2/udp/4001/quic-v1 (missing /p2p/<peer_id>)".to_string()
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn update_network_without_node_saves_for_next_start() {
+        let runtime = Arc::new(Mutex::new(test_runtime()));
+        let mut request = DaemonRequest {
+            payload: Some(daemon_request::Payload::UpdateNetwork(
+                UpdateNetworkRequest {
+                    bootstrap_multiaddrs: vec!["/ip4/203.0.113.1/udp/4001/quic-v1".to_string()],
+                    relay_multiaddrs: vec!["not-a-multiaddr".to_string()],
+                },
+            )),
+        };
+        let (response, _) = process_request(request.clone(), runtime.clone()).await;
+        let Some(daemon_response::Payload::UpdateNetwork(ack)) = response.payload else {
+            panic!("unexpected response");
+        };
+        assert!(!ack.ok);
+        assert!(ack.detail.contains("not-a-multiaddr"));
+        assert!(runtime.lock().await.config.bootstrap_multiaddrs.is_empty());
+
+        // A relay the node could not reserve on is refused before anything is saved.
+        if let Some(daemon_request::Payload::UpdateNetwork(update)) = request.payload.as_mut() {
+            update.relay_multiaddrs = vec!["/ip4/203.0.113.2/udp/4001/quic-v1".to_string()];
+        }
+        let (response, _) = process_request(request.clone(), runtime.clone()).await;
+        let Some(daemon_response::Payload::UpdateNetwork(ack)) = response.payload else {
+            panic!("unexpected response");
+        };
+        assert!(!ack.ok);
+        assert!(ack.detail.contains("missing /p2p/<peer_id>"));
+        assert_eq!(response.error_code, DaemonErrorCode::InvalidArgument as i32);
+        let guard = runtime.lock().await;
+        assert!(guard.config.bootstrap_multiaddrs.is_empty());
+        assert!(guard.config.relay_multiaddrs.is_empty());
+        drop(guard);
+
+        if let Some(daemon_request::Payload::UpdateNetwork(update)) = request.payload.as_mut() {
+            update.relay_multiaddrs.clear();
+        }
+        let (response, _) = process_request(request, runtime.clone()).await;
+        let Some(daemon_response::Payload::UpdateNetwork(ack)) = response.payload else {
+            panic!("unexpected response");
+        };
+        assert!(ack.ok, "{}", ack.detail);
+        assert_eq!(
+            runtime.lock().await.config.bootstrap_multiaddrs,
+            vec!["/ip4/203.0.113.1/udp/4001/quic-v1".to_string()]
+        );
+    }
+
+    #[test]
+    fn live_discovery_merges_into_trust_store_entries() {
+        let device = |code: &str, peer: &str, seen: u64, trusted: bool, addr: Option<&str>| {
+            DiscoveredDevice {
+                device_code: code.to_string(),
+                peer_id: peer.to_string(),
+                last_seen_unix_ms: seen,
+                trusted,
+                addrs: addr.into_iter().map(str::to_string).collect(),
+                pairing_code: String::new(),
+            }
+        };
+        let mut known = vec![
+            device("device-b", "peer-b", 50, true, None),
+            device("device-a", "peer-a-old", 10, false, None),
+        ];
+        known[0].pairing_code = "111 111".to_string();
+        let mut live = vec![
+            device(
+                "device-a",
+                "peer-a",
+                40,
+                true,
+                Some("/ip4/203.0.113.1/udp/9000/quic-v1"),
+            ),
+            device(
+                "device-b",
+                "",
+                20,
+                false,
+                Some("/ip4/203.0.113.2/udp/9000/quic-v1"),
+            ),
+            device(
+                "device-c",
+                "peer-c",
+                30,
+                false,
+                Some("/ip4/203.0.113.3/udp/9000/quic-v1"),
+            ),
+        ];
+        live[0].pairing_code = "222 222".to_string();
+
+        let merged = merge_discovered(known, live);
+        let codes: Vec<_> = merged.iter().map(|d| d.device_code.as_str()).collect();
+        assert_eq!(codes, ["device-a", "device-b", "device-c"]);
+
+        assert_eq!(merged[0].peer_id, "peer-a");
+        assert_eq!(merged[0].last_seen_unix_ms, 40);
+        assert!(!merged[0].trusted, "trust comes from the trust store");
+        assert_eq!(merged[0].addrs.len(), 1);
+        assert_eq!(merged[0].pairing_code, "222 222");
+
+        assert_eq!(merged[1].peer_id, "peer-b", "empty live peer id is ignored");
+        assert_eq!(merged[1].last_seen_unix_ms, 50);
+        assert_eq!(
+            merged[1].pairing_code, "111 111",
+            "empty live pairing code keeps the trust-store one"
+        );
+        assert!(merged[1].trusted);
+
+        assert!(!merged[2].trusted);
+    }
+
+    fn test_state() -> DaemonState {
+        DaemonState {
+            node_binary: "aetherlink-node".to_string(),
+            listen_multiaddr: "/ip4/127.0.0.1/udp/0/quic-v1".to_string(),
+            bootstrap_multiaddrs: Vec::new(),
+            relay_multiaddrs: Vec::new(),
+            trust_on_first_use: false,
+            identity_file: PathBuf::from("device.key"),
+            trust_store_file: PathBuf::from("trusted_peers.json"),
+            paired_devices: HashSet::new(),
+            session_stats: HashMap::new(),
+            node_admin: NodeAdminEndpoint {
+                address: "node.sock".to_string(),
+                token: "token".into(),
+                token_file: PathBuf::from("daemon.token"),
+            },
+            sessions: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn test_runtime() -> Runtime {
+        Runtime {
+            config: test_state(),
+            child: None,
+            node_started_unix_ms: None,
+            last_error: None,
+            restart_policy: RestartPolicy::default(),
+            pending_restart: None,
+            node_exit_reported: false,
+            recordings: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn upsert_session_keeps_one_stable_session_per_device() {
+        let mut state = test_state();
+        let a = upsert_session(&mut state, "device-a");
+        let b = upsert_session(&mut state, "device-b");
+        assert_ne!(a, b);
+        assert_eq!(upsert_session(&mut state, "device-a"), a);
+        assert_eq!(state.sessions.len(), 2);
+        assert_eq!(state.sessions[&a].device_code, "device-a");
+        assert_eq!(state.sessions[&b].device_code, "device-b");
+    }
+
+    #[test]
+    fn pending_session_is_removed_unless_kept() {
+        let runtime = Arc::new(Mutex::new(test_runtime()));
+        let open = |device_code: &str| {
+            let mut guard = runtime.try_lock().unwrap();
+            PendingSession::open(&runtime, &mut guard, device_code)
+        };
+        let sessions = || runtime.try_lock().unwrap().config.sessions.clone();
+        let existing = upsert_session(&mut runtime.try_lock().unwrap().config, "device-a");
+
+        drop(open("device-b"));
+        assert!(!sessions().contains_key("session-device-b"));
+
+        // Dropping the connect future mid-await drops the guard the same way.
+        drop(open("device-a"));
+        assert!(sessions().contains_key(&existing));
+
+        open("device-b").keep();
+        assert!(sessions().contains_key("session-device-b"));
+    }
+
+    #[test]
+    fn clipboard_requests_resolve_session_to_device_code() {
+        let mut state = test_state();
+        let session_id = upsert_session(&mut state, "device-a");
+        assert_eq!(
+            session_device_code(&state, &session_id).unwrap(),
+            "device-a"
+        );
+        let unknown = session_device_code(&state, "session-unknown");
+        assert_eq!(
+            error_code_of(&unknown),
+            DaemonErrorCode::SessionNotFound as i32
+        );
+        let refused = ack(unknown);
+        assert!(!refused.ok);
+        assert!(refused.detail.contains("unknown session"));
+    }
+
+    #[test]
+    fn recording_starts_and_stops_per_session() {
+        let mut runtime = test_runtime();
+        let output =
+            std::env::temp_dir().join(format!("aetherlink-daemon-recording-{}.alrc", unix_ms()));
+        let output_path = output.to_string_lossy().to_string();
+        assert!(start_recording(&mut runtime, "session-device-a", &output_path).is_err());
+
+        let session_id = upsert_session(&mut runtime.config, "device-a");
+        start_recording(&mut runtime, &session_id, &output_path).unwrap();
+        assert!(start_recording(&mut runtime, &session_id, &output_path).is_err());
+        stop_recording(&mut runtime, &session_id).unwrap();
+        assert!(stop_recording(&mut runtime, &session_id).is_err());
+
+        let written = fs::read(&output).unwrap();
+        assert_eq!(
+            written,
+            aetherlink_media::recording_header(&VideoProfile::default(), VideoCodec::H264)
+        );
+
+        // An existing file is never truncated.
+        let err = start_recording(&mut runtime, &session_id, &output_path).unwrap_err();
+        assert!(format!("{err:#}").contains("create recording file failed"));
+        assert!(!runtime.recordings.contains_key(&session_id));
+        assert_eq!(fs::read(&output).unwrap(), written);
+        let _ = fs::remove_file(output);
+    }
+
+    #[test]
+    fn send_input_requires_known_session_and_valid_event() {
+        use aetherlink_proto::v1::{KeyEvent, MouseEvent, input_event};
+
+        let mut state = test_state();
+        let session_id = upsert_session(&mut state, "device-a");
+        let request = |session_id: &str, payload: Option<input_event::Payload>| SendInputRequest {
+            event: Some(InputEvent {
+                session_id: session_id.to_string(),
+                source: 1,
+                event_unix_ms: 0,
+                payload,
+            }),
+        };
+        let mouse = Some(input_event::Payload::Mouse(MouseEvent {
+            x: 10,
+            y: 20,
+            buttons_mask: 1,
+            ..Default::default()
+        }));
+
+        assert_eq!(
+            validate_send_input(&state, &SendInputRequest { event: None }),
+            Err(CodedError::new(
+                DaemonErrorCode::InvalidArgument,
+                "missing input event"
+            ))
+        );
+        assert_eq!(
+            validate_send_input(&state, &request("session-unknown", mouse)),
+            Err(CodedError::new(
+                DaemonErrorCode::SessionNotFound,
+                "unknown session session-unknown"
+            ))
+        );
+        assert_eq!(
+            validate_send_input(&state, &request(&session_id, None)),
+            Err(CodedError::new(
+                DaemonErrorCode::InvalidArgument,
+                "invalid input event: missing input payload"
+            ))
+        );
+        let both_edges = Some(input_event::Payload::Key(KeyEvent {
+            key_code: 30,
+            down: true,
+            up: true,
+            repeat: false,
+            modifiers_mask: 0,
+        }));
+        assert!(validate_send_input(&state, &request(&session_id, both_edges)).is_err());
+        // Fields normalization does not carry are not forwarded.
+        let with_delta = Some(input_event::Payload::Mouse(MouseEvent {
+            x: 10,
+            y: 20,
+            delta_x: 5,
+            buttons_mask: 1,
+            ..Default::default()
+        }));
+        assert_eq!(
+            validate_send_input(&state, &request(&session_id, with_delta)),
+            Ok(request(&session_id, mouse).event.unwrap())
+        );
+    }
+
+    #[test]
+    fn status_before_the_node_was_ever_started() {
+        let mut runtime = test_runtime();
+        upsert_session(&mut runtime.config, "device-a");
+        let status = daemon_status(&mut runtime);
+        assert_eq!(status.node_state, ManagedNodeState::NeverStarted as i32);
+        assert_eq!(status.node_uptime_ms, 0);
+        assert_eq!(status.active_sessions, 1);
+        assert!(status.last_error.is_empty());
+
+        runtime.node_started_unix_ms = Some(unix_ms());
+        let status = daemon_status(&mut runtime);
+        assert_eq!(status.node_state, ManagedNodeState::Stopped as i32);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn status_of_running_node() {
+        let mut runtime = test_runtime();
+        runtime.child = Some(Command::new("sleep").arg("30").spawn().unwrap());
+        runtime.node_started_unix_ms = Some(unix_ms().saturating_sub(1_500));
+        let status = daemon_status(&mut runtime);
+        assert_eq!(status.node_state, ManagedNodeState::Running as i32);
+        assert!(status.node_uptime_ms >= 1_500);
+        assert!(status.last_error.is_empty());
+        stop_managed_node(&mut runtime).await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn terminate_child_stops_with_sigterm_first() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        terminate_child(&mut child, 5_000).await.unwrap();
+        let status = child.try_wait().unwrap().expect("child exited");
+        assert_eq!(
+            status.signal(),
+            Some(nix::sys::signal::Signal::SIGTERM as i32)
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn terminate_child_kills_after_grace_period() {
+        use std::os::unix::process::ExitStatusExt;
+        use tokio::io::AsyncBufReadExt;
+
+        // The ignored SIGTERM disposition survives the exec into sleep.
+        let mut child = Command::new("sh")
+            .args(["-c", "trap '' TERM; echo ready; exec sleep 30"])
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let mut line = String::new();
+        tokio::io::BufReader::new(stdout)
+            .read_line(&mut line)
+            .await
+            .unwrap();
+        assert_eq!(line.trim(), "ready");
+
+        let started = std::time::Instant::now();
+        terminate_child(&mut child, 200).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        let status = child.wait().await.unwrap();
+        assert_eq!(
+            status.signal(),
+            Some(nix::sys::signal::Signal::SIGKILL as i32)
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn status_of_exited_node() {
+        let mut runtime = test_runtime();
+        let mut child = Command::new("sh").args(["-c", "exit 3"]).spawn().unwrap();
+        child.wait().await.unwrap();
+        runtime.child = Some(child);
+        runtime.node_started_unix_ms = Some(unix_ms());
+        let status = daemon_status(&mut runtime);
+        assert_eq!(status.node_state, ManagedNodeState::Exited as i32);
+        assert_eq!(status.node_exit_code, 3);
+        assert_eq!(status.node_uptime_ms, 0);
+        assert!(status.last_error.contains("managed node exited"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn socket_and_new_parent_dir_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = std::env::temp_dir().join(format!("aetherlink-daemon-sock-{}", unix_ms()));
+        let socket = root.join("run").join("daemon.sock");
+        let socket_path = socket.to_string_lossy().to_string();
+        let listener = bind_listener(&socket_path).await.unwrap();
+
+        let mode =
+            |path: &std::path::Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&socket), 0o600);
+        assert_eq!(mode(&root.join("run")), 0o700);
+        assert_eq!(mode(&root), 0o700);
+
+        let err = bind_listener(&socket_path).await.unwrap_err();
+        assert_eq!(classify_spawn_error(&err), DaemonErrorCode::AlreadyRunning);
+
+        // Rebinding over a stale socket works and keeps the mode.
+        drop(listener);
+        let _listener = bind_listener(&socket_path).await.unwrap();
+        assert_eq!(mode(&socket), 0o600);
+        let _ = fs::remove_dir_all(root);
+    }
 }
@@ -0,0 +1,54 @@
+use std::{path::PathBuf, sync::Arc};
+
+use aetherlink_proto::v1::{
+    NodeAdminEnvelope, NodeAdminRequest, NodeAdminResponse, node_admin_envelope,
+};
+use anyhow::{Context, Result, anyhow};
+use prost::Message;
+
+use crate::{read_frame, unix_ms, write_frame};
+
+// The node's admin endpoint is still loopback TCP on Windows.
+#[cfg(windows)]
+use tokio::net::TcpStream as NodeAdminStream;
+#[cfg(unix)]
+use tokio::net::UnixStream as NodeAdminStream;
+
+/// Where the managed node serves its admin channel and the token it expects first.
+/// The node is started with the daemon's own token file, so this is the daemon token.
+#[derive(Debug, Clone)]
+pub struct NodeAdminEndpoint {
+    pub address: String,
+    pub token: Arc<str>,
+    pub token_file: PathBuf,
+}
+
+/// One request/response round trip on the managed node's admin socket.
+pub async fn send_node_admin_request(
+    endpoint: &NodeAdminEndpoint,
+    request: NodeAdminRequest,
+) -> Result<NodeAdminResponse> {
+    let address = &endpoint.address;
+    let mut stream = NodeAdminStream::connect(address)
+        .await
+        .with_context(|| format!("connect node admin socket failed: {address}"))?;
+    write_frame(&mut stream, endpoint.token.as_bytes()).await?;
+    let envelope = NodeAdminEnvelope {
+        seq: 1,
+        request_id: format!("daemon-{}", unix_ms()),
+        payload: Some(node_admin_envelope::Payload::Request(request)),
+    };
+    write_frame(&mut stream, &envelope.encode_to_vec()).await?;
+    let payload = read_frame(&mut stream)
+        .await?
+        .ok_or_else(|| anyhow!("node admin socket closed before responding"))?;
+    let reply =
+        NodeAdminEnvelope::decode(payload.as_slice()).context("decode node admin envelope")?;
+    let Some(node_admin_envelope::Payload::Response(response)) = reply.payload else {
+        return Err(anyhow!("node admin reply carried no response"));
+    };
+    if !response.error.is_empty() {
+        return Err(anyhow!("node admin request failed: {}", response.error));
+    }
+    Ok(response)
+}
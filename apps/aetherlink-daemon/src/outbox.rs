@@ -0,0 +1,193 @@
+use aetherlink_proto::{
+    ipc_pong_for,
+    v1::{DaemonEvent, DaemonResponse, IpcEnvelope, ipc_envelope},
+};
+use anyhow::{Context, Result};
+use prost::Message;
+use tokio::{io::AsyncWrite, sync::mpsc};
+
+use crate::write_frame;
+
+/// Envelopes buffered per client between request handling and the socket writer.
+pub const CLIENT_OUTBOX_CAPACITY: usize = 64;
+/// Events dropped back to back before a client is considered stuck and disconnected.
+pub const MAX_CONSECUTIVE_DROPPED_EVENTS: u32 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventDelivery {
+    Queued,
+    /// The buffer was full and the event was discarded.
+    Dropped,
+    /// Too many events were dropped in a row, or the writer is gone; the caller should
+    /// end the client session.
+    Disconnect,
+}
+
+/// Sending half of a client's bounded outbound queue. Responses wait for room, so a
+/// slow reader only slows its own requests; events never wait and are dropped when
+/// the reader falls behind.
+#[derive(Debug)]
+pub struct ClientOutbox {
+    tx: mpsc::Sender<IpcEnvelope>,
+    seq: u64,
+    consecutive_dropped: u32,
+    max_consecutive_dropped: u32,
+    dropped_total: u64,
+}
+
+impl ClientOutbox {
+    pub fn new(tx: mpsc::Sender<IpcEnvelope>, max_consecutive_dropped: u32) -> Self {
+        Self {
+            tx,
+            seq: 1,
+            consecutive_dropped: 0,
+            max_consecutive_dropped: max_consecutive_dropped.max(1),
+            dropped_total: 0,
+        }
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        let seq = self.seq;
+        self.seq = self.seq.saturating_add(1);
+        seq
+    }
+
+    pub async fn send_response(
+        &mut self,
+        request_id: &str,
+        response: DaemonResponse,
+    ) -> Result<()> {
+        let envelope = IpcEnvelope {
+            seq: self.next_seq(),
+            request_id: request_id.to_string(),
+            payload: Some(ipc_envelope::Payload::Response(response)),
+        };
+        self.tx
+            .send(envelope)
+            .await
+            .context("client writer stopped before response was queued")
+    }
+
+    /// Answers a client's `IpcPing`; other envelopes are ignored. Waits for room like a
+    /// response, since an unanswered ping reads as a dead daemon.
+    pub async fn send_pong(&mut self, ping: &IpcEnvelope) -> Result<()> {
+        let seq = self.next_seq();
+        let Some(pong) = ipc_pong_for(ping, seq) else {
+            return Ok(());
+        };
+        self.tx
+            .send(pong)
+            .await
+            .context("client writer stopped before pong was queued")
+    }
+
+    pub fn push_event(&mut self, request_id: &str, event: DaemonEvent) -> EventDelivery {
+        let envelope = IpcEnvelope {
+            seq: self.next_seq(),
+            request_id: request_id.to_string(),
+            payload: Some(ipc_envelope::Payload::Event(event)),
+        };
+        match self.tx.try_send(envelope) {
+            Ok(()) => {
+                self.consecutive_dropped = 0;
+                EventDelivery::Queued
+            }
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.consecutive_dropped += 1;
+                self.dropped_total += 1;
+                if self.consecutive_dropped >= self.max_consecutive_dropped {
+                    EventDelivery::Disconnect
+                } else {
+                    EventDelivery::Dropped
+                }
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => EventDelivery::Disconnect,
+        }
+    }
+
+    pub fn dropped_total(&self) -> u64 {
+        self.dropped_total
+    }
+}
+
+/// Drains a client's outbox onto its socket until the outbox is dropped or a write
+/// fails.
+pub async fn run_client_writer<W>(mut writer: W, mut rx: mpsc::Receiver<IpcEnvelope>) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    while let Some(envelope) = rx.recv().await {
+        write_frame(&mut writer, &envelope.encode_to_vec()).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aetherlink_proto::v1::{SessionStateEvent, daemon_event};
+
+    fn state_event(n: usize) -> DaemonEvent {
+        DaemonEvent {
+            payload: Some(daemon_event::Payload::SessionState(SessionStateEvent {
+                session_id: format!("session-{n}"),
+                state: "active".to_string(),
+                detail: "x".repeat(64),
+            })),
+        }
+    }
+
+    #[tokio::test]
+    async fn stalled_reader_drops_events_then_disconnects() {
+        // The far end of the duplex is never read, so the writer stalls on its first
+        // frame and the outbox fills up.
+        let (writer, _unread) = tokio::io::duplex(16);
+        let (tx, rx) = mpsc::channel(2);
+        let writer_task = tokio::spawn(run_client_writer(writer, rx));
+        let mut outbox = ClientOutbox::new(tx, 4);
+
+        let mut outcomes = Vec::new();
+        for n in 0..12 {
+            outcomes.push(outbox.push_event("req-1", state_event(n)));
+            tokio::task::yield_now().await;
+        }
+        let queued = outcomes
+            .iter()
+            .filter(|outcome| **outcome == EventDelivery::Queued)
+            .count();
+        assert!(
+            queued <= 3,
+            "at most one in flight plus the buffer: {outcomes:?}"
+        );
+        assert!(outcomes.contains(&EventDelivery::Dropped));
+        assert_eq!(outcomes.last(), Some(&EventDelivery::Disconnect));
+        assert!(outbox.dropped_total() >= 4);
+
+        drop(outbox);
+        writer_task.abort();
+    }
+
+    #[tokio::test]
+    async fn draining_reader_resets_drop_streak() {
+        let (writer, mut reader) = tokio::io::duplex(64 * 1024);
+        let (tx, rx) = mpsc::channel(1);
+        let writer_task = tokio::spawn(run_client_writer(writer, rx));
+        let mut outbox = ClientOutbox::new(tx, 2);
+
+        for n in 0..8 {
+            let outcome = outbox.push_event("req-1", state_event(n));
+            assert_ne!(outcome, EventDelivery::Disconnect);
+            while outbox.tx.capacity() == 0 {
+                tokio::task::yield_now().await;
+            }
+        }
+        drop(outbox);
+        writer_task.await.unwrap().unwrap();
+
+        let mut frames = 0;
+        while crate::read_frame(&mut reader).await.unwrap().is_some() {
+            frames += 1;
+        }
+        assert_eq!(frames, 8);
+    }
+}
@@ -0,0 +1,68 @@
+use std::io;
+
+use tokio::net::windows::named_pipe::{NamedPipeServer, ServerOptions};
+
+/// Accept loop over a Windows named pipe. Each pipe instance serves one client, so a
+/// fresh instance is created as soon as the pending one is connected, keeping the name
+/// claimed between clients. Remote (SMB) clients are refused.
+#[derive(Debug)]
+pub struct PipeListener {
+    name: String,
+    next: NamedPipeServer,
+}
+
+impl PipeListener {
+    /// Fails if another process already owns a pipe with this name.
+    pub fn bind(name: &str) -> io::Result<Self> {
+        let next = ServerOptions::new()
+            .first_pipe_instance(true)
+            .reject_remote_clients(true)
+            .create(name)?;
+        Ok(Self {
+            name: name.to_string(),
+            next,
+        })
+    }
+
+    /// A client that went away before being connected leaves the pending instance
+    /// unusable, so it is replaced before the error is returned and the next call
+    /// can succeed.
+    pub async fn accept(&mut self) -> io::Result<(NamedPipeServer, ())> {
+        if let Err(err) = self.next.connect().await {
+            self.next = self.create_instance()?;
+            return Err(err);
+        }
+        let fresh = self.create_instance()?;
+        Ok((std::mem::replace(&mut self.next, fresh), ()))
+    }
+
+    fn create_instance(&self) -> io::Result<NamedPipeServer> {
+        ServerOptions::new()
+            .reject_remote_clients(true)
+            .create(&self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::windows::named_pipe::ClientOptions;
+
+    #[tokio::test]
+    async fn bind_accept_and_exchange_a_frame() {
+        let name = format!(r"\\.\pipe\aetherlink-daemon-test-{}", crate::unix_ms());
+        let mut listener = PipeListener::bind(&name).unwrap();
+        assert!(
+            PipeListener::bind(&name).is_err(),
+            "second owner of the name"
+        );
+
+        let server = tokio::spawn(async move {
+            let (mut stream, ()) = listener.accept().await.unwrap();
+            crate::read_frame(&mut stream).await.unwrap()
+        });
+        let mut client = ClientOptions::new().open(&name).unwrap();
+        crate::write_frame(&mut client, b"hello").await.unwrap();
+        assert_eq!(server.await.unwrap(), Some(b"hello".to_vec()));
+    }
+}
@@ -0,0 +1,143 @@
+use std::{sync::Arc, time::Duration};
+
+use aetherlink_proto::v1::{
+    NodeAdminRequest, NodeTakeVideoFramesRequest, NodeTakeVideoFramesResponse, node_admin_request,
+    node_admin_response,
+};
+use anyhow::{Context, Result, anyhow};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::{Runtime, node_admin::send_node_admin_request};
+
+pub const RECORDING_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Pulls the frames the node reassembled for every session being recorded and
+/// appends them to its recording. The node admin round trip runs without the
+/// runtime lock.
+pub async fn run_recording_pump(runtime: Arc<Mutex<Runtime>>) {
+    let mut ticker = tokio::time::interval(RECORDING_POLL_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let (node_admin, targets) = {
+            let guard = runtime.lock().await;
+            if guard.child.is_none() || guard.recordings.is_empty() {
+                continue;
+            }
+            let targets: Vec<(String, String)> = guard
+                .recordings
+                .keys()
+                .filter_map(|session_id| {
+                    let session = guard.config.sessions.get(session_id)?;
+                    Some((session_id.clone(), session.device_code.clone()))
+                })
+                .collect();
+            (guard.config.node_admin.clone(), targets)
+        };
+        for (session_id, device_code) in targets {
+            let request = NodeAdminRequest {
+                payload: Some(node_admin_request::Payload::TakeVideoFrames(
+                    NodeTakeVideoFramesRequest { device_code },
+                )),
+            };
+            let taken = match send_node_admin_request(&node_admin, request).await {
+                Ok(response) => match response.payload {
+                    Some(node_admin_response::Payload::TakeVideoFrames(taken)) => taken,
+                    _ => {
+                        warn!("node answered TakeVideoFrames with an unexpected payload");
+                        continue;
+                    }
+                },
+                Err(err) => {
+                    warn!("taking video frames for session {session_id} failed: {err:#}");
+                    continue;
+                }
+            };
+            let mut guard = runtime.lock().await;
+            if let Err(err) = append_frames(&mut guard, &session_id, taken) {
+                // A recording that missed frames mid-stream cannot be played back past
+                // the gap, so it is closed rather than written on.
+                warn!("recording for session {session_id} stopped: {err:#}");
+                if let Some(mut writer) = guard.recordings.remove(&session_id) {
+                    let _ = writer.stop();
+                }
+                guard.last_error = Some(format!("recording for session {session_id}: {err:#}"));
+            }
+        }
+    }
+}
+
+/// Writes `taken` to the session's recording. A session whose recording was stopped
+/// while the frames were in flight is skipped.
+pub fn append_frames(
+    runtime: &mut Runtime,
+    session_id: &str,
+    taken: NodeTakeVideoFramesResponse,
+) -> Result<()> {
+    let Some(writer) = runtime.recordings.get_mut(session_id) else {
+        return Ok(());
+    };
+    if taken.dropped > 0 {
+        return Err(anyhow!(
+            "node discarded {} frames before they were taken",
+            taken.dropped
+        ));
+    }
+    for frame in taken.frames {
+        writer
+            .write_frame(frame.pts_us, frame.keyframe, &frame.data)
+            .context("write recording frame failed")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use aetherlink_proto::v1::NodeVideoFrame;
+
+    use super::*;
+
+    fn frame(frame_id: u64, data: &[u8]) -> NodeVideoFrame {
+        NodeVideoFrame {
+            frame_id,
+            pts_us: frame_id * 33_333,
+            keyframe: frame_id == 0,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn taken_frames_are_appended_to_the_recording() {
+        let mut runtime = crate::tests::test_runtime();
+        let session_id = crate::upsert_session(&mut runtime.config, "dev-a");
+        let path = std::env::temp_dir().join(format!(
+            "aetherlink-recording-pump-{}.alrc",
+            crate::unix_ms()
+        ));
+        crate::start_recording(&mut runtime, &session_id, path.to_str().unwrap()).unwrap();
+
+        let taken = NodeTakeVideoFramesResponse {
+            frames: vec![frame(0, b"key"), frame(1, b"delta")],
+            dropped: 0,
+        };
+        append_frames(&mut runtime, &session_id, taken).unwrap();
+        assert_eq!(runtime.recordings[&session_id].frames_written(), 2);
+
+        let gap = NodeTakeVideoFramesResponse {
+            frames: vec![frame(5, b"late")],
+            dropped: 3,
+        };
+        assert!(append_frames(&mut runtime, &session_id, gap).is_err());
+        assert_eq!(runtime.recordings[&session_id].frames_written(), 2);
+
+        // Frames for a session that is no longer recording are let go.
+        let taken = NodeTakeVideoFramesResponse {
+            frames: vec![frame(2, b"delta")],
+            dropped: 0,
+        };
+        append_frames(&mut runtime, "session-unknown", taken).unwrap();
+
+        runtime.recordings.clear();
+        let _ = std::fs::remove_file(path);
+    }
+}
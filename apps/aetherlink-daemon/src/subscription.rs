@@ -0,0 +1,246 @@
+use aetherlink_proto::{
+    is_ipc_ping,
+    v1::{DaemonEvent, DaemonEventKind, IpcEnvelope, SubscribeRequest, daemon_event},
+};
+use anyhow::{Result, anyhow};
+use prost::Message;
+use tokio::{
+    io::AsyncRead,
+    sync::{broadcast, mpsc},
+};
+use tracing::warn;
+
+use crate::{
+    outbox::{ClientOutbox, EventDelivery},
+    read_frame,
+};
+
+/// Events fanned out to subscribers; a subscriber that falls this far behind skips ahead.
+pub const EVENT_BUS_CAPACITY: usize = 256;
+
+/// Sends `event` to every subscriber. Nobody listening on the bus is not an error.
+pub fn publish(event_bus: &broadcast::Sender<DaemonEvent>, event: DaemonEvent) {
+    let _ = event_bus.send(event);
+}
+
+pub fn event_kind(event: &DaemonEvent) -> DaemonEventKind {
+    match &event.payload {
+        Some(daemon_event::Payload::DiscoveryUpdate(_)) => DaemonEventKind::DiscoveryUpdate,
+        Some(daemon_event::Payload::PairingRequired(_)) => DaemonEventKind::PairingRequired,
+        Some(daemon_event::Payload::SessionState(_)) => DaemonEventKind::SessionState,
+        Some(daemon_event::Payload::StreamStats(_)) => DaemonEventKind::StreamStats,
+        Some(daemon_event::Payload::TransferProgress(_)) => DaemonEventKind::TransferProgress,
+        Some(daemon_event::Payload::Error(_)) => DaemonEventKind::Error,
+        None => DaemonEventKind::Unspecified,
+    }
+}
+
+fn event_session_id(event: &DaemonEvent) -> Option<&str> {
+    match &event.payload {
+        Some(daemon_event::Payload::SessionState(ev)) => Some(&ev.session_id),
+        Some(daemon_event::Payload::StreamStats(ev)) => Some(&ev.session_id),
+        Some(daemon_event::Payload::TransferProgress(ev)) => Some(&ev.session_id),
+        _ => None,
+    }
+}
+
+/// Whether `event` belongs on a stream opened with `subscription`. Events that are
+/// not tied to a session (discovery, errors) pass the session filter; daemon-wide
+/// session state events carry an empty session id and pass it too.
+pub fn matches_subscription(event: &DaemonEvent, subscription: &SubscribeRequest) -> bool {
+    let kind = event_kind(event);
+    if kind == DaemonEventKind::Unspecified {
+        return false;
+    }
+    if !subscription.event_kinds.is_empty() && !subscription.event_kinds.contains(&(kind as i32)) {
+        return false;
+    }
+    match event_session_id(event) {
+        Some(session_id) if !subscription.session_id.is_empty() && !session_id.is_empty() => {
+            session_id == subscription.session_id
+        }
+        _ => true,
+    }
+}
+
+/// Forwards matching bus events to the client until it disconnects. Further frames
+/// from the client are read so its close is noticed promptly; pings are answered and
+/// anything else is discarded.
+pub async fn stream_subscription<R>(
+    mut reader: R,
+    outbox: &mut ClientOutbox,
+    mut events: broadcast::Receiver<DaemonEvent>,
+    subscription: SubscribeRequest,
+    request_id: &str,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let (ping_tx, mut pings) = mpsc::channel::<IpcEnvelope>(4);
+    let mut client_closed = tokio::spawn(async move {
+        while let Ok(Some(frame)) = read_frame(&mut reader).await {
+            match IpcEnvelope::decode(frame.as_slice()) {
+                Ok(envelope) if is_ipc_ping(&envelope) => {
+                    // A full queue means pongs are already on their way.
+                    let _ = ping_tx.try_send(envelope);
+                }
+                _ => warn!("ignoring request on subscription stream"),
+            }
+        }
+    });
+    loop {
+        tokio::select! {
+            _ = &mut client_closed => return Ok(()),
+            Some(ping) = pings.recv() => {
+                if let Err(err) = outbox.send_pong(&ping).await {
+                    client_closed.abort();
+                    return Err(err);
+                }
+            }
+            received = events.recv() => match received {
+                Ok(event) => {
+                    if !matches_subscription(&event, &subscription) {
+                        continue;
+                    }
+                    match outbox.push_event(request_id, event) {
+                        EventDelivery::Queued => {}
+                        EventDelivery::Dropped => {
+                            warn!("subscriber {request_id} is not keeping up, dropped event");
+                        }
+                        EventDelivery::Disconnect => {
+                            client_closed.abort();
+                            return Err(anyhow!(
+                                "subscriber {request_id} stopped reading, {} events dropped",
+                                outbox.dropped_total()
+                            ));
+                        }
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("subscriber {request_id} lagged, skipped {skipped} events");
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    client_closed.abort();
+                    return Ok(());
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aetherlink_proto::v1::{
+        DiscoveryUpdateEvent, ErrorEvent, SessionStateEvent, StreamStatsEvent,
+    };
+
+    fn session_state(session_id: &str) -> DaemonEvent {
+        DaemonEvent {
+            payload: Some(daemon_event::Payload::SessionState(SessionStateEvent {
+                session_id: session_id.to_string(),
+                state: "active".to_string(),
+                detail: String::new(),
+            })),
+        }
+    }
+
+    fn stats(session_id: &str) -> DaemonEvent {
+        DaemonEvent {
+            payload: Some(daemon_event::Payload::StreamStats(StreamStatsEvent {
+                session_id: session_id.to_string(),
+                stats: None,
+            })),
+        }
+    }
+
+    fn subscribe(kinds: &[DaemonEventKind], session_id: &str) -> SubscribeRequest {
+        SubscribeRequest {
+            event_kinds: kinds.iter().map(|kind| *kind as i32).collect(),
+            session_id: session_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn empty_kind_list_matches_everything() {
+        let all = subscribe(&[], "");
+        assert!(matches_subscription(&session_state("s1"), &all));
+        assert!(matches_subscription(&stats("s1"), &all));
+        let discovery = DaemonEvent {
+            payload: Some(daemon_event::Payload::DiscoveryUpdate(
+                DiscoveryUpdateEvent::default(),
+            )),
+        };
+        assert!(matches_subscription(&discovery, &all));
+        assert!(!matches_subscription(&DaemonEvent { payload: None }, &all));
+    }
+
+    #[test]
+    fn event_kind_filter() {
+        let only_state = subscribe(&[DaemonEventKind::SessionState], "");
+        assert!(matches_subscription(&session_state("s1"), &only_state));
+        assert!(!matches_subscription(&stats("s1"), &only_state));
+
+        let state_and_errors =
+            subscribe(&[DaemonEventKind::SessionState, DaemonEventKind::Error], "");
+        let error = DaemonEvent {
+            payload: Some(daemon_event::Payload::Error(ErrorEvent::default())),
+        };
+        assert!(matches_subscription(&error, &state_and_errors));
+        assert!(!matches_subscription(&stats("s1"), &state_and_errors));
+    }
+
+    #[tokio::test]
+    async fn pings_are_answered_on_a_subscription_stream() {
+        use aetherlink_proto::v1::{IpcPing, IpcPong, ipc_envelope};
+
+        use crate::{outbox::ClientOutbox, write_frame};
+
+        let (mut client, server) = tokio::io::duplex(1024);
+        let (reader, _writer) = tokio::io::split(server);
+        let (tx, mut rx) = mpsc::channel(8);
+        let (bus, events) = broadcast::channel(8);
+        let stream = tokio::spawn(async move {
+            let mut outbox = ClientOutbox::new(tx, 4);
+            stream_subscription(reader, &mut outbox, events, subscribe(&[], ""), "sub-1").await
+        });
+
+        let ping = IpcEnvelope {
+            seq: 1,
+            request_id: "ping-1".to_string(),
+            payload: Some(ipc_envelope::Payload::Ping(IpcPing {
+                nonce: 7,
+                send_unix_ms: 1_000,
+            })),
+        };
+        write_frame(&mut client, &ping.encode_to_vec())
+            .await
+            .unwrap();
+        let pong = rx.recv().await.unwrap();
+        assert_eq!(pong.request_id, "ping-1");
+        assert_eq!(
+            pong.payload,
+            Some(ipc_envelope::Payload::Pong(IpcPong {
+                nonce: 7,
+                echo_send_unix_ms: 1_000,
+            }))
+        );
+
+        drop(client);
+        stream.await.unwrap().unwrap();
+        drop(bus);
+    }
+
+    #[test]
+    fn session_filter_applies_to_session_scoped_events() {
+        let one_session = subscribe(&[], "s1");
+        assert!(matches_subscription(&session_state("s1"), &one_session));
+        assert!(!matches_subscription(&session_state("s2"), &one_session));
+        assert!(!matches_subscription(&stats("s2"), &one_session));
+        assert!(matches_subscription(&session_state(""), &one_session));
+        let error = DaemonEvent {
+            payload: Some(daemon_event::Payload::Error(ErrorEvent::default())),
+        };
+        assert!(matches_subscription(&error, &one_session));
+    }
+}
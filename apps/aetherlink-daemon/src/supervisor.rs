@@ -0,0 +1,281 @@
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use aetherlink_proto::v1::{
+    DaemonErrorCode, DaemonEvent, ErrorEvent, SessionStateEvent, daemon_event,
+};
+use tokio::sync::{Mutex, broadcast};
+use tracing::warn;
+
+use crate::{Runtime, classify_spawn_error, restart_managed_node, subscription::publish, unix_ms};
+
+pub const SUPERVISOR_INTERVAL: Duration = Duration::from_secs(1);
+pub const RESTART_BACKOFF_BASE_MS: u64 = 1_000;
+pub const RESTART_BACKOFF_MAX_MS: u64 = 60_000;
+/// More restarts than this within `RESTART_WINDOW_MS` trips the breaker.
+pub const MAX_RESTARTS_IN_WINDOW: usize = 5;
+pub const RESTART_WINDOW_MS: u64 = 5 * 60_000;
+
+/// Whether a node that exited with `exit_code` (`None` when killed by a signal) at
+/// `exited_ms` should be restarted now, after `restart_count` restarts within the
+/// window. Clean exits are left alone; crashes are restarted with a delay that
+/// doubles per restart, up to `MAX_RESTARTS_IN_WINDOW`.
+pub fn should_restart(
+    exit_code: Option<i32>,
+    restart_count: usize,
+    exited_ms: u64,
+    now_ms: u64,
+) -> bool {
+    if exit_code == Some(0) || restart_count >= MAX_RESTARTS_IN_WINDOW {
+        return false;
+    }
+    let backoff_ms = RESTART_BACKOFF_BASE_MS
+        .saturating_mul(1_u64 << restart_count.min(16))
+        .min(RESTART_BACKOFF_MAX_MS);
+    now_ms.saturating_sub(exited_ms) >= backoff_ms
+}
+
+/// Restarts within the last `window_ms`, the count `should_restart` backs off and
+/// trips the breaker on.
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    window_ms: u64,
+    recent_restarts_ms: VecDeque<u64>,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self::new(RESTART_WINDOW_MS)
+    }
+}
+
+impl RestartPolicy {
+    pub fn new(window_ms: u64) -> Self {
+        Self {
+            window_ms,
+            recent_restarts_ms: VecDeque::new(),
+        }
+    }
+
+    /// Restarts still inside the window at `now_ms`.
+    pub fn recent_restarts(&mut self, now_ms: u64) -> usize {
+        while self
+            .recent_restarts_ms
+            .front()
+            .is_some_and(|at| now_ms.saturating_sub(*at) >= self.window_ms)
+        {
+            self.recent_restarts_ms.pop_front();
+        }
+        self.recent_restarts_ms.len()
+    }
+
+    pub fn record_restart(&mut self, now_ms: u64) {
+        self.recent_restarts_ms.push_back(now_ms);
+    }
+
+    pub fn reset(&mut self) {
+        self.recent_restarts_ms.clear();
+    }
+}
+
+/// A crash the supervisor will restart from once `should_restart` allows it. The
+/// restart count is taken when the exit is seen, so the breaker cannot reset itself
+/// while the node is down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PendingRestart {
+    pub exit_code: Option<i32>,
+    pub restart_count: usize,
+    pub exited_ms: u64,
+}
+
+/// Polls the managed node once. The first time an exit is seen it is reported to
+/// subscribers and, for a crash with `auto_restart` on, a restart is scheduled; a
+/// tripped breaker is reported as fatal. A scheduled restart runs once
+/// `should_restart` allows it.
+pub async fn supervise_once(
+    runtime: &mut Runtime,
+    auto_restart: bool,
+    now_ms: u64,
+) -> Vec<DaemonEvent> {
+    let Some(child) = runtime.child.as_mut() else {
+        return Vec::new();
+    };
+    let status = match child.try_wait() {
+        Ok(Some(status)) => status,
+        Ok(None) => return Vec::new(),
+        Err(err) => {
+            warn!("poll managed node failed: {err}");
+            return Vec::new();
+        }
+    };
+
+    let mut events = Vec::new();
+    if !runtime.node_exit_reported {
+        runtime.node_exit_reported = true;
+        let detail = format!("managed node exited: {status}");
+        warn!("{detail}");
+        runtime.last_error = Some(detail.clone());
+        events.push(error_event(
+            "managed_node_exited",
+            detail,
+            DaemonErrorCode::Unspecified,
+        ));
+
+        if auto_restart && !status.success() {
+            let restart_count = runtime.restart_policy.recent_restarts(now_ms);
+            if restart_count < MAX_RESTARTS_IN_WINDOW {
+                runtime.pending_restart = Some(PendingRestart {
+                    exit_code: status.code(),
+                    restart_count,
+                    exited_ms: now_ms,
+                });
+            } else {
+                let detail = format!(
+                    "managed node crashed more than {MAX_RESTARTS_IN_WINDOW} times in {}s, not restarting",
+                    RESTART_WINDOW_MS / 1_000
+                );
+                warn!("{detail}");
+                runtime.last_error = Some(detail.clone());
+                events.push(error_event(
+                    "managed_node_restart_gave_up",
+                    detail,
+                    DaemonErrorCode::Internal,
+                ));
+            }
+        }
+    }
+    let Some(pending) = runtime.pending_restart else {
+        return events;
+    };
+    if !should_restart(
+        pending.exit_code,
+        pending.restart_count,
+        pending.exited_ms,
+        now_ms,
+    ) {
+        return events;
+    }
+
+    runtime.pending_restart = None;
+    runtime.restart_policy.record_restart(now_ms);
+    match restart_managed_node(runtime).await {
+        Ok(()) => events.push(DaemonEvent {
+            payload: Some(daemon_event::Payload::SessionState(SessionStateEvent {
+                session_id: String::new(),
+                state: "daemon_running".to_string(),
+                detail: "managed node restarted after exit".to_string(),
+            })),
+        }),
+        Err(err) => {
+            let detail = format!("restart managed node failed: {err:#}");
+            runtime.last_error = Some(detail.clone());
+            events.push(error_event(
+                "managed_node_restart_failed",
+                detail,
+                classify_spawn_error(&err),
+            ));
+        }
+    }
+    events
+}
+
+fn error_event(code: &str, detail: String, error_code: DaemonErrorCode) -> DaemonEvent {
+    DaemonEvent {
+        payload: Some(daemon_event::Payload::Error(ErrorEvent {
+            code: code.to_string(),
+            detail,
+            error_code: error_code as i32,
+        })),
+    }
+}
+
+pub async fn run_supervisor(
+    runtime: Arc<Mutex<Runtime>>,
+    event_bus: broadcast::Sender<DaemonEvent>,
+    auto_restart: bool,
+) {
+    let mut ticker = tokio::time::interval(SUPERVISOR_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let events = {
+            let mut guard = runtime.lock().await;
+            supervise_once(&mut guard, auto_restart, unix_ms()).await
+        };
+        for event in events {
+            publish(&event_bus, event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_exit_is_not_restarted() {
+        assert!(!should_restart(Some(0), 0, 0, 1_000_000));
+    }
+
+    #[test]
+    fn crashes_restart_with_doubling_backoff() {
+        let last = 1_000_000;
+        assert!(should_restart(Some(1), 0, 0, last));
+        assert!(should_restart(None, 0, 0, last));
+        assert!(!should_restart(Some(1), 1, last, last + 1_999));
+        assert!(should_restart(Some(1), 1, last, last + 2_000));
+        assert!(!should_restart(Some(1), 3, last, last + 7_999));
+        assert!(should_restart(Some(1), 3, last, last + 8_000));
+    }
+
+    #[test]
+    fn crash_loop_stops_restarting() {
+        let last = 1_000_000;
+        assert!(should_restart(
+            Some(101),
+            MAX_RESTARTS_IN_WINDOW - 1,
+            last,
+            last + RESTART_BACKOFF_MAX_MS
+        ));
+        assert!(!should_restart(
+            Some(101),
+            MAX_RESTARTS_IN_WINDOW,
+            last,
+            u64::MAX
+        ));
+    }
+
+    #[test]
+    fn restarts_age_out_of_the_window() {
+        let mut policy = RestartPolicy::new(10_000);
+        policy.record_restart(0);
+        policy.record_restart(1_000);
+        policy.record_restart(3_000);
+        assert_eq!(policy.recent_restarts(9_999), 3);
+        // Once the oldest restart leaves the window there is room again.
+        assert_eq!(policy.recent_restarts(10_000), 2);
+
+        policy.reset();
+        assert_eq!(policy.recent_restarts(10_500), 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn reports_exit_once_without_auto_restart() {
+        let mut runtime = crate::tests::test_runtime();
+        let mut child = tokio::process::Command::new("sh")
+            .args(["-c", "exit 7"])
+            .spawn()
+            .unwrap();
+        child.wait().await.unwrap();
+        runtime.child = Some(child);
+        runtime.node_started_unix_ms = Some(1);
+
+        let events = supervise_once(&mut runtime, false, 10_000).await;
+        assert_eq!(events.len(), 1);
+        assert!(matches!(
+            &events[0].payload,
+            Some(daemon_event::Payload::Error(error)) if error.code == "managed_node_exited"
+        ));
+        assert!(supervise_once(&mut runtime, false, 20_000).await.is_empty());
+        assert!(runtime.last_error.unwrap().contains("managed node exited"));
+    }
+}
@@ -0,0 +1,119 @@
+use std::{path::Path, sync::Arc, time::Duration, time::SystemTime};
+
+use aetherlink_proto::v1::{DaemonEvent, DiscoveryUpdateEvent, daemon_event};
+use tokio::sync::{Mutex, broadcast};
+
+use crate::{Runtime, discover_devices_from_trust_store, subscription::publish};
+
+pub const TRUST_STORE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Returns the file's modification time when it differs from `prev_mtime`, including
+/// when the file first appears. A missing or unreadable file counts as unchanged.
+pub fn trust_store_changed(prev_mtime: Option<SystemTime>, path: &Path) -> Option<SystemTime> {
+    let mtime = std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()?;
+    (prev_mtime != Some(mtime)).then_some(mtime)
+}
+
+/// The trust store's state between polls. The first poll only takes a baseline, so
+/// the file as found at startup is not a change; a file missing then that appears
+/// later is.
+#[derive(Debug, Default)]
+pub struct TrustStoreWatch {
+    baseline_taken: bool,
+    /// `None` while the file has not been seen.
+    last_mtime: Option<SystemTime>,
+}
+
+impl TrustStoreWatch {
+    /// Whether the file at `path` changed since the previous poll.
+    pub fn poll(&mut self, path: &Path) -> bool {
+        let changed = trust_store_changed(self.last_mtime, path);
+        if let Some(mtime) = changed {
+            self.last_mtime = Some(mtime);
+        }
+        std::mem::replace(&mut self.baseline_taken, true) && changed.is_some()
+    }
+}
+
+/// Publishes a `DiscoveryUpdateEvent` whenever the node rewrites the trust store, e.g.
+/// after trusting a new peer on first use.
+pub async fn run_trust_store_watch(
+    runtime: Arc<Mutex<Runtime>>,
+    event_bus: broadcast::Sender<DaemonEvent>,
+) {
+    let mut ticker = tokio::time::interval(TRUST_STORE_POLL_INTERVAL);
+    let mut watch = TrustStoreWatch::default();
+    loop {
+        ticker.tick().await;
+        let (path, paired_devices) = {
+            let guard = runtime.lock().await;
+            (
+                guard.config.trust_store_file.clone(),
+                guard.config.paired_devices.clone(),
+            )
+        };
+        if !watch.poll(&path) {
+            continue;
+        }
+        let devices = discover_devices_from_trust_store(&path, &paired_devices);
+        publish(
+            &event_bus,
+            DaemonEvent {
+                payload: Some(daemon_event::Payload::DiscoveryUpdate(
+                    DiscoveryUpdateEvent { devices },
+                )),
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_touches_between_calls() {
+        let path =
+            std::env::temp_dir().join(format!("aetherlink-trust-watch-{}.json", crate::unix_ms()));
+        assert_eq!(trust_store_changed(None, &path), None, "missing file");
+
+        std::fs::write(&path, b"{}").unwrap();
+        let first = trust_store_changed(None, &path).expect("file appeared");
+        assert_eq!(trust_store_changed(Some(first), &path), None);
+
+        let later = first + Duration::from_secs(5);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert_eq!(trust_store_changed(Some(first), &path), Some(later));
+        assert_eq!(trust_store_changed(Some(later), &path), None);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn startup_file_is_the_baseline_but_a_later_one_is_a_change() {
+        let present =
+            std::env::temp_dir().join(format!("aetherlink-trust-base-{}.json", crate::unix_ms()));
+        std::fs::write(&present, b"{}").unwrap();
+        let mut watch = TrustStoreWatch::default();
+        assert!(!watch.poll(&present));
+        assert!(!watch.poll(&present));
+        let _ = std::fs::remove_file(present);
+
+        let missing =
+            std::env::temp_dir().join(format!("aetherlink-trust-late-{}.json", crate::unix_ms()));
+        let mut watch = TrustStoreWatch::default();
+        assert!(!watch.poll(&missing));
+        assert!(!watch.poll(&missing));
+        std::fs::write(&missing, b"{}").unwrap();
+        assert!(watch.poll(&missing), "file appeared after startup");
+        assert!(!watch.poll(&missing));
+        let _ = std::fs::remove_file(missing);
+    }
+}
@@ -1,18 +1,33 @@
 #![forbid(unsafe_code)]
 
+use std::{
+    io::{BufRead, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use aetherlink_proto::ipc_pong_for;
 use aetherlink_proto::v1::{
-    ConnectSessionRequest, DaemonRequest, DiscoverDevicesRequest, GetSessionStatsRequest,
-    IpcEnvelope, PairDeviceRequest, daemon_request, ipc_envelope,
+    ConnectSessionRequest, DaemonErrorCode, DaemonEvent, DaemonEventKind, DaemonRequest,
+    DaemonResponse, DaemonStatusResponse, DiscoverDevicesRequest, DiscoveredDevice,
+    GetDaemonStatusRequest, GetSessionStatsRequest, IpcEnvelope, ManagedNodeState,
+    PairDeviceRequest, SubscribeRequest, UpdateNetworkRequest, daemon_event, daemon_request,
+    daemon_response, ipc_envelope,
 };
 use anyhow::{Context, Result, bail};
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
 use prost::Message;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
-#[cfg(windows)]
-use tokio::net::TcpStream as IpcStream;
 #[cfg(unix)]
 use tokio::net::UnixStream as IpcStream;
+#[cfg(windows)]
+use tokio::net::windows::named_pipe::NamedPipeClient as IpcStream;
+
+/// `ERROR_PIPE_BUSY`: every instance of the pipe is serving another client.
+#[cfg(windows)]
+const ERROR_PIPE_BUSY: i32 = 231;
 
 #[derive(Debug, Parser)]
 #[command(
@@ -21,9 +36,46 @@ use tokio::net::UnixStream as IpcStream;
     about = "Control AetherLink daemon over local IPC"
 )]
 struct Args {
-    #[arg(long, help = "IPC endpoint (unix path on Unix, host:port on Windows)")]
+    #[arg(long, help = "IPC endpoint (unix path on Unix, named pipe on Windows)")]
     socket_path: Option<String>,
 
+    #[arg(
+        long,
+        help = "Daemon IPC auth token file (default: ~/.config/aetherlink/daemon.token)"
+    )]
+    token_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "print responses and events as JSON, one per line"
+    )]
+    json: bool,
+
+    #[arg(
+        long,
+        global = true,
+        default_value_t = 5_000,
+        help = "give up when the daemon has not answered within this many milliseconds"
+    )]
+    timeout_ms: u64,
+
+    #[arg(
+        long,
+        global = true,
+        default_value_t = 3,
+        help = "retry connecting this many times while the daemon is not up yet"
+    )]
+    connect_retries: u32,
+
+    #[arg(
+        long,
+        global = true,
+        default_value_t = 200,
+        help = "delay before the first connect retry; doubles on each further retry"
+    )]
+    connect_retry_delay_ms: u64,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -45,12 +97,25 @@ enum Command {
         trust_on_first_use: bool,
     },
     Stop,
-    Discover,
+    Discover {
+        #[arg(long, help = "also look this device code up in the DHT (repeatable)")]
+        device_code: Vec<String>,
+    },
     Pair {
         #[arg(long)]
         device_code: String,
         #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
         approved: bool,
+        #[arg(
+            long,
+            help = "look the device up, show its pairing code and ask before approving (ignores --approved)"
+        )]
+        interactive: bool,
+        #[arg(
+            long,
+            help = "peer id to trust under the device code on approval, as shown with its pairing code"
+        )]
+        peer_id: Option<String>,
     },
     Connect {
         #[arg(long)]
@@ -60,37 +125,417 @@ enum Command {
         #[arg(long)]
         session_id: String,
     },
+    /// Show whether the managed node is running, its uptime and the last error.
+    Status,
+    /// Replace the managed node's bootstrap and relay peers without restarting it.
+    UpdateNetwork {
+        #[arg(long, help = "bootstrap multiaddr (repeatable)")]
+        bootstrap: Vec<String>,
+        #[arg(long, help = "relay multiaddr with /p2p/<peer_id> (repeatable)")]
+        relay: Vec<String>,
+    },
+    /// Send a `DaemonRequest` read from a JSON file, e.g.
+    /// `{"payload": {"DiscoverDevices": {"device_codes": ["desk"]}}}`.
+    Raw {
+        #[arg(long)]
+        file: PathBuf,
+    },
+    /// Print a shell completion script to stdout.
+    #[command(hide = true)]
+    Completions {
+        shell: Shell,
+    },
+    /// Stream daemon events until interrupted (Ctrl-C).
+    Watch {
+        #[arg(
+            long,
+            visible_alias = "event-type",
+            value_parser = parse_event_kind,
+            help = "event kind to include: discovery, pairing, session, stats, transfer, error (repeatable; default all)"
+        )]
+        kind: Vec<DaemonEventKind>,
+        #[arg(long, default_value = "", help = "only events for this session id")]
+        session_id: String,
+    },
+}
+
+fn parse_event_kind(value: &str) -> Result<DaemonEventKind, String> {
+    match value {
+        "discovery" => Ok(DaemonEventKind::DiscoveryUpdate),
+        "pairing" => Ok(DaemonEventKind::PairingRequired),
+        "session" => Ok(DaemonEventKind::SessionState),
+        "stats" => Ok(DaemonEventKind::StreamStats),
+        "transfer" => Ok(DaemonEventKind::TransferProgress),
+        "error" => Ok(DaemonEventKind::Error),
+        other => Err(format!("unknown event kind '{other}'")),
+    }
+}
+
+/// Pretty debug output for people, or a single line of JSON for scripts.
+fn render_response(resp: &DaemonResponse, json: bool) -> String {
+    if json {
+        to_json_line(resp)
+    } else {
+        format!("{resp:#?}")
+    }
+}
+
+fn render_event(event: &DaemonEvent, json: bool) -> String {
+    if json {
+        to_json_line(event)
+    } else {
+        format_event(event)
+    }
+}
+
+/// One line per event, for `watch`.
+fn format_event(event: &DaemonEvent) -> String {
+    let Some(payload) = &event.payload else {
+        return "empty event".to_string();
+    };
+    match payload {
+        daemon_event::Payload::DiscoveryUpdate(update) => {
+            let devices: Vec<String> = update
+                .devices
+                .iter()
+                .map(|device| {
+                    if device.trusted {
+                        format!("{} (trusted)", device.device_code)
+                    } else {
+                        device.device_code.clone()
+                    }
+                })
+                .collect();
+            format!(
+                "discovery: {} device(s) [{}]",
+                devices.len(),
+                devices.join(", ")
+            )
+        }
+        daemon_event::Payload::PairingRequired(pairing) => {
+            format!("pairing required: device={}", pairing.device_code)
+        }
+        daemon_event::Payload::SessionState(state) => {
+            let scope = if state.session_id.is_empty() {
+                "daemon".to_string()
+            } else {
+                format!("session {}", state.session_id)
+            };
+            if state.detail.is_empty() {
+                format!("{scope}: {}", state.state)
+            } else {
+                format!("{scope}: {} ({})", state.state, state.detail)
+            }
+        }
+        daemon_event::Payload::StreamStats(stats) => match &stats.stats {
+            Some(s) => format!(
+                "stats session {}: rtt={}ms tx={}kbps rx={}kbps loss={:.2}% relay={}",
+                stats.session_id,
+                s.rtt_ms,
+                s.tx_bitrate_kbps,
+                s.rx_bitrate_kbps,
+                f64::from(s.packet_loss_x10000) / 100.0,
+                if s.using_relay { "yes" } else { "no" }
+            ),
+            None => format!("stats session {}: no data", stats.session_id),
+        },
+        daemon_event::Payload::TransferProgress(progress) => {
+            let percent = progress
+                .sent_bytes
+                .saturating_mul(100)
+                .checked_div(progress.total_bytes)
+                .unwrap_or(0);
+            format!(
+                "transfer {} session {}: {}/{} bytes ({percent}%)",
+                progress.transfer_id,
+                progress.session_id,
+                progress.sent_bytes,
+                progress.total_bytes
+            )
+        }
+        daemon_event::Payload::Error(error) => match DaemonErrorCode::try_from(error.error_code) {
+            Ok(code) if code != DaemonErrorCode::Unspecified => {
+                format!(
+                    "error {} [{}]: {}",
+                    error.code,
+                    code.as_str_name(),
+                    error.detail
+                )
+            }
+            _ => format!("error {}: {}", error.code, error.detail),
+        },
+    }
+}
+
+fn to_json_line<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string(value)
+        .unwrap_or_else(|err| format!("{{\"error\":\"encode JSON failed: {err}\"}}"))
+}
+
+fn write_completions(shell: Shell, out: &mut dyn std::io::Write) {
+    clap_complete::generate(shell, &mut Args::command(), "aetherlink-daemonctl", out);
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+    if let Command::Completions { shell } = args.command {
+        write_completions(shell, &mut std::io::stdout());
+        return Ok(());
+    }
     let socket_path = args.socket_path.unwrap_or_else(default_socket_path);
-    let request = build_request(args.command);
-    let mut stream = IpcStream::connect(&socket_path)
-        .await
-        .with_context(|| format!("connect daemon socket failed: {}", socket_path))?;
+    let token_file = args.token_file.unwrap_or_else(default_token_file);
+    let token = std::fs::read_to_string(&token_file)
+        .with_context(|| format!("read daemon token file failed: {}", token_file.display()))?;
+    let watch = matches!(args.command, Command::Watch { .. });
+    let interactive_pair = match &args.command {
+        Command::Pair {
+            device_code,
+            interactive: true,
+            ..
+        } => Some(device_code.clone()),
+        _ => None,
+    };
+    let request = match args.command {
+        Command::Raw { file } => {
+            let json = std::fs::read_to_string(&file)
+                .with_context(|| format!("read request file failed: {}", file.display()))?;
+            envelope(parse_raw_request(&json).with_context(|| file.display().to_string())?)
+        }
+        command => build_request(command),
+    };
+    let mut stream = connect_with_retry(
+        &socket_path,
+        args.connect_retries,
+        args.connect_retry_delay_ms,
+    )
+    .await?;
+    write_frame(&mut stream, token.trim().as_bytes()).await?;
+    if let Some(device_code) = interactive_pair {
+        let approved =
+            pair_interactively(&mut stream, &device_code, args.timeout_ms, args.json).await?;
+        if !approved {
+            eprintln!("pairing not approved");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
     send_request(&mut stream, request).await?;
 
-    while let Some(env) = read_envelope(&mut stream).await? {
-        if let Some(payload) = env.payload {
-            match payload {
-                ipc_envelope::Payload::Response(resp) => {
-                    println!("{resp:#?}");
-                    break;
-                }
-                ipc_envelope::Payload::Event(event) => {
-                    eprintln!("event: {event:?}");
+    let receive = async {
+        // Once a watch is subscribed, silence just means no events yet.
+        let mut subscribed = false;
+        let mut exit_code = 0;
+        loop {
+            let env = if subscribed {
+                read_envelope(&mut stream).await?
+            } else {
+                read_with_timeout(&mut stream, args.timeout_ms).await?
+            };
+            let Some(env) = env else {
+                break;
+            };
+            if answer_ping(&mut stream, &env).await? {
+                continue;
+            }
+            if let Some(payload) = env.payload {
+                match payload {
+                    ipc_envelope::Payload::Response(resp) => {
+                        match &resp.payload {
+                            Some(daemon_response::Payload::GetDaemonStatus(status))
+                                if !args.json =>
+                            {
+                                println!("{}", format_status(status));
+                            }
+                            _ => println!("{}", render_response(&resp, args.json)),
+                        }
+                        if let Some(daemon_response::Payload::GetDaemonStatus(status)) =
+                            &resp.payload
+                        {
+                            exit_code = status_exit_code(status);
+                        }
+                        if !watch {
+                            break;
+                        }
+                        subscribed = true;
+                    }
+                    ipc_envelope::Payload::Event(event) if watch => {
+                        println!("{}", render_event(&event, args.json));
+                    }
+                    ipc_envelope::Payload::Event(event) => {
+                        eprintln!("event: {}", render_event(&event, args.json));
+                    }
+                    ipc_envelope::Payload::Request(_)
+                    | ipc_envelope::Payload::Ping(_)
+                    | ipc_envelope::Payload::Pong(_) => {}
                 }
-                ipc_envelope::Payload::Request(_) => {}
             }
         }
+        Ok::<_, anyhow::Error>(exit_code)
+    };
+    let exit_code = if watch {
+        // Dropping the connection ends the daemon-side subscription.
+        tokio::select! {
+            result = receive => result?,
+            _ = tokio::signal::ctrl_c() => {
+                eprintln!("interrupted, stopping watch");
+                0
+            }
+        }
+    } else {
+        receive.await?
+    };
+    if exit_code != 0 {
+        std::process::exit(exit_code);
     }
     Ok(())
 }
 
+/// Looks `device_code` up (trust store and DHT), asks on the terminal whether to trust
+/// it, and only sends an approving `pair_device` when the user says yes. Returns
+/// whether the pairing was approved.
+async fn pair_interactively<S>(
+    stream: &mut S,
+    device_code: &str,
+    timeout_ms: u64,
+    json: bool,
+) -> Result<bool>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let discovered = round_trip(
+        stream,
+        daemon_request::Payload::DiscoverDevices(DiscoverDevicesRequest {
+            device_codes: vec![device_code.to_string()],
+        }),
+        timeout_ms,
+    )
+    .await?;
+    let Some(daemon_response::Payload::DiscoverDevices(discovered)) = discovered.payload else {
+        bail!("discover failed: {}", discovered.error);
+    };
+    let Some(device) = discovered
+        .devices
+        .iter()
+        .find(|device| device.device_code == device_code)
+    else {
+        bail!("device {device_code} was not found");
+    };
+    // The approval names the identity the user compared, so a different key showing
+    // up under the same device code later is not trusted along with it.
+    if !confirm_pairing(device, std::io::stdin().lock(), std::io::stderr()) {
+        return Ok(false);
+    }
+    let resp = round_trip(
+        stream,
+        daemon_request::Payload::PairDevice(PairDeviceRequest {
+            device_code: device_code.to_string(),
+            approved: true,
+            peer_id: device.peer_id.clone(),
+        }),
+        timeout_ms,
+    )
+    .await?;
+    println!("{}", render_response(&resp, json));
+    Ok(true)
+}
+
+/// Shows who is being paired with and reads a yes/no answer; anything but `y`/`yes`
+/// (including EOF) declines. Without a pairing code there is nothing to compare, so
+/// it refuses without asking.
+fn confirm_pairing(
+    device: &DiscoveredDevice,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> bool {
+    if device.pairing_code.is_empty() {
+        let _ = writeln!(
+            output,
+            "no pairing code is known for {}; cannot confirm its identity",
+            device.device_code
+        );
+        return false;
+    }
+    let _ = writeln!(output, "device:       {}", device.device_code);
+    let _ = writeln!(output, "peer id:      {}", device.peer_id);
+    let _ = writeln!(output, "pairing code: {}", device.pairing_code);
+    let _ = write!(output, "Approve pairing? [y/N] ");
+    let _ = output.flush();
+    let mut answer = String::new();
+    if input.read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+/// Sends one request and waits for its response, skipping events in between.
+async fn round_trip<S>(
+    stream: &mut S,
+    payload: daemon_request::Payload,
+    timeout_ms: u64,
+) -> Result<DaemonResponse>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = envelope(DaemonRequest {
+        payload: Some(payload),
+    });
+    send_request(stream, request).await?;
+    loop {
+        let Some(env) = read_with_timeout(stream, timeout_ms).await? else {
+            bail!("daemon closed the connection");
+        };
+        if answer_ping(stream, &env).await? {
+            continue;
+        }
+        if let Some(ipc_envelope::Payload::Response(resp)) = env.payload {
+            return Ok(resp);
+        }
+    }
+}
+
+/// Exit status for `status`: 0 while the managed node runs, 1 after it exited on its
+/// own, 3 when it is stopped or was never started (the LSB "not running" code).
+fn status_exit_code(status: &DaemonStatusResponse) -> i32 {
+    match ManagedNodeState::try_from(status.node_state) {
+        Ok(ManagedNodeState::Running) => 0,
+        Ok(ManagedNodeState::Exited) => 1,
+        _ => 3,
+    }
+}
+
+fn format_status(status: &DaemonStatusResponse) -> String {
+    let node = match ManagedNodeState::try_from(status.node_state) {
+        Ok(ManagedNodeState::Running) => {
+            format!("running (uptime {})", format_uptime(status.node_uptime_ms))
+        }
+        Ok(ManagedNodeState::Exited) => format!("exited (code {})", status.node_exit_code),
+        Ok(ManagedNodeState::Stopped) => "stopped".to_string(),
+        Ok(ManagedNodeState::NeverStarted) => "never started".to_string(),
+        _ => "unknown".to_string(),
+    };
+    let last_error = if status.last_error.is_empty() {
+        "none"
+    } else {
+        &status.last_error
+    };
+    format!(
+        "node: {node}\nsessions: {}\nlast error: {last_error}",
+        status.active_sessions
+    )
+}
+
+fn format_uptime(ms: u64) -> String {
+    let secs = ms / 1_000;
+    format!("{}h {:02}m {:02}s", secs / 3_600, secs / 60 % 60, secs % 60)
+}
+
 fn build_request(command: Command) -> IpcEnvelope {
     let payload = match command {
+        Command::Completions { .. } => {
+            unreachable!("completions are printed without contacting the daemon")
+        }
+        Command::Raw { .. } => unreachable!("raw requests are read from their file"),
         Command::Start {
             listen,
             bootstrap,
@@ -105,13 +550,26 @@ fn build_request(command: Command) -> IpcEnvelope {
         Command::Stop => {
             daemon_request::Payload::StopDaemon(aetherlink_proto::v1::DaemonStopRequest {})
         }
-        Command::Discover => daemon_request::Payload::DiscoverDevices(DiscoverDevicesRequest {}),
+        Command::UpdateNetwork { bootstrap, relay } => {
+            daemon_request::Payload::UpdateNetwork(UpdateNetworkRequest {
+                bootstrap_multiaddrs: bootstrap,
+                relay_multiaddrs: relay,
+            })
+        }
+        Command::Discover { device_code } => {
+            daemon_request::Payload::DiscoverDevices(DiscoverDevicesRequest {
+                device_codes: device_code,
+            })
+        }
         Command::Pair {
             device_code,
             approved,
+            peer_id,
+            ..
         } => daemon_request::Payload::PairDevice(PairDeviceRequest {
             device_code,
             approved,
+            peer_id: peer_id.unwrap_or_default(),
         }),
         Command::Connect { device_code } => {
             daemon_request::Payload::ConnectSession(ConnectSessionRequest { device_code })
@@ -119,35 +577,75 @@ fn build_request(command: Command) -> IpcEnvelope {
         Command::Stats { session_id } => {
             daemon_request::Payload::GetSessionStats(GetSessionStatsRequest { session_id })
         }
+        Command::Status => daemon_request::Payload::GetDaemonStatus(GetDaemonStatusRequest {}),
+        Command::Watch { kind, session_id } => {
+            daemon_request::Payload::Subscribe(SubscribeRequest {
+                event_kinds: kind.into_iter().map(|kind| kind as i32).collect(),
+                session_id,
+            })
+        }
     };
 
+    envelope(DaemonRequest {
+        payload: Some(payload),
+    })
+}
+
+fn envelope(request: DaemonRequest) -> IpcEnvelope {
     IpcEnvelope {
         seq: 1,
         request_id: format!("ctl-{}", chrono_like_unix_ms()),
-        payload: Some(ipc_envelope::Payload::Request(DaemonRequest {
-            payload: Some(payload),
-        })),
+        payload: Some(ipc_envelope::Payload::Request(request)),
+    }
+}
+
+/// Parses a JSON `DaemonRequest` as written for `raw`. Fields may be omitted and take
+/// their proto default, but the request must name a payload.
+fn parse_raw_request(json: &str) -> Result<DaemonRequest> {
+    let request: DaemonRequest =
+        serde_json::from_str(json).context("invalid DaemonRequest JSON")?;
+    if request.payload.is_none() {
+        bail!("DaemonRequest JSON has no payload");
     }
+    Ok(request)
 }
 
 async fn send_request<S>(stream: &mut S, request: IpcEnvelope) -> Result<()>
 where
     S: AsyncWrite + Unpin,
 {
-    let bytes = request.encode_to_vec();
+    write_frame(stream, &request.encode_to_vec()).await
+}
+
+async fn write_frame<S>(stream: &mut S, bytes: &[u8]) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
     let len = u32::try_from(bytes.len()).context("request too large")?;
     stream
         .write_all(&len.to_be_bytes())
         .await
         .context("write frame length failed")?;
     stream
-        .write_all(&bytes)
+        .write_all(bytes)
         .await
         .context("write frame payload failed")?;
     stream.flush().await.context("flush request failed")?;
     Ok(())
 }
 
+/// Replies to an `IpcPing` from the daemon. Returns whether `env` was one.
+async fn answer_ping<S>(stream: &mut S, env: &IpcEnvelope) -> Result<bool>
+where
+    S: AsyncWrite + Unpin,
+{
+    let Some(pong) = ipc_pong_for(env, env.seq) else {
+        return Ok(false);
+    };
+    write_frame(stream, &pong.encode_to_vec()).await?;
+    Ok(true)
+}
+
 async fn read_envelope<S>(stream: &mut S) -> Result<Option<IpcEnvelope>>
 where
     S: AsyncRead + Unpin,
@@ -172,6 +670,16 @@ where
     ))
 }
 
+async fn read_with_timeout<S>(stream: &mut S, ms: u64) -> Result<Option<IpcEnvelope>>
+where
+    S: AsyncRead + Unpin,
+{
+    match tokio::time::timeout(Duration::from_millis(ms), read_envelope(stream)).await {
+        Ok(result) => result,
+        Err(_) => bail!("daemon did not respond within {ms}ms"),
+    }
+}
+
 fn chrono_like_unix_ms() -> u64 {
     std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
@@ -179,6 +687,61 @@ fn chrono_like_unix_ms() -> u64 {
         .unwrap_or_default()
 }
 
+/// Max delay between connect retries, however many there are.
+const MAX_CONNECT_RETRY_DELAY_MS: u64 = 5_000;
+
+/// `connect_ipc`, retried up to `retries` more times with doubling delays so a
+/// command issued while the daemon is still starting does not fail outright.
+async fn connect_with_retry(path: &str, retries: u32, delay_ms: u64) -> Result<IpcStream> {
+    let mut delay_ms = delay_ms;
+    let mut attempt = 0;
+    loop {
+        match connect_ipc(path).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) if attempt < retries => {
+                attempt += 1;
+                eprintln!("connect daemon socket failed ({err}), retry {attempt}/{retries}");
+                tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+                delay_ms = delay_ms.saturating_mul(2).min(MAX_CONNECT_RETRY_DELAY_MS);
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("connect daemon socket failed: {path}"));
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn connect_ipc(path: &str) -> std::io::Result<IpcStream> {
+    IpcStream::connect(path).await
+}
+
+/// Opens the daemon pipe, waiting briefly while the daemon swaps in the next pipe
+/// instance for another client.
+#[cfg(windows)]
+async fn connect_ipc(path: &str) -> std::io::Result<IpcStream> {
+    use tokio::net::windows::named_pipe::ClientOptions;
+
+    let mut attempts = 0;
+    loop {
+        match ClientOptions::new().open(path) {
+            Err(err) if err.raw_os_error() == Some(ERROR_PIPE_BUSY) && attempts < 20 => {
+                attempts += 1;
+            }
+            result => return result,
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+}
+
+fn default_token_file() -> PathBuf {
+    let data_dir = match std::env::var("HOME") {
+        Ok(home) => PathBuf::from(home).join(".config").join("aetherlink"),
+        Err(_) => PathBuf::from(".aetherlink"),
+    };
+    data_dir.join("daemon.token")
+}
+
 fn default_socket_path() -> String {
     #[cfg(unix)]
     {
@@ -186,6 +749,434 @@ fn default_socket_path() -> String {
     }
     #[cfg(windows)]
     {
-        "127.0.0.1:59321".to_string()
+        r"\\.\pipe\aetherlink-daemon".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aetherlink_proto::v1::{
+        DiscoverDevicesResponse, DiscoveryUpdateEvent, ErrorEvent, GenericAck,
+        PairingRequiredEvent, SessionStateEvent, SessionStats, StreamStatsEvent,
+        TransferProgressEvent,
+    };
+
+    fn event(payload: daemon_event::Payload) -> DaemonEvent {
+        DaemonEvent {
+            payload: Some(payload),
+        }
+    }
+
+    fn status(state: ManagedNodeState) -> DaemonStatusResponse {
+        DaemonStatusResponse {
+            node_state: state as i32,
+            node_uptime_ms: 3_723_000,
+            node_exit_code: 3,
+            active_sessions: 2,
+            last_error: String::new(),
+        }
+    }
+
+    #[test]
+    fn status_exit_code_reflects_node_health() {
+        assert_eq!(status_exit_code(&status(ManagedNodeState::Running)), 0);
+        assert_eq!(status_exit_code(&status(ManagedNodeState::Exited)), 1);
+        assert_eq!(status_exit_code(&status(ManagedNodeState::Stopped)), 3);
+        assert_eq!(status_exit_code(&status(ManagedNodeState::NeverStarted)), 3);
+        assert_eq!(status_exit_code(&status(ManagedNodeState::Unspecified)), 3);
+    }
+
+    #[test]
+    fn status_is_pretty_printed() {
+        assert_eq!(
+            format_status(&status(ManagedNodeState::Running)),
+            "node: running (uptime 1h 02m 03s)\nsessions: 2\nlast error: none"
+        );
+        let mut exited = status(ManagedNodeState::Exited);
+        exited.last_error = "managed node exited: exit status: 3".to_string();
+        assert_eq!(
+            format_status(&exited),
+            "node: exited (code 3)\nsessions: 2\nlast error: managed node exited: exit status: 3"
+        );
+    }
+
+    #[test]
+    fn completions_generate_for_every_shell() {
+        use clap::ValueEnum;
+
+        for shell in Shell::value_variants() {
+            let mut out = Vec::new();
+            write_completions(*shell, &mut out);
+            let script = String::from_utf8(out).unwrap();
+            assert!(script.contains("aetherlink-daemonctl"), "{shell}");
+            assert!(script.contains("discover"), "{shell}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn connect_retries_until_socket_appears() {
+        let path = std::env::temp_dir().join(format!(
+            "aetherlink-daemonctl-retry-{}-{}.sock",
+            std::process::id(),
+            chrono_like_unix_ms()
+        ));
+        let path_str = path.to_string_lossy().into_owned();
+
+        assert!(connect_with_retry(&path_str, 1, 10).await.is_err());
+
+        let bind_path = path.clone();
+        let daemon = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let listener = tokio::net::UnixListener::bind(&bind_path).unwrap();
+            listener.accept().await.unwrap();
+        });
+        let stream = connect_with_retry(&path_str, 10, 20).await;
+        assert!(stream.is_ok());
+        daemon.await.unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn pings_from_the_daemon_are_answered() {
+        use aetherlink_proto::v1::{IpcPing, IpcPong};
+
+        let (mut client, mut daemon) = tokio::io::duplex(1024);
+        let ping = IpcEnvelope {
+            seq: 4,
+            request_id: "daemon-ping".to_string(),
+            payload: Some(ipc_envelope::Payload::Ping(IpcPing {
+                nonce: 11,
+                send_unix_ms: 2_000,
+            })),
+        };
+        assert!(answer_ping(&mut client, &ping).await.unwrap());
+        let pong = read_envelope(&mut daemon).await.unwrap().unwrap();
+        assert_eq!(pong.request_id, "daemon-ping");
+        assert_eq!(
+            pong.payload,
+            Some(ipc_envelope::Payload::Pong(IpcPong {
+                nonce: 11,
+                echo_send_unix_ms: 2_000,
+            }))
+        );
+
+        let not_ping = IpcEnvelope {
+            payload: Some(ipc_envelope::Payload::Response(DaemonResponse::default())),
+            ..ping
+        };
+        assert!(!answer_ping(&mut client, &not_ping).await.unwrap());
+        drop(client);
+        assert_eq!(read_envelope(&mut daemon).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn read_times_out_on_silent_daemon() {
+        let (mut client, _daemon) = tokio::io::duplex(64);
+        let err = read_with_timeout(&mut client, 20).await.unwrap_err();
+        assert_eq!(err.to_string(), "daemon did not respond within 20ms");
+    }
+
+    #[tokio::test]
+    async fn read_returns_envelope_before_deadline() {
+        let (mut client, mut daemon) = tokio::io::duplex(1024);
+        let envelope = IpcEnvelope {
+            seq: 7,
+            request_id: "req-7".to_string(),
+            payload: None,
+        };
+        write_frame(&mut daemon, &envelope.encode_to_vec())
+            .await
+            .unwrap();
+        let read = read_with_timeout(&mut client, 1_000).await.unwrap();
+        assert_eq!(read, Some(envelope));
+
+        drop(daemon);
+        assert_eq!(read_with_timeout(&mut client, 1_000).await.unwrap(), None);
+    }
+
+    fn pairing_device(pairing_code: &str) -> DiscoveredDevice {
+        DiscoveredDevice {
+            device_code: "desk".to_string(),
+            peer_id: "12D3KooWPeer".to_string(),
+            last_seen_unix_ms: 0,
+            trusted: false,
+            addrs: Vec::new(),
+            pairing_code: pairing_code.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_raw_request_json() {
+        let request =
+            parse_raw_request(r#"{"payload": {"DiscoverDevices": {"device_codes": ["desk"]}}}"#)
+                .unwrap();
+        assert_eq!(
+            request.payload,
+            Some(daemon_request::Payload::DiscoverDevices(
+                DiscoverDevicesRequest {
+                    device_codes: vec!["desk".to_string()],
+                }
+            ))
+        );
+
+        let request =
+            parse_raw_request(r#"{"payload": {"PairDevice": {"device_code": "desk"}}}"#).unwrap();
+        assert_eq!(
+            request.payload,
+            Some(daemon_request::Payload::PairDevice(PairDeviceRequest {
+                device_code: "desk".to_string(),
+                approved: false,
+                peer_id: String::new(),
+            })),
+            "omitted fields take their proto default"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_raw_request_json() {
+        let err = parse_raw_request("{\"payload\": ").unwrap_err();
+        assert!(format!("{err:#}").starts_with("invalid DaemonRequest JSON: "));
+        assert!(parse_raw_request(r#"{"payload": {"NoSuchRequest": {}}}"#).is_err());
+        assert!(parse_raw_request(r#"{"payload": {"PairDevice": {"approved": "yes"}}}"#).is_err());
+
+        let err = parse_raw_request("{}").unwrap_err();
+        assert_eq!(err.to_string(), "DaemonRequest JSON has no payload");
+    }
+
+    #[test]
+    fn confirm_pairing_accepts_only_yes() {
+        let device = pairing_device("123 456 789 012");
+        for (answer, expected) in [
+            ("y\n", true),
+            ("YES\n", true),
+            (" yes \n", true),
+            ("n\n", false),
+            ("\n", false),
+            ("yep\n", false),
+            ("", false),
+        ] {
+            let mut shown = Vec::new();
+            assert_eq!(
+                confirm_pairing(&device, answer.as_bytes(), &mut shown),
+                expected,
+                "answer {answer:?}"
+            );
+            let shown = String::from_utf8(shown).unwrap();
+            assert!(shown.contains("pairing code: 123 456 789 012"));
+            assert!(shown.contains("12D3KooWPeer"));
+            assert!(shown.ends_with("Approve pairing? [y/N] "));
+        }
+    }
+
+    #[test]
+    fn confirm_pairing_refuses_without_code() {
+        let mut shown = Vec::new();
+        assert!(!confirm_pairing(
+            &pairing_device(""),
+            "y\n".as_bytes(),
+            &mut shown
+        ));
+        assert!(!String::from_utf8(shown).unwrap().contains("Approve"));
+    }
+
+    #[tokio::test]
+    async fn round_trip_skips_events_until_response() {
+        let (mut client, mut daemon) = tokio::io::duplex(4096);
+        let notice = IpcEnvelope {
+            seq: 1,
+            request_id: String::new(),
+            payload: Some(ipc_envelope::Payload::Event(event(
+                daemon_event::Payload::PairingRequired(PairingRequiredEvent {
+                    device_code: "desk".to_string(),
+                }),
+            ))),
+        };
+        let response = DaemonResponse {
+            payload: Some(daemon_response::Payload::DiscoverDevices(
+                DiscoverDevicesResponse {
+                    devices: vec![pairing_device("123 456 789 012")],
+                },
+            )),
+            ..Default::default()
+        };
+        let reply = IpcEnvelope {
+            seq: 1,
+            request_id: "ctl".to_string(),
+            payload: Some(ipc_envelope::Payload::Response(response.clone())),
+        };
+        write_frame(&mut daemon, &notice.encode_to_vec())
+            .await
+            .unwrap();
+        write_frame(&mut daemon, &reply.encode_to_vec())
+            .await
+            .unwrap();
+
+        let got = round_trip(
+            &mut client,
+            daemon_request::Payload::DiscoverDevices(DiscoverDevicesRequest {
+                device_codes: vec!["desk".to_string()],
+            }),
+            1_000,
+        )
+        .await
+        .unwrap();
+        assert_eq!(got, response);
+
+        let sent = read_envelope(&mut daemon).await.unwrap().unwrap();
+        assert!(matches!(
+            sent.payload,
+            Some(ipc_envelope::Payload::Request(DaemonRequest {
+                payload: Some(daemon_request::Payload::DiscoverDevices(_))
+            }))
+        ));
+    }
+
+    #[test]
+    fn formats_each_event_type() {
+        let device = |code: &str, trusted: bool| DiscoveredDevice {
+            device_code: code.to_string(),
+            peer_id: String::new(),
+            last_seen_unix_ms: 0,
+            trusted,
+            addrs: Vec::new(),
+            pairing_code: String::new(),
+        };
+        assert_eq!(
+            format_event(&event(daemon_event::Payload::DiscoveryUpdate(
+                DiscoveryUpdateEvent {
+                    devices: vec![device("dev-a", true), device("dev-b", false)],
+                }
+            ))),
+            "discovery: 2 device(s) [dev-a (trusted), dev-b]"
+        );
+        assert_eq!(
+            format_event(&event(daemon_event::Payload::PairingRequired(
+                PairingRequiredEvent {
+                    device_code: "dev-a".to_string(),
+                }
+            ))),
+            "pairing required: device=dev-a"
+        );
+        assert_eq!(
+            format_event(&event(daemon_event::Payload::SessionState(
+                SessionStateEvent {
+                    session_id: "s-1".to_string(),
+                    state: "active".to_string(),
+                    detail: "target=dev-a".to_string(),
+                }
+            ))),
+            "session s-1: active (target=dev-a)"
+        );
+        assert_eq!(
+            format_event(&event(daemon_event::Payload::SessionState(
+                SessionStateEvent {
+                    session_id: String::new(),
+                    state: "daemon_running".to_string(),
+                    detail: String::new(),
+                }
+            ))),
+            "daemon: daemon_running"
+        );
+        assert_eq!(
+            format_event(&event(daemon_event::Payload::StreamStats(
+                StreamStatsEvent {
+                    session_id: "s-1".to_string(),
+                    stats: Some(SessionStats {
+                        session_id: "s-1".to_string(),
+                        rtt_ms: 42,
+                        tx_bitrate_kbps: 8_000,
+                        rx_bitrate_kbps: 120,
+                        packet_loss_x10000: 125,
+                        encode_latency_ms: 4,
+                        decode_latency_ms: 3,
+                        using_relay: true,
+                    }),
+                }
+            ))),
+            "stats session s-1: rtt=42ms tx=8000kbps rx=120kbps loss=1.25% relay=yes"
+        );
+        assert_eq!(
+            format_event(&event(daemon_event::Payload::TransferProgress(
+                TransferProgressEvent {
+                    session_id: "s-1".to_string(),
+                    transfer_id: "t-9".to_string(),
+                    sent_bytes: 512,
+                    total_bytes: 2_048,
+                }
+            ))),
+            "transfer t-9 session s-1: 512/2048 bytes (25%)"
+        );
+        assert_eq!(
+            format_event(&event(daemon_event::Payload::Error(ErrorEvent {
+                code: "request_timeout".to_string(),
+                detail: "request timed out after 20ms".to_string(),
+                error_code: DaemonErrorCode::Timeout as i32,
+            }))),
+            "error request_timeout [DAEMON_ERROR_CODE_TIMEOUT]: request timed out after 20ms"
+        );
+        assert_eq!(
+            format_event(&event(daemon_event::Payload::Error(ErrorEvent {
+                code: "managed_node_exited".to_string(),
+                detail: "exit status: 3".to_string(),
+                error_code: DaemonErrorCode::Unspecified as i32,
+            }))),
+            "error managed_node_exited: exit status: 3"
+        );
+        assert_eq!(format_event(&DaemonEvent { payload: None }), "empty event");
+    }
+
+    fn sample_response() -> DaemonResponse {
+        DaemonResponse {
+            payload: Some(daemon_response::Payload::StartDaemon(GenericAck {
+                ok: true,
+                detail: "managed node started".to_string(),
+            })),
+            error: String::new(),
+            error_code: DaemonErrorCode::Unspecified as i32,
+        }
+    }
+
+    #[test]
+    fn response_renders_as_debug_or_json() {
+        let resp = sample_response();
+        let debug = render_response(&resp, false);
+        assert!(debug.contains("StartDaemon("));
+        assert!(debug.contains('\n'), "debug output is pretty-printed");
+
+        let json = render_response(&resp, true);
+        assert!(!json.contains('\n'), "json output is a single line");
+        let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed["payload"]["StartDaemon"]["ok"], true);
+        assert_eq!(
+            parsed["payload"]["StartDaemon"]["detail"],
+            "managed node started"
+        );
+        assert_eq!(parsed["error"], "");
+        assert_eq!(parsed["error_code"], 0);
+    }
+
+    #[test]
+    fn event_renders_as_single_json_line() {
+        let event = DaemonEvent {
+            payload: Some(daemon_event::Payload::Error(ErrorEvent {
+                code: "request_timeout".to_string(),
+                detail: "request timed out after 20ms".to_string(),
+                error_code: DaemonErrorCode::Timeout as i32,
+            })),
+        };
+        assert_eq!(
+            render_event(&event, false),
+            "error request_timeout [DAEMON_ERROR_CODE_TIMEOUT]: request timed out after 20ms"
+        );
+        let json = render_event(&event, true);
+        assert!(!json.contains('\n'));
+        let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed["payload"]["Error"]["code"], "request_timeout");
+        assert_eq!(
+            parsed["payload"]["Error"]["error_code"],
+            DaemonErrorCode::Timeout as i32
+        );
     }
 }
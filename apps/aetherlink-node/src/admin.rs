@@ -0,0 +1,279 @@
+use std::{path::Path, sync::Arc, time::Duration};
+
+use aetherlink_core::constant_time_eq;
+use aetherlink_proto::{
+    MAX_CONTROL_ENVELOPE_BYTES,
+    v1::{NodeAdminEnvelope, NodeAdminRequest, NodeAdminResponse, node_admin_envelope},
+};
+use anyhow::{Context, Result, anyhow};
+use prost::Message;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::{mpsc, oneshot},
+};
+use tracing::{info, warn};
+
+#[cfg(windows)]
+use tokio::net::{TcpListener as AdminListener, TcpStream as AdminStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener as AdminListener, UnixStream as AdminStream};
+
+/// How long a new admin client has to present the token.
+const ADMIN_AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+/// Bytes an auth frame may carry beyond the token, for a trailing newline and the like.
+const ADMIN_AUTH_FRAME_SLACK: usize = 2;
+/// Largest admin frame after authentication. The biggest request, `SendClipboard`,
+/// carries at most a control envelope's worth of data.
+const ADMIN_MAX_FRAME_BYTES: usize = MAX_CONTROL_ENVELOPE_BYTES;
+
+/// An admin request handed from an IPC client task to the swarm event loop, which
+/// owns `App` and answers through `reply`.
+#[derive(Debug)]
+pub struct AdminCommand {
+    pub request: NodeAdminRequest,
+    pub reply: oneshot::Sender<NodeAdminResponse>,
+}
+
+/// The token admin clients must present, read from `path`. The daemon hands the node
+/// its own IPC token file, so whoever may drive the daemon may drive the node.
+pub fn read_admin_token(path: Option<&Path>) -> Result<Arc<str>> {
+    let path = path.ok_or_else(|| anyhow!("--admin-socket needs --admin-token-file"))?;
+    let token = std::fs::read_to_string(path)
+        .with_context(|| format!("read admin token file failed: {}", path.display()))?;
+    let token = token.trim();
+    if token.is_empty() {
+        return Err(anyhow!("admin token file is empty: {}", path.display()));
+    }
+    Ok(token.into())
+}
+
+pub async fn spawn_admin_server(
+    endpoint: &str,
+    token: Arc<str>,
+    commands: mpsc::Sender<AdminCommand>,
+) -> Result<()> {
+    let listener = bind_admin_listener(endpoint).await?;
+    info!("node admin IPC listening on {endpoint}");
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    warn!("accept admin IPC connection failed: {err}");
+                    continue;
+                }
+            };
+            let commands = commands.clone();
+            let token = token.clone();
+            tokio::spawn(async move {
+                if let Err(err) = serve_admin_client(stream, &token, commands).await {
+                    warn!("admin client session ended with error: {err}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn serve_admin_client(
+    mut stream: AdminStream,
+    token: &str,
+    commands: mpsc::Sender<AdminCommand>,
+) -> Result<()> {
+    authenticate_admin_client(&mut stream, token).await?;
+    let mut seq: u64 = 1;
+    while let Some(payload) = read_frame(&mut stream, ADMIN_MAX_FRAME_BYTES).await? {
+        let envelope =
+            NodeAdminEnvelope::decode(payload.as_slice()).context("decode admin envelope")?;
+        let Some(node_admin_envelope::Payload::Request(request)) = envelope.payload else {
+            continue;
+        };
+        let (reply_tx, reply_rx) = oneshot::channel();
+        commands
+            .send(AdminCommand {
+                request,
+                reply: reply_tx,
+            })
+            .await
+            .context("node event loop is not running")?;
+        let response = reply_rx
+            .await
+            .context("node event loop dropped admin request")?;
+
+        let response_envelope = NodeAdminEnvelope {
+            seq,
+            request_id: envelope.request_id,
+            payload: Some(node_admin_envelope::Payload::Response(response)),
+        };
+        seq = seq.saturating_add(1);
+        write_frame(&mut stream, &response_envelope.encode_to_vec()).await?;
+    }
+    Ok(())
+}
+
+/// The first frame on every admin connection must be the token.
+async fn authenticate_admin_client<R>(reader: &mut R, token: &str) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    let max_len = token.len() + ADMIN_AUTH_FRAME_SLACK;
+    let presented = tokio::time::timeout(ADMIN_AUTH_TIMEOUT, read_frame(reader, max_len))
+        .await
+        .context("admin client did not authenticate in time")??
+        .ok_or_else(|| anyhow!("admin client closed before authenticating"))?;
+    if !constant_time_eq(presented.trim_ascii(), token.as_bytes()) {
+        return Err(anyhow!("admin client presented an invalid auth token"));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn bind_admin_listener(endpoint: &str) -> Result<AdminListener> {
+    let path = std::path::Path::new(endpoint);
+    if path.exists() {
+        std::fs::remove_file(path)
+            .with_context(|| format!("remove stale admin socket failed: {endpoint}"))?;
+    }
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    std::fs::create_dir_all(parent)
+        .with_context(|| format!("create admin socket parent failed: {}", parent.display()))?;
+    // The socket is bound inside a fresh 0700 directory and only moved to `endpoint`
+    // once it is 0600, so it is never reachable with the umask's permissions.
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    let staging = parent.join(format!(".aetherlink-admin-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&staging)
+        .with_context(|| {
+            format!(
+                "create admin socket staging dir failed: {}",
+                staging.display()
+            )
+        })?;
+    let staged = staging.join("admin.sock");
+    let bound = (|| {
+        let listener = AdminListener::bind(&staged)
+            .with_context(|| format!("bind admin socket failed: {endpoint}"))?;
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("restrict admin socket permissions failed: {endpoint}"))?;
+        std::fs::rename(&staged, path)
+            .with_context(|| format!("move admin socket into place failed: {endpoint}"))?;
+        Ok(listener)
+    })();
+    let _ = std::fs::remove_dir_all(&staging);
+    bound
+}
+
+#[cfg(windows)]
+async fn bind_admin_listener(endpoint: &str) -> Result<AdminListener> {
+    AdminListener::bind(endpoint)
+        .await
+        .with_context(|| format!("bind admin socket failed: {endpoint}"))
+}
+
+/// Reads one frame, refusing before allocating one longer than `max_len`.
+async fn read_frame<S>(stream: &mut S, max_len: usize) -> Result<Option<Vec<u8>>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut len_buf = [0_u8; 4];
+    match stream.read_exact(&mut len_buf).await {
+        Ok(_) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err).context("read admin frame length failed"),
+    }
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len == 0 {
+        return Ok(None);
+    }
+    if len > max_len {
+        return Err(anyhow!("admin frame of {len} bytes exceeds {max_len}"));
+    }
+    let mut payload = vec![0_u8; len];
+    stream
+        .read_exact(&mut payload)
+        .await
+        .context("read admin frame payload failed")?;
+    Ok(Some(payload))
+}
+
+async fn write_frame<S>(stream: &mut S, payload: &[u8]) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let len = payload.len() as u32;
+    stream
+        .write_all(&len.to_be_bytes())
+        .await
+        .context("write admin frame length failed")?;
+    stream
+        .write_all(payload)
+        .await
+        .context("write admin frame payload failed")?;
+    stream.flush().await.context("flush admin frame failed")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn admin_clients_must_present_the_token_first() {
+        let token = "ab".repeat(32);
+
+        let (mut client, mut server) = tokio::io::duplex(256);
+        write_frame(&mut client, format!("{token}\n").as_bytes())
+            .await
+            .unwrap();
+        authenticate_admin_client(&mut server, &token)
+            .await
+            .unwrap();
+
+        let (mut client, mut server) = tokio::io::duplex(256);
+        write_frame(&mut client, "cd".repeat(32).as_bytes())
+            .await
+            .unwrap();
+        assert!(
+            authenticate_admin_client(&mut server, &token)
+                .await
+                .is_err()
+        );
+
+        // An oversized length is refused before the payload is awaited.
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client.write_all(&u32::MAX.to_be_bytes()).await.unwrap();
+        let err = authenticate_admin_client(&mut server, &token)
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("exceeds"), "{err:#}");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn admin_socket_is_private_once_bound() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!(
+            "aetherlink-admin-test-{}-{}",
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+        let endpoint = dir.join("node.sock");
+        let _listener = bind_admin_listener(endpoint.to_str().unwrap())
+            .await
+            .unwrap();
+        let mode = std::fs::metadata(&endpoint).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(
+            std::fs::read_dir(&dir).unwrap().count(),
+            1,
+            "staging dir removed"
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,365 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use aetherlink_core::{AuthAuditSink, AuthEvent};
+use anyhow::{Context, Result, anyhow};
+use libp2p::identity;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::write_atomic;
+
+/// Chain value the first entry of a log links to.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// One authentication decision as written to the audit log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct AuditEntry {
+    unix_ms: i64,
+    message: String,
+    accepted: bool,
+    peer_id: Option<String>,
+    device_code: String,
+    /// The `SessionAuthError` exactly as the verifier returned it, variant and fields.
+    error: Option<String>,
+    /// The error's human-readable message.
+    reason: Option<String>,
+    prev_hash: String,
+}
+
+/// An entry and the SHA-256 of its JSON encoding. Each entry names the hash of the one
+/// before it, so editing, dropping or reordering lines breaks the chain.
+#[derive(Debug, Serialize, Deserialize)]
+struct AuditLine {
+    #[serde(flatten)]
+    entry: AuditEntry,
+    hash: String,
+}
+
+/// The log's entry count and last hash, signed with the node's identity key and kept
+/// next to the log as `<log>.head`. The chain alone can be recomputed after an edit and
+/// says nothing about lines cut from the end; the signed head catches both.
+#[derive(Debug, Serialize, Deserialize)]
+struct AuditHead {
+    entries: usize,
+    hash: String,
+    signature: String,
+}
+
+impl AuditHead {
+    fn sign(key: &identity::Keypair, entries: usize, hash: String) -> Result<Self> {
+        let signature = key
+            .sign(&head_payload(entries, &hash))
+            .context("sign audit log head failed")?;
+        Ok(Self {
+            entries,
+            hash,
+            signature: encode_hex(&signature),
+        })
+    }
+
+    fn verify(&self, key: &identity::PublicKey) -> bool {
+        decode_hex(&self.signature)
+            .is_some_and(|sig| key.verify(&head_payload(self.entries, &self.hash), &sig))
+    }
+}
+
+fn head_payload(entries: usize, hash: &str) -> Vec<u8> {
+    format!("aetherlink.auth-audit-head\n{entries}\n{hash}").into_bytes()
+}
+
+fn head_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".head");
+    PathBuf::from(name)
+}
+
+impl AuditEntry {
+    fn from_event(event: &AuthEvent, prev_hash: String) -> Self {
+        match event {
+            AuthEvent::Accepted {
+                message,
+                peer_id,
+                device_code,
+                unix_ms,
+            } => Self {
+                unix_ms: *unix_ms,
+                message: message.as_str().to_string(),
+                accepted: true,
+                peer_id: Some(peer_id.to_string()),
+                device_code: device_code.clone(),
+                error: None,
+                reason: None,
+                prev_hash,
+            },
+            AuthEvent::Rejected {
+                message,
+                peer_id,
+                device_code,
+                unix_ms,
+                error,
+            } => Self {
+                unix_ms: *unix_ms,
+                message: message.as_str().to_string(),
+                accepted: false,
+                peer_id: peer_id.map(|peer_id| peer_id.to_string()),
+                device_code: device_code.clone(),
+                error: Some(format!("{error:?}")),
+                reason: Some(error.to_string()),
+                prev_hash,
+            },
+        }
+    }
+
+    fn hash(&self) -> Result<String> {
+        let json = serde_json::to_vec(self).context("serialize audit entry failed")?;
+        Ok(encode_hex(&Sha256::digest(&json)))
+    }
+}
+
+/// Appends authentication decisions to a JSON-lines file, one hash-chained entry per
+/// line, and re-signs the log's head after each. Reopening an existing log checks it
+/// against its head and continues its chain.
+#[derive(Debug)]
+pub struct AuthAuditLog {
+    path: PathBuf,
+    file: File,
+    key: identity::Keypair,
+    entries: usize,
+    last_hash: String,
+}
+
+impl AuthAuditLog {
+    /// Opens the log at `path`, signing its head with `key`. A log that no longer
+    /// matches its head is refused rather than re-signed, which would bless the damage.
+    pub fn open(path: &Path, key: identity::Keypair) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("create parent dir failed: {}", parent.display()))?;
+        }
+        let (entries, last_hash) = match fs::metadata(path) {
+            Ok(_) => verify_audit_log(path, &key.public())?,
+            Err(err) if err.kind() == ErrorKind::NotFound => (0, GENESIS_HASH.to_string()),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("open audit log failed: {}", path.display()));
+            }
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("open audit log failed: {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            key,
+            entries,
+            last_hash,
+        })
+    }
+
+    fn append(&mut self, event: &AuthEvent) -> Result<()> {
+        let entry = AuditEntry::from_event(event, self.last_hash.clone());
+        let hash = entry.hash()?;
+        let mut line = serde_json::to_vec(&AuditLine {
+            entry,
+            hash: hash.clone(),
+        })
+        .context("serialize audit line failed")?;
+        line.push(b'\n');
+        self.file
+            .write_all(&line)
+            .and_then(|()| self.file.flush())
+            .context("append audit line failed")?;
+        self.entries += 1;
+        self.last_hash = hash;
+        let head = AuditHead::sign(&self.key, self.entries, self.last_hash.clone())?;
+        let head = serde_json::to_vec(&head).context("serialize audit log head failed")?;
+        write_atomic(&head_path(&self.path), &head)
+    }
+}
+
+impl AuthAuditSink for AuthAuditLog {
+    fn record(&mut self, event: &AuthEvent) {
+        if let Err(err) = self.append(event) {
+            warn!(
+                "failed to write auth audit log {}: {err:#}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// Checks every entry's hash and its link to the entry before it, then that the entry
+/// count and last hash match the head `key` signed. Returns the entry count and last
+/// hash.
+pub fn verify_audit_log(path: &Path, key: &identity::PublicKey) -> Result<(usize, String)> {
+    let file =
+        File::open(path).with_context(|| format!("open audit log failed: {}", path.display()))?;
+    let (count, last_hash) = verify_chain(BufReader::new(file))?;
+    let head_path = head_path(path);
+    let head: AuditHead = match fs::read(&head_path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .with_context(|| format!("parse audit log head failed: {}", head_path.display()))?,
+        // Nothing was ever appended, so there is nothing to sign yet.
+        Err(err) if err.kind() == ErrorKind::NotFound && count == 0 => return Ok((0, last_hash)),
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("read audit log head failed: {}", head_path.display()));
+        }
+    };
+    if !head.verify(key) {
+        return Err(anyhow!(
+            "audit log head is not signed by this node's identity key; after an identity rotation, move the old log aside"
+        ));
+    }
+    if head.entries != count || head.hash != last_hash {
+        return Err(anyhow!(
+            "audit log holds {count} entries but its signed head records {}; lines were cut or rewritten",
+            head.entries
+        ));
+    }
+    Ok((count, last_hash))
+}
+
+fn verify_chain(reader: impl BufRead) -> Result<(usize, String)> {
+    let mut prev_hash = GENESIS_HASH.to_string();
+    let mut count = 0;
+    for (index, line) in reader.lines().enumerate() {
+        let line_no = index + 1;
+        let line = line.with_context(|| format!("read audit log line {line_no} failed"))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let parsed: AuditLine = serde_json::from_str(&line)
+            .with_context(|| format!("parse audit log line {line_no} failed"))?;
+        if parsed.entry.prev_hash != prev_hash {
+            return Err(anyhow!(
+                "audit log line {line_no} does not follow the previous entry"
+            ));
+        }
+        if parsed.entry.hash()? != parsed.hash {
+            return Err(anyhow!("audit log line {line_no} was modified"));
+        }
+        prev_hash = parsed.hash;
+        count += 1;
+    }
+    Ok((count, prev_hash))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use aetherlink_core::{AuthMessage, SessionAuthError};
+    use libp2p::PeerId;
+
+    use super::*;
+
+    fn temp_log_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "aetherlink-audit-{name}-{}.jsonl",
+            PeerId::random()
+        ))
+    }
+
+    fn remove_log(path: &Path) {
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(head_path(path));
+    }
+
+    fn rejected(unix_ms: i64, error: SessionAuthError) -> AuthEvent {
+        AuthEvent::Rejected {
+            message: AuthMessage::SessionRequest,
+            peer_id: Some(PeerId::random()),
+            device_code: "device-a".to_string(),
+            unix_ms,
+            error,
+        }
+    }
+
+    #[test]
+    fn log_records_error_variants_and_resumes_its_chain() {
+        let key = identity::Keypair::generate_ed25519();
+        let path = temp_log_path("chain");
+        let mut log = AuthAuditLog::open(&path, key.clone()).unwrap();
+        log.record(&rejected(1, SessionAuthError::ReplayDetected));
+        log.record(&AuthEvent::Accepted {
+            message: AuthMessage::SessionAccept,
+            peer_id: PeerId::random(),
+            device_code: "device-a".to_string(),
+            unix_ms: 2,
+        });
+        drop(log);
+        let mut log = AuthAuditLog::open(&path, key.clone()).unwrap();
+        log.record(&rejected(3, SessionAuthError::InvalidSignature));
+        drop(log);
+
+        assert_eq!(verify_audit_log(&path, &key.public()).unwrap().0, 3);
+        let text = fs::read_to_string(&path).unwrap();
+        let entries: Vec<AuditLine> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        remove_log(&path);
+        assert_eq!(entries[0].entry.error.as_deref(), Some("ReplayDetected"));
+        assert!(entries[1].entry.accepted);
+        assert_eq!(entries[1].entry.error, None);
+        assert_eq!(entries[2].entry.error.as_deref(), Some("InvalidSignature"));
+        assert_eq!(entries[2].entry.prev_hash, entries[1].hash);
+    }
+
+    #[test]
+    fn edited_dropped_or_truncated_lines_fail_verification() {
+        let key = identity::Keypair::generate_ed25519();
+        let path = temp_log_path("tamper");
+        let mut log = AuthAuditLog::open(&path, key.clone()).unwrap();
+        for unix_ms in 1..=3 {
+            log.record(&rejected(unix_ms, SessionAuthError::ReplayDetected));
+        }
+        drop(log);
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        fs::write(
+            &path,
+            text.replacen("ReplayDetected", "InvalidSignature", 1),
+        )
+        .unwrap();
+        assert!(verify_audit_log(&path, &key.public()).is_err());
+
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        assert!(verify_audit_log(&path, &key.public()).is_err());
+
+        // A consistent chain missing its newest entry still disagrees with the head.
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[1])).unwrap();
+        assert!(verify_audit_log(&path, &key.public()).is_err());
+        assert!(AuthAuditLog::open(&path, key.clone()).is_err());
+
+        fs::write(&path, &text).unwrap();
+        assert!(verify_audit_log(&path, &key.public()).is_ok());
+        let other = identity::Keypair::generate_ed25519();
+        assert!(verify_audit_log(&path, &other.public()).is_err());
+        fs::remove_file(head_path(&path)).unwrap();
+        assert!(verify_audit_log(&path, &key.public()).is_err());
+        remove_log(&path);
+    }
+}
@@ -0,0 +1,48 @@
+use aetherlink_proto::{CONTROL_ENVELOPE_OVERHEAD_BYTES, MAX_CONTROL_ENVELOPE_BYTES};
+
+/// Largest clipboard payload sent or accepted over the control channel: whatever fits
+/// in a control envelope once the fields around it are accounted for.
+pub const CLIPBOARD_MAX_BYTES: usize = MAX_CONTROL_ENVELOPE_BYTES - CONTROL_ENVELOPE_OVERHEAD_BYTES;
+
+const CLIPBOARD_MIME_ALLOWLIST: &[&str] = &["text/plain", "image/png"];
+
+/// Whether clipboard contents of type `mime` and `len` bytes may cross the control
+/// channel. Parameters such as `; charset=utf-8` are ignored when matching the type.
+pub fn clipboard_allowed(mime: &str, len: usize) -> bool {
+    if len == 0 || len > CLIPBOARD_MAX_BYTES {
+        return false;
+    }
+    let essence = mime.split(';').next().unwrap_or_default().trim();
+    CLIPBOARD_MIME_ALLOWLIST
+        .iter()
+        .any(|allowed| essence.eq_ignore_ascii_case(allowed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowlisted_mime_types_pass() {
+        assert!(clipboard_allowed("text/plain", 5));
+        assert!(clipboard_allowed("text/plain; charset=utf-8", 5));
+        assert!(clipboard_allowed("TEXT/PLAIN", 5));
+        assert!(clipboard_allowed("image/png", 1024));
+    }
+
+    #[test]
+    fn other_mime_types_are_refused() {
+        assert!(!clipboard_allowed("text/html", 5));
+        assert!(!clipboard_allowed("image/jpeg", 5));
+        assert!(!clipboard_allowed("application/octet-stream", 5));
+        assert!(!clipboard_allowed("", 5));
+        assert!(!clipboard_allowed("text/plainx", 5));
+    }
+
+    #[test]
+    fn size_cap_is_enforced() {
+        assert!(clipboard_allowed("image/png", CLIPBOARD_MAX_BYTES));
+        assert!(!clipboard_allowed("image/png", CLIPBOARD_MAX_BYTES + 1));
+        assert!(!clipboard_allowed("text/plain", 0));
+    }
+}
@@ -0,0 +1,69 @@
+use std::{
+    fmt::Debug,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Source of wall-clock time for `App`. Timeout, keepalive and throttle logic reads the
+/// time through this trait so tests can drive it with [`FakeClock`] instead of sleeping.
+pub trait Clock: Debug + Send {
+    fn now_unix_ms(&self) -> i64;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealClock;
+
+impl Clock for RealClock {
+    fn now_unix_ms(&self) -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or_default()
+    }
+}
+
+/// Manually advanced clock. Clones share the same time, so a test can keep one handle
+/// while `App` owns another.
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub struct FakeClock {
+    now_unix_ms: std::sync::Arc<std::sync::atomic::AtomicI64>,
+}
+
+#[cfg(test)]
+impl FakeClock {
+    pub fn new(start_unix_ms: i64) -> Self {
+        Self {
+            now_unix_ms: std::sync::Arc::new(std::sync::atomic::AtomicI64::new(start_unix_ms)),
+        }
+    }
+
+    pub fn advance(&self, delta_ms: i64) {
+        self.now_unix_ms
+            .fetch_add(delta_ms, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn now_unix_ms(&self) -> i64 {
+        self.now_unix_ms.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fake_clock_clones_share_time() {
+        let clock = FakeClock::new(1_000);
+        let handle = clock.clone();
+        handle.advance(250);
+        assert_eq!(clock.now_unix_ms(), 1_250);
+    }
+
+    #[test]
+    fn real_clock_is_past_epoch() {
+        assert!(RealClock.now_unix_ms() > 0);
+    }
+}
@@ -0,0 +1,458 @@
+use std::{collections::HashMap, fmt, time::Duration};
+
+use aetherlink_core::DEFAULT_ALLOWED_SKEW_MS;
+use anyhow::{Context, Result, anyhow};
+use futures::StreamExt;
+use libp2p::{
+    Multiaddr, SwarmBuilder, autonat, identify, identity,
+    swarm::{ConnectionId, NetworkBehaviour, SwarmEvent, dial_opts::DialOpts},
+};
+use tokio::net::UdpSocket;
+
+use crate::clock::{Clock, RealClock};
+
+pub const DEFAULT_TIME_SERVER: &str = "pool.ntp.org:123";
+const NTP_UNIX_EPOCH_OFFSET_SECS: i64 = 2_208_988_800;
+const SNTP_PACKET_LEN: usize = 48;
+
+#[derive(Debug, Clone)]
+pub struct DoctorConfig {
+    pub listen: Multiaddr,
+    pub bootstrap: Vec<Multiaddr>,
+    pub time_server: String,
+    pub timeout: Duration,
+    pub agent_version: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CheckStatus::Pass => "PASS",
+            CheckStatus::Warn => "WARN",
+            CheckStatus::Fail => "FAIL",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl CheckResult {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Outcome of dialing one configured bootstrap address; `None` means the dial was
+/// still pending when the doctor deadline hit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootstrapProbe {
+    pub addr: Multiaddr,
+    pub outcome: Option<Result<(), String>>,
+}
+
+#[derive(NetworkBehaviour)]
+struct DoctorBehaviour {
+    identify: identify::Behaviour,
+    autonat: autonat::Behaviour,
+}
+
+/// Runs every diagnostic and returns the results in report order. Network checks share
+/// one throwaway swarm with an ephemeral identity so the persisted device key and trust
+/// store are never touched.
+pub async fn run_doctor(config: &DoctorConfig) -> Result<Vec<CheckResult>> {
+    let clock = RealClock;
+    let time_source = query_time_source(&config.time_server, config.timeout)
+        .await
+        .map_err(|err| err.to_string());
+    let clock_check = check_clock_skew(clock.now_unix_ms(), time_source);
+
+    let local_key = identity::Keypair::generate_ed25519();
+    let local_peer_id = local_key.public().to_peer_id();
+    let mut swarm = SwarmBuilder::with_existing_identity(local_key)
+        .with_tokio()
+        .with_quic()
+        .with_dns()?
+        .with_behaviour(|key| DoctorBehaviour {
+            identify: identify::Behaviour::new(identify::Config::new(
+                config.agent_version.clone(),
+                key.public(),
+            )),
+            autonat: autonat::Behaviour::new(
+                local_peer_id,
+                autonat::Config {
+                    boot_delay: Duration::from_millis(500),
+                    ..Default::default()
+                },
+            ),
+        })?
+        .build();
+
+    let mut listen_outcome = match swarm.listen_on(config.listen.clone()) {
+        Ok(_) => None,
+        Err(err) => Some(Err(err.to_string())),
+    };
+
+    let mut probes = config
+        .bootstrap
+        .iter()
+        .map(|addr| BootstrapProbe {
+            addr: addr.clone(),
+            outcome: None,
+        })
+        .collect::<Vec<_>>();
+    let mut pending_dials = HashMap::<ConnectionId, usize>::new();
+    for (index, probe) in probes.iter_mut().enumerate() {
+        if let Some(peer_id) = crate::extract_peer_id(&probe.addr) {
+            swarm
+                .behaviour_mut()
+                .autonat
+                .add_server(peer_id, Some(probe.addr.clone()));
+        }
+        let opts = DialOpts::from(probe.addr.clone());
+        let connection_id = opts.connection_id();
+        match swarm.dial(opts) {
+            Ok(()) => {
+                pending_dials.insert(connection_id, index);
+            }
+            Err(err) => probe.outcome = Some(Err(err.to_string())),
+        }
+    }
+
+    let mut nat_status = None;
+    let deadline = tokio::time::sleep(config.timeout);
+    tokio::pin!(deadline);
+    loop {
+        let dials_done = pending_dials.is_empty();
+        let reached_any = probes
+            .iter()
+            .any(|probe| matches!(probe.outcome, Some(Ok(()))));
+        if listen_outcome.is_some() && dials_done && (nat_status.is_some() || !reached_any) {
+            break;
+        }
+        tokio::select! {
+            _ = &mut deadline => break,
+            event = swarm.select_next_some() => match event {
+                SwarmEvent::NewListenAddr { address, .. } => {
+                    listen_outcome.get_or_insert(Ok(address));
+                }
+                SwarmEvent::ListenerError { error, .. } => {
+                    listen_outcome.get_or_insert(Err(error.to_string()));
+                }
+                SwarmEvent::ListenerClosed { reason: Err(error), .. } => {
+                    listen_outcome.get_or_insert(Err(error.to_string()));
+                }
+                SwarmEvent::ConnectionEstablished { connection_id, .. } => {
+                    if let Some(index) = pending_dials.remove(&connection_id) {
+                        probes[index].outcome = Some(Ok(()));
+                    }
+                }
+                SwarmEvent::OutgoingConnectionError { connection_id, error, .. } => {
+                    if let Some(index) = pending_dials.remove(&connection_id) {
+                        probes[index].outcome = Some(Err(error.to_string()));
+                    }
+                }
+                SwarmEvent::Behaviour(DoctorBehaviourEvent::Autonat(
+                    autonat::Event::StatusChanged { new, .. },
+                )) => {
+                    nat_status = Some(new);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let reached_any = probes
+        .iter()
+        .any(|probe| matches!(probe.outcome, Some(Ok(()))));
+    Ok(vec![
+        check_listen(&config.listen, listen_outcome.as_ref()),
+        clock_check,
+        check_bootstrap(&probes),
+        check_nat(nat_status.as_ref(), reached_any),
+    ])
+}
+
+pub fn check_listen(
+    requested: &Multiaddr,
+    outcome: Option<&Result<Multiaddr, String>>,
+) -> CheckResult {
+    match outcome {
+        Some(Ok(bound)) => CheckResult::new("listen", CheckStatus::Pass, format!("bound {bound}")),
+        Some(Err(err)) => CheckResult::new(
+            "listen",
+            CheckStatus::Fail,
+            format!("cannot bind {requested}: {err} (port in use or blocked?)"),
+        ),
+        None => CheckResult::new(
+            "listen",
+            CheckStatus::Fail,
+            format!("no listen address reported for {requested} before timeout"),
+        ),
+    }
+}
+
+/// Compares local time against a reference. Skew beyond the SessionRequest freshness
+/// window fails because peers would reject every signed request; half of it warns.
+pub fn check_clock_skew(local_unix_ms: i64, reference: Result<i64, String>) -> CheckResult {
+    let reference_unix_ms = match reference {
+        Ok(ms) => ms,
+        Err(err) => {
+            return CheckResult::new(
+                "clock",
+                CheckStatus::Warn,
+                format!("time source unreachable ({err}); skew not verified"),
+            );
+        }
+    };
+    let skew_ms = local_unix_ms - reference_unix_ms;
+    let detail = format!(
+        "local clock is {skew_ms:+}ms from reference (allowed +/-{DEFAULT_ALLOWED_SKEW_MS}ms)"
+    );
+    let status = if skew_ms.abs() > DEFAULT_ALLOWED_SKEW_MS {
+        CheckStatus::Fail
+    } else if skew_ms.abs() > DEFAULT_ALLOWED_SKEW_MS / 2 {
+        CheckStatus::Warn
+    } else {
+        CheckStatus::Pass
+    };
+    CheckResult::new("clock", status, detail)
+}
+
+pub fn check_bootstrap(probes: &[BootstrapProbe]) -> CheckResult {
+    if probes.is_empty() {
+        return CheckResult::new(
+            "bootstrap",
+            CheckStatus::Warn,
+            "no bootstrap configured; only mDNS peers on the local network can be found",
+        );
+    }
+    let reached = probes
+        .iter()
+        .filter(|probe| matches!(probe.outcome, Some(Ok(()))))
+        .count();
+    if reached > 0 {
+        return CheckResult::new(
+            "bootstrap",
+            CheckStatus::Pass,
+            format!("reached {reached}/{} bootstrap peers", probes.len()),
+        );
+    }
+    let first_failure = probes
+        .iter()
+        .find_map(|probe| match &probe.outcome {
+            Some(Err(err)) => Some(format!("{}: {err}", probe.addr)),
+            _ => None,
+        })
+        .unwrap_or_else(|| "all dials timed out".to_string());
+    CheckResult::new(
+        "bootstrap",
+        CheckStatus::Fail,
+        format!(
+            "none of {} bootstrap peers reachable ({first_failure})",
+            probes.len()
+        ),
+    )
+}
+
+pub fn check_nat(status: Option<&autonat::NatStatus>, probe_possible: bool) -> CheckResult {
+    match status {
+        Some(autonat::NatStatus::Public(addr)) => CheckResult::new(
+            "nat",
+            CheckStatus::Pass,
+            format!("publicly reachable at {addr}"),
+        ),
+        Some(autonat::NatStatus::Private) => CheckResult::new(
+            "nat",
+            CheckStatus::Warn,
+            "behind NAT; direct dials need hole punching or a --relay",
+        ),
+        Some(autonat::NatStatus::Unknown) | None if !probe_possible => CheckResult::new(
+            "nat",
+            CheckStatus::Warn,
+            "skipped: no reachable peer to probe NAT type",
+        ),
+        Some(autonat::NatStatus::Unknown) | None => CheckResult::new(
+            "nat",
+            CheckStatus::Warn,
+            "undetermined: peers did not answer AutoNAT probes in time",
+        ),
+    }
+}
+
+pub fn format_report(results: &[CheckResult]) -> String {
+    let mut report = String::from("aetherlink-node doctor\n");
+    for result in results {
+        report.push_str(&format!(
+            "  [{}] {:<10} {}\n",
+            result.status, result.name, result.detail
+        ));
+    }
+    let count = |status| results.iter().filter(|r| r.status == status).count();
+    report.push_str(&format!(
+        "{} passed, {} warnings, {} failed\n",
+        count(CheckStatus::Pass),
+        count(CheckStatus::Warn),
+        count(CheckStatus::Fail)
+    ));
+    report
+}
+
+/// Single SNTP exchange; returns the server time adjusted by half the round trip so it
+/// lines up with the local clock at the moment of receipt.
+async fn query_time_source(server: &str, timeout: Duration) -> Result<i64> {
+    let socket = UdpSocket::bind("0.0.0.0:0")
+        .await
+        .context("bind time query socket")?;
+    socket
+        .connect(server)
+        .await
+        .with_context(|| format!("resolve time source {server}"))?;
+
+    let mut request = [0u8; SNTP_PACKET_LEN];
+    // LI=0, VN=3, Mode=3 (client).
+    request[0] = 0x1b;
+    let clock = RealClock;
+    let sent_unix_ms = clock.now_unix_ms();
+    socket.send(&request).await.context("send time query")?;
+
+    let mut response = [0u8; SNTP_PACKET_LEN];
+    let len = tokio::time::timeout(timeout, socket.recv(&mut response))
+        .await
+        .map_err(|_| anyhow!("time source {server} did not answer"))?
+        .context("receive time response")?;
+    let received_unix_ms = clock.now_unix_ms();
+    let transmit_unix_ms = parse_sntp_transmit_unix_ms(&response[..len])
+        .ok_or_else(|| anyhow!("malformed time response from {server}"))?;
+    Ok(transmit_unix_ms + (received_unix_ms - sent_unix_ms) / 2)
+}
+
+pub fn parse_sntp_transmit_unix_ms(packet: &[u8]) -> Option<i64> {
+    if packet.len() < SNTP_PACKET_LEN || packet[0] & 0x07 != 4 {
+        return None;
+    }
+    let seconds = u32::from_be_bytes(packet[40..44].try_into().ok()?) as i64;
+    let fraction = u32::from_be_bytes(packet[44..48].try_into().ok()?) as i64;
+    if seconds == 0 {
+        return None;
+    }
+    Some((seconds - NTP_UNIX_EPOCH_OFFSET_SECS) * 1000 + ((fraction * 1000) >> 32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_skew_thresholds() {
+        let now = 1_700_000_000_000;
+        assert_eq!(
+            check_clock_skew(now, Ok(now + 200)).status,
+            CheckStatus::Pass
+        );
+        assert_eq!(
+            check_clock_skew(now, Ok(now - DEFAULT_ALLOWED_SKEW_MS / 2 - 1)).status,
+            CheckStatus::Warn
+        );
+        let fail = check_clock_skew(now + DEFAULT_ALLOWED_SKEW_MS + 1, Ok(now));
+        assert_eq!(fail.status, CheckStatus::Fail);
+        assert!(fail.detail.contains("+30001ms"));
+        assert_eq!(
+            check_clock_skew(now, Err("timeout".to_string())).status,
+            CheckStatus::Warn
+        );
+    }
+
+    #[test]
+    fn bootstrap_check_reports_reachability() {
+        let addr: Multiaddr = "/ip4/203.0.113.5/udp/9000/quic-v1".parse().unwrap();
+        assert_eq!(check_bootstrap(&[]).status, CheckStatus::Warn);
+
+        let failed = BootstrapProbe {
+            addr: addr.clone(),
+            outcome: Some(Err("handshake timed out".to_string())),
+        };
+        let result = check_bootstrap(std::slice::from_ref(&failed));
+        assert_eq!(result.status, CheckStatus::Fail);
+        assert!(result.detail.contains("handshake timed out"));
+
+        let reached = BootstrapProbe {
+            addr,
+            outcome: Some(Ok(())),
+        };
+        let result = check_bootstrap(&[failed, reached]);
+        assert_eq!(result.status, CheckStatus::Pass);
+        assert_eq!(result.detail, "reached 1/2 bootstrap peers");
+    }
+
+    #[test]
+    fn listen_and_nat_checks() {
+        let requested: Multiaddr = "/ip4/0.0.0.0/udp/9000/quic-v1".parse().unwrap();
+        assert_eq!(
+            check_listen(&requested, Some(&Err("address in use".to_string()))).status,
+            CheckStatus::Fail
+        );
+        assert_eq!(check_listen(&requested, None).status, CheckStatus::Fail);
+        assert_eq!(
+            check_listen(&requested, Some(&Ok(requested.clone()))).status,
+            CheckStatus::Pass
+        );
+
+        let public = autonat::NatStatus::Public(requested);
+        assert_eq!(check_nat(Some(&public), true).status, CheckStatus::Pass);
+        assert_eq!(
+            check_nat(Some(&autonat::NatStatus::Private), true).status,
+            CheckStatus::Warn
+        );
+        assert!(check_nat(None, false).detail.starts_with("skipped"));
+    }
+
+    #[test]
+    fn report_lists_checks_and_summary() {
+        let report = format_report(&[
+            CheckResult::new("listen", CheckStatus::Pass, "bound /ip4/127.0.0.1"),
+            CheckResult::new("clock", CheckStatus::Warn, "skew +20000ms"),
+            CheckResult::new("bootstrap", CheckStatus::Fail, "none reachable"),
+        ]);
+        assert_eq!(
+            report,
+            "aetherlink-node doctor\n\
+             \x20 [PASS] listen     bound /ip4/127.0.0.1\n\
+             \x20 [WARN] clock      skew +20000ms\n\
+             \x20 [FAIL] bootstrap  none reachable\n\
+             1 passed, 1 warnings, 1 failed\n"
+        );
+    }
+
+    #[test]
+    fn parses_sntp_transmit_timestamp() {
+        let mut packet = [0u8; SNTP_PACKET_LEN];
+        packet[0] = 0x1c; // VN=3, Mode=4 (server)
+        let seconds = (1_700_000_000 + NTP_UNIX_EPOCH_OFFSET_SECS) as u32;
+        packet[40..44].copy_from_slice(&seconds.to_be_bytes());
+        packet[44..48].copy_from_slice(&(1u32 << 31).to_be_bytes());
+        assert_eq!(
+            parse_sntp_transmit_unix_ms(&packet),
+            Some(1_700_000_000_500)
+        );
+
+        packet[0] = 0x1b; // client mode echoed back is not a valid answer
+        assert_eq!(parse_sntp_transmit_unix_ms(&packet), None);
+        assert_eq!(parse_sntp_transmit_unix_ms(&packet[..40]), None);
+    }
+}
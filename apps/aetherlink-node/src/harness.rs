@@ -0,0 +1,186 @@
+use std::{path::PathBuf, time::Duration};
+
+use aetherlink_core::TrustedPeers;
+use anyhow::{Context, Result, bail};
+use futures::StreamExt;
+use libp2p::{Multiaddr, PeerId, Swarm, identity, swarm::SwarmEvent};
+
+use crate::{
+    App, NodeBehaviour, SwarmTuning, TICK_INTERVAL_MS, build_swarm, handle_swarm_event, handle_tick,
+};
+
+const TEST_AGENT_VERSION: &str = "AetherLink-v1.0.0";
+
+/// In-process node for end-to-end tests: a real swarm on loopback QUIC and its `App`,
+/// driven by the same event and tick handlers as `main`.
+pub struct TestNode {
+    pub swarm: Swarm<NodeBehaviour>,
+    pub app: App,
+    pub peer_id: PeerId,
+    /// Loopback QUIC address the node listens on, including `/p2p/<peer_id>`.
+    pub addr: Multiaddr,
+    trust_store_path: PathBuf,
+}
+
+impl TestNode {
+    /// Starts a node listening on an ephemeral loopback port. `auto_request` makes it
+    /// send a `SessionRequest` to every peer it connects to; trust on first use is on
+    /// so two fresh nodes can pair.
+    pub async fn start(auto_request: bool) -> Result<Self> {
+        let local_key = identity::Keypair::generate_ed25519();
+        let peer_id = PeerId::from(local_key.public());
+        let trust_store_path =
+            std::env::temp_dir().join(format!("aetherlink-node-e2e-{peer_id}.json"));
+        let mut swarm = build_swarm(
+            local_key.clone(),
+            TEST_AGENT_VERSION,
+            SwarmTuning {
+                idle_connection_timeout: Duration::from_secs(30),
+            },
+            false,
+            None,
+        )
+        .context("build test swarm")?;
+        swarm
+            .listen_on("/ip4/127.0.0.1/udp/0/quic-v1".parse()?)
+            .context("listen on loopback")?;
+        let mut app = App::new(
+            local_key,
+            peer_id,
+            auto_request,
+            trust_store_path.clone(),
+            TrustedPeers::default(),
+            true,
+            2_000,
+            3,
+            Vec::new(),
+            2_500,
+            15_000,
+            false,
+            1_000,
+            1_200,
+            3,
+            0,
+        );
+
+        let addr = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let event = swarm.select_next_some().await;
+                let listen_addr = match &event {
+                    SwarmEvent::NewListenAddr { address, .. } => Some(address.clone()),
+                    _ => None,
+                };
+                handle_swarm_event(&mut swarm, &mut app, event).await?;
+                if let Some(address) = listen_addr {
+                    return Ok::<_, anyhow::Error>(address);
+                }
+            }
+        })
+        .await
+        .context("test node did not start listening")??;
+
+        Ok(Self {
+            swarm,
+            app,
+            peer_id,
+            addr: addr
+                .with_p2p(peer_id)
+                .expect("listen address already names a peer"),
+            trust_store_path,
+        })
+    }
+
+    pub fn dial(&mut self, other: &TestNode) -> Result<()> {
+        self.swarm
+            .dial(other.addr.clone())
+            .with_context(|| format!("dial {}", other.addr))
+    }
+
+    pub fn is_active_with(&self, peer: &PeerId) -> bool {
+        self.app.active_sessions.contains_key(peer)
+    }
+
+    pub fn trusts(&self, peer: &PeerId) -> bool {
+        let peer = peer.to_string();
+        self.app
+            .trusted_peers
+            .to_records()
+            .iter()
+            .any(|record| record.peer_id == peer)
+    }
+}
+
+impl Drop for TestNode {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.trust_store_path);
+    }
+}
+
+/// Runs both nodes' event loops side by side until `done` holds, failing after
+/// `timeout`.
+pub async fn drive_until(
+    a: &mut TestNode,
+    b: &mut TestNode,
+    timeout: Duration,
+    done: impl Fn(&TestNode, &TestNode) -> bool,
+) -> Result<()> {
+    let mut tick = tokio::time::interval(Duration::from_millis(TICK_INTERVAL_MS));
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+    while !done(a, b) {
+        tokio::select! {
+            () = &mut deadline => bail!("nodes did not reach the expected state within {timeout:?}"),
+            _ = tick.tick() => {
+                handle_tick(&mut a.swarm, &mut a.app);
+                handle_tick(&mut b.swarm, &mut b.app);
+            }
+            event = a.swarm.select_next_some() => {
+                handle_swarm_event(&mut a.swarm, &mut a.app, event).await?;
+            }
+            event = b.swarm.select_next_some() => {
+                handle_swarm_event(&mut b.swarm, &mut b.app, event).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use aetherlink_core::ResponderState;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn two_nodes_connect_and_reach_an_active_session() {
+        let mut controller = TestNode::start(true).await.unwrap();
+        let mut target = TestNode::start(false).await.unwrap();
+        let (controller_id, target_id) = (controller.peer_id, target.peer_id);
+
+        controller.dial(&target).unwrap();
+        drive_until(
+            &mut controller,
+            &mut target,
+            Duration::from_secs(20),
+            |controller, target| {
+                controller.is_active_with(&target_id) && target.is_active_with(&controller_id)
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            controller.app.active_sessions[&target_id], target.app.active_sessions[&controller_id],
+            "both sides agree on the session id"
+        );
+        assert!(controller.trusts(&target_id));
+        assert!(target.trusts(&controller_id));
+        assert!(matches!(
+            target.app.responders[&controller_id].state(),
+            ResponderState::Accepted | ResponderState::Active
+        ));
+        assert!(!controller.app.responders.contains_key(&target_id));
+        assert!(controller.trust_store_path.exists());
+        assert!(target.trust_store_path.exists());
+    }
+}
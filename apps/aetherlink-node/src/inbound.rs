@@ -0,0 +1,278 @@
+use std::collections::{HashMap, VecDeque};
+
+use libp2p::PeerId;
+
+/// Bounded per-peer queues drained round-robin, so one chatty peer cannot starve the
+/// others of verification time.
+#[derive(Debug)]
+pub struct InboundRequestQueues<T> {
+    queues: HashMap<PeerId, VecDeque<T>>,
+    rotation: VecDeque<PeerId>,
+    per_peer_capacity: usize,
+}
+
+impl<T> InboundRequestQueues<T> {
+    pub fn new(per_peer_capacity: usize) -> Self {
+        Self {
+            queues: HashMap::new(),
+            rotation: VecDeque::new(),
+            per_peer_capacity: per_peer_capacity.max(1),
+        }
+    }
+
+    /// Queues `item` behind the peer's earlier requests, handing it back when the
+    /// peer already has `per_peer_capacity` requests waiting.
+    pub fn push(&mut self, peer_id: PeerId, item: T) -> Result<(), T> {
+        let queue = self.queues.entry(peer_id).or_default();
+        if queue.len() >= self.per_peer_capacity {
+            return Err(item);
+        }
+        if queue.is_empty() {
+            self.rotation.push_back(peer_id);
+        }
+        queue.push_back(item);
+        Ok(())
+    }
+
+    pub fn remove_peer(&mut self, peer_id: &PeerId) -> usize {
+        self.rotation.retain(|queued| queued != peer_id);
+        self.queues.remove(peer_id).map(|q| q.len()).unwrap_or(0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.values().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rotation.is_empty()
+    }
+
+    /// One request from each peer that had work queued when the round started.
+    pub fn take_round(&mut self) -> Vec<(PeerId, T)> {
+        let peers_in_round = self.rotation.len();
+        (0..peers_in_round)
+            .map_while(|_| next_request_to_process(self))
+            .collect()
+    }
+}
+
+/// Pops the oldest request of the peer at the head of the rotation and moves that peer
+/// to the back if it still has requests waiting.
+pub fn next_request_to_process<T>(queues: &mut InboundRequestQueues<T>) -> Option<(PeerId, T)> {
+    while let Some(peer_id) = queues.rotation.pop_front() {
+        let Some(queue) = queues.queues.get_mut(&peer_id) else {
+            continue;
+        };
+        let Some(item) = queue.pop_front() else {
+            queues.queues.remove(&peer_id);
+            continue;
+        };
+        if queue.is_empty() {
+            queues.queues.remove(&peer_id);
+        } else {
+            queues.rotation.push_back(peer_id);
+        }
+        return Some((peer_id, item));
+    }
+    None
+}
+
+pub const CONTROL_REQUEST_DEDUP_TTL_MS: i64 = 30_000;
+/// Request ids remembered at most; past it the oldest are forgotten even inside the TTL.
+pub const MAX_RECENT_CONTROL_REQUESTS: usize = 1_024;
+
+#[derive(Debug)]
+struct SeenControlRequest {
+    first_seen_unix_ms: i64,
+    response: Option<Vec<u8>>,
+}
+
+/// Control `request_id`s seen per peer within the last `CONTROL_REQUEST_DEDUP_TTL_MS`,
+/// up to `MAX_RECENT_CONTROL_REQUESTS`, with the encoded response once one has been
+/// sent, so a retransmit is answered without running the handler twice.
+#[derive(Debug, Default)]
+pub struct RecentControlRequests {
+    seen: HashMap<(PeerId, String), SeenControlRequest>,
+}
+
+impl RecentControlRequests {
+    pub fn record_response(&mut self, peer_id: PeerId, request_id: &str, payload: Vec<u8>) {
+        if let Some(entry) = self.seen.get_mut(&(peer_id, request_id.to_string())) {
+            entry.response = Some(payload);
+        }
+    }
+
+    pub fn cached_response(&self, peer_id: &PeerId, request_id: &str) -> Option<Vec<u8>> {
+        self.seen
+            .get(&(*peer_id, request_id.to_string()))
+            .and_then(|entry| entry.response.clone())
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    fn evict_expired(&mut self, now_unix_ms: i64) {
+        self.seen.retain(|_, entry| {
+            now_unix_ms.saturating_sub(entry.first_seen_unix_ms) < CONTROL_REQUEST_DEDUP_TTL_MS
+        });
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .seen
+            .iter()
+            .min_by_key(|(_, entry)| entry.first_seen_unix_ms)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            self.seen.remove(&key);
+        }
+    }
+}
+
+/// Records `request_id` from `peer_id` and reports whether it was already seen inside
+/// the dedup window. Requests without an id are never treated as duplicates.
+pub fn is_duplicate_request(
+    seen: &mut RecentControlRequests,
+    peer_id: PeerId,
+    request_id: &str,
+    now_unix_ms: i64,
+) -> bool {
+    seen.evict_expired(now_unix_ms);
+    if request_id.is_empty() {
+        return false;
+    }
+    let key = (peer_id, request_id.to_string());
+    if seen.seen.contains_key(&key) {
+        return true;
+    }
+    while seen.seen.len() >= MAX_RECENT_CONTROL_REQUESTS {
+        seen.evict_oldest();
+    }
+    seen.seen.insert(
+        key,
+        SeenControlRequest {
+            first_seen_unix_ms: now_unix_ms,
+            response: None,
+        },
+    );
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alternates_between_peers() {
+        let chatty = PeerId::random();
+        let quiet = PeerId::random();
+        let mut queues = InboundRequestQueues::new(8);
+        for i in 0..4 {
+            queues.push(chatty, format!("chatty-{i}")).unwrap();
+        }
+        queues.push(quiet, "quiet-0".to_string()).unwrap();
+
+        let order = std::iter::from_fn(|| next_request_to_process(&mut queues))
+            .map(|(_, item)| item)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            vec!["chatty-0", "quiet-0", "chatty-1", "chatty-2", "chatty-3"]
+        );
+        assert_eq!(queues.len(), 0);
+    }
+
+    #[test]
+    fn round_serves_each_peer_at_most_once() {
+        let a = PeerId::random();
+        let b = PeerId::random();
+        let mut queues = InboundRequestQueues::new(8);
+        queues.push(a, 1).unwrap();
+        queues.push(a, 2).unwrap();
+        queues.push(b, 10).unwrap();
+
+        let first = queues.take_round();
+        assert_eq!(first, vec![(a, 1), (b, 10)]);
+
+        // A peer arriving mid-stream joins the next round behind existing work.
+        let c = PeerId::random();
+        queues.push(c, 20).unwrap();
+        assert_eq!(queues.take_round(), vec![(a, 2), (c, 20)]);
+        assert!(queues.take_round().is_empty());
+    }
+
+    #[test]
+    fn per_peer_capacity_is_enforced() {
+        let peer = PeerId::random();
+        let other = PeerId::random();
+        let mut queues = InboundRequestQueues::new(2);
+        queues.push(peer, 1).unwrap();
+        queues.push(peer, 2).unwrap();
+        assert_eq!(queues.push(peer, 3), Err(3));
+        assert_eq!(queues.push(other, 4), Ok(()));
+        assert_eq!(queues.len(), 3);
+    }
+
+    #[test]
+    fn removed_peer_is_skipped() {
+        let gone = PeerId::random();
+        let stays = PeerId::random();
+        let mut queues = InboundRequestQueues::new(4);
+        queues.push(gone, 1).unwrap();
+        queues.push(stays, 2).unwrap();
+        assert_eq!(queues.remove_peer(&gone), 1);
+        assert_eq!(next_request_to_process(&mut queues), Some((stays, 2)));
+        assert_eq!(next_request_to_process(&mut queues), None);
+    }
+
+    #[test]
+    fn duplicate_request_ids_are_detected_per_peer_within_ttl() {
+        let mut seen = RecentControlRequests::default();
+        let a = PeerId::random();
+        let b = PeerId::random();
+        assert!(!is_duplicate_request(&mut seen, a, "req-1", 1_000));
+        assert!(is_duplicate_request(&mut seen, a, "req-1", 2_000));
+        assert!(!is_duplicate_request(&mut seen, b, "req-1", 2_000));
+        assert!(!is_duplicate_request(&mut seen, a, "", 2_000));
+        assert!(!is_duplicate_request(&mut seen, a, "", 2_000));
+
+        let expired = 2_000 + CONTROL_REQUEST_DEDUP_TTL_MS;
+        assert!(!is_duplicate_request(&mut seen, a, "req-1", expired));
+        assert_eq!(seen.len(), 1, "b's entry also aged out");
+    }
+
+    #[test]
+    fn cached_response_is_replayed_for_duplicates() {
+        let mut seen = RecentControlRequests::default();
+        let peer = PeerId::random();
+        assert!(!is_duplicate_request(&mut seen, peer, "req-1", 0));
+        assert_eq!(seen.cached_response(&peer, "req-1"), None);
+
+        seen.record_response(peer, "req-1", vec![1, 2, 3]);
+        seen.record_response(peer, "unknown", vec![9]);
+        assert!(is_duplicate_request(&mut seen, peer, "req-1", 10));
+        assert_eq!(seen.cached_response(&peer, "req-1"), Some(vec![1, 2, 3]));
+        assert_eq!(seen.cached_response(&peer, "unknown"), None);
+    }
+
+    #[test]
+    fn oldest_request_ids_are_forgotten_past_the_cap() {
+        let mut seen = RecentControlRequests::default();
+        let peer = PeerId::random();
+        for i in 0..=MAX_RECENT_CONTROL_REQUESTS {
+            assert!(!is_duplicate_request(
+                &mut seen,
+                peer,
+                &format!("req-{i}"),
+                i as i64
+            ));
+        }
+        assert_eq!(seen.len(), MAX_RECENT_CONTROL_REQUESTS);
+        let newest = format!("req-{MAX_RECENT_CONTROL_REQUESTS}");
+        assert!(is_duplicate_request(&mut seen, peer, &newest, 2_000));
+        assert!(is_duplicate_request(&mut seen, peer, "req-1", 2_000));
+        assert!(!is_duplicate_request(&mut seen, peer, "req-0", 2_000));
+    }
+}
@@ -0,0 +1,36 @@
+/// Fewest milliseconds between two keyframes forced by a peer's `RequestKeyframe`.
+pub const KEYFRAME_REQUEST_MIN_INTERVAL_MS: i64 = 1_000;
+
+/// Whether a keyframe request arriving at `now_ms` may be honoured, given when the last
+/// honoured one arrived. Requests inside the interval are dropped, so a receiver that
+/// keeps losing packets cannot push the encoder into back-to-back keyframes.
+pub fn keyframe_request_allowed(last_ms: Option<i64>, now_ms: i64, min_interval_ms: i64) -> bool {
+    match last_ms {
+        None => true,
+        // A clock that went backwards should not lock requests out until it catches up.
+        Some(last_ms) => now_ms < last_ms || now_ms - last_ms >= min_interval_ms,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_request_is_allowed() {
+        assert!(keyframe_request_allowed(None, 0, 1_000));
+    }
+
+    #[test]
+    fn requests_inside_the_interval_are_dropped() {
+        assert!(!keyframe_request_allowed(Some(5_000), 5_000, 1_000));
+        assert!(!keyframe_request_allowed(Some(5_000), 5_999, 1_000));
+        assert!(keyframe_request_allowed(Some(5_000), 6_000, 1_000));
+        assert!(keyframe_request_allowed(Some(5_000), 60_000, 1_000));
+    }
+
+    #[test]
+    fn clock_going_backwards_does_not_block() {
+        assert!(keyframe_request_allowed(Some(5_000), 4_000, 1_000));
+    }
+}
@@ -1,42 +1,116 @@
 #![forbid(unsafe_code)]
 
+mod admin;
+mod audit_log;
+mod clipboard;
+mod clock;
+mod doctor;
+#[cfg(test)]
+mod harness;
+mod inbound;
+mod keyframe;
+mod relay;
+mod relay_server;
+
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     env, fs,
+    io::Read,
+    net::IpAddr,
     path::{Path, PathBuf},
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use aetherlink_core::{
-    ConnectionState, ConnectionStateMachine, DEFAULT_ALLOWED_SKEW_MS, NonceReplayCache,
-    SessionAuthError, Trigger, TrustedPeerRecord, TrustedPeers, sign_session_accept,
-    sign_session_request, verify_session_accept, verify_session_request,
+    AuthAuditSink, AuthEvent, AuthMessage, AuthThrottle, Compatibility, ConnectionState,
+    ConnectionStateMachine, DEFAULT_REPLAY_MAX_ENTRIES, DEFAULT_REPLAY_RETENTION_MS,
+    DEFAULT_RESUMPTION_TICKET_TTL_MS, MIN_NONCE_BYTES, NonceReplayCache, PROTOCOL_MAJOR,
+    ResponderStateMachine, ResponderTrigger, SessionAuthError, Trigger, TrustStoreFile,
+    TrustedPeers, VerifiedSessionPeer, VerifierContext, VerifierPolicy, inline_public_key,
+    is_compatible, issue_challenge, issue_ticket, local_protocol_version, pairing_code,
+    sign_identity_rotation, sign_session_accept, sign_session_request,
+    verify_challenged_session_accept, verify_challenged_session_request,
+    verify_resumed_session_request, verify_rotation_proof, verify_session_accept,
+    verify_session_request,
+};
+use aetherlink_media::{
+    AssembledFrame, FrameAssembler, FrameChunk, VideoProfile as MediaVideoProfile, agree_profile,
 };
 use aetherlink_proto::v1::{
-    CandidateAnnouncement, CandidateType, ControlEnvelope, DeviceIdentity, NetworkCandidate,
-    Ping as ControlPing, Pong as ControlPong, ProtocolVersion, PunchSync, RejectReason,
-    SessionAccept, SessionClose, SessionReject, SessionRequest, SessionRole,
+    AddTargetResponse, CandidateAnnouncement, CandidateType, Challenge, ClipboardData,
+    ControlEnvelope, DeviceAnnouncement, DeviceIdentity, DiscoveredDevice, GenericAck,
+    IdentityRotationProof, InputEvent, ListSessionsResponse, LookupDevicesResponse,
+    NetworkCandidate, NodeAdminRequest, NodeAdminResponse, NodeGetClipboardResponse,
+    NodeSessionInfo, NodeTakeVideoFramesResponse, NodeVideoFrame, Ping as ControlPing,
+    Pong as ControlPong, PunchSync, RejectReason, RequestKeyframe, ResumptionTicket,
+    RotateIdentityResponse, SessionAccept, SessionClose, SessionReject, SessionRejectDetailCode,
+    SessionRenegotiate, SessionRenegotiateAck, SessionRequest, SessionRole, UpdateNetworkRequest,
+    VideoChunk, VideoProfile, node_admin_request, node_admin_response,
 };
+use aetherlink_proto::{MAX_CONTROL_ENVELOPE_BYTES, try_decode_control};
 use anyhow::{Context, Result, anyhow};
 use clap::{ArgAction, Parser};
 use futures::StreamExt;
 use libp2p::{
-    Multiaddr, PeerId, StreamProtocol, Swarm, SwarmBuilder, identify, identity,
+    Multiaddr, PeerId, StreamProtocol, Swarm, SwarmBuilder, TransportError,
+    core::{ConnectedPoint, Endpoint, transport::ListenerId},
+    identify, identity,
     kad::{self, store::MemoryStore},
-    mdns, ping,
+    mdns, noise, ping, relay as p2p_relay,
     request_response::{self, ProtocolSupport},
-    swarm::NetworkBehaviour,
+    swarm::{NetworkBehaviour, behaviour::toggle::Toggle},
+    upnp, yamux,
 };
 use prost::Message;
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
 use tracing::{info, warn};
 
+use crate::{
+    admin::AdminCommand,
+    clipboard::clipboard_allowed,
+    clock::{Clock, RealClock},
+    inbound::{InboundRequestQueues, RecentControlRequests, is_duplicate_request},
+    keyframe::{KEYFRAME_REQUEST_MIN_INTERVAL_MS, keyframe_request_allowed},
+    relay::RelayReservations,
+    relay_server::{RelayBudget, RelayLimits, SharedRelayBudget},
+};
+
 const CONTROL_PROTOCOL: &str = "/aetherlink/control/1.0.0";
-const PROTOCOL_MAJOR: u32 = 1;
+const VIDEO_PROTOCOL: &str = "/aetherlink/video/1.0.0";
 const TICK_INTERVAL_MS: u64 = 200;
+const IDENTITY_KEY_ENV: &str = "AETHERLINK_IDENTITY_KEY";
 const DEVICE_RECORD_KEY_PREFIX: &str = "/aetherlink/device/v1/";
 const DISCOVERY_DIAL_COOLDOWN_MS: i64 = 2_500;
+const DEFAULT_QUIC_PORT: u16 = 9000;
+const INBOUND_SESSION_REQUESTS_PER_PEER: usize = 4;
+const KEEPALIVE_RTT_WINDOW: usize = 8;
+const BOOTSTRAP_RETRY_BASE_MS: i64 = 5_000;
+const BOOTSTRAP_RETRY_MAX_MS: i64 = 120_000;
+const MIN_IDLE_CONNECTION_TIMEOUT_SECS: u64 = 10;
+const MAX_IDLE_CONNECTION_TIMEOUT_SECS: u64 = 3_600;
+const MIN_RELAY_RESERVATION_TTL_MS: u64 = relay::MIN_RESERVATION_TTL_MS as u64;
+const ADMIN_LOOKUP_TIMEOUT_MS: i64 = 10_000;
+/// How long shutdown waits for peers to acknowledge `SessionClose`.
+const SHUTDOWN_DRAIN_MS: u64 = 2_000;
+/// How often stale trusted peers are looked for when `--trusted-peer-max-age-ms` is set.
+const TRUST_PRUNE_INTERVAL_MS: i64 = 60_000;
+/// Saved next to the trust store so a restart does not reopen a replay window.
+const REPLAY_CACHE_FILE_NAME: &str = "nonce_replay_cache.json";
+/// How often the replay cache is saved while running, so a crash loses at most this
+/// much of it rather than everything since the last clean shutdown.
+const REPLAY_CACHE_SAVE_INTERVAL_MS: i64 = 10_000;
+/// Reassembled video kept per device until the daemon takes it; the oldest goes first.
+const MAX_BUFFERED_VIDEO_BYTES: usize = 32 * 1024 * 1024;
+/// Most this node will encode when a peer renegotiates the session profile.
+const HOST_MAX_VIDEO_PROFILE: MediaVideoProfile = MediaVideoProfile {
+    width: 1280,
+    height: 720,
+    fps: 30,
+    bitrate_kbps: 8_000,
+};
 
 #[derive(Debug, Parser)]
 #[command(
@@ -48,9 +122,9 @@ struct Args {
     #[arg(
         long,
         default_value = "/ip4/0.0.0.0/udp/9000/quic-v1",
-        help = "Listen multiaddr (QUIC)"
+        help = "Listen multiaddr, QUIC (can repeat, e.g. once for IPv4 and once for IPv6)"
     )]
-    listen: Multiaddr,
+    listen: Vec<Multiaddr>,
 
     #[arg(long, help = "Dial peer multiaddr (can repeat)")]
     dial: Vec<Multiaddr>,
@@ -58,6 +132,13 @@ struct Args {
     #[arg(long, help = "Bootstrap peer multiaddr for Kademlia (can repeat)")]
     bootstrap: Vec<Multiaddr>,
 
+    #[arg(
+        long,
+        value_name = "HOST[:PORT][/p2p/PEER_ID]",
+        help = "Bootstrap peer by hostname, resolved via DNS at dial time (can repeat)"
+    )]
+    bootstrap_dns: Vec<String>,
+
     #[arg(
         long,
         default_value_t = false,
@@ -72,12 +153,38 @@ struct Args {
     )]
     agent_version: String,
 
+    #[arg(
+        long,
+        help = "Directory for the identity key and trust store (default: $XDG_CONFIG_HOME/aetherlink or ~/.config/aetherlink)"
+    )]
+    data_dir: Option<PathBuf>,
+
     #[arg(long, help = "Path to persisted local identity key file")]
     identity_file: Option<PathBuf>,
 
+    #[arg(
+        long,
+        default_value_t = false,
+        action = ArgAction::SetTrue,
+        help = "Read the identity key (protobuf, raw or base64) from stdin instead of the identity file"
+    )]
+    identity_stdin: bool,
+
     #[arg(long, help = "Path to trusted peers JSON file")]
     trust_store_file: Option<PathBuf>,
 
+    #[arg(
+        long,
+        help = "Append every session authentication decision to this hash-chained JSON-lines file"
+    )]
+    auth_audit_log: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Check the hash chain of an auth audit log against the head signed by this node's identity key, print the entry count and exit"
+    )]
+    verify_auth_audit_log: Option<PathBuf>,
+
     #[arg(
         long,
         default_value_t = false,
@@ -86,6 +193,27 @@ struct Args {
     )]
     trust_on_first_use: bool,
 
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Forget trusted peers not seen for this long (milliseconds, 0 to disable)"
+    )]
+    trusted_peer_max_age_ms: u64,
+
+    #[arg(
+        long,
+        default_value_t = MIN_NONCE_BYTES,
+        help = "Shortest handshake nonce accepted from peers (bytes, raised to the built-in minimum)"
+    )]
+    min_nonce_bytes: usize,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Close inbound connections from peers not in the trust store (relays and bootstrap nodes excepted); other nodes can then no longer use this one as a relay or DHT server"
+    )]
+    known_peers_only: bool,
+
     #[arg(
         long,
         default_value_t = 1200,
@@ -129,6 +257,14 @@ struct Args {
     )]
     disable_device_record_publish: bool,
 
+    #[arg(
+        long,
+        default_value_t = false,
+        action = ArgAction::SetTrue,
+        help = "Also publish RFC1918/link-local/ULA addresses in the DHT device record (loopback is never published)"
+    )]
+    publish_private_addrs: bool,
+
     #[arg(
         long,
         default_value_t = 1000,
@@ -156,6 +292,115 @@ struct Args {
         help = "Auto send SessionClose after session active duration (0 to disable)"
     )]
     session_auto_close_ms: u64,
+
+    #[arg(
+        long,
+        default_value_t = 300,
+        value_parser = clap::value_parser!(u64)
+            .range(MIN_IDLE_CONNECTION_TIMEOUT_SECS..=MAX_IDLE_CONNECTION_TIMEOUT_SECS),
+        help = "Close connections idle for this long; lower it on mobile where NAT mappings expire fast (seconds)"
+    )]
+    idle_connection_timeout_secs: u64,
+
+    #[arg(
+        long,
+        help = "Relay server multiaddr with /p2p/<peer_id> to hold a reservation on (can repeat)"
+    )]
+    relay: Vec<Multiaddr>,
+
+    #[arg(
+        long,
+        default_value_t = 3_600_000,
+        help = "Assumed relay reservation lifetime used to schedule renewal (milliseconds, at least 360000)"
+    )]
+    relay_reservation_ttl_ms: u64,
+
+    #[arg(
+        long,
+        requires = "admin_token_file",
+        help = "Admin IPC endpoint (unix path on Unix, host:port on Windows); disabled when unset"
+    )]
+    admin_socket: Option<String>,
+
+    #[arg(
+        long,
+        help = "File holding the token admin IPC clients must present first (the daemon's token file)"
+    )]
+    admin_token_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        action = ArgAction::SetTrue,
+        help = "Ask the local router (UPnP IGD) to map the listen port and publish the mapped address"
+    )]
+    enable_upnp: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        action = ArgAction::SetTrue,
+        help = "Run connectivity diagnostics (listen, clock, bootstrap, NAT), print a report and exit"
+    )]
+    doctor: bool,
+
+    #[arg(
+        long,
+        default_value = doctor::DEFAULT_TIME_SERVER,
+        help = "SNTP server (host:port) used by --doctor to check clock skew"
+    )]
+    doctor_time_server: String,
+
+    #[arg(
+        long,
+        default_value_t = 10_000,
+        help = "Time budget for --doctor network checks (milliseconds)"
+    )]
+    doctor_timeout_ms: u64,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        action = ArgAction::SetTrue,
+        help = "Print the effective configuration (defaults, flags and resolved paths) as JSON and exit"
+    )]
+    print_config: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        action = ArgAction::SetTrue,
+        help = "Act as a circuit relay for other peers, within the --relay-max-* limits"
+    )]
+    relay_server: bool,
+
+    #[arg(
+        long,
+        default_value_t = relay_server::DEFAULT_MAX_CIRCUITS,
+        help = "Relay server: maximum concurrent circuits"
+    )]
+    relay_max_circuits: usize,
+
+    #[arg(
+        long,
+        default_value_t = relay_server::DEFAULT_MAX_CIRCUIT_BYTES,
+        help = "Relay server: bytes after which a single circuit is torn down"
+    )]
+    relay_max_circuit_bytes: u64,
+
+    #[arg(
+        long,
+        default_value_t = relay_server::DEFAULT_MAX_CIRCUIT_DURATION_SECS,
+        help = "Relay server: lifetime after which a single circuit is torn down (seconds)"
+    )]
+    relay_max_circuit_duration_secs: u64,
+
+    #[arg(
+        long,
+        default_value_t = relay_server::DEFAULT_BUDGET_BYTES_PER_HOUR,
+        help = "Relay server: relayed bytes committed per hour before new reservations and circuits are refused"
+    )]
+    relay_budget_bytes_per_hour: u64,
 }
 
 #[derive(NetworkBehaviour)]
@@ -166,6 +411,10 @@ struct NodeBehaviour {
     mdns: mdns::tokio::Behaviour,
     kad: kad::Behaviour<MemoryStore>,
     control: request_response::cbor::Behaviour<Vec<u8>, Vec<u8>>,
+    video: request_response::cbor::Behaviour<Vec<u8>, ()>,
+    relay_client: p2p_relay::client::Behaviour,
+    upnp: Toggle<upnp::tokio::Behaviour>,
+    relay_server: Toggle<p2p_relay::Behaviour>,
 }
 
 #[derive(Debug)]
@@ -175,6 +424,10 @@ enum NodeEvent {
     Mdns(mdns::Event),
     Kad(Box<kad::Event>),
     Control(request_response::Event<Vec<u8>, Vec<u8>>),
+    Video(request_response::Event<Vec<u8>, ()>),
+    RelayClient(p2p_relay::client::Event),
+    Upnp(upnp::Event),
+    RelayServer(p2p_relay::Event),
 }
 
 impl From<ping::Event> for NodeEvent {
@@ -207,6 +460,30 @@ impl From<request_response::Event<Vec<u8>, Vec<u8>>> for NodeEvent {
     }
 }
 
+impl From<request_response::Event<Vec<u8>, ()>> for NodeEvent {
+    fn from(value: request_response::Event<Vec<u8>, ()>) -> Self {
+        Self::Video(value)
+    }
+}
+
+impl From<p2p_relay::client::Event> for NodeEvent {
+    fn from(value: p2p_relay::client::Event) -> Self {
+        Self::RelayClient(value)
+    }
+}
+
+impl From<upnp::Event> for NodeEvent {
+    fn from(value: upnp::Event) -> Self {
+        Self::Upnp(value)
+    }
+}
+
+impl From<p2p_relay::Event> for NodeEvent {
+    fn from(value: p2p_relay::Event) -> Self {
+        Self::RelayServer(value)
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt()
@@ -216,39 +493,132 @@ async fn main() -> Result<()> {
         .init();
 
     let args = Args::parse();
+    if args.doctor {
+        return run_doctor(&args).await;
+    }
+    let data_dir = resolve_data_dir(args.data_dir.clone(), &DataDirEnv::from_process());
+    if args.print_config {
+        let config = EffectiveConfig::resolve(&args, &data_dir);
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&config).context("serialize effective config failed")?
+        );
+        return Ok(());
+    }
     let identity_path = args
         .identity_file
-        .unwrap_or_else(|| default_data_dir().join("device.key"));
+        .clone()
+        .unwrap_or_else(|| data_dir.join("device.key"));
     let trust_store_path = args
         .trust_store_file
-        .unwrap_or_else(|| default_data_dir().join("trusted_peers.json"));
-    let local_key =
-        load_or_create_identity_key(&identity_path).context("load/create identity key")?;
+        .clone()
+        .unwrap_or_else(|| data_dir.join("trusted_peers.json"));
+    let inline_identity_key = if args.identity_stdin {
+        let mut bytes = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut bytes)
+            .context("read identity key from stdin failed")?;
+        Some((InlineIdentitySource::Stdin, bytes))
+    } else {
+        env::var(IDENTITY_KEY_ENV)
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(|value| (InlineIdentitySource::Env, value.into_bytes()))
+    };
+    let identity_is_inline = inline_identity_key.is_some();
+    let local_key = load_or_create_identity_key(&identity_path, inline_identity_key)
+        .context("load/create identity key")?;
     let local_peer_id = PeerId::from(local_key.public());
+    if let Some(path) = &args.verify_auth_audit_log {
+        let (entries, _) = audit_log::verify_audit_log(path, &local_key.public())?;
+        println!(
+            "{}: {entries} entries, hash chain intact and matching its signed head",
+            path.display()
+        );
+        return Ok(());
+    }
     let trusted_peers = load_trusted_peers(&trust_store_path).context("load trusted peers")?;
+    let replay_cache_path = trust_store_path.with_file_name(REPLAY_CACHE_FILE_NAME);
+    let nonce_cache = load_replay_cache(&replay_cache_path, unix_ms() as i64)
+        .context("load nonce replay cache")?;
 
     info!(
         "local peer id: {local_peer_id}, identity={}",
         identity_path.display()
     );
+    info!(
+        "local pairing code: {}",
+        pairing_code(&local_key.public().encode_protobuf())
+    );
 
-    let mut swarm = build_swarm(local_key.clone(), &args.agent_version).context("build swarm")?;
-    swarm
-        .listen_on(args.listen.clone())
-        .context("listen on address failed")?;
+    let relay_server_budget = args.relay_server.then(|| {
+        let limits = RelayLimits {
+            max_circuits: args.relay_max_circuits.max(1),
+            max_circuit_bytes: args.relay_max_circuit_bytes,
+            max_circuit_duration: Duration::from_secs(args.relay_max_circuit_duration_secs),
+            budget_bytes_per_window: args.relay_budget_bytes_per_hour,
+            ..RelayLimits::default()
+        };
+        info!("relay server enabled with limits {limits:?}");
+        (
+            limits,
+            Arc::new(Mutex::new(RelayBudget::new(limits, Instant::now()))),
+        )
+    });
+    let mut swarm = build_swarm(
+        local_key.clone(),
+        &args.agent_version,
+        SwarmTuning::from_args(&args),
+        args.enable_upnp,
+        relay_server_budget.as_ref(),
+    )
+    .context("build swarm")?;
+    let listen_results = listen_on_all(&mut swarm, &args.listen);
+    if listen_results.iter().all(|(_, result)| result.is_err()) {
+        return Err(anyhow!("failed to listen on any of {:?}", args.listen));
+    }
 
+    let mut bootstrap_addrs = Vec::new();
     for addr in &args.bootstrap {
         if let Some(peer_id) = extract_peer_id(addr) {
             swarm
                 .behaviour_mut()
                 .kad
                 .add_address(&peer_id, addr.clone());
+            bootstrap_addrs.push(addr.clone());
             info!("added bootstrap peer {peer_id} at {addr}");
         } else {
             warn!("bootstrap address missing /p2p/<peer_id>: {addr}");
         }
     }
 
+    let mut has_bootstrap_peers = !args.bootstrap.is_empty();
+    for spec in &args.bootstrap_dns {
+        let addr = match expand_bootstrap_host(spec) {
+            Ok(addr) => addr,
+            Err(err) => {
+                warn!("ignore invalid --bootstrap-dns '{spec}': {err}");
+                continue;
+            }
+        };
+        bootstrap_addrs.push(addr.clone());
+        if let Some(peer_id) = extract_peer_id(&addr) {
+            swarm
+                .behaviour_mut()
+                .kad
+                .add_address(&peer_id, addr.clone());
+            has_bootstrap_peers = true;
+            info!("added DNS bootstrap peer {peer_id} at {addr}");
+        } else {
+            // Without a peer id the address cannot seed Kademlia directly; dial it and
+            // let identify feed the routing table once the connection is up.
+            info!("dialing DNS bootstrap {addr}");
+            if let Err(err) = swarm.dial(addr.clone()) {
+                warn!("dial failed immediately for DNS bootstrap {addr}: {err}");
+            }
+        }
+    }
+
     let mut app = App::new(
         local_key,
         local_peer_id,
@@ -267,15 +637,40 @@ async fn main() -> Result<()> {
         args.control_keepalive_max_misses,
         args.session_auto_close_ms,
     );
+    app.publish_private_addrs = args.publish_private_addrs;
+    app.trusted_peer_max_age_ms = args.trusted_peer_max_age_ms as i64;
+    app.min_nonce_bytes = args.min_nonce_bytes.max(MIN_NONCE_BYTES);
+    app.known_peers_only = args.known_peers_only;
+    app.identity_path = (!identity_is_inline).then_some(identity_path);
+    app.nonce_cache = nonce_cache;
+    app.replay_cache_path = Some(replay_cache_path);
+    app.relay_server_budget = relay_server_budget.map(|(_, budget)| budget);
+    app.bootstrap_addrs = bootstrap_addrs;
+    if let Some(path) = &args.auth_audit_log {
+        app.auth_audit = Some(audit_log::AuthAuditLog::open(path, app.local_key.clone())?);
+        info!("auth audit log: {}", path.display());
+    }
+    app.relay_reservations.ttl_ms = args
+        .relay_reservation_ttl_ms
+        .max(MIN_RELAY_RESERVATION_TTL_MS) as i64;
+    for addr in &args.relay {
+        if let Some(relay_peer_id) = extract_peer_id(addr) {
+            app.relay_reservations
+                .add_relay(relay_peer_id, addr.clone());
+            info!("relay reservation target {relay_peer_id} at {addr}");
+        } else {
+            warn!("relay address missing /p2p/<peer_id>: {addr}");
+        }
+    }
 
     if !app.connect_device_codes.is_empty() {
         info!(
             "device-code discovery targets: {:?}",
             app.connect_device_codes
         );
-        if args.bootstrap.is_empty() && args.dial.is_empty() {
+        if args.bootstrap.is_empty() && args.bootstrap_dns.is_empty() && args.dial.is_empty() {
             warn!(
-                "device-code discovery configured without --bootstrap/--dial; DHT lookups may fail until some peers are known"
+                "device-code discovery configured without --bootstrap/--bootstrap-dns/--dial; DHT lookups may fail until some peers are known"
             );
         }
     }
@@ -283,12 +678,13 @@ async fn main() -> Result<()> {
         warn!("no auto session trigger set; use --auto-request, --dial, or --connect-device-code");
     }
 
-    if !args.bootstrap.is_empty() {
+    if has_bootstrap_peers {
         match swarm.behaviour_mut().kad.bootstrap() {
             Ok(query_id) => info!("kademlia bootstrap started, query={query_id:?}"),
             Err(err) => warn!("kademlia bootstrap failed to start: {err}"),
         }
     }
+    app.last_bootstrap_attempt_unix_ms = app.now_ms();
 
     for addr in &args.dial {
         info!("dialing {addr}");
@@ -297,107 +693,460 @@ async fn main() -> Result<()> {
         }
     }
 
+    // Keep a sender alive so the admin branch below simply stays pending when no
+    // admin endpoint is configured.
+    let (admin_tx, mut admin_rx) = mpsc::channel::<AdminCommand>(32);
+    if let Some(endpoint) = &args.admin_socket {
+        let token = admin::read_admin_token(args.admin_token_file.as_deref())?;
+        admin::spawn_admin_server(endpoint, token, admin_tx.clone())
+            .await
+            .context("start admin IPC server")?;
+    }
+
     let mut tick = tokio::time::interval(Duration::from_millis(TICK_INTERVAL_MS));
     tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
 
     loop {
         tokio::select! {
-            _ = tick.tick() => {
-                handle_pending_session_timeouts(&mut swarm, &mut app);
-                handle_discovery_tick(&mut swarm, &mut app);
-                handle_control_keepalive_tick(&mut swarm, &mut app);
-                handle_session_lifecycle_tick(&mut swarm, &mut app);
+            () = &mut shutdown => {
+                info!("shutdown requested, closing sessions");
+                break;
             }
-            event = swarm.select_next_some() => {
-                match event {
-                    libp2p::swarm::SwarmEvent::NewListenAddr { address, .. } => {
-                        info!("listening on {address}");
-                        app.note_local_addr(address);
-                    }
-                    libp2p::swarm::SwarmEvent::ConnectionEstablished {
-                        peer_id, endpoint, ..
-                    } => {
-                        info!("connection established with {peer_id} via {endpoint:?}");
-                        app.on_connected(peer_id);
-                        if app.should_send_session_request(peer_id) {
-                            if let Err(err) = send_session_request(&mut swarm, &mut app, peer_id) {
-                                warn!("failed to send SessionRequest to {peer_id}: {err}");
-                            }
-                        }
-                    }
-                    libp2p::swarm::SwarmEvent::ConnectionClosed { peer_id, cause, .. } => {
-                        warn!("connection closed with {peer_id}, cause: {cause:?}");
-                        app.on_disconnected(peer_id);
-                    }
-                    libp2p::swarm::SwarmEvent::Behaviour(event) => {
-                        handle_behaviour_event(&mut swarm, &mut app, event).await?;
-                    }
-                    libp2p::swarm::SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
-                        warn!("outgoing connection error for peer {peer_id:?}: {error}");
-                    }
-                    libp2p::swarm::SwarmEvent::IncomingConnectionError { error, .. } => {
-                        warn!("incoming connection error: {error}");
+            _ = tick.tick() => handle_tick(&mut swarm, &mut app),
+            Some(command) = admin_rx.recv() => match command.request.payload {
+                Some(node_admin_request::Payload::LookupDevices(lookup)) => {
+                    start_admin_device_lookup(
+                        &mut swarm,
+                        &mut app,
+                        lookup.device_codes,
+                        command.reply,
+                    );
+                }
+                payload => {
+                    let request = NodeAdminRequest { payload };
+                    let response = handle_admin_request(&mut swarm, &mut app, request);
+                    if command.reply.send(response).is_err() {
+                        warn!("admin client went away before response was sent");
                     }
-                    _ => {}
                 }
+            },
+            event = swarm.select_next_some() => {
+                handle_swarm_event(&mut swarm, &mut app, event).await?;
             }
         }
     }
+    shutdown_gracefully(&mut swarm, &mut app).await
 }
 
-fn build_swarm(local_key: identity::Keypair, agent_version: &str) -> Result<Swarm<NodeBehaviour>> {
-    let local_peer_id = PeerId::from(local_key.public());
-    let mut kad = kad::Behaviour::new(local_peer_id, MemoryStore::new(local_peer_id));
-    kad.set_mode(Some(kad::Mode::Server));
+/// Periodic work for timeouts, retries, keepalives and republishing.
+fn handle_tick(swarm: &mut Swarm<NodeBehaviour>, app: &mut App) {
+    handle_pending_session_timeouts(swarm, app);
+    handle_inbound_session_requests(swarm, app);
+    handle_bootstrap_watchdog_tick(swarm, app);
+    handle_discovery_tick(swarm, app);
+    handle_control_keepalive_tick(swarm, app);
+    handle_session_lifecycle_tick(swarm, app);
+    handle_relay_reservation_tick(swarm, app);
+    handle_admin_lookup_tick(app);
+    handle_trust_prune_tick(app);
+    handle_replay_cache_save_tick(app);
+    let now_ms = app.now_ms();
+    app.auth_throttle.prune_expired(now_ms);
+}
 
-    let behaviour = NodeBehaviour {
-        ping: ping::Behaviour::new(ping::Config::new()),
-        identify: identify::Behaviour::new(
-            identify::Config::new(agent_version.to_string(), local_key.public())
-                .with_agent_version(agent_version.to_string()),
-        ),
-        mdns: mdns::tokio::Behaviour::new(mdns::Config::default(), local_peer_id)?,
-        kad,
-        control: request_response::cbor::Behaviour::new(
-            [(StreamProtocol::new(CONTROL_PROTOCOL), ProtocolSupport::Full)],
-            request_response::Config::default(),
-        ),
-    };
+async fn handle_swarm_event(
+    swarm: &mut Swarm<NodeBehaviour>,
+    app: &mut App,
+    event: libp2p::swarm::SwarmEvent<NodeEvent>,
+) -> Result<()> {
+    match event {
+        libp2p::swarm::SwarmEvent::NewListenAddr { address, .. } => {
+            info!("listening on {address}");
+            app.note_local_addr(address);
+        }
+        libp2p::swarm::SwarmEvent::ConnectionEstablished {
+            peer_id, endpoint, ..
+        } => {
+            info!("connection established with {peer_id} via {endpoint:?}");
+            if endpoint.is_listener() && !app.accepts_inbound_from(&peer_id) {
+                warn!("closing inbound connection from {peer_id}: not in the trust store");
+                let _ = swarm.disconnect_peer_id(peer_id);
+                return Ok(());
+            }
+            app.on_connected(peer_id, trigger_for_endpoint(&endpoint));
+            if app.should_send_session_request(peer_id)
+                && let Err(err) = send_session_request(swarm, app, peer_id)
+            {
+                warn!("failed to send SessionRequest to {peer_id}: {err}");
+            }
+        }
+        libp2p::swarm::SwarmEvent::ConnectionClosed {
+            peer_id,
+            cause,
+            num_established,
+            ..
+        } => {
+            warn!("connection closed with {peer_id}, cause: {cause:?}");
+            app.on_disconnected(peer_id);
+            if num_established == 0 && app.relay_reservations.note_relay_disconnected(peer_id) {
+                warn!("lost connection to relay {peer_id}, reservation dropped");
+            }
+        }
+        libp2p::swarm::SwarmEvent::ListenerClosed {
+            listener_id,
+            reason,
+            ..
+        } => {
+            if let Some(relay_peer_id) = app.relay_reservations.note_listener_closed(listener_id) {
+                warn!("relay reservation listener closed relay={relay_peer_id} reason={reason:?}");
+            }
+        }
+        libp2p::swarm::SwarmEvent::Behaviour(event) => {
+            handle_behaviour_event(swarm, app, event).await?;
+        }
+        libp2p::swarm::SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
+            warn!("outgoing connection error for peer {peer_id:?}: {error}");
+        }
+        libp2p::swarm::SwarmEvent::IncomingConnectionError { error, .. } => {
+            warn!("incoming connection error: {error}");
+        }
+        _ => {}
+    }
+    Ok(())
+}
 
-    let swarm = SwarmBuilder::with_existing_identity(local_key)
-        .with_tokio()
-        .with_quic()
-        .with_behaviour(|_| behaviour)?
-        .with_swarm_config(|cfg| cfg.with_idle_connection_timeout(Duration::from_secs(300)))
-        .build();
+/// Resolves on SIGTERM (Unix) or Ctrl-C.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = sigterm.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+                return;
+            }
+            Err(err) => warn!("install SIGTERM handler failed: {err}"),
+        }
+    }
+    if let Err(err) = tokio::signal::ctrl_c().await {
+        warn!("install Ctrl-C handler failed: {err}");
+        std::future::pending::<()>().await;
+    }
+}
 
-    Ok(swarm)
+/// Sends `SessionClose` for every active session, persists the trust store and keeps
+/// the swarm running until the closes are acknowledged or `SHUTDOWN_DRAIN_MS` passes.
+async fn shutdown_gracefully(swarm: &mut Swarm<NodeBehaviour>, app: &mut App) -> Result<()> {
+    let sessions: Vec<(PeerId, String)> = app
+        .active_sessions
+        .iter()
+        .map(|(peer, session_id)| (*peer, session_id.clone()))
+        .collect();
+    for (peer, session_id) in sessions {
+        if let Err(err) = send_session_close(swarm, app, peer, &session_id, "shutdown") {
+            warn!("SessionClose on shutdown failed peer={peer}: {err}");
+        }
+    }
+    if let Err(err) = app.persist_trust_store() {
+        warn!("failed to persist trust store: {err}");
+    }
+    if let Err(err) = app.persist_replay_cache() {
+        warn!("failed to persist nonce replay cache: {err}");
+    }
+
+    let drain = async {
+        while app
+            .pending_outbound_control_requests
+            .values()
+            .any(|kind| matches!(kind, OutboundControlRequestKind::SessionClose))
+        {
+            if let libp2p::swarm::SwarmEvent::Behaviour(event) = swarm.select_next_some().await {
+                handle_behaviour_event(swarm, app, event).await?;
+            }
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+    match tokio::time::timeout(Duration::from_millis(SHUTDOWN_DRAIN_MS), drain).await {
+        Ok(result) => result?,
+        Err(_) => warn!("peers did not acknowledge SessionClose within {SHUTDOWN_DRAIN_MS}ms"),
+    }
+    info!("shutdown complete");
+    Ok(())
 }
 
-#[derive(Debug)]
-struct App {
-    local_key: identity::Keypair,
-    local_peer_id: PeerId,
-    local_device_code: String,
-    auto_request: bool,
-    sessions: HashMap<PeerId, ConnectionStateMachine>,
-    pending_outbound_sessions: HashMap<PeerId, PendingOutboundSession>,
-    nonce_cache: NonceReplayCache,
-    trusted_peers: TrustedPeers,
-    trust_store_path: PathBuf,
+async fn run_doctor(args: &Args) -> Result<()> {
+    let mut bootstrap = args.bootstrap.clone();
+    for spec in &args.bootstrap_dns {
+        match expand_bootstrap_host(spec) {
+            Ok(addr) => bootstrap.push(addr),
+            Err(err) => warn!("ignore invalid --bootstrap-dns '{spec}': {err}"),
+        }
+    }
+    let config = doctor::DoctorConfig {
+        listen: args
+            .listen
+            .first()
+            .cloned()
+            .context("no --listen address configured")?,
+        bootstrap,
+        time_server: args.doctor_time_server.clone(),
+        timeout: Duration::from_millis(args.doctor_timeout_ms.max(1_000)),
+        agent_version: args.agent_version.clone(),
+    };
+    let results = doctor::run_doctor(&config)
+        .await
+        .context("run doctor checks")?;
+    print!("{}", doctor::format_report(&results));
+    let failed = results
+        .iter()
+        .filter(|result| result.status == doctor::CheckStatus::Fail)
+        .count();
+    if failed > 0 {
+        return Err(anyhow!("doctor found {failed} failing checks"));
+    }
+    Ok(())
+}
+
+/// What the node would run with after defaults, flags, environment and clamping are
+/// applied. Resolving it touches neither the identity key nor the network.
+#[derive(Debug, Clone, Serialize)]
+struct EffectiveConfig {
+    listen: Vec<String>,
+    dial: Vec<String>,
+    bootstrap: Vec<String>,
+    bootstrap_dns: Vec<String>,
+    relay: Vec<String>,
+    agent_version: String,
+    data_dir: PathBuf,
+    identity_source: &'static str,
+    identity_file: PathBuf,
+    trust_store_file: PathBuf,
+    auth_audit_log: Option<PathBuf>,
     trust_on_first_use: bool,
-    session_request_timeout_ms: i64,
+    trusted_peer_max_age_ms: u64,
+    min_nonce_bytes: usize,
+    known_peers_only: bool,
+    auto_request: bool,
+    session_request_timeout_ms: u64,
     session_request_max_attempts: u32,
     connect_device_codes: Vec<String>,
-    device_lookup_interval_ms: i64,
-    device_record_republish_ms: i64,
-    publish_device_record: bool,
+    device_lookup_interval_ms: u64,
+    device_record_republish_ms: u64,
+    device_record_publish: bool,
+    publish_private_addrs: bool,
+    control_keepalive_interval_ms: u64,
+    control_keepalive_timeout_ms: u64,
+    control_keepalive_max_misses: u32,
+    session_auto_close_ms: u64,
+    idle_connection_timeout_secs: u64,
+    relay_reservation_ttl_ms: u64,
+    admin_socket: Option<String>,
+    enable_upnp: bool,
+    relay_server: bool,
+    relay_max_circuits: usize,
+    relay_max_circuit_bytes: u64,
+    relay_max_circuit_duration_secs: u64,
+    relay_budget_bytes_per_hour: u64,
+}
+
+impl EffectiveConfig {
+    fn resolve(args: &Args, data_dir: &Path) -> Self {
+        let addrs = |addrs: &[Multiaddr]| addrs.iter().map(ToString::to_string).collect();
+        let identity_source = if args.identity_stdin {
+            "stdin"
+        } else if env::var(IDENTITY_KEY_ENV).is_ok_and(|value| !value.trim().is_empty()) {
+            "env"
+        } else {
+            "file"
+        };
+        Self {
+            listen: addrs(&args.listen),
+            dial: addrs(&args.dial),
+            bootstrap: addrs(&args.bootstrap),
+            bootstrap_dns: args.bootstrap_dns.clone(),
+            relay: addrs(&args.relay),
+            agent_version: args.agent_version.clone(),
+            data_dir: data_dir.to_path_buf(),
+            identity_source,
+            identity_file: args
+                .identity_file
+                .clone()
+                .unwrap_or_else(|| data_dir.join("device.key")),
+            trust_store_file: args
+                .trust_store_file
+                .clone()
+                .unwrap_or_else(|| data_dir.join("trusted_peers.json")),
+            auth_audit_log: args.auth_audit_log.clone(),
+            trust_on_first_use: args.trust_on_first_use,
+            trusted_peer_max_age_ms: args.trusted_peer_max_age_ms,
+            min_nonce_bytes: args.min_nonce_bytes.max(MIN_NONCE_BYTES),
+            known_peers_only: args.known_peers_only,
+            auto_request: args.auto_request,
+            session_request_timeout_ms: args.session_request_timeout_ms,
+            session_request_max_attempts: args.session_request_max_attempts,
+            connect_device_codes: args.connect_device_code.clone(),
+            device_lookup_interval_ms: args.device_lookup_interval_ms,
+            device_record_republish_ms: args.device_record_republish_ms,
+            device_record_publish: !args.disable_device_record_publish,
+            publish_private_addrs: args.publish_private_addrs,
+            control_keepalive_interval_ms: args.control_keepalive_interval_ms,
+            control_keepalive_timeout_ms: args.control_keepalive_timeout_ms,
+            control_keepalive_max_misses: args.control_keepalive_max_misses,
+            session_auto_close_ms: args.session_auto_close_ms,
+            idle_connection_timeout_secs: args.idle_connection_timeout_secs,
+            relay_reservation_ttl_ms: args
+                .relay_reservation_ttl_ms
+                .max(MIN_RELAY_RESERVATION_TTL_MS),
+            admin_socket: args.admin_socket.clone(),
+            enable_upnp: args.enable_upnp,
+            relay_server: args.relay_server,
+            relay_max_circuits: args.relay_max_circuits.max(1),
+            relay_max_circuit_bytes: args.relay_max_circuit_bytes,
+            relay_max_circuit_duration_secs: args.relay_max_circuit_duration_secs,
+            relay_budget_bytes_per_hour: args.relay_budget_bytes_per_hour,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SwarmTuning {
+    idle_connection_timeout: Duration,
+}
+
+impl SwarmTuning {
+    fn from_args(args: &Args) -> Self {
+        Self {
+            idle_connection_timeout: Duration::from_secs(args.idle_connection_timeout_secs),
+        }
+    }
+
+    fn apply(self, cfg: libp2p::swarm::Config) -> libp2p::swarm::Config {
+        cfg.with_idle_connection_timeout(self.idle_connection_timeout)
+    }
+}
+
+/// Starts a listener per address. A failing address is logged and skipped so a
+/// dual-stack node still comes up when, say, IPv6 is unavailable.
+fn listen_on_all<B: NetworkBehaviour>(
+    swarm: &mut Swarm<B>,
+    addrs: &[Multiaddr],
+) -> Vec<(
+    Multiaddr,
+    Result<ListenerId, TransportError<std::io::Error>>,
+)> {
+    addrs
+        .iter()
+        .map(|addr| {
+            let result = swarm.listen_on(addr.clone());
+            match &result {
+                Ok(listener_id) => info!("listen requested on {addr}, listener={listener_id:?}"),
+                Err(err) => warn!("listen on {addr} failed: {err}"),
+            }
+            (addr.clone(), result)
+        })
+        .collect()
+}
+
+fn build_swarm(
+    local_key: identity::Keypair,
+    agent_version: &str,
+    tuning: SwarmTuning,
+    enable_upnp: bool,
+    relay_server: Option<&(RelayLimits, SharedRelayBudget)>,
+) -> Result<Swarm<NodeBehaviour>> {
+    let local_peer_id = PeerId::from(local_key.public());
+    let mut kad = kad::Behaviour::new(local_peer_id, MemoryStore::new(local_peer_id));
+    kad.set_mode(Some(kad::Mode::Server));
+
+    let identify = identify::Behaviour::new(
+        identify::Config::new(agent_version.to_string(), local_key.public())
+            .with_agent_version(agent_version.to_string()),
+    );
+    let mdns = mdns::tokio::Behaviour::new(mdns::Config::default(), local_peer_id)?;
+
+    let swarm = SwarmBuilder::with_existing_identity(local_key)
+        .with_tokio()
+        .with_quic()
+        .with_dns()?
+        .with_relay_client(noise::Config::new, yamux::Config::default)?
+        .with_behaviour(|_, relay_client| NodeBehaviour {
+            ping: ping::Behaviour::new(ping::Config::new()),
+            identify,
+            mdns,
+            kad,
+            control: request_response::cbor::Behaviour::new(
+                [(StreamProtocol::new(CONTROL_PROTOCOL), ProtocolSupport::Full)],
+                request_response::Config::default(),
+            ),
+            video: request_response::cbor::Behaviour::new(
+                [(StreamProtocol::new(VIDEO_PROTOCOL), ProtocolSupport::Full)],
+                request_response::Config::default(),
+            ),
+            relay_client,
+            upnp: Toggle::from(enable_upnp.then(upnp::tokio::Behaviour::default)),
+            relay_server: Toggle::from(relay_server.map(|(limits, budget)| {
+                p2p_relay::Behaviour::new(
+                    local_peer_id,
+                    relay_server::relay_server_config(limits, budget),
+                )
+            })),
+        })?
+        .with_swarm_config(|cfg| tuning.apply(cfg))
+        .build();
+
+    Ok(swarm)
+}
+
+#[derive(Debug)]
+struct App {
+    local_key: identity::Keypair,
+    local_peer_id: PeerId,
+    local_device_code: String,
+    /// Where the identity key lives; `None` when it was handed in via env or stdin and
+    /// therefore cannot be rotated by the node.
+    identity_path: Option<PathBuf>,
+    /// Where the nonce replay cache is saved on shutdown; `None` keeps it in memory.
+    replay_cache_path: Option<PathBuf>,
+    auto_request: bool,
+    sessions: HashMap<PeerId, ConnectionStateMachine>,
+    /// Accepting-side lifecycle for peers that sent us a `SessionRequest`.
+    responders: HashMap<PeerId, ResponderStateMachine>,
+    pending_outbound_sessions: HashMap<PeerId, PendingOutboundSession>,
+    nonce_cache: NonceReplayCache,
+    auth_throttle: AuthThrottle,
+    /// Where session authentication decisions are recorded, if anywhere.
+    auth_audit: Option<audit_log::AuthAuditLog>,
+    trusted_peers: TrustedPeers,
+    trust_store_path: PathBuf,
+    trust_on_first_use: bool,
+    min_nonce_bytes: usize,
+    /// Inbound connections from peers outside the trust store are closed on arrival.
+    known_peers_only: bool,
+    /// Trusted peers unseen for longer are pruned; zero keeps them forever.
+    trusted_peer_max_age_ms: i64,
+    last_trust_prune_ms: i64,
+    last_replay_cache_save_ms: i64,
+    session_request_timeout_ms: i64,
+    session_request_max_attempts: u32,
+    connect_device_codes: Vec<String>,
+    device_lookup_interval_ms: i64,
+    device_record_republish_ms: i64,
+    publish_device_record: bool,
+    publish_private_addrs: bool,
     known_local_addrs: Vec<Multiaddr>,
     pending_device_lookup_queries: HashMap<kad::QueryId, String>,
     pending_device_publish_queries: HashSet<kad::QueryId>,
     last_device_lookup_unix_ms: HashMap<String, i64>,
     last_device_record_publish_unix_ms: i64,
     last_peer_dial_unix_ms: HashMap<PeerId, i64>,
+    bootstrap_addrs: Vec<Multiaddr>,
+    last_bootstrap_attempt_unix_ms: i64,
+    bootstrap_retry_interval_ms: i64,
     active_sessions: HashMap<PeerId, String>,
     control_keepalive: HashMap<PeerId, ControlKeepaliveState>,
     control_keepalive_interval_ms: i64,
@@ -405,10 +1154,53 @@ struct App {
     control_keepalive_max_misses: u32,
     pending_outbound_control_requests:
         HashMap<request_response::OutboundRequestId, OutboundControlRequestKind>,
+    /// Suffix keeping the ids of control requests sent in the same millisecond apart;
+    /// the peer drops a repeated id as a retransmit.
+    control_request_counter: u64,
     session_started_unix_ms: HashMap<PeerId, i64>,
     session_auto_close_ms: i64,
     closing_peers: HashSet<PeerId>,
     pending_punch_actions: Vec<PendingPunchAction>,
+    peer_device_codes: HashMap<PeerId, String>,
+    relayed_peers: HashSet<PeerId>,
+    peer_protocols: HashMap<PeerId, HashSet<StreamProtocol>>,
+    peer_reconnects: HashMap<PeerId, u32>,
+    relay_server_budget: Option<SharedRelayBudget>,
+    resumption_tickets: HashMap<PeerId, ResumptionTicket>,
+    relay_reservations: RelayReservations,
+    inbound_session_requests: InboundRequestQueues<QueuedSessionRequest>,
+    recent_control_requests: RecentControlRequests,
+    /// Device codes clipboard contents are exchanged with, in both directions.
+    clipboard_sync_devices: HashSet<String>,
+    /// Latest clipboard contents received per device code.
+    remote_clipboards: HashMap<String, ClipboardData>,
+    /// Reassembles the video chunks each peer sends in its active session.
+    video_assemblers: HashMap<PeerId, FrameAssembler>,
+    /// Reassembled frames per device code, waiting for `TakeVideoFrames`.
+    received_video: HashMap<String, ReceivedVideo>,
+    /// Profile agreed per peer through `SessionRenegotiate`.
+    session_profiles: HashMap<PeerId, MediaVideoProfile>,
+    /// When each peer's last honoured `RequestKeyframe` arrived.
+    last_keyframe_request_ms: HashMap<PeerId, i64>,
+    /// Latest DHT announcement seen per device code, from any lookup.
+    discovered_devices: HashMap<String, DiscoveredDevice>,
+    pending_admin_lookups: Vec<PendingAdminLookup>,
+    clock: Box<dyn Clock>,
+}
+
+/// An admin `lookup_devices` call waiting for its DHT queries.
+#[derive(Debug)]
+struct PendingAdminLookup {
+    device_codes: Vec<String>,
+    started_unix_ms: i64,
+    reply: oneshot::Sender<NodeAdminResponse>,
+}
+
+#[derive(Debug)]
+struct QueuedSessionRequest {
+    request_id: String,
+    request: SessionRequest,
+    channel: request_response::ResponseChannel<Vec<u8>>,
 }
 
 #[derive(Debug, Clone)]
@@ -417,6 +1209,10 @@ struct PendingOutboundSession {
     request_nonces: Vec<Vec<u8>>,
     last_send_unix_ms: i64,
     attempts: u32,
+    /// Challenge from a timestamp-skew reject, taken by the next attempt.
+    challenge: Option<Challenge>,
+    /// Whether an attempt already echoed a challenge; a session retries with one once.
+    challenge_echoed: bool,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -426,6 +1222,52 @@ struct ControlKeepaliveState {
     awaiting_seq: Option<u64>,
     awaiting_since_unix_ms: Option<i64>,
     consecutive_misses: u32,
+    last_rtt_ms: Option<i64>,
+    recent_rtts_ms: VecDeque<i64>,
+    pings_sent: u64,
+    pings_missed: u64,
+}
+
+impl ControlKeepaliveState {
+    /// Mean of the last `KEEPALIVE_RTT_WINDOW` samples.
+    fn smoothed_rtt_ms(&self) -> Option<i64> {
+        if self.recent_rtts_ms.is_empty() {
+            return None;
+        }
+        let sum: i64 = self.recent_rtts_ms.iter().sum();
+        Some((sum as f64 / self.recent_rtts_ms.len() as f64).round() as i64)
+    }
+
+    /// Population variance of the RTT window (ms^2).
+    fn rtt_variance_ms2(&self) -> Option<f64> {
+        if self.recent_rtts_ms.is_empty() {
+            return None;
+        }
+        let n = self.recent_rtts_ms.len() as f64;
+        let mean = self.recent_rtts_ms.iter().sum::<i64>() as f64 / n;
+        Some(
+            self.recent_rtts_ms
+                .iter()
+                .map(|rtt| (*rtt as f64 - mean).powi(2))
+                .sum::<f64>()
+                / n,
+        )
+    }
+
+    /// Jitter as the standard deviation of the RTT window, in milliseconds.
+    fn rtt_jitter_ms(&self) -> Option<i64> {
+        self.rtt_variance_ms2()
+            .map(|variance| variance.sqrt().round() as i64)
+    }
+}
+
+fn update_rtt(state: &mut ControlKeepaliveState, rtt_ms: i64) {
+    let rtt_ms = rtt_ms.max(0);
+    state.last_rtt_ms = Some(rtt_ms);
+    if state.recent_rtts_ms.len() == KEEPALIVE_RTT_WINDOW {
+        state.recent_rtts_ms.pop_front();
+    }
+    state.recent_rtts_ms.push_back(rtt_ms);
 }
 
 #[derive(Debug, Clone)]
@@ -435,6 +1277,8 @@ enum OutboundControlRequestKind {
     SessionClose,
     CandidateAnnouncement,
     PunchSync,
+    ClipboardData,
+    InputEvent,
 }
 
 #[derive(Debug, Clone)]
@@ -446,16 +1290,18 @@ struct PendingPunchAction {
     attempt_index: u32,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct DeviceAnnouncementV1 {
-    version: u32,
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SessionInfo {
+    peer_id: PeerId,
     device_code: String,
-    peer_id: String,
-    addrs: Vec<String>,
-    unix_ms: i64,
+    state: String,
+    session_id: Option<String>,
+    keepalive_rtt_ms: Option<i64>,
+    health_score: u8,
 }
 
 impl App {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         local_key: identity::Keypair,
         local_peer_id: PeerId,
@@ -487,38 +1333,87 @@ impl App {
             local_key,
             local_peer_id,
             local_device_code: local_peer_id.to_string(),
+            identity_path: None,
+            replay_cache_path: None,
             auto_request,
             sessions: HashMap::new(),
+            responders: HashMap::new(),
             pending_outbound_sessions: HashMap::new(),
             nonce_cache: NonceReplayCache::default(),
+            auth_throttle: AuthThrottle::default(),
+            auth_audit: None,
             trusted_peers,
             trust_store_path,
             trust_on_first_use,
+            min_nonce_bytes: MIN_NONCE_BYTES,
+            known_peers_only: false,
+            trusted_peer_max_age_ms: 0,
+            last_trust_prune_ms: 0,
+            last_replay_cache_save_ms: 0,
             session_request_timeout_ms: session_request_timeout_ms.max(100) as i64,
             session_request_max_attempts: session_request_max_attempts.max(1),
             connect_device_codes,
             device_lookup_interval_ms: device_lookup_interval_ms.max(500) as i64,
             device_record_republish_ms: device_record_republish_ms.max(2_000) as i64,
             publish_device_record,
+            publish_private_addrs: false,
             known_local_addrs: Vec::new(),
             pending_device_lookup_queries: HashMap::new(),
             pending_device_publish_queries: HashSet::new(),
             last_device_lookup_unix_ms: HashMap::new(),
             last_device_record_publish_unix_ms: 0,
             last_peer_dial_unix_ms: HashMap::new(),
+            bootstrap_addrs: Vec::new(),
+            last_bootstrap_attempt_unix_ms: 0,
+            bootstrap_retry_interval_ms: BOOTSTRAP_RETRY_BASE_MS,
             active_sessions: HashMap::new(),
             control_keepalive: HashMap::new(),
             control_keepalive_interval_ms: control_keepalive_interval_ms.max(300) as i64,
             control_keepalive_timeout_ms: control_keepalive_timeout_ms.max(500) as i64,
             control_keepalive_max_misses: control_keepalive_max_misses.max(1),
             pending_outbound_control_requests: HashMap::new(),
+            control_request_counter: 0,
             session_started_unix_ms: HashMap::new(),
             session_auto_close_ms: session_auto_close_ms as i64,
             closing_peers: HashSet::new(),
             pending_punch_actions: Vec::new(),
+            peer_device_codes: HashMap::new(),
+            relayed_peers: HashSet::new(),
+            peer_protocols: HashMap::new(),
+            peer_reconnects: HashMap::new(),
+            relay_server_budget: None,
+            resumption_tickets: HashMap::new(),
+            relay_reservations: RelayReservations::default(),
+            inbound_session_requests: InboundRequestQueues::new(INBOUND_SESSION_REQUESTS_PER_PEER),
+            recent_control_requests: RecentControlRequests::default(),
+            clipboard_sync_devices: HashSet::new(),
+            remote_clipboards: HashMap::new(),
+            video_assemblers: HashMap::new(),
+            received_video: HashMap::new(),
+            session_profiles: HashMap::new(),
+            last_keyframe_request_ms: HashMap::new(),
+            discovered_devices: HashMap::new(),
+            pending_admin_lookups: Vec::new(),
+            clock: Box::new(RealClock),
         }
     }
 
+    #[cfg(test)]
+    fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    fn now_ms(&self) -> i64 {
+        self.clock.now_unix_ms()
+    }
+
+    /// A control `request_id` unique for this node run, e.g. `input-1700000000000-7`.
+    fn next_control_request_id(&mut self, kind: &str) -> String {
+        self.control_request_counter = self.control_request_counter.wrapping_add(1);
+        format!("{kind}-{}-{}", self.now_ms(), self.control_request_counter)
+    }
+
     fn note_local_addr(&mut self, addr: Multiaddr) {
         if !self
             .known_local_addrs
@@ -529,6 +1424,47 @@ impl App {
         }
     }
 
+    /// Relays and bootstrap nodes are plain libp2p infrastructure and need not speak the
+    /// AetherLink control protocol, so the identify-stage gate leaves them connected.
+    fn is_infrastructure_peer(&self, peer_id: &PeerId) -> bool {
+        self.relay_reservations.by_relay.contains_key(peer_id)
+            || self
+                .bootstrap_addrs
+                .iter()
+                .any(|addr| extract_peer_id(addr).as_ref() == Some(peer_id))
+    }
+
+    /// Under `--known-peers-only`, decides from the PeerId alone whether an inbound
+    /// connection may stay, before it has sent any control message.
+    fn accepts_inbound_from(&self, peer_id: &PeerId) -> bool {
+        !self.known_peers_only
+            || self.trusted_peers.get_by_peer_id(peer_id).is_some()
+            || self.is_infrastructure_peer(peer_id)
+    }
+
+    fn forget_local_addr(&mut self, addr: &Multiaddr) {
+        self.known_local_addrs.retain(|existing| existing != addr);
+    }
+
+    /// Adds a device-code target at runtime; targets imply auto-request, since the only
+    /// reason to look a device up is to open a session with it.
+    fn add_connect_target(&mut self, device_code: &str) -> bool {
+        let device_code = device_code.trim();
+        if device_code.is_empty()
+            || device_code == self.local_device_code
+            || self
+                .connect_device_codes
+                .iter()
+                .any(|code| code == device_code)
+        {
+            return false;
+        }
+        self.connect_device_codes.push(device_code.to_string());
+        self.connect_device_codes.sort();
+        self.auto_request = true;
+        true
+    }
+
     fn should_auto_request_for_peer(&self, peer_id: PeerId) -> bool {
         if !self.auto_request {
             return false;
@@ -536,9 +1472,13 @@ impl App {
         if self.connect_device_codes.is_empty() {
             return true;
         }
+        let trusted_code = self
+            .trusted_peers
+            .get_by_peer_id(&peer_id)
+            .map(|record| record.device_code.as_str());
         self.connect_device_codes
             .iter()
-            .any(|code| code == &peer_id.to_string())
+            .any(|code| code == &peer_id.to_string() || Some(code.as_str()) == trusted_code)
     }
 
     fn should_send_session_request(&self, peer_id: PeerId) -> bool {
@@ -559,15 +1499,72 @@ impl App {
     fn set_active_session(&mut self, peer_id: PeerId, session_id: String) {
         self.active_sessions.insert(peer_id, session_id);
         self.control_keepalive.entry(peer_id).or_default();
-        self.session_started_unix_ms
-            .insert(peer_id, unix_ms() as i64);
+        let now_unix_ms = self.now_ms();
+        self.session_started_unix_ms.insert(peer_id, now_unix_ms);
         self.closing_peers.remove(&peer_id);
     }
 
+    /// Revokes or restores trust in `device_code` and persists the change. Returns the
+    /// connected peers that presented the revoked code, which the caller disconnects.
+    fn set_device_revoked(&mut self, device_code: &str, revoked: bool) -> Result<Vec<PeerId>> {
+        let peers = if revoked {
+            self.trusted_peers.revoke(device_code);
+            self.connect_device_codes.retain(|code| code != device_code);
+            let peers: Vec<PeerId> = self
+                .peer_device_codes
+                .iter()
+                .filter(|(_, code)| code.as_str() == device_code)
+                .map(|(peer_id, _)| *peer_id)
+                .collect();
+            // Tickets it issued us are no use once we refuse to talk to it.
+            for peer_id in &peers {
+                self.resumption_tickets.remove(peer_id);
+            }
+            peers
+        } else {
+            self.trusted_peers.clear_revocation(device_code);
+            Vec::new()
+        };
+        self.persist_trust_store()?;
+        Ok(peers)
+    }
+
+    /// Trusts `peer_id` under `device_code` on the operator's approval and persists the
+    /// change. Returns whether the identity was new.
+    fn approve_identity(&mut self, device_code: &str, peer_id: &PeerId) -> Result<bool> {
+        let pubkey = inline_public_key(peer_id)
+            .ok_or_else(|| anyhow!("peer id {peer_id} does not embed its public key"))?;
+        let added = self.trusted_peers.add_identity(
+            device_code,
+            peer_id,
+            &pubkey.encode_protobuf(),
+            self.now_ms(),
+        )?;
+        if added {
+            self.persist_trust_store()?;
+        }
+        Ok(added)
+    }
+
+    /// The peer and session id of the active session with `device_code`, if any.
+    fn active_session_for_device(&self, device_code: &str) -> Option<(PeerId, String)> {
+        self.peer_device_codes
+            .iter()
+            .filter(|(_, code)| code.as_str() == device_code)
+            .find_map(|(peer_id, _)| {
+                self.active_sessions
+                    .get(peer_id)
+                    .map(|session_id| (*peer_id, session_id.clone()))
+            })
+    }
+
     fn clear_active_session(&mut self, peer_id: PeerId) {
         self.active_sessions.remove(&peer_id);
         self.control_keepalive.remove(&peer_id);
         self.session_started_unix_ms.remove(&peer_id);
+        self.last_keyframe_request_ms.remove(&peer_id);
+        self.session_profiles.remove(&peer_id);
+        self.video_assemblers.remove(&peer_id);
     }
 
     fn mark_graceful_closing(&mut self, peer_id: PeerId) {
@@ -575,9 +1572,7 @@ impl App {
     }
 
     fn note_control_pong(&mut self, peer_id: PeerId, pong: &ControlPong) -> Option<i64> {
-        let Some(active_session_id) = self.active_sessions.get(&peer_id) else {
-            return None;
-        };
+        let active_session_id = self.active_sessions.get(&peer_id)?;
         if active_session_id != &pong.session_id {
             warn!(
                 "ignore Pong with mismatched session id from peer={peer_id}: expected={}, got={}",
@@ -585,9 +1580,7 @@ impl App {
             );
             return None;
         }
-        let Some(state) = self.control_keepalive.get_mut(&peer_id) else {
-            return None;
-        };
+        let state = self.control_keepalive.get_mut(&peer_id)?;
         if let Some(expected_seq) = state.awaiting_seq
             && expected_seq != pong.seq
         {
@@ -600,7 +1593,12 @@ impl App {
         state.awaiting_seq = None;
         state.awaiting_since_unix_ms = None;
         state.consecutive_misses = 0;
-        Some((unix_ms() as i64).saturating_sub(pong.echo_send_unix_ms as i64))
+        let rtt_ms = self
+            .clock
+            .now_unix_ms()
+            .saturating_sub(pong.echo_send_unix_ms as i64);
+        update_rtt(state, rtt_ms);
+        Some(rtt_ms)
     }
 
     fn note_keepalive_send_failure(&mut self, peer_id: PeerId, seq: u64) -> bool {
@@ -640,6 +1638,7 @@ impl App {
                     state.awaiting_since_unix_ms = None;
                     state.awaiting_seq = None;
                     state.consecutive_misses = state.consecutive_misses.saturating_add(1);
+                    state.pings_missed = state.pings_missed.saturating_add(1);
                     warn!(
                         "control keepalive timeout peer={peer_id} misses={}",
                         state.consecutive_misses
@@ -663,6 +1662,7 @@ impl App {
             state.last_send_unix_ms = now_unix_ms;
             state.awaiting_seq = Some(seq);
             state.awaiting_since_unix_ms = Some(now_unix_ms);
+            state.pings_sent = state.pings_sent.saturating_add(1);
             send_actions.push((peer_id, session_id, seq));
         }
 
@@ -712,36 +1712,62 @@ impl App {
         if self.pending_outbound_sessions.contains_key(&peer_id) {
             return false;
         }
-        if let Some(last) = self.last_peer_dial_unix_ms.get(&peer_id) {
-            if unix_ms() as i64 - *last < DISCOVERY_DIAL_COOLDOWN_MS {
-                return false;
-            }
-        }
-        true
+        !self.discovery_dial_throttled(peer_id)
     }
 
-    fn mark_discovery_dial_attempt(&mut self, peer_id: PeerId) {
+    fn discovery_dial_throttled(&self, peer_id: PeerId) -> bool {
         self.last_peer_dial_unix_ms
-            .insert(peer_id, unix_ms() as i64);
+            .get(&peer_id)
+            .is_some_and(|last| self.now_ms().saturating_sub(*last) < DISCOVERY_DIAL_COOLDOWN_MS)
+    }
+
+    fn mark_discovery_dial_attempt(&mut self, peer_id: PeerId) {
+        let now_unix_ms = self.now_ms();
+        self.last_peer_dial_unix_ms.insert(peer_id, now_unix_ms);
     }
 
-    fn on_connected(&mut self, peer_id: PeerId) {
+    /// Walks the session state machine along the path the connection actually took;
+    /// `path` is one of `DirectConnected`, `PunchConnected` or `RelayConnected`.
+    fn on_connected(&mut self, peer_id: PeerId, path: Trigger) {
+        if self.sessions.get(&peer_id).is_some_and(|sm| {
+            matches!(
+                sm.state(),
+                ConnectionState::Reconnecting
+                    | ConnectionState::Closed
+                    | ConnectionState::Failed(_)
+            )
+        }) {
+            *self.peer_reconnects.entry(peer_id).or_default() += 1;
+        }
+        if path == Trigger::RelayConnected {
+            self.relayed_peers.insert(peer_id);
+        }
         let entry = self.sessions.entry(peer_id).or_default();
         let _ = entry.apply(Trigger::StartConnect);
         let _ = entry.apply(Trigger::CandidatesFound);
-        let _ = entry.apply(Trigger::DirectConnected);
+        if matches!(path, Trigger::PunchConnected | Trigger::RelayConnected) {
+            let _ = entry.apply(Trigger::DirectNoSuccess);
+        }
+        if path == Trigger::RelayConnected {
+            let _ = entry.apply(Trigger::PunchTimeout);
+        }
+        let _ = entry.apply(path);
     }
 
     fn on_disconnected(&mut self, peer_id: PeerId) {
         let graceful = self.closing_peers.contains(&peer_id);
         self.pending_outbound_sessions.remove(&peer_id);
+        self.inbound_session_requests.remove_peer(&peer_id);
+        self.relayed_peers.remove(&peer_id);
+        self.peer_protocols.remove(&peer_id);
         self.clear_active_session(peer_id);
         self.closing_peers.remove(&peer_id);
+        self.on_responder(peer_id, ResponderTrigger::SessionClosed);
         if let Some(sm) = self.sessions.get_mut(&peer_id) {
             if graceful {
                 let _ = sm.apply(Trigger::UserHangup);
             } else {
-                let _ = sm.apply(Trigger::PathLost);
+                let _ = sm.apply_with_rng(Trigger::PathLost, &mut rand::rng());
             }
         }
     }
@@ -753,9 +1779,37 @@ impl App {
         }
     }
 
+    /// Feeds the responder state machine. Only a new request starts tracking a peer;
+    /// other triggers for untracked peers are ignored.
+    fn on_responder(&mut self, peer_id: PeerId, trigger: ResponderTrigger) {
+        let rsm = if trigger == ResponderTrigger::RequestReceived {
+            self.responders.entry(peer_id).or_default()
+        } else {
+            match self.responders.get_mut(&peer_id) {
+                Some(rsm) => rsm,
+                None => return,
+            }
+        };
+        let _ = rsm.apply(trigger);
+    }
+
     fn on_auth_failed(&mut self, peer_id: PeerId) {
         if let Some(sm) = self.sessions.get_mut(&peer_id) {
-            let _ = sm.apply(Trigger::AuthFailed);
+            let _ = sm.apply_with_rng(Trigger::AuthFailed, &mut rand::rng());
+        }
+    }
+
+    fn on_version_mismatch(&mut self, peer_id: PeerId) {
+        if let Some(sm) = self.sessions.get_mut(&peer_id) {
+            let _ = sm.apply(Trigger::VersionMismatch);
+        }
+    }
+
+    fn verifier_policy(&self) -> VerifierPolicy {
+        VerifierPolicy {
+            min_nonce_bytes: self.min_nonce_bytes,
+            trust_on_first_use: self.trust_on_first_use,
+            ..VerifierPolicy::default()
         }
     }
 
@@ -763,6 +1817,13 @@ impl App {
         save_trusted_peers(&self.trust_store_path, &self.trusted_peers)
     }
 
+    fn persist_replay_cache(&self) -> Result<()> {
+        match &self.replay_cache_path {
+            Some(path) => save_replay_cache(path, &self.nonce_cache),
+            None => Ok(()),
+        }
+    }
+
     fn collect_pending_retry_actions(&self, now_unix_ms: i64) -> (Vec<PeerId>, Vec<PeerId>) {
         let mut retry = Vec::new();
         let mut fail = Vec::new();
@@ -799,6 +1860,30 @@ async fn handle_behaviour_event(
         NodeEvent::Identify(ev) => {
             if let identify::Event::Received { peer_id, info, .. } = *ev {
                 info!("identify from {peer_id}: protocols={:?}", info.protocols);
+                let protocols: HashSet<StreamProtocol> = info.protocols.iter().cloned().collect();
+                if let Some(previous) = app.peer_protocols.insert(peer_id, protocols.clone())
+                    && protocols_changed(&previous, &protocols)
+                {
+                    info!("identify protocols changed for {peer_id}, re-checking compatibility");
+                }
+                let action = identify_gate_action(
+                    is_compatible_peer(&info.protocols, &info.agent_version),
+                    app.is_infrastructure_peer(&peer_id),
+                    app.active_sessions.contains_key(&peer_id),
+                );
+                if action != IdentifyGateAction::Keep {
+                    warn!(
+                        "disconnecting incompatible peer {peer_id}: agent={} lacks {CONTROL_PROTOCOL} or protocol major {PROTOCOL_MAJOR}",
+                        info.agent_version
+                    );
+                    if action == IdentifyGateAction::CloseSessionAndDisconnect
+                        && let Some(session_id) = app.active_sessions.get(&peer_id).cloned()
+                    {
+                        send_session_close(swarm, app, peer_id, &session_id, "peer_incompatible")?;
+                    }
+                    let _ = swarm.disconnect_peer_id(peer_id);
+                    return Ok(());
+                }
                 for addr in info.listen_addrs {
                     swarm.behaviour_mut().kad.add_address(&peer_id, addr);
                 }
@@ -867,6 +1952,12 @@ async fn handle_behaviour_event(
                 Some(OutboundControlRequestKind::PunchSync) => {
                     warn!("PunchSync outbound failed peer={peer}");
                 }
+                Some(OutboundControlRequestKind::ClipboardData) => {
+                    warn!("ClipboardData outbound failed peer={peer}");
+                }
+                Some(OutboundControlRequestKind::InputEvent) => {
+                    warn!("InputEvent outbound failed peer={peer}");
+                }
                 None => {
                     warn!("unknown outbound control request failed req={request_id:?}");
                 }
@@ -892,28 +1983,129 @@ async fn handle_behaviour_event(
         }) => {
             warn!("ping failed peer={peer} err={err}");
         }
+        NodeEvent::RelayClient(p2p_relay::client::Event::ReservationReqAccepted {
+            relay_peer_id,
+            renewal,
+            ..
+        }) => {
+            let now_unix_ms = app.now_ms();
+            if app
+                .relay_reservations
+                .note_accepted(relay_peer_id, now_unix_ms)
+            {
+                info!("relay reservation accepted relay={relay_peer_id} renewal={renewal}");
+                if let Some(reservation) = app.relay_reservations.by_relay.get(&relay_peer_id) {
+                    let circuit_addr = relay::relay_circuit_addr(
+                        &relay_peer_id,
+                        &reservation.relay_addr,
+                        &app.local_peer_id,
+                    );
+                    app.note_local_addr(circuit_addr);
+                }
+            }
+        }
+        NodeEvent::RelayClient(other) => {
+            info!("relay client event: {other:?}");
+        }
+        NodeEvent::Upnp(event) => match upnp_external_addr_change(&event) {
+            Some(UpnpAddrChange::Mapped(addr)) => {
+                info!("UPnP mapped external address {addr}");
+                app.note_local_addr(addr);
+            }
+            Some(UpnpAddrChange::Expired(addr)) => {
+                warn!("UPnP mapping expired for {addr}");
+                app.forget_local_addr(&addr);
+            }
+            None => warn!("UPnP port mapping unavailable: {event:?}"),
+        },
+        NodeEvent::Video(request_response::Event::Message {
+            peer,
+            message:
+                request_response::Message::Request {
+                    request, channel, ..
+                },
+            ..
+        }) => {
+            match VideoChunk::decode(request.as_slice()) {
+                Ok(chunk) => {
+                    if let Err(reason) = handle_video_chunk(app, peer, chunk) {
+                        warn!("dropping VideoChunk from peer={peer}: {reason}");
+                    }
+                }
+                Err(err) => warn!("dropping undecodable VideoChunk from peer={peer}: {err}"),
+            }
+            let _ = swarm.behaviour_mut().video.send_response(channel, ());
+        }
+        NodeEvent::Video(_) => {}
+        NodeEvent::RelayServer(event) => handle_relay_server_event(app, event),
     }
     Ok(())
 }
 
+fn handle_relay_server_event(app: &App, event: p2p_relay::Event) {
+    let Some(budget) = &app.relay_server_budget else {
+        return;
+    };
+    let mut budget = budget.lock().unwrap_or_else(|err| err.into_inner());
+    match event {
+        p2p_relay::Event::CircuitReqAccepted {
+            src_peer_id,
+            dst_peer_id,
+        } => {
+            budget.note_circuit_opened(Instant::now());
+            info!(
+                "relaying circuit {src_peer_id} -> {dst_peer_id}, active={}",
+                budget.active_circuits()
+            );
+        }
+        p2p_relay::Event::CircuitClosed {
+            src_peer_id,
+            dst_peer_id,
+            error,
+        } => {
+            budget.note_circuit_closed();
+            info!(
+                "relay circuit {src_peer_id} -> {dst_peer_id} closed error={error:?}, active={}",
+                budget.active_circuits()
+            );
+        }
+        p2p_relay::Event::ReservationReqDenied { src_peer_id, .. } => {
+            warn!("relay reservation from {src_peer_id} denied (limits or budget)");
+        }
+        p2p_relay::Event::CircuitReqDenied {
+            src_peer_id,
+            dst_peer_id,
+            ..
+        } => {
+            warn!("relay circuit {src_peer_id} -> {dst_peer_id} denied (limits or budget)");
+        }
+        other => info!("relay server event: {other:?}"),
+    }
+}
+
 fn send_session_request(
     swarm: &mut Swarm<NodeBehaviour>,
     app: &mut App,
     peer_id: PeerId,
 ) -> Result<()> {
-    let now_unix_ms = unix_ms() as i64;
+    let now_unix_ms = app.now_ms();
     let session_id = app
         .pending_outbound_sessions
         .get(&peer_id)
         .map(|p| p.session_id.clone())
         .unwrap_or_else(|| format!("session-{now_unix_ms}"));
     let request_nonce = random_nonce(16);
+    let challenge = app
+        .pending_outbound_sessions
+        .get_mut(&peer_id)
+        .and_then(|pending| pending.challenge.take());
     let req = build_session_request(
         app,
         peer_id,
         &session_id,
         request_nonce.clone(),
         now_unix_ms,
+        challenge,
     )?;
 
     let env = ControlEnvelope {
@@ -936,6 +2128,8 @@ fn send_session_request(
             request_nonces: Vec::new(),
             last_send_unix_ms: 0,
             attempts: 0,
+            challenge: None,
+            challenge_echoed: false,
         });
     pending.last_send_unix_ms = now_unix_ms;
     pending.attempts = pending.attempts.saturating_add(1);
@@ -957,6 +2151,7 @@ fn build_session_request(
     session_id: &str,
     request_nonce: Vec<u8>,
     now_unix_ms: i64,
+    challenge: Option<Challenge>,
 ) -> Result<SessionRequest> {
     let mut req = SessionRequest {
         session_id: session_id.to_string(),
@@ -975,24 +2170,26 @@ fn build_session_request(
         nonce: request_nonce,
         unix_ms: now_unix_ms,
         signature: Vec::new(),
-        version: Some(ProtocolVersion {
-            major: PROTOCOL_MAJOR,
-            minor: 0,
-            patch: 0,
-        }),
+        version: Some(local_protocol_version()),
         feature_bits: vec![
             "pairing.confirm.v1".to_string(),
             "file.transfer.v1".to_string(),
             "clipboard.sync.v1".to_string(),
             "recording.v1".to_string(),
         ],
+        resumption_ticket: app
+            .resumption_tickets
+            .get(&peer_id)
+            .filter(|ticket| ticket.expires_unix_ms > now_unix_ms)
+            .cloned(),
+        challenge,
     };
     sign_session_request(&mut req, &app.local_key).context("sign SessionRequest")?;
     Ok(req)
 }
 
 fn handle_pending_session_timeouts(swarm: &mut Swarm<NodeBehaviour>, app: &mut App) {
-    let now_unix_ms = unix_ms() as i64;
+    let now_unix_ms = app.now_ms();
     let (retry_peers, fail_peers) = app.collect_pending_retry_actions(now_unix_ms);
 
     for peer_id in retry_peers {
@@ -1009,6 +2206,64 @@ fn handle_pending_session_timeouts(swarm: &mut Swarm<NodeBehaviour>, app: &mut A
     }
 }
 
+/// Re-seeds Kademlia from the configured bootstrap addresses and re-runs `bootstrap()`
+/// while the routing table stays empty, doubling the retry interval up to
+/// `BOOTSTRAP_RETRY_MAX_MS`. The interval resets once the table has entries again.
+fn handle_bootstrap_watchdog_tick(swarm: &mut Swarm<NodeBehaviour>, app: &mut App) {
+    if app.bootstrap_addrs.is_empty() {
+        return;
+    }
+    let routing_table_len = swarm
+        .behaviour_mut()
+        .kad
+        .kbuckets()
+        .map(|bucket| bucket.num_entries())
+        .sum::<usize>();
+    if routing_table_len > 0 {
+        app.bootstrap_retry_interval_ms = BOOTSTRAP_RETRY_BASE_MS;
+        return;
+    }
+    let now_unix_ms = app.now_ms();
+    if !should_rebootstrap(
+        routing_table_len,
+        app.last_bootstrap_attempt_unix_ms,
+        now_unix_ms,
+        app.bootstrap_retry_interval_ms,
+    ) {
+        return;
+    }
+
+    app.last_bootstrap_attempt_unix_ms = now_unix_ms;
+    app.bootstrap_retry_interval_ms =
+        (app.bootstrap_retry_interval_ms * 2).min(BOOTSTRAP_RETRY_MAX_MS);
+    for addr in &app.bootstrap_addrs {
+        if let Some(peer_id) = extract_peer_id(addr) {
+            swarm
+                .behaviour_mut()
+                .kad
+                .add_address(&peer_id, addr.clone());
+        } else if let Err(err) = swarm.dial(addr.clone()) {
+            warn!("bootstrap watchdog dial failed for {addr}: {err}");
+        }
+    }
+    match swarm.behaviour_mut().kad.bootstrap() {
+        Ok(query_id) => warn!(
+            "routing table empty, restarted kademlia bootstrap query={query_id:?}, next retry in {}ms",
+            app.bootstrap_retry_interval_ms
+        ),
+        Err(err) => warn!("kademlia bootstrap retry failed to start: {err}"),
+    }
+}
+
+fn should_rebootstrap(
+    routing_table_len: usize,
+    last_attempt_ms: i64,
+    now_ms: i64,
+    interval_ms: i64,
+) -> bool {
+    routing_table_len == 0 && now_ms.saturating_sub(last_attempt_ms) >= interval_ms
+}
+
 fn handle_discovery_tick(swarm: &mut Swarm<NodeBehaviour>, app: &mut App) {
     if let Err(err) = maybe_publish_local_device_record(swarm, app) {
         warn!("publish local device announcement failed: {err}");
@@ -1017,7 +2272,7 @@ fn handle_discovery_tick(swarm: &mut Swarm<NodeBehaviour>, app: &mut App) {
 }
 
 fn handle_control_keepalive_tick(swarm: &mut Swarm<NodeBehaviour>, app: &mut App) {
-    let now_unix_ms = unix_ms() as i64;
+    let now_unix_ms = app.now_ms();
     let connected_peers = swarm.connected_peers().copied().collect::<HashSet<_>>();
     let (send_actions, lost_peers) = app.collect_keepalive_actions(now_unix_ms, &connected_peers);
 
@@ -1041,7 +2296,7 @@ fn send_control_ping(
     session_id: &str,
     seq: u64,
 ) -> Result<()> {
-    let now_unix_ms = unix_ms();
+    let now_unix_ms = app.now_ms() as u64;
     let env = ControlEnvelope {
         seq,
         request_id: format!("ping-{now_unix_ms}-{seq}"),
@@ -1065,7 +2320,7 @@ fn send_control_ping(
 }
 
 fn handle_session_lifecycle_tick(swarm: &mut Swarm<NodeBehaviour>, app: &mut App) {
-    let now_unix_ms = unix_ms() as i64;
+    let now_unix_ms = app.now_ms();
 
     for (peer_id, session_id) in app.collect_auto_close_actions(now_unix_ms) {
         if let Err(err) = send_session_close(
@@ -1091,43 +2346,139 @@ fn handle_session_lifecycle_tick(swarm: &mut Swarm<NodeBehaviour>, app: &mut App
     }
 }
 
-fn send_session_close(
+/// Replaces the bootstrap and relay sets at runtime. New bootstrap peers seed Kademlia
+/// and a bootstrap query is started; dropped relays lose their reservation listener
+/// and new ones are reserved on the next relay tick.
+fn apply_network_update(
     swarm: &mut Swarm<NodeBehaviour>,
     app: &mut App,
-    peer_id: PeerId,
-    session_id: &str,
-    reason: &str,
-) -> Result<()> {
-    let now_unix_ms = unix_ms();
-    let env = ControlEnvelope {
-        seq: now_unix_ms,
-        request_id: format!("close-{now_unix_ms}"),
-        message: Some(
-            aetherlink_proto::v1::control_envelope::Message::SessionClose(SessionClose {
-                session_id: session_id.to_string(),
-                reason: reason.to_string(),
-            }),
-        ),
+    update: UpdateNetworkRequest,
+) -> Result<String> {
+    let parse = |raw: &[String]| {
+        raw.iter()
+            .map(|addr| {
+                addr.trim()
+                    .parse::<Multiaddr>()
+                    .with_context(|| format!("invalid multiaddr: {addr}"))
+            })
+            .collect::<Result<Vec<_>>>()
     };
-    let request_id = swarm
-        .behaviour_mut()
-        .control
-        .send_request(&peer_id, encode_envelope(&env));
-    app.pending_outbound_control_requests
-        .insert(request_id, OutboundControlRequestKind::SessionClose);
-    app.mark_graceful_closing(peer_id);
-    app.clear_active_session(peer_id);
-    info!("sent SessionClose peer={peer_id} session={session_id}");
-    Ok(())
-}
-
+    let bootstrap = parse(&update.bootstrap_multiaddrs)?;
+    let relays = parse(&update.relay_multiaddrs)?;
+    let relay_peers = relays
+        .iter()
+        .map(|addr| {
+            extract_peer_id(addr)
+                .map(|peer_id| (peer_id, addr.clone()))
+                .with_context(|| format!("relay address missing /p2p/<peer_id>: {addr}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    for addr in &bootstrap {
+        if let Some(peer_id) = extract_peer_id(addr) {
+            swarm
+                .behaviour_mut()
+                .kad
+                .add_address(&peer_id, addr.clone());
+        } else if let Err(err) = swarm.dial(addr.clone()) {
+            warn!("dial updated bootstrap {addr} failed: {err}");
+        }
+    }
+    if !bootstrap.is_empty() {
+        match swarm.behaviour_mut().kad.bootstrap() {
+            Ok(query_id) => info!("kademlia bootstrap after network update, query={query_id:?}"),
+            Err(err) => warn!("kademlia bootstrap after network update failed: {err}"),
+        }
+    }
+    app.bootstrap_addrs = bootstrap;
+    app.bootstrap_retry_interval_ms = BOOTSTRAP_RETRY_BASE_MS;
+
+    let keep: HashSet<PeerId> = relay_peers.iter().map(|(peer_id, _)| *peer_id).collect();
+    for listener_id in app.relay_reservations.retain_relays(&keep) {
+        swarm.remove_listener(listener_id);
+    }
+    for (relay_peer_id, addr) in relay_peers {
+        app.relay_reservations.add_relay(relay_peer_id, addr);
+    }
+    let detail = format!(
+        "network updated: {} bootstrap, {} relay",
+        app.bootstrap_addrs.len(),
+        app.relay_reservations.by_relay.len()
+    );
+    info!("{detail}");
+    Ok(detail)
+}
+
+fn handle_relay_reservation_tick(swarm: &mut Swarm<NodeBehaviour>, app: &mut App) {
+    let now_unix_ms = app.now_ms();
+    for relay_peer_id in relay::reservations_needing_renewal(&app.relay_reservations, now_unix_ms) {
+        let Some(reservation) = app.relay_reservations.by_relay.get(&relay_peer_id).cloned() else {
+            continue;
+        };
+        if let Some(listener_id) = reservation.listener_id {
+            swarm.remove_listener(listener_id);
+        }
+        if !swarm.is_connected(&relay_peer_id)
+            && let Err(err) = swarm.dial(reservation.relay_addr.clone())
+        {
+            warn!("re-dial relay {relay_peer_id} failed: {err}");
+        }
+        let circuit_addr = reservation
+            .relay_addr
+            .clone()
+            .with(libp2p::multiaddr::Protocol::P2pCircuit);
+        match swarm.listen_on(circuit_addr.clone()) {
+            Ok(listener_id) => {
+                app.relay_reservations
+                    .note_attempt(relay_peer_id, Some(listener_id), now_unix_ms);
+                info!("requested relay reservation via {circuit_addr}");
+            }
+            Err(err) => {
+                app.relay_reservations
+                    .note_attempt(relay_peer_id, None, now_unix_ms);
+                warn!("relay reservation request failed via {circuit_addr}: {err}");
+            }
+        }
+    }
+}
+
+fn send_session_close(
+    swarm: &mut Swarm<NodeBehaviour>,
+    app: &mut App,
+    peer_id: PeerId,
+    session_id: &str,
+    reason: &str,
+) -> Result<()> {
+    let now_unix_ms = app.now_ms() as u64;
+    let env = ControlEnvelope {
+        seq: now_unix_ms,
+        request_id: format!("close-{now_unix_ms}"),
+        message: Some(
+            aetherlink_proto::v1::control_envelope::Message::SessionClose(SessionClose {
+                session_id: session_id.to_string(),
+                reason: reason.to_string(),
+            }),
+        ),
+    };
+    let request_id = swarm
+        .behaviour_mut()
+        .control
+        .send_request(&peer_id, encode_envelope(&env));
+    app.pending_outbound_control_requests
+        .insert(request_id, OutboundControlRequestKind::SessionClose);
+    app.mark_graceful_closing(peer_id);
+    app.clear_active_session(peer_id);
+    info!("sent SessionClose peer={peer_id} session={session_id}");
+    Ok(())
+}
+
 fn send_candidate_announcement(
     swarm: &mut Swarm<NodeBehaviour>,
     app: &mut App,
     peer_id: PeerId,
     session_id: &str,
 ) {
-    let now_unix_ms = unix_ms() as i64;
+    let now_unix_ms = app.now_ms();
     let mut all_addrs = app.known_local_addrs.clone();
     for addr in swarm.external_addresses() {
         if !all_addrs.iter().any(|x| x == addr) {
@@ -1141,15 +2492,17 @@ fn send_candidate_announcement(
             address: addr.to_string(),
             priority: 100,
             expires_unix_ms: (now_unix_ms + 120_000) as u64,
-            relay_peer_id: String::new(),
+            relay_peer_id: relay::parse_circuit_addr(&addr)
+                .map(|(relay_peer_id, _)| relay_peer_id.to_string())
+                .unwrap_or_default(),
         })
         .collect::<Vec<_>>();
     if candidates.is_empty() {
         return;
     }
     let env = ControlEnvelope {
-        seq: unix_ms(),
-        request_id: format!("cand-{}", unix_ms()),
+        seq: app.now_ms() as u64,
+        request_id: format!("cand-{}", app.now_ms() as u64),
         message: Some(
             aetherlink_proto::v1::control_envelope::Message::CandidateAnnouncement(
                 CandidateAnnouncement {
@@ -1175,10 +2528,10 @@ fn send_punch_sync(
     peer_id: PeerId,
     session_id: &str,
 ) {
-    let start_after_unix_ms = unix_ms() + 400;
+    let start_after_unix_ms = app.now_ms() as u64 + 400;
     let env = ControlEnvelope {
-        seq: unix_ms(),
-        request_id: format!("punch-{}", unix_ms()),
+        seq: app.now_ms() as u64,
+        request_id: format!("punch-{}", app.now_ms() as u64),
         message: Some(aetherlink_proto::v1::control_envelope::Message::PunchSync(
             PunchSync {
                 session_id: session_id.to_string(),
@@ -1214,7 +2567,7 @@ fn maybe_publish_local_device_record(
         return Ok(());
     }
 
-    let now_unix_ms = unix_ms() as i64;
+    let now_unix_ms = app.now_ms();
     if now_unix_ms.saturating_sub(app.last_device_record_publish_unix_ms)
         < app.device_record_republish_ms
     {
@@ -1227,12 +2580,14 @@ fn maybe_publish_local_device_record(
             addrs.push(addr.clone());
         }
     }
-    let announcement = DeviceAnnouncementV1 {
+    addrs.retain(|addr| is_publishable_addr(addr, app.publish_private_addrs));
+    let announcement = DeviceAnnouncement {
         version: 1,
         device_code: app.local_device_code.clone(),
         peer_id: app.local_peer_id.to_string(),
         addrs: addrs.into_iter().map(|x| x.to_string()).collect(),
         unix_ms: now_unix_ms,
+        rotation_proof: None,
     };
     let payload = serde_json::to_vec(&announcement).context("serialize announcement failed")?;
     let key = device_record_key(&app.local_device_code);
@@ -1263,7 +2618,7 @@ fn maybe_start_device_code_lookups(swarm: &mut Swarm<NodeBehaviour>, app: &mut A
     if app.connect_device_codes.is_empty() {
         return;
     }
-    let now_unix_ms = unix_ms() as i64;
+    let now_unix_ms = app.now_ms();
     let pending_targets = app
         .pending_device_lookup_queries
         .values()
@@ -1282,16 +2637,139 @@ fn maybe_start_device_code_lookups(swarm: &mut Swarm<NodeBehaviour>, app: &mut A
         if now_unix_ms.saturating_sub(last_lookup) < app.device_lookup_interval_ms {
             continue;
         }
-        let key = device_record_key(&target);
-        let query_id = swarm.behaviour_mut().kad.get_record(key);
-        app.pending_device_lookup_queries
-            .insert(query_id, target.clone());
-        app.last_device_lookup_unix_ms
-            .insert(target.clone(), now_unix_ms);
-        info!("started DHT device lookup target={target}, query={query_id:?}");
+        start_device_lookup(swarm, app, &target, now_unix_ms);
+    }
+}
+
+fn start_device_lookup(
+    swarm: &mut Swarm<NodeBehaviour>,
+    app: &mut App,
+    target: &str,
+    now_unix_ms: i64,
+) {
+    let key = device_record_key(target);
+    let query_id = swarm.behaviour_mut().kad.get_record(key);
+    app.pending_device_lookup_queries
+        .insert(query_id, target.to_string());
+    app.last_device_lookup_unix_ms
+        .insert(target.to_string(), now_unix_ms);
+    info!("started DHT device lookup target={target}, query={query_id:?}");
+}
+
+/// Starts DHT lookups for the codes not already being looked up; the reply is sent
+/// from `handle_admin_lookup_tick` once they finish.
+fn start_admin_device_lookup(
+    swarm: &mut Swarm<NodeBehaviour>,
+    app: &mut App,
+    device_codes: Vec<String>,
+    reply: oneshot::Sender<NodeAdminResponse>,
+) {
+    let now_unix_ms = app.now_ms();
+    let device_codes: Vec<String> = device_codes
+        .iter()
+        .map(|code| code.trim().to_string())
+        .filter(|code| !code.is_empty() && *code != app.local_device_code)
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    for code in &device_codes {
+        if !app
+            .pending_device_lookup_queries
+            .values()
+            .any(|pending| pending == code)
+        {
+            start_device_lookup(swarm, app, code, now_unix_ms);
+        }
+    }
+    app.pending_admin_lookups.push(PendingAdminLookup {
+        device_codes,
+        started_unix_ms: now_unix_ms,
+        reply,
+    });
+}
+
+/// A lookup is answered once none of its codes has a DHT query in flight, or when it
+/// times out with whatever was found so far.
+fn admin_lookup_done(
+    lookup: &PendingAdminLookup,
+    in_flight: &HashSet<&String>,
+    now_unix_ms: i64,
+) -> bool {
+    now_unix_ms.saturating_sub(lookup.started_unix_ms) >= ADMIN_LOOKUP_TIMEOUT_MS
+        || lookup
+            .device_codes
+            .iter()
+            .all(|code| !in_flight.contains(code))
+}
+
+fn handle_trust_prune_tick(app: &mut App) {
+    if app.trusted_peer_max_age_ms <= 0 {
+        return;
+    }
+    let now_unix_ms = app.now_ms();
+    if now_unix_ms.saturating_sub(app.last_trust_prune_ms) < TRUST_PRUNE_INTERVAL_MS {
+        return;
+    }
+    app.last_trust_prune_ms = now_unix_ms;
+    let connected: HashSet<PeerId> = app.active_sessions.keys().copied().collect();
+    let pruned =
+        app.trusted_peers
+            .prune_expired(now_unix_ms, app.trusted_peer_max_age_ms, &connected);
+    if pruned == 0 {
+        return;
+    }
+    info!("pruned {pruned} trusted peer(s) not seen within the max age");
+    if let Err(err) = app.persist_trust_store() {
+        warn!("failed to persist trust store: {err}");
+    }
+}
+
+fn handle_replay_cache_save_tick(app: &mut App) {
+    let now_unix_ms = app.now_ms();
+    if now_unix_ms.saturating_sub(app.last_replay_cache_save_ms) < REPLAY_CACHE_SAVE_INTERVAL_MS {
+        return;
+    }
+    app.last_replay_cache_save_ms = now_unix_ms;
+    if let Err(err) = app.persist_replay_cache() {
+        warn!("failed to persist nonce replay cache: {err}");
+    }
+}
+
+fn handle_admin_lookup_tick(app: &mut App) {
+    if app.pending_admin_lookups.is_empty() {
+        return;
+    }
+    let now_unix_ms = app.now_ms();
+    let lookups = std::mem::take(&mut app.pending_admin_lookups);
+    let (done, waiting): (Vec<_>, Vec<_>) = {
+        let in_flight: HashSet<&String> = app.pending_device_lookup_queries.values().collect();
+        lookups
+            .into_iter()
+            .partition(|lookup| admin_lookup_done(lookup, &in_flight, now_unix_ms))
+    };
+    app.pending_admin_lookups = waiting;
+    for lookup in done {
+        let response = NodeAdminResponse {
+            payload: Some(node_admin_response::Payload::LookupDevices(
+                LookupDevicesResponse {
+                    devices: discovered_devices_for(app, &lookup.device_codes),
+                },
+            )),
+            error: String::new(),
+        };
+        if lookup.reply.send(response).is_err() {
+            warn!("admin client went away before device lookup finished");
+        }
     }
 }
 
+fn discovered_devices_for(app: &App, device_codes: &[String]) -> Vec<DiscoveredDevice> {
+    device_codes
+        .iter()
+        .filter_map(|code| app.discovered_devices.get(code).cloned())
+        .collect()
+}
+
 fn handle_kad_event(
     swarm: &mut Swarm<NodeBehaviour>,
     app: &mut App,
@@ -1331,15 +2809,15 @@ fn handle_kad_query_progress(
         kad::QueryResult::GetRecord(result) => {
             handle_get_record_query_result(swarm, app, query_id, result)?;
         }
-        kad::QueryResult::PutRecord(result) | kad::QueryResult::RepublishRecord(result) => {
-            if app.pending_device_publish_queries.remove(&query_id) {
-                match result {
-                    Ok(ok) => info!(
-                        "local device announcement record stored, key={}",
-                        String::from_utf8_lossy(ok.key.as_ref())
-                    ),
-                    Err(err) => warn!("local device announcement publish failed: {err}"),
-                }
+        kad::QueryResult::PutRecord(result) | kad::QueryResult::RepublishRecord(result)
+            if app.pending_device_publish_queries.remove(&query_id) =>
+        {
+            match result {
+                Ok(ok) => info!(
+                    "local device announcement record stored, key={}",
+                    String::from_utf8_lossy(ok.key.as_ref())
+                ),
+                Err(err) => warn!("local device announcement publish failed: {err}"),
             }
         }
         _ => {}
@@ -1373,14 +2851,22 @@ fn handle_get_record_query_result(
     Ok(())
 }
 
+/// Pairing code for a peer whose id inlines its public key (ed25519 ids do); empty
+/// otherwise, since a hashed id cannot be mapped back to a key.
+fn pairing_code_for_peer(peer_id: &PeerId) -> String {
+    inline_public_key(peer_id)
+        .map(|public| pairing_code(&public.encode_protobuf()))
+        .unwrap_or_default()
+}
+
 fn process_discovery_record_payload(
     swarm: &mut Swarm<NodeBehaviour>,
     app: &mut App,
     target_device_code: &str,
     payload: &[u8],
 ) -> Result<()> {
-    let announcement: DeviceAnnouncementV1 = serde_json::from_slice(payload)
-        .context("decode DeviceAnnouncementV1 from DHT record failed")?;
+    let announcement: DeviceAnnouncement = serde_json::from_slice(payload)
+        .context("decode DeviceAnnouncement from DHT record failed")?;
     if announcement.version != 1 {
         warn!(
             "ignore unsupported device announcement version={}",
@@ -1403,6 +2889,33 @@ fn process_discovery_record_payload(
     if peer_id == app.local_peer_id {
         return Ok(());
     }
+    if let Err(err) = apply_announced_rotation(app, &announcement, peer_id) {
+        warn!(
+            "ignore device announcement with invalid rotation for {}: {err:#}",
+            announcement.device_code
+        );
+        return Ok(());
+    }
+    let last_seen_unix_ms = app.now_ms().max(0) as u64;
+    app.discovered_devices.insert(
+        announcement.device_code.clone(),
+        DiscoveredDevice {
+            device_code: announcement.device_code.clone(),
+            peer_id: peer_id.to_string(),
+            last_seen_unix_ms,
+            trusted: false,
+            addrs: announcement.addrs.clone(),
+            pairing_code: pairing_code_for_peer(&peer_id),
+        },
+    );
+    // Lookups made for the admin channel only report; dialing is for connect targets.
+    if !app
+        .connect_device_codes
+        .iter()
+        .any(|code| code == target_device_code)
+    {
+        return Ok(());
+    }
     if !app.can_attempt_discovery_dial(swarm, peer_id) {
         info!(
             "device discovery resolved target={} peer={} (already connected or throttled)",
@@ -1455,101 +2968,70 @@ fn handle_control_request(
     channel: request_response::ResponseChannel<Vec<u8>>,
 ) -> Result<()> {
     let env = decode_envelope(&request)?;
+    let now_unix_ms = app.now_ms();
+    if is_duplicate_request(
+        &mut app.recent_control_requests,
+        peer,
+        &env.request_id,
+        now_unix_ms,
+    ) {
+        match app
+            .recent_control_requests
+            .cached_response(&peer, &env.request_id)
+        {
+            Some(payload) => {
+                info!(
+                    "duplicate control request_id={} from {peer}, replaying cached response",
+                    env.request_id
+                );
+                swarm
+                    .behaviour_mut()
+                    .control
+                    .send_response(channel, payload)
+                    .map_err(|_| anyhow!("replay control response failed: channel closed"))?;
+            }
+            None => info!(
+                "duplicate control request_id={} from {peer} still in progress, dropping",
+                env.request_id
+            ),
+        }
+        return Ok(());
+    }
     match env.message {
         Some(aetherlink_proto::v1::control_envelope::Message::SessionRequest(req)) => {
             info!(
                 "received SessionRequest from={peer} target={}",
                 req.target_device_code
             );
-
-            if req.version.as_ref().map(|v| v.major).unwrap_or_default() != PROTOCOL_MAJOR {
+            app.on_responder(peer, ResponderTrigger::RequestReceived);
+            let queued = QueuedSessionRequest {
+                request_id: env.request_id,
+                request: req,
+                channel,
+            };
+            if let Err(rejected) = app.inbound_session_requests.push(peer, queued) {
+                warn!(
+                    "inbound SessionRequest queue full for peer={peer} (queued total={}), rejecting",
+                    app.inbound_session_requests.len()
+                );
+                app.on_responder(peer, ResponderTrigger::VerifyFailed(RejectReason::Busy));
                 return send_session_reject(
                     swarm,
-                    channel,
-                    env.request_id,
-                    req.session_id,
-                    RejectReason::VersionMismatch,
-                    format!(
-                        "protocol major mismatch: expected {}, got {:?}",
-                        PROTOCOL_MAJOR,
-                        req.version.map(|v| v.major)
+                    app,
+                    peer,
+                    rejected.channel,
+                    rejected.request_id,
+                    session_reject(
+                        rejected.request.session_id,
+                        RejectReason::Busy,
+                        SessionRejectDetailCode::InboundQueueFull,
+                        "too many pending SessionRequests".to_string(),
                     ),
                 );
             }
-
-            let verify_result = verify_session_request(
-                &req,
-                Some(&peer),
-                Some(&app.local_device_code),
-                unix_ms() as i64,
-                DEFAULT_ALLOWED_SKEW_MS,
-                &mut app.nonce_cache,
-                &mut app.trusted_peers,
-                app.trust_on_first_use,
-            );
-            let verified = match verify_result {
-                Ok(v) => v,
-                Err(err) => {
-                    app.on_auth_failed(peer);
-                    return send_session_reject(
-                        swarm,
-                        channel,
-                        env.request_id,
-                        req.session_id,
-                        map_auth_error_to_reject(&err),
-                        err.to_string(),
-                    );
-                }
-            };
-
-            if verified.trust_store_changed {
-                if let Err(err) = app.persist_trust_store() {
-                    warn!("failed to persist trust store: {err}");
-                } else {
-                    info!(
-                        "trust store updated for device_code={}",
-                        verified.device_code
-                    );
-                }
-            }
-
-            let mut accept = SessionAccept {
-                session_id: req.session_id,
-                selected_codec: aetherlink_proto::v1::VideoCodec::H264 as i32,
-                selected_fps: 30,
-                selected_width: 1280,
-                selected_height: 720,
-                using_relay: false,
-                path_id: "direct-quic".to_string(),
-                from: Some(DeviceIdentity {
-                    peer_id: app.local_peer_id.to_bytes(),
-                    identity_pubkey: app.local_key.public().encode_protobuf(),
-                    device_code: app.local_device_code.clone(),
-                }),
-                nonce: random_nonce(16),
-                unix_ms: unix_ms() as i64,
-                signature: Vec::new(),
-                request_nonce: req.nonce.clone(),
-                accepted_feature_bits: req.feature_bits.clone(),
-            };
-            sign_session_accept(&mut accept, &app.local_key).context("sign SessionAccept")?;
-            let response = ControlEnvelope {
-                seq: unix_ms(),
-                request_id: env.request_id,
-                message: Some(
-                    aetherlink_proto::v1::control_envelope::Message::SessionAccept(accept.clone()),
-                ),
-            };
-            let payload = encode_envelope(&response);
-            swarm
-                .behaviour_mut()
-                .control
-                .send_response(channel, payload)
-                .map_err(|_| anyhow!("send control response failed: channel closed"))?;
-            app.on_accept(peer, accept.session_id.clone());
-            on_session_activated(swarm, app, peer, &accept.session_id);
         }
         Some(aetherlink_proto::v1::control_envelope::Message::Ping(ping)) => {
+            app.on_responder(peer, ResponderTrigger::KeepaliveReceived);
             if let Some(active_session_id) = app.active_sessions.get(&peer)
                 && active_session_id != &ping.session_id
             {
@@ -1559,22 +3041,18 @@ fn handle_control_request(
                 );
             }
             let response = ControlEnvelope {
-                seq: unix_ms(),
+                seq: app.now_ms() as u64,
                 request_id: env.request_id,
                 message: Some(aetherlink_proto::v1::control_envelope::Message::Pong(
                     ControlPong {
                         session_id: ping.session_id,
                         seq: ping.seq,
                         echo_send_unix_ms: ping.send_unix_ms,
-                        recv_unix_ms: unix_ms(),
+                        recv_unix_ms: app.now_ms() as u64,
                     },
                 )),
             };
-            swarm
-                .behaviour_mut()
-                .control
-                .send_response(channel, encode_envelope(&response))
-                .map_err(|_| anyhow!("send Pong failed: channel closed"))?;
+            send_control_response(swarm, app, peer, channel, &response)?;
         }
         Some(aetherlink_proto::v1::control_envelope::Message::SessionClose(close)) => {
             info!(
@@ -1584,7 +3062,7 @@ fn handle_control_request(
             app.mark_graceful_closing(peer);
             app.clear_active_session(peer);
             let _ = swarm.disconnect_peer_id(peer);
-            send_control_ack(swarm, channel, env.request_id)?;
+            send_control_ack(swarm, app, peer, channel, env.request_id)?;
         }
         Some(aetherlink_proto::v1::control_envelope::Message::CandidateAnnouncement(ann)) => {
             info!(
@@ -1601,7 +3079,7 @@ fn handle_control_request(
                     swarm.behaviour_mut().kad.add_address(&peer, dial_addr);
                 }
             }
-            send_control_ack(swarm, channel, env.request_id)?;
+            send_control_ack(swarm, app, peer, channel, env.request_id)?;
         }
         Some(aetherlink_proto::v1::control_envelope::Message::PunchSync(punch)) => {
             info!(
@@ -1618,109 +3096,620 @@ fn handle_control_request(
                 start_after_unix_ms: punch.start_after_unix_ms as i64,
                 attempt_index: punch.attempt_index,
             });
-            send_control_ack(swarm, channel, env.request_id)?;
+            send_control_ack(swarm, app, peer, channel, env.request_id)?;
+        }
+        Some(aetherlink_proto::v1::control_envelope::Message::ClipboardData(data)) => {
+            if let Err(reason) = handle_clipboard_data(app, peer, data) {
+                warn!("dropping ClipboardData from peer={peer}: {reason}");
+            }
+            send_control_ack(swarm, app, peer, channel, env.request_id)?;
+        }
+        Some(aetherlink_proto::v1::control_envelope::Message::SessionRenegotiate(req)) => {
+            match handle_session_renegotiate(app, peer, &req) {
+                Ok(ack) => {
+                    let response = ControlEnvelope {
+                        seq: app.now_ms() as u64,
+                        request_id: env.request_id,
+                        message: Some(
+                            aetherlink_proto::v1::control_envelope::Message::SessionRenegotiateAck(
+                                ack,
+                            ),
+                        ),
+                    };
+                    send_control_response(swarm, app, peer, channel, &response)?;
+                }
+                Err(reason) => {
+                    warn!("ignoring SessionRenegotiate from peer={peer}: {reason}");
+                    send_control_ack(swarm, app, peer, channel, env.request_id)?;
+                }
+            }
+        }
+        Some(aetherlink_proto::v1::control_envelope::Message::RequestKeyframe(req)) => {
+            if let Err(reason) = handle_request_keyframe(app, peer, &req) {
+                info!("dropping RequestKeyframe from peer={peer}: {reason}");
+            }
+            send_control_ack(swarm, app, peer, channel, env.request_id)?;
         }
         _ => {
-            send_control_ack(swarm, channel, env.request_id)?;
+            send_control_ack(swarm, app, peer, channel, env.request_id)?;
         }
     }
     Ok(())
 }
 
-fn send_session_reject(
-    swarm: &mut Swarm<NodeBehaviour>,
-    channel: request_response::ResponseChannel<Vec<u8>>,
-    request_id: String,
-    session_id: String,
-    reason: RejectReason,
-    detail: String,
-) -> Result<()> {
-    let response = ControlEnvelope {
-        seq: unix_ms(),
-        request_id,
-        message: Some(
-            aetherlink_proto::v1::control_envelope::Message::SessionReject(SessionReject {
-                session_id,
-                reason: reason as i32,
-                detail,
-            }),
-        ),
-    };
-    swarm
-        .behaviour_mut()
-        .control
-        .send_response(channel, encode_envelope(&response))
-        .map_err(|_| anyhow!("send SessionReject failed: channel closed"))?;
+/// Agrees a new profile for `peer`'s active session: the proposal clamped to
+/// `HOST_MAX_VIDEO_PROFILE`. The session keeps running throughout.
+fn handle_session_renegotiate(
+    app: &mut App,
+    peer: PeerId,
+    req: &SessionRenegotiate,
+) -> Result<SessionRenegotiateAck, &'static str> {
+    match app.active_sessions.get(&peer) {
+        Some(session_id) if *session_id == req.session_id => {}
+        Some(_) => return Err("session id does not match the active session"),
+        None => return Err("no active session"),
+    }
+    let proposed = req.proposed.as_ref().ok_or("missing proposed profile")?;
+    let agreed = agree_profile(
+        MediaVideoProfile {
+            width: proposed.width,
+            height: proposed.height,
+            fps: proposed.fps,
+            bitrate_kbps: proposed.bitrate_kbps,
+        },
+        HOST_MAX_VIDEO_PROFILE,
+    );
+    app.session_profiles.insert(peer, agreed);
+    info!(
+        "renegotiated session {} with peer={peer}: {}x{}@{} {}kbps",
+        req.session_id, agreed.width, agreed.height, agreed.fps, agreed.bitrate_kbps
+    );
+    Ok(SessionRenegotiateAck {
+        session_id: req.session_id.clone(),
+        accepted: Some(VideoProfile {
+            width: agreed.width,
+            height: agreed.height,
+            fps: agreed.fps,
+            bitrate_kbps: agreed.bitrate_kbps,
+        }),
+    })
+}
+
+/// Passes a peer's keyframe request on to the encoder, at most once per
+/// `KEYFRAME_REQUEST_MIN_INTERVAL_MS` per peer.
+fn handle_request_keyframe(
+    app: &mut App,
+    peer: PeerId,
+    req: &RequestKeyframe,
+) -> Result<(), &'static str> {
+    match app.active_sessions.get(&peer) {
+        Some(session_id) if *session_id == req.session_id => {}
+        Some(_) => return Err("session id does not match the active session"),
+        None => return Err("no active session"),
+    }
+    let now_ms = app.now_ms();
+    if !keyframe_request_allowed(
+        app.last_keyframe_request_ms.get(&peer).copied(),
+        now_ms,
+        KEYFRAME_REQUEST_MIN_INTERVAL_MS,
+    ) {
+        return Err("rate limited");
+    }
+    app.last_keyframe_request_ms.insert(peer, now_ms);
+    // No encoder is wired into the node yet; this is where it would be told.
+    info!(
+        "keyframe requested by peer={peer} session={} reason={}",
+        req.session_id, req.reason
+    );
     Ok(())
 }
 
-fn send_control_ack(
+fn send_input_event(
     swarm: &mut Swarm<NodeBehaviour>,
-    channel: request_response::ResponseChannel<Vec<u8>>,
-    request_id: String,
+    app: &mut App,
+    device_code: &str,
+    mut event: InputEvent,
 ) -> Result<()> {
-    let response = ControlEnvelope {
-        seq: unix_ms(),
-        request_id,
-        message: None,
+    let (peer_id, session_id) = app
+        .active_session_for_device(device_code)
+        .ok_or_else(|| anyhow!("no active session with device {device_code}"))?;
+    event.session_id = session_id;
+    let env = ControlEnvelope {
+        seq: app.now_ms() as u64,
+        request_id: app.next_control_request_id("input"),
+        message: Some(aetherlink_proto::v1::control_envelope::Message::InputEvent(
+            event,
+        )),
     };
-    swarm
+    let request_id = swarm
         .behaviour_mut()
         .control
-        .send_response(channel, encode_envelope(&response))
-        .map_err(|_| anyhow!("send control ack failed: channel closed"))?;
+        .send_request(&peer_id, encode_envelope(&env));
+    app.pending_outbound_control_requests
+        .insert(request_id, OutboundControlRequestKind::InputEvent);
     Ok(())
 }
 
-fn handle_control_response(
-    swarm: &mut Swarm<NodeBehaviour>,
+/// Stores clipboard contents pushed by `peer` when they belong to its active session,
+/// sync is enabled for its device and the payload passes the size cap and allowlist.
+fn handle_clipboard_data(
     app: &mut App,
     peer: PeerId,
-    request_id: request_response::OutboundRequestId,
-    response: Vec<u8>,
-) -> Result<()> {
-    let request_kind = app.pending_outbound_control_requests.remove(&request_id);
-    let env = decode_envelope(&response)?;
-    match env.message {
-        Some(aetherlink_proto::v1::control_envelope::Message::SessionAccept(accept)) => {
-            if !matches!(
-                request_kind,
-                Some(OutboundControlRequestKind::SessionRequest)
-            ) {
-                warn!("unexpected SessionAccept for request kind: {request_kind:?}");
-            }
-            let Some(pending) = app.pending_outbound_sessions.remove(&peer) else {
-                warn!("received SessionAccept from {peer} without pending outbound session");
-                app.on_auth_failed(peer);
-                return Ok(());
-            };
+    data: ClipboardData,
+) -> Result<(), &'static str> {
+    match app.active_sessions.get(&peer) {
+        Some(session_id) if *session_id == data.session_id => {}
+        Some(_) => return Err("session id does not match the active session"),
+        None => return Err("no active session"),
+    }
+    let Some(device_code) = app.peer_device_codes.get(&peer) else {
+        return Err("unknown device code");
+    };
+    if !app.clipboard_sync_devices.contains(device_code) {
+        return Err("clipboard sync is disabled for this device");
+    }
+    if !clipboard_allowed(&data.mime, data.data.len()) {
+        return Err("clipboard type or size not allowed");
+    }
+    info!(
+        "received clipboard from peer={peer} mime={} bytes={}",
+        data.mime,
+        data.data.len()
+    );
+    app.remote_clipboards.insert(device_code.clone(), data);
+    Ok(())
+}
 
-            if accept.request_nonce.is_empty() {
-                warn!("invalid SessionAccept from {peer}: missing request nonce binding");
-                app.on_auth_failed(peer);
-                return Ok(());
+/// Frames reassembled from one device, oldest first, with a running byte total so
+/// the buffer can be held under `MAX_BUFFERED_VIDEO_BYTES`.
+#[derive(Debug, Default)]
+struct ReceivedVideo {
+    frames: VecDeque<AssembledFrame>,
+    bytes: usize,
+    dropped: u64,
+}
+
+impl ReceivedVideo {
+    fn push(&mut self, frame: AssembledFrame) {
+        self.bytes += frame.data.len();
+        self.frames.push_back(frame);
+        while self.bytes > MAX_BUFFERED_VIDEO_BYTES {
+            let Some(oldest) = self.frames.pop_front() else {
+                break;
+            };
+            self.bytes -= oldest.data.len();
+            self.dropped += 1;
+        }
+    }
+}
+
+/// Feeds a chunk received on `VIDEO_PROTOCOL` from `peer`'s active session into its
+/// assembler and queues the frame for the daemon once every chunk of it is in.
+fn handle_video_chunk(app: &mut App, peer: PeerId, chunk: VideoChunk) -> Result<(), &'static str> {
+    match app.active_sessions.get(&peer) {
+        Some(session_id) if *session_id == chunk.session_id => {}
+        Some(_) => return Err("session id does not match the active session"),
+        None => return Err("no active session"),
+    }
+    let Some(device_code) = app.peer_device_codes.get(&peer).cloned() else {
+        return Err("unknown device code");
+    };
+    let frame = app
+        .video_assemblers
+        .entry(peer)
+        .or_default()
+        .push(FrameChunk {
+            frame_id: chunk.frame_id,
+            chunk_id: chunk.chunk_id,
+            chunk_count: chunk.chunk_count,
+            pts_us: chunk.pts_us,
+            keyframe: chunk.keyframe,
+            payload: chunk.payload,
+        })
+        .map_err(|_| "malformed or oversized chunk")?;
+    if let Some(frame) = frame {
+        app.received_video
+            .entry(device_code)
+            .or_default()
+            .push(frame);
+    }
+    Ok(())
+}
+
+fn send_clipboard_data(
+    swarm: &mut Swarm<NodeBehaviour>,
+    app: &mut App,
+    device_code: &str,
+    mime: String,
+    data: Vec<u8>,
+) -> Result<()> {
+    if !app.clipboard_sync_devices.contains(device_code) {
+        return Err(anyhow!(
+            "clipboard sync is disabled for device {device_code}"
+        ));
+    }
+    if !clipboard_allowed(&mime, data.len()) {
+        return Err(anyhow!(
+            "clipboard type {mime:?} or size {} bytes not allowed",
+            data.len()
+        ));
+    }
+    let (peer_id, session_id) = app
+        .active_session_for_device(device_code)
+        .ok_or_else(|| anyhow!("no active session with device {device_code}"))?;
+    let env = ControlEnvelope {
+        seq: app.now_ms() as u64,
+        request_id: app.next_control_request_id("clip"),
+        message: Some(
+            aetherlink_proto::v1::control_envelope::Message::ClipboardData(ClipboardData {
+                session_id,
+                mime,
+                data,
+            }),
+        ),
+    };
+    // A MIME type with long parameters could still push the envelope past what the
+    // peer decodes; refuse here rather than have the peer drop it.
+    let payload = encode_envelope(&env);
+    if payload.len() > MAX_CONTROL_ENVELOPE_BYTES {
+        return Err(anyhow!(
+            "clipboard envelope of {} bytes exceeds {MAX_CONTROL_ENVELOPE_BYTES}",
+            payload.len()
+        ));
+    }
+    let request_id = swarm
+        .behaviour_mut()
+        .control
+        .send_request(&peer_id, payload);
+    app.pending_outbound_control_requests
+        .insert(request_id, OutboundControlRequestKind::ClipboardData);
+    Ok(())
+}
+
+fn handle_inbound_session_requests(swarm: &mut Swarm<NodeBehaviour>, app: &mut App) {
+    if app.inbound_session_requests.is_empty() {
+        return;
+    }
+    for (peer, queued) in app.inbound_session_requests.take_round() {
+        if let Err(err) = process_session_request(swarm, app, peer, queued) {
+            warn!("processing SessionRequest from {peer} failed: {err}");
+        }
+    }
+}
+
+fn process_session_request(
+    swarm: &mut Swarm<NodeBehaviour>,
+    app: &mut App,
+    peer: PeerId,
+    queued: QueuedSessionRequest,
+) -> Result<()> {
+    let QueuedSessionRequest {
+        request_id,
+        request: req,
+        channel,
+    } = queued;
+
+    let remote_version = req.version.unwrap_or_default();
+    match is_compatible(&local_protocol_version(), &remote_version) {
+        Compatibility::Compatible => {}
+        Compatibility::MinorAhead => info!(
+            "peer {peer} speaks newer protocol minor {}.{}",
+            remote_version.major, remote_version.minor
+        ),
+        Compatibility::Incompatible => {
+            app.on_responder(
+                peer,
+                ResponderTrigger::VerifyFailed(RejectReason::VersionMismatch),
+            );
+            return send_session_reject(
+                swarm,
+                app,
+                peer,
+                channel,
+                request_id,
+                session_reject(
+                    req.session_id,
+                    RejectReason::VersionMismatch,
+                    SessionRejectDetailCode::ProtocolVersionMismatch,
+                    format!(
+                        "protocol major mismatch: expected {}, got {:?}",
+                        PROTOCOL_MAJOR,
+                        req.version.map(|v| v.major)
+                    ),
+                ),
+            );
+        }
+    }
+
+    let now_unix_ms = app.now_ms();
+    let verify_result = verify_inbound_session_request(app, peer, &req);
+    let verified = match verify_result {
+        Ok(v) => {
+            app.auth_throttle.record_success(&peer);
+            v
+        }
+        Err(err) => {
+            if !matches!(err, SessionAuthError::Throttled { .. }) {
+                let now_ms = app.now_ms();
+                app.auth_throttle.record_failure(peer, now_ms);
             }
-            if !pending
-                .request_nonces
-                .iter()
-                .any(|nonce| nonce == &accept.request_nonce)
-            {
-                warn!("invalid SessionAccept from {peer}: request nonce mismatch");
-                app.on_auth_failed(peer);
-                return Ok(());
+            app.on_auth_failed(peer);
+            let (reason, detail_code) = map_auth_error_to_reject(&err);
+            app.on_responder(peer, ResponderTrigger::VerifyFailed(reason));
+            let mut reject = session_reject(req.session_id, reason, detail_code, err.to_string());
+            // A requester whose clock is off can retry against our clock instead.
+            if matches!(err, SessionAuthError::TimestampSkew { .. }) {
+                reject.challenge = issue_challenge(&app.local_key, now_unix_ms)
+                    .inspect_err(|err| warn!("issue challenge for {peer} failed: {err}"))
+                    .ok();
             }
+            return send_session_reject(swarm, app, peer, channel, request_id, reject);
+        }
+    };
+
+    if verified.trust_store_changed {
+        if let Err(err) = app.persist_trust_store() {
+            warn!("failed to persist trust store: {err}");
+        } else {
+            info!(
+                "trust store updated for device_code={}",
+                verified.device_code
+            );
+        }
+    }
+    app.peer_device_codes
+        .insert(peer, verified.device_code.clone());
+
+    let mut accept = SessionAccept {
+        session_id: req.session_id,
+        selected_codec: aetherlink_proto::v1::VideoCodec::H264 as i32,
+        selected_fps: 30,
+        selected_width: 1280,
+        selected_height: 720,
+        using_relay: false,
+        path_id: "direct-quic".to_string(),
+        from: Some(DeviceIdentity {
+            peer_id: app.local_peer_id.to_bytes(),
+            identity_pubkey: app.local_key.public().encode_protobuf(),
+            device_code: app.local_device_code.clone(),
+        }),
+        nonce: random_nonce(16),
+        unix_ms: app.now_ms(),
+        signature: Vec::new(),
+        request_nonce: req.nonce.clone(),
+        accepted_feature_bits: req.feature_bits.clone(),
+        version: Some(local_protocol_version()),
+        resumption_ticket: issue_ticket(
+            &app.local_key,
+            &peer,
+            &verified.device_code,
+            now_unix_ms,
+            DEFAULT_RESUMPTION_TICKET_TTL_MS,
+        )
+        .inspect_err(|err| warn!("issue resumption ticket for {peer} failed: {err}"))
+        .ok(),
+    };
+    sign_session_accept(&mut accept, &app.local_key).context("sign SessionAccept")?;
+    let response = ControlEnvelope {
+        seq: app.now_ms() as u64,
+        request_id,
+        message: Some(
+            aetherlink_proto::v1::control_envelope::Message::SessionAccept(accept.clone()),
+        ),
+    };
+    send_control_response(swarm, app, peer, channel, &response)?;
+    app.on_responder(peer, ResponderTrigger::VerifyOk);
+    app.on_accept(peer, accept.session_id.clone());
+    on_session_activated(swarm, app, peer, &accept.session_id);
+    Ok(())
+}
+
+fn session_reject(
+    session_id: String,
+    reason: RejectReason,
+    detail_code: SessionRejectDetailCode,
+    detail: String,
+) -> SessionReject {
+    SessionReject {
+        session_id,
+        reason: reason as i32,
+        detail,
+        detail_code: detail_code as i32,
+        challenge: None,
+    }
+}
+
+fn send_session_reject(
+    swarm: &mut Swarm<NodeBehaviour>,
+    app: &mut App,
+    peer: PeerId,
+    channel: request_response::ResponseChannel<Vec<u8>>,
+    request_id: String,
+    reject: SessionReject,
+) -> Result<()> {
+    let response = ControlEnvelope {
+        seq: app.now_ms() as u64,
+        request_id,
+        message: Some(aetherlink_proto::v1::control_envelope::Message::SessionReject(reject)),
+    };
+    send_control_response(swarm, app, peer, channel, &response)
+}
+
+fn send_control_ack(
+    swarm: &mut Swarm<NodeBehaviour>,
+    app: &mut App,
+    peer: PeerId,
+    channel: request_response::ResponseChannel<Vec<u8>>,
+    request_id: String,
+) -> Result<()> {
+    let response = ControlEnvelope {
+        seq: app.now_ms() as u64,
+        request_id,
+        message: None,
+    };
+    send_control_response(swarm, app, peer, channel, &response)
+}
+
+/// Every inbound control request is answered through here so the encoded response can
+/// be replayed if the peer redelivers the same `request_id`.
+fn send_control_response(
+    swarm: &mut Swarm<NodeBehaviour>,
+    app: &mut App,
+    peer: PeerId,
+    channel: request_response::ResponseChannel<Vec<u8>>,
+    response: &ControlEnvelope,
+) -> Result<()> {
+    let payload = encode_envelope(response);
+    app.recent_control_requests
+        .record_response(peer, &response.request_id, payload.clone());
+    swarm
+        .behaviour_mut()
+        .control
+        .send_response(channel, payload)
+        .map_err(|_| {
+            anyhow!(
+                "send control response request_id={} failed: channel closed",
+                response.request_id
+            )
+        })?;
+    Ok(())
+}
 
-            let verified = match verify_session_accept(
-                &accept,
+/// Verifies `req` from `peer` at the node clock's time: throttling first, then the
+/// resumption ticket if one is offered, then the full signature check.
+fn verify_inbound_session_request(
+    app: &mut App,
+    peer: PeerId,
+    req: &SessionRequest,
+) -> Result<VerifiedSessionPeer, SessionAuthError> {
+    let now_unix_ms = app.now_ms();
+    let policy = app.verifier_policy();
+    // Refuse peers that keep failing before spending any signature work on them.
+    let resumed = if let Err(err) = app.auth_throttle.check(&peer, app.now_ms()) {
+        Some(Err(err))
+    } else {
+        match req.resumption_ticket {
+            Some(_) => match verify_resumed_session_request(
+                req,
                 Some(&peer),
-                Some(&pending.session_id),
-                None,
-                unix_ms() as i64,
-                DEFAULT_ALLOWED_SKEW_MS,
+                Some(&app.local_device_code),
+                now_unix_ms,
+                &policy,
+                &app.local_key.public(),
+                &mut VerifierContext::new(&mut app.nonce_cache, &mut app.trusted_peers, &mut ()),
+            ) {
+                Ok(verified) => {
+                    info!("resumed session for peer={peer} via resumption ticket");
+                    Some(Ok(verified))
+                }
+                Err(
+                    err @ (SessionAuthError::ResumptionTicketExpired
+                    | SessionAuthError::InvalidResumptionTicket(_)),
+                ) => {
+                    info!("resumption ticket from peer={peer} not usable ({err}), verifying fully");
+                    None
+                }
+                Err(err) => Some(Err(err)),
+            },
+            None => None,
+        }
+    };
+    // Full verification reports to the audit log itself; throttling and resumption
+    // decide outside it and are recorded here.
+    if let Some(outcome) = &resumed {
+        app.auth_audit.record(&AuthEvent::from_outcome(
+            AuthMessage::SessionRequest,
+            req.from.as_ref(),
+            Some(&peer),
+            now_unix_ms,
+            outcome,
+        ));
+    }
+    resumed.unwrap_or_else(|| {
+        let mut ctx = VerifierContext::new(
+            &mut app.nonce_cache,
+            &mut app.trusted_peers,
+            &mut app.auth_audit,
+        );
+        // An echoed challenge of ours vouches for freshness in place of the
+        // requester's clock.
+        if req.challenge.is_some() {
+            verify_challenged_session_request(
+                req,
+                Some(&peer),
+                Some(&app.local_device_code),
+                now_unix_ms,
+                &policy,
+                &app.local_key.public(),
+                &mut ctx,
+            )
+        } else {
+            verify_session_request(
+                req,
+                Some(&peer),
+                Some(&app.local_device_code),
+                now_unix_ms,
+                &policy,
+                &mut ctx,
+            )
+        }
+    })
+}
+
+fn handle_control_response(
+    swarm: &mut Swarm<NodeBehaviour>,
+    app: &mut App,
+    peer: PeerId,
+    request_id: request_response::OutboundRequestId,
+    response: Vec<u8>,
+) -> Result<()> {
+    let request_kind = app.pending_outbound_control_requests.remove(&request_id);
+    let env = decode_envelope(&response)?;
+    match env.message {
+        Some(aetherlink_proto::v1::control_envelope::Message::SessionAccept(accept)) => {
+            if !matches!(
+                request_kind,
+                Some(OutboundControlRequestKind::SessionRequest)
+            ) {
+                warn!("unexpected SessionAccept for request kind: {request_kind:?}");
+            }
+            let Some(pending) = app.pending_outbound_sessions.remove(&peer) else {
+                warn!("received SessionAccept from {peer} without pending outbound session");
+                app.on_auth_failed(peer);
+                return Ok(());
+            };
+
+            let policy = app.verifier_policy();
+            let now_unix_ms = app.now_ms();
+            let mut ctx = VerifierContext::new(
                 &mut app.nonce_cache,
                 &mut app.trusted_peers,
-                app.trust_on_first_use,
-            ) {
+                &mut app.auth_audit,
+            );
+            // Once the responder refused our clock, its accept timestamp can't be
+            // judged by it either; the request nonce binding vouches instead.
+            let outcome = if pending.challenge_echoed {
+                verify_challenged_session_accept(
+                    &accept,
+                    Some(&peer),
+                    Some(&pending.session_id),
+                    &pending.request_nonces,
+                    now_unix_ms,
+                    &policy,
+                    &mut ctx,
+                )
+            } else {
+                verify_session_accept(
+                    &accept,
+                    Some(&peer),
+                    Some(&pending.session_id),
+                    Some(&pending.request_nonces),
+                    now_unix_ms,
+                    &policy,
+                    &mut ctx,
+                )
+            };
+            let verified = match outcome {
                 Ok(v) => v,
                 Err(err) => {
                     warn!("invalid SessionAccept from {peer}: {err}");
@@ -1739,6 +3728,23 @@ fn handle_control_response(
                     );
                 }
             }
+            // Checked only once the accept is known to come from the peer, so a forged
+            // version cannot end a session, and reported as what it is, not an auth failure.
+            if let Some(version) = &accept.version
+                && !is_compatible(&local_protocol_version(), version).is_usable()
+            {
+                warn!(
+                    "SessionAccept from {peer} uses incompatible protocol {}.{}",
+                    version.major, version.minor
+                );
+                app.on_version_mismatch(peer);
+                return Ok(());
+            }
+            app.peer_device_codes
+                .insert(peer, verified.device_code.clone());
+            if let Some(ticket) = accept.resumption_ticket.clone() {
+                app.resumption_tickets.insert(peer, ticket);
+            }
 
             info!(
                 "session accepted by {peer}: codec={}, {}x{}@{} relay={}",
@@ -1758,22 +3764,38 @@ fn handle_control_response(
             ) {
                 warn!("unexpected SessionReject for request kind: {request_kind:?}");
             }
+            if reject.detail_code == SessionRejectDetailCode::TimestampSkew as i32
+                && let Some(challenge) = reject.challenge
+                && let Some(pending) = app.pending_outbound_sessions.get_mut(&peer)
+                && !pending.challenge_echoed
+            {
+                info!("peer {peer} refused our clock, retrying with its challenge");
+                pending.challenge = Some(challenge);
+                pending.challenge_echoed = true;
+                return send_session_request(swarm, app, peer);
+            }
             app.pending_outbound_sessions.remove(&peer);
             let reason = RejectReason::try_from(reject.reason)
                 .map(|x| x.as_str_name().to_string())
                 .unwrap_or_else(|_| format!("UNKNOWN({})", reject.reason));
+            let detail_code = SessionRejectDetailCode::try_from(reject.detail_code)
+                .map(|x| x.as_str_name().to_string())
+                .unwrap_or_else(|_| format!("UNKNOWN({})", reject.detail_code));
             warn!(
-                "session rejected by {peer}: reason={} detail={}",
-                reason, reject.detail
+                "session rejected by {peer}: reason={} code={} detail={}",
+                reason, detail_code, reject.detail
             );
             app.on_auth_failed(peer);
         }
         Some(aetherlink_proto::v1::control_envelope::Message::Pong(pong)) => match request_kind {
             Some(OutboundControlRequestKind::KeepalivePing { seq }) if seq == pong.seq => {
                 if let Some(rtt_ms) = app.note_control_pong(peer, &pong) {
+                    let state = app.control_keepalive.get(&peer);
                     info!(
-                        "control keepalive pong peer={peer} seq={} rtt_ms={rtt_ms}",
-                        pong.seq
+                        "control keepalive pong peer={peer} seq={} rtt_ms={rtt_ms} srtt_ms={:?} jitter_ms={:?}",
+                        pong.seq,
+                        state.and_then(ControlKeepaliveState::smoothed_rtt_ms),
+                        state.and_then(ControlKeepaliveState::rtt_jitter_ms)
                     );
                 }
             }
@@ -1804,6 +3826,8 @@ fn handle_control_response(
             Some(OutboundControlRequestKind::PunchSync) => {
                 info!("PunchSync acknowledged by peer={peer}");
             }
+            Some(OutboundControlRequestKind::ClipboardData)
+            | Some(OutboundControlRequestKind::InputEvent) => {}
             Some(OutboundControlRequestKind::SessionRequest)
             | Some(OutboundControlRequestKind::KeepalivePing { .. }) => {
                 warn!("received empty ack for unexpected request kind from peer={peer}");
@@ -1815,34 +3839,590 @@ fn handle_control_response(
     Ok(())
 }
 
-fn encode_envelope(env: &ControlEnvelope) -> Vec<u8> {
-    env.encode_to_vec()
+fn handle_admin_request(
+    swarm: &mut Swarm<NodeBehaviour>,
+    app: &mut App,
+    request: NodeAdminRequest,
+) -> NodeAdminResponse {
+    let payload = match request.payload {
+        Some(node_admin_request::Payload::LookupDevices(lookup)) => {
+            // Normally answered asynchronously by the event loop; answer with what is
+            // already known if it ends up here.
+            node_admin_response::Payload::LookupDevices(LookupDevicesResponse {
+                devices: discovered_devices_for(app, &lookup.device_codes),
+            })
+        }
+        Some(node_admin_request::Payload::ListSessions(_)) | None => {
+            node_admin_response::Payload::ListSessions(ListSessionsResponse {
+                sessions: snapshot_sessions(app)
+                    .into_iter()
+                    .map(session_info_to_proto)
+                    .collect(),
+            })
+        }
+        Some(node_admin_request::Payload::AddTarget(target)) => {
+            let added = app.add_connect_target(&target.device_code);
+            if added {
+                info!("added connect target device_code={}", target.device_code);
+            }
+            node_admin_response::Payload::AddTarget(AddTargetResponse { added })
+        }
+        Some(node_admin_request::Payload::SetClipboardSync(sync)) => {
+            let device_code = sync.device_code.trim().to_string();
+            let detail = if sync.enabled {
+                app.clipboard_sync_devices.insert(device_code.clone());
+                format!("clipboard sync enabled for {device_code}")
+            } else {
+                app.clipboard_sync_devices.remove(&device_code);
+                app.remote_clipboards.remove(&device_code);
+                format!("clipboard sync disabled for {device_code}")
+            };
+            node_admin_response::Payload::SetClipboardSync(GenericAck { ok: true, detail })
+        }
+        Some(node_admin_request::Payload::SendClipboard(send)) => {
+            let bytes = send.data.len();
+            if let Err(err) =
+                send_clipboard_data(swarm, app, send.device_code.trim(), send.mime, send.data)
+            {
+                return NodeAdminResponse {
+                    payload: None,
+                    error: format!("{err:#}"),
+                };
+            }
+            node_admin_response::Payload::SendClipboard(GenericAck {
+                ok: true,
+                detail: format!("sent {bytes} clipboard bytes"),
+            })
+        }
+        Some(node_admin_request::Payload::SendInput(send)) => {
+            let Some(event) = send.event else {
+                return NodeAdminResponse {
+                    payload: None,
+                    error: "missing input event".to_string(),
+                };
+            };
+            if let Err(err) = send_input_event(swarm, app, send.device_code.trim(), event) {
+                return NodeAdminResponse {
+                    payload: None,
+                    error: format!("{err:#}"),
+                };
+            }
+            node_admin_response::Payload::SendInput(GenericAck {
+                ok: true,
+                detail: "input event forwarded".to_string(),
+            })
+        }
+        Some(node_admin_request::Payload::GetClipboard(get)) => {
+            let latest = app
+                .remote_clipboards
+                .get(get.device_code.trim())
+                .cloned()
+                .unwrap_or_default();
+            node_admin_response::Payload::GetClipboard(NodeGetClipboardResponse {
+                mime: latest.mime,
+                data: latest.data,
+            })
+        }
+        Some(node_admin_request::Payload::TakeVideoFrames(take)) => {
+            let received = app
+                .received_video
+                .remove(take.device_code.trim())
+                .unwrap_or_default();
+            node_admin_response::Payload::TakeVideoFrames(NodeTakeVideoFramesResponse {
+                frames: received
+                    .frames
+                    .into_iter()
+                    .map(|frame| NodeVideoFrame {
+                        frame_id: frame.frame_id,
+                        pts_us: frame.pts_us,
+                        keyframe: frame.keyframe,
+                        data: frame.data,
+                    })
+                    .collect(),
+                dropped: received.dropped,
+            })
+        }
+        Some(node_admin_request::Payload::UpdateNetwork(update)) => {
+            match apply_network_update(swarm, app, update) {
+                Ok(detail) => {
+                    node_admin_response::Payload::UpdateNetwork(GenericAck { ok: true, detail })
+                }
+                Err(err) => {
+                    return NodeAdminResponse {
+                        payload: None,
+                        error: format!("{err:#}"),
+                    };
+                }
+            }
+        }
+        Some(node_admin_request::Payload::SetPeerRevoked(request)) => {
+            let device_code = request.device_code.trim();
+            match app.set_device_revoked(device_code, request.revoked) {
+                Ok(peers) => {
+                    for peer in &peers {
+                        app.mark_graceful_closing(*peer);
+                        let _ = swarm.disconnect_peer_id(*peer);
+                    }
+                    let detail = if request.revoked {
+                        info!("revoked trust in device_code={device_code}");
+                        format!(
+                            "device {device_code} revoked, {} connection(s) closed",
+                            peers.len()
+                        )
+                    } else {
+                        info!("cleared revocation of device_code={device_code}");
+                        format!("device {device_code} no longer revoked")
+                    };
+                    node_admin_response::Payload::SetPeerRevoked(GenericAck { ok: true, detail })
+                }
+                Err(err) => {
+                    return NodeAdminResponse {
+                        payload: None,
+                        error: format!("{err:#}"),
+                    };
+                }
+            }
+        }
+        Some(node_admin_request::Payload::TrustPeer(request)) => {
+            let device_code = request.device_code.trim();
+            let approved = request
+                .peer_id
+                .trim()
+                .parse::<PeerId>()
+                .context("invalid peer id")
+                .and_then(|peer_id| {
+                    app.approve_identity(device_code, &peer_id)
+                        .map(|added| (peer_id, added))
+                });
+            match approved {
+                Ok((peer_id, added)) => {
+                    let detail = if added {
+                        info!("approved peer={peer_id} for device_code={device_code}");
+                        format!("peer {peer_id} trusted as device {device_code}")
+                    } else {
+                        format!("peer {peer_id} was already trusted as device {device_code}")
+                    };
+                    node_admin_response::Payload::TrustPeer(GenericAck { ok: true, detail })
+                }
+                Err(err) => {
+                    return NodeAdminResponse {
+                        payload: None,
+                        error: format!("{err:#}"),
+                    };
+                }
+            }
+        }
+        Some(node_admin_request::Payload::RotateIdentity(_)) => {
+            match rotate_local_identity(swarm, app) {
+                Ok(response) => node_admin_response::Payload::RotateIdentity(response),
+                Err(err) => {
+                    warn!("identity rotation failed: {err:#}");
+                    return NodeAdminResponse {
+                        payload: None,
+                        error: format!("{err:#}"),
+                    };
+                }
+            }
+        }
+    };
+    NodeAdminResponse {
+        payload: Some(payload),
+        error: String::new(),
+    }
 }
 
-fn decode_envelope(payload: &[u8]) -> Result<ControlEnvelope> {
-    ControlEnvelope::decode(payload).context("decode ControlEnvelope failed")
+/// Signs, with both keys, a rebind of this node's device code from `old_key` to
+/// `new_key`. Device codes are peer id strings, so the proof also names the new code.
+fn build_rotation_proof(
+    old_key: &identity::Keypair,
+    new_key: &identity::Keypair,
+    now_unix_ms: i64,
+) -> Result<Vec<u8>> {
+    let proof = sign_identity_rotation(
+        old_key,
+        new_key,
+        &PeerId::from(old_key.public()).to_string(),
+        &PeerId::from(new_key.public()).to_string(),
+        now_unix_ms,
+    )
+    .context("sign identity rotation proof failed")?;
+    Ok(proof.encode_to_vec())
 }
 
-fn random_nonce(len: usize) -> Vec<u8> {
-    let mut bytes = vec![0_u8; len];
-    rand::rng().fill_bytes(&mut bytes);
-    bytes
-}
+/// Replaces the on-disk identity key with a fresh one and announces the move in the
+/// DHT: a forwarding record under the old device code carries the rotation proof so
+/// peers that trust the old key can rebind, and a record under the new code points at
+/// the new peer id. The running swarm keeps its old identity until restarted.
+fn rotate_local_identity(
+    swarm: &mut Swarm<NodeBehaviour>,
+    app: &mut App,
+) -> Result<RotateIdentityResponse> {
+    let identity_path = app.identity_path.clone().ok_or_else(|| {
+        anyhow!("identity key was provided inline ({IDENTITY_KEY_ENV} or stdin); rotate it at its source")
+    })?;
+    let new_key = identity::Keypair::generate_ed25519();
+    let new_peer_id = PeerId::from(new_key.public());
+    let proof = build_rotation_proof(&app.local_key, &new_key, app.now_ms())?;
+
+    let encoded = new_key
+        .to_protobuf_encoding()
+        .context("encode identity key protobuf failed")?;
+    write_atomic(&identity_path, &encoded)?;
+    set_restrictive_permissions(&identity_path)?;
+    info!(
+        "rotated identity key {} -> {new_peer_id}; restart the node to serve under the new identity",
+        app.local_peer_id
+    );
+
+    let mut addrs = app.known_local_addrs.clone();
+    for addr in swarm.external_addresses() {
+        if !addrs.contains(addr) {
+            addrs.push(addr.clone());
+        }
+    }
+    addrs.retain(|addr| is_publishable_addr(addr, app.publish_private_addrs));
+    let addrs: Vec<String> = addrs.iter().map(ToString::to_string).collect();
+    let now_unix_ms = app.now_ms();
+    for (device_code, rotation_proof) in [
+        (app.local_device_code.clone(), Some(proof.clone())),
+        (new_peer_id.to_string(), None),
+    ] {
+        let announcement = DeviceAnnouncement {
+            version: 1,
+            device_code: device_code.clone(),
+            peer_id: new_peer_id.to_string(),
+            addrs: addrs.clone(),
+            unix_ms: now_unix_ms,
+            rotation_proof,
+        };
+        let payload = serde_json::to_vec(&announcement).context("serialize announcement failed")?;
+        let record = kad::Record::new(device_record_key(&device_code), payload);
+        match swarm
+            .behaviour_mut()
+            .kad
+            .put_record(record, kad::Quorum::One)
+        {
+            Ok(query_id) => {
+                app.pending_device_publish_queries.insert(query_id);
+            }
+            Err(err) => warn!("unable to publish rotated device record for {device_code}: {err}"),
+        }
+    }
+    // Stop republishing the old code's plain record over the forwarding one.
+    app.publish_device_record = false;
+
+    Ok(RotateIdentityResponse {
+        old_peer_id: app.local_peer_id.to_string(),
+        new_peer_id: new_peer_id.to_string(),
+        rotation_proof: proof,
+    })
+}
+
+/// Rebinds our trust record for a device that announced an identity rotation and
+/// retargets discovery to its new device code. Rotations for devices we never trusted
+/// are ignored; the new identity then goes through the usual first-use checks.
+fn apply_announced_rotation(
+    app: &mut App,
+    announcement: &DeviceAnnouncement,
+    new_peer_id: PeerId,
+) -> Result<()> {
+    let Some(encoded) = &announcement.rotation_proof else {
+        return Ok(());
+    };
+    let proof = IdentityRotationProof::decode(encoded.as_slice())
+        .context("decode IdentityRotationProof failed")?;
+    let (_, new_pubkey) = verify_rotation_proof(&proof)?;
+    if proof.device_code != announcement.device_code
+        || PeerId::from_public_key(&new_pubkey) != new_peer_id
+    {
+        return Err(anyhow!(
+            "rotation proof does not match announcement for {}",
+            announcement.device_code
+        ));
+    }
+    let now_unix_ms = app.now_ms();
+    let new_device_code = match app.trusted_peers.rotate_identity(&proof, now_unix_ms) {
+        Ok(code) => code,
+        Err(SessionAuthError::UntrustedPeer { .. }) => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    app.persist_trust_store()?;
+    for target in &mut app.connect_device_codes {
+        if *target == proof.device_code {
+            *target = new_device_code.clone();
+        }
+    }
+    info!(
+        "device {} rotated identity to {new_peer_id} (device code {new_device_code})",
+        proof.device_code
+    );
+    Ok(())
+}
+
+/// Collects one entry per peer that has a state machine or an active session, sorted by
+/// peer id so admin output is stable between calls.
+fn snapshot_sessions(app: &App) -> Vec<SessionInfo> {
+    let mut peers = app
+        .sessions
+        .keys()
+        .chain(app.active_sessions.keys())
+        .copied()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    peers.sort_by_key(|peer_id| peer_id.to_string());
+
+    peers
+        .into_iter()
+        .map(|peer_id| SessionInfo {
+            peer_id,
+            device_code: app
+                .peer_device_codes
+                .get(&peer_id)
+                .cloned()
+                .unwrap_or_else(|| peer_id.to_string()),
+            state: app
+                .sessions
+                .get(&peer_id)
+                .map(|sm| format!("{:?}", sm.state()))
+                .unwrap_or_else(|| format!("{:?}", ConnectionState::Idle)),
+            session_id: app.active_sessions.get(&peer_id).cloned(),
+            keepalive_rtt_ms: app
+                .control_keepalive
+                .get(&peer_id)
+                .and_then(|state| state.last_rtt_ms),
+            health_score: connection_health(app, peer_id),
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct HealthInputs {
+    active: bool,
+    smoothed_rtt_ms: Option<i64>,
+    jitter_ms: Option<i64>,
+    loss_ratio: f64,
+    relayed: bool,
+    reconnects: u32,
+}
+
+/// Single 0-100 health number for the UI. Only active sessions score above zero; RTT
+/// and jitter are ignored until the first keepalive round trip completes.
+fn connection_health(app: &App, peer_id: PeerId) -> u8 {
+    let keepalive = app.control_keepalive.get(&peer_id);
+    let loss_ratio = keepalive
+        .filter(|state| state.pings_sent > 0)
+        .map(|state| state.pings_missed as f64 / state.pings_sent as f64)
+        .unwrap_or_default();
+    health_score(HealthInputs {
+        active: app.active_sessions.contains_key(&peer_id),
+        smoothed_rtt_ms: keepalive.and_then(ControlKeepaliveState::smoothed_rtt_ms),
+        jitter_ms: keepalive.and_then(ControlKeepaliveState::rtt_jitter_ms),
+        loss_ratio,
+        relayed: app.relayed_peers.contains(&peer_id),
+        reconnects: app
+            .peer_reconnects
+            .get(&peer_id)
+            .copied()
+            .unwrap_or_default(),
+    })
+}
+
+fn health_score(inputs: HealthInputs) -> u8 {
+    if !inputs.active {
+        return 0;
+    }
+    // Up to 40 points for RTT between 50ms and 500ms, 10 for jitter, 30 for loss
+    // (saturating at 20%), 10 for relaying and 5 per reconnect up to 20.
+    let rtt_penalty = inputs
+        .smoothed_rtt_ms
+        .map(|rtt| ((rtt - 50).max(0) as f64 / 450.0 * 40.0).min(40.0))
+        .unwrap_or_default();
+    let jitter_penalty = inputs
+        .jitter_ms
+        .map(|jitter| (jitter as f64 / 10.0).min(10.0))
+        .unwrap_or_default();
+    let loss_penalty = (inputs.loss_ratio.clamp(0.0, 1.0) * 150.0).min(30.0);
+    let relay_penalty = if inputs.relayed { 10.0 } else { 0.0 };
+    let reconnect_penalty = (inputs.reconnects as f64 * 5.0).min(20.0);
+    let score =
+        100.0 - rtt_penalty - jitter_penalty - loss_penalty - relay_penalty - reconnect_penalty;
+    score.clamp(0.0, 100.0).round() as u8
+}
+
+/// Early compatibility gate run on identify: the peer must serve the control protocol,
+/// and an `AetherLink-v<major>...` agent string must carry our protocol major. Custom
+/// agent strings (see `--agent-version`) are judged on the protocol list alone.
+fn is_compatible_peer(protocols: &[StreamProtocol], agent_version: &str) -> bool {
+    let speaks_control = protocols
+        .iter()
+        .any(|protocol| protocol.as_ref() == CONTROL_PROTOCOL);
+    speaks_control && agent_major_version(agent_version).is_none_or(|major| major == PROTOCOL_MAJOR)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IdentifyGateAction {
+    Keep,
+    Disconnect,
+    CloseSessionAndDisconnect,
+}
+
+/// Applied on every identify, not just the first, so a peer that drops the control
+/// protocol or changes major mid-connection loses its session instead of being
+/// trusted on stale assumptions.
+fn identify_gate_action(
+    compatible: bool,
+    infrastructure: bool,
+    has_active_session: bool,
+) -> IdentifyGateAction {
+    match (compatible || infrastructure, has_active_session) {
+        (true, _) => IdentifyGateAction::Keep,
+        (false, false) => IdentifyGateAction::Disconnect,
+        (false, true) => IdentifyGateAction::CloseSessionAndDisconnect,
+    }
+}
+
+fn protocols_changed(prev: &HashSet<StreamProtocol>, now: &HashSet<StreamProtocol>) -> bool {
+    prev != now
+}
+
+fn agent_major_version(agent_version: &str) -> Option<u32> {
+    agent_version
+        .strip_prefix("AetherLink-v")?
+        .split(['.', '-', ' '])
+        .next()?
+        .parse()
+        .ok()
+}
+
+fn is_relayed_addr(addr: &Multiaddr) -> bool {
+    addr.iter()
+        .any(|protocol| matches!(protocol, libp2p::multiaddr::Protocol::P2pCircuit))
+}
+
+/// Maps how a connection was established onto the state machine's path trigger. A
+/// circuit address on either side means the bytes flow through a relay; a dial with
+/// the listener role forced on us is the simultaneous-open half of a hole punch.
+fn trigger_for_endpoint(endpoint: &ConnectedPoint) -> Trigger {
+    match endpoint {
+        ConnectedPoint::Dialer { address, .. } if is_relayed_addr(address) => {
+            Trigger::RelayConnected
+        }
+        ConnectedPoint::Listener {
+            local_addr,
+            send_back_addr,
+        } if is_relayed_addr(local_addr) || is_relayed_addr(send_back_addr) => {
+            Trigger::RelayConnected
+        }
+        ConnectedPoint::Dialer {
+            role_override: Endpoint::Listener,
+            ..
+        } => Trigger::PunchConnected,
+        ConnectedPoint::Dialer { .. } | ConnectedPoint::Listener { .. } => Trigger::DirectConnected,
+    }
+}
+
+fn session_info_to_proto(info: SessionInfo) -> NodeSessionInfo {
+    NodeSessionInfo {
+        peer_id: info.peer_id.to_string(),
+        device_code: info.device_code,
+        state: info.state,
+        session_id: info.session_id.unwrap_or_default(),
+        keepalive_rtt_ms: info
+            .keepalive_rtt_ms
+            .map(|rtt| rtt.clamp(0, u32::MAX as i64) as u32)
+            .unwrap_or_default(),
+        health_score: info.health_score as u32,
+    }
+}
+
+fn encode_envelope(env: &ControlEnvelope) -> Vec<u8> {
+    env.encode_to_vec()
+}
+
+fn decode_envelope(payload: &[u8]) -> Result<ControlEnvelope> {
+    try_decode_control(payload).context("decode ControlEnvelope failed")
+}
+
+fn random_nonce(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0_u8; len];
+    rand::rng().fill_bytes(&mut bytes);
+    bytes
+}
 
 #[derive(Debug, Serialize, Deserialize, Default)]
-struct TrustStoreFileV1 {
+struct ReplayCacheFileV1 {
     version: u32,
-    peers: Vec<TrustedPeerRecord>,
+    /// `(nonce, seen_unix_ms)`, oldest first.
+    nonces: Vec<(Vec<u8>, i64)>,
+}
+
+/// The parts of the process environment that decide the default data directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct DataDirEnv {
+    xdg_config_home: Option<PathBuf>,
+    home: Option<PathBuf>,
 }
 
-fn default_data_dir() -> PathBuf {
-    if let Ok(home) = env::var("HOME") {
-        return PathBuf::from(home).join(".config").join("aetherlink");
+impl DataDirEnv {
+    fn from_process() -> Self {
+        let non_empty = |name: &str| {
+            env::var_os(name)
+                .filter(|value| !value.is_empty())
+                .map(PathBuf::from)
+        };
+        Self {
+            xdg_config_home: if cfg!(unix) {
+                non_empty("XDG_CONFIG_HOME")
+            } else {
+                None
+            },
+            home: non_empty("HOME"),
+        }
+    }
+}
+
+/// `--data-dir`, then `$XDG_CONFIG_HOME/aetherlink`, then `$HOME/.config/aetherlink`,
+/// then `.aetherlink` in the working directory. A relative `$XDG_CONFIG_HOME` is
+/// ignored, as the XDG base directory spec requires.
+fn resolve_data_dir(cli: Option<PathBuf>, env: &DataDirEnv) -> PathBuf {
+    if let Some(dir) = cli {
+        return dir;
+    }
+    if let Some(xdg) = env.xdg_config_home.as_ref().filter(|dir| dir.is_absolute()) {
+        return xdg.join("aetherlink");
+    }
+    if let Some(home) = &env.home {
+        return home.join(".config").join("aetherlink");
     }
     PathBuf::from(".aetherlink")
 }
 
-fn load_or_create_identity_key(path: &Path) -> Result<identity::Keypair> {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InlineIdentitySource {
+    Env,
+    Stdin,
+}
+
+/// Loads the identity key, preferring a key handed in via `AETHERLINK_IDENTITY_KEY` or
+/// stdin over the identity file. Inline keys are never written to disk, which keeps
+/// read-only container filesystems working.
+fn load_or_create_identity_key(
+    path: &Path,
+    inline_key: Option<(InlineIdentitySource, Vec<u8>)>,
+) -> Result<identity::Keypair> {
+    if let Some((source, bytes)) = inline_key {
+        let key = decode_inline_identity_key(&bytes).with_context(|| match source {
+            InlineIdentitySource::Env => format!("decode {IDENTITY_KEY_ENV} failed"),
+            InlineIdentitySource::Stdin => "decode identity key from stdin failed".to_string(),
+        })?;
+        info!(
+            "using identity key from {source:?}, file {} ignored",
+            path.display()
+        );
+        return Ok(key);
+    }
+
     if path.exists() {
         let bytes = fs::read(path)
             .with_context(|| format!("read identity file failed: {}", path.display()))?;
@@ -1859,23 +4439,81 @@ fn load_or_create_identity_key(path: &Path) -> Result<identity::Keypair> {
     Ok(key)
 }
 
+/// Accepts either the raw protobuf key encoding or its base64 text form (standard or
+/// URL-safe alphabet, padding optional).
+fn decode_inline_identity_key(bytes: &[u8]) -> Result<identity::Keypair> {
+    if let Ok(key) = identity::Keypair::from_protobuf_encoding(bytes) {
+        return Ok(key);
+    }
+    let text = std::str::from_utf8(bytes).context("identity key is neither protobuf nor text")?;
+    let decoded = decode_base64(text.trim()).context("identity key is not valid base64")?;
+    identity::Keypair::from_protobuf_encoding(&decoded)
+        .context("identity key is not a protobuf-encoded keypair")
+}
+
+fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for ch in input.trim_end_matches('=').bytes() {
+        let value = match ch {
+            b'A'..=b'Z' => ch - b'A',
+            b'a'..=b'z' => ch - b'a' + 26,
+            b'0'..=b'9' => ch - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+        acc = (acc << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    // A lone trailing sextet cannot encode a whole byte.
+    (bits < 6).then_some(out)
+}
+
 fn load_trusted_peers(path: &Path) -> Result<TrustedPeers> {
     if !path.exists() {
         return Ok(TrustedPeers::default());
     }
     let bytes =
         fs::read(path).with_context(|| format!("read trust file failed: {}", path.display()))?;
-    let parsed: TrustStoreFileV1 = serde_json::from_slice(&bytes)
+    let parsed: TrustStoreFile = serde_json::from_slice(&bytes)
         .with_context(|| format!("parse trust file failed: {}", path.display()))?;
-    TrustedPeers::from_records(parsed.peers).context("decode trusted peers failed")
+    TrustedPeers::from_store(parsed).context("decode trusted peers failed")
 }
 
 fn save_trusted_peers(path: &Path, trusted_peers: &TrustedPeers) -> Result<()> {
-    let payload = TrustStoreFileV1 {
+    let payload = trusted_peers.to_store();
+    let json = serde_json::to_vec_pretty(&payload).context("serialize trust file failed")?;
+    write_atomic(path, &json)
+}
+
+fn load_replay_cache(path: &Path, now_unix_ms: i64) -> Result<NonceReplayCache> {
+    if !path.exists() {
+        return Ok(NonceReplayCache::default());
+    }
+    let bytes =
+        fs::read(path).with_context(|| format!("read replay cache failed: {}", path.display()))?;
+    let parsed: ReplayCacheFileV1 = serde_json::from_slice(&bytes)
+        .with_context(|| format!("parse replay cache failed: {}", path.display()))?;
+    Ok(NonceReplayCache::from_records(
+        DEFAULT_REPLAY_RETENTION_MS,
+        DEFAULT_REPLAY_MAX_ENTRIES,
+        parsed.nonces,
+        now_unix_ms,
+    ))
+}
+
+fn save_replay_cache(path: &Path, cache: &NonceReplayCache) -> Result<()> {
+    let payload = ReplayCacheFileV1 {
         version: 1,
-        peers: trusted_peers.to_records(),
+        nonces: cache.to_records(),
     };
-    let json = serde_json::to_vec_pretty(&payload).context("serialize trust file failed")?;
+    let json = serde_json::to_vec(&payload).context("serialize replay cache failed")?;
     write_atomic(path, &json)
 }
 
@@ -1914,12 +4552,13 @@ fn unix_ms() -> u64 {
         .unwrap_or_default()
 }
 
-fn map_auth_error_to_reject(err: &SessionAuthError) -> RejectReason {
-    match err {
+fn map_auth_error_to_reject(err: &SessionAuthError) -> (RejectReason, SessionRejectDetailCode) {
+    let reason = match err {
         SessionAuthError::InvalidTargetDeviceCode { .. } => RejectReason::PolicyDenied,
-        SessionAuthError::UntrustedPeer { .. } | SessionAuthError::TrustedPeerMismatch { .. } => {
-            RejectReason::PolicyDenied
-        }
+        SessionAuthError::UntrustedPeer { .. }
+        | SessionAuthError::TrustedPeerMismatch { .. }
+        | SessionAuthError::RevokedPeer { .. }
+        | SessionAuthError::Throttled { .. } => RejectReason::PolicyDenied,
         SessionAuthError::TimestampSkew { .. }
         | SessionAuthError::ReplayDetected
         | SessionAuthError::InvalidSenderPublicKey
@@ -1936,7 +4575,50 @@ fn map_auth_error_to_reject(err: &SessionAuthError) -> RejectReason {
         | SessionAuthError::SessionIdMismatch { .. }
         | SessionAuthError::MissingRequestNonceBinding
         | SessionAuthError::RequestNonceMismatch
-        | SessionAuthError::TrustStoreCorrupt(_) => RejectReason::AuthFailed,
+        | SessionAuthError::TrustStoreCorrupt(_)
+        | SessionAuthError::ResumptionTicketExpired
+        | SessionAuthError::InvalidResumptionTicket(_)
+        | SessionAuthError::InvalidRotationProof(_)
+        | SessionAuthError::MissingChallenge
+        | SessionAuthError::InvalidChallenge(_)
+        | SessionAuthError::ChallengeExpired => RejectReason::AuthFailed,
+    };
+    (reason, auth_error_to_detail_code(err))
+}
+
+/// Machine-readable counterpart of `SessionAuthError`. Deliberately has no wildcard arm
+/// so adding an error variant fails to compile until it gets a wire code.
+fn auth_error_to_detail_code(err: &SessionAuthError) -> SessionRejectDetailCode {
+    use SessionRejectDetailCode as Code;
+    match err {
+        SessionAuthError::MissingSenderIdentity => Code::MissingSenderIdentity,
+        SessionAuthError::MissingResponderIdentity => Code::MissingResponderIdentity,
+        SessionAuthError::MissingDeviceCode => Code::MissingDeviceCode,
+        SessionAuthError::MissingNonce => Code::MissingNonce,
+        SessionAuthError::NonceTooShort { .. } => Code::NonceTooShort,
+        SessionAuthError::InvalidTargetDeviceCode { .. } => Code::InvalidTargetDeviceCode,
+        SessionAuthError::TimestampSkew { .. } => Code::TimestampSkew,
+        SessionAuthError::ReplayDetected => Code::ReplayDetected,
+        SessionAuthError::InvalidSenderPublicKey => Code::InvalidSenderPublicKey,
+        SessionAuthError::InvalidSenderPeerId => Code::InvalidSenderPeerId,
+        SessionAuthError::PeerIdMismatch => Code::PeerIdMismatch,
+        SessionAuthError::TransportPeerIdMismatch => Code::TransportPeerIdMismatch,
+        SessionAuthError::InvalidSignature => Code::InvalidSignature,
+        SessionAuthError::SigningFailed => Code::SigningFailed,
+        SessionAuthError::SessionIdMismatch { .. } => Code::SessionIdMismatch,
+        SessionAuthError::MissingRequestNonceBinding => Code::MissingRequestNonceBinding,
+        SessionAuthError::RequestNonceMismatch => Code::RequestNonceMismatch,
+        SessionAuthError::UntrustedPeer { .. } => Code::UntrustedPeer,
+        SessionAuthError::TrustedPeerMismatch { .. } => Code::TrustedPeerMismatch,
+        SessionAuthError::RevokedPeer { .. } => Code::PeerRevoked,
+        SessionAuthError::TrustStoreCorrupt(_) => Code::TrustStoreCorrupt,
+        SessionAuthError::ResumptionTicketExpired => Code::ResumptionTicketExpired,
+        SessionAuthError::InvalidResumptionTicket(_) => Code::InvalidResumptionTicket,
+        SessionAuthError::InvalidRotationProof(_) => Code::InvalidRotationProof,
+        SessionAuthError::Throttled { .. } => Code::AuthThrottled,
+        SessionAuthError::MissingChallenge => Code::MissingChallenge,
+        SessionAuthError::InvalidChallenge(_) => Code::InvalidChallenge,
+        SessionAuthError::ChallengeExpired => Code::ChallengeExpired,
     }
 }
 
@@ -1953,7 +4635,9 @@ fn ensure_addr_has_peer_id(mut addr: Multiaddr, peer_id: PeerId) -> Multiaddr {
 }
 
 fn candidate_type_for_addr(addr: &Multiaddr) -> i32 {
-    if addr
+    if relay::parse_circuit_addr(addr).is_some() {
+        CandidateType::Relay as i32
+    } else if addr
         .iter()
         .any(|p| matches!(p, libp2p::multiaddr::Protocol::Ip6(_)))
     {
@@ -1963,9 +4647,1527 @@ fn candidate_type_for_addr(addr: &Multiaddr) -> i32 {
     }
 }
 
+/// Whether `addr` is worth putting in the global DHT record. Loopback and unspecified
+/// addresses never are; private-range addresses (RFC1918, link-local, IPv6 ULA) only
+/// when `allow_private` is set. Addresses without an IP component (e.g. `/dns4`) pass.
+fn is_publishable_addr(addr: &Multiaddr, allow_private: bool) -> bool {
+    let ip = addr.iter().find_map(|protocol| match protocol {
+        libp2p::multiaddr::Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+        libp2p::multiaddr::Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+        _ => None,
+    });
+    let Some(ip) = ip else {
+        return true;
+    };
+    if ip.is_loopback() || ip.is_unspecified() {
+        return false;
+    }
+    let private = match ip {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_link_local(),
+        IpAddr::V6(ip) => ip.is_unicast_link_local() || ip.is_unique_local(),
+    };
+    allow_private || !private
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum UpnpAddrChange {
+    Mapped(Multiaddr),
+    Expired(Multiaddr),
+}
+
+/// Returns the external address a UPnP event adds or withdraws; gateway failures carry
+/// no address and yield `None`.
+fn upnp_external_addr_change(event: &upnp::Event) -> Option<UpnpAddrChange> {
+    match event {
+        upnp::Event::NewExternalAddr(addr) => Some(UpnpAddrChange::Mapped(addr.clone())),
+        upnp::Event::ExpiredExternalAddr(addr) => Some(UpnpAddrChange::Expired(addr.clone())),
+        upnp::Event::GatewayNotFound | upnp::Event::NonRoutableGateway => None,
+    }
+}
+
 fn extract_peer_id(addr: &Multiaddr) -> Option<PeerId> {
     addr.iter().find_map(|protocol| match protocol {
         libp2p::multiaddr::Protocol::P2p(peer_id) => Some(peer_id),
         _ => None,
     })
 }
+
+/// Expands `HOST[:PORT][/p2p/PEER_ID]` into a QUIC multiaddr. Hostnames become
+/// `/dns4/...` so the DNS transport resolves them to current addresses on dial;
+/// IP literals (IPv6 in brackets when a port is given) are used as-is.
+fn expand_bootstrap_host(spec: &str) -> Result<Multiaddr> {
+    let spec = spec.trim();
+    let (host_port, peer_id) = match spec.split_once("/p2p/") {
+        Some((head, peer_id)) => (
+            head,
+            Some(
+                peer_id
+                    .parse::<PeerId>()
+                    .with_context(|| format!("invalid peer id in bootstrap spec: {spec}"))?,
+            ),
+        ),
+        None => (spec, None),
+    };
+    let (host, port) = split_host_port(host_port)?;
+
+    let mut addr = Multiaddr::empty();
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => addr.push(libp2p::multiaddr::Protocol::Ip4(ip)),
+        Ok(IpAddr::V6(ip)) => addr.push(libp2p::multiaddr::Protocol::Ip6(ip)),
+        Err(_) => addr.push(libp2p::multiaddr::Protocol::Dns4(host.to_string().into())),
+    }
+    addr.push(libp2p::multiaddr::Protocol::Udp(port));
+    addr.push(libp2p::multiaddr::Protocol::QuicV1);
+    if let Some(peer_id) = peer_id {
+        addr.push(libp2p::multiaddr::Protocol::P2p(peer_id));
+    }
+    Ok(addr)
+}
+
+fn split_host_port(text: &str) -> Result<(&str, u16)> {
+    let (host, port) = if let Some(rest) = text.strip_prefix('[') {
+        let (host, tail) = rest
+            .split_once(']')
+            .ok_or_else(|| anyhow!("unterminated IPv6 literal: {text}"))?;
+        if tail.is_empty() {
+            (host, None)
+        } else {
+            let port = tail
+                .strip_prefix(':')
+                .ok_or_else(|| anyhow!("unexpected characters after IPv6 literal: {text}"))?;
+            (host, Some(port))
+        }
+    } else {
+        match text.rsplit_once(':') {
+            // More than one colon without brackets is a bare IPv6 literal.
+            Some((host, port)) if !host.contains(':') => (host, Some(port)),
+            _ => (text, None),
+        }
+    };
+
+    if host.is_empty() {
+        return Err(anyhow!("missing host in bootstrap spec: {text}"));
+    }
+    let port = match port {
+        Some(port) => port
+            .parse::<u16>()
+            .with_context(|| format!("invalid port in bootstrap spec: {text}"))?,
+        None => DEFAULT_QUIC_PORT,
+    };
+    Ok((host, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+    use aetherlink_core::{FailureReason, TrustedPeerRecord};
+
+    fn test_app() -> App {
+        let local_key = identity::Keypair::generate_ed25519();
+        let local_peer_id = PeerId::from(local_key.public());
+        App::new(
+            local_key,
+            local_peer_id,
+            false,
+            std::env::temp_dir().join(format!("aetherlink-node-test-{local_peer_id}.json")),
+            TrustedPeers::default(),
+            false,
+            1200,
+            3,
+            Vec::new(),
+            2500,
+            15000,
+            true,
+            1000,
+            1200,
+            3,
+            0,
+        )
+    }
+
+    fn test_app_at(clock: &FakeClock) -> App {
+        test_app().with_clock(clock.clone())
+    }
+
+    fn drive_to_active(app: &mut App, peer_id: PeerId, session_id: &str) {
+        app.on_connected(peer_id, Trigger::DirectConnected);
+        app.on_accept(peer_id, session_id.to_string());
+    }
+
+    #[test]
+    fn expands_hostname_with_port_and_peer_id() {
+        let peer_id = PeerId::random();
+        let addr = expand_bootstrap_host(&format!("boot.example.com:4001/p2p/{peer_id}")).unwrap();
+        assert_eq!(
+            addr.to_string(),
+            format!("/dns4/boot.example.com/udp/4001/quic-v1/p2p/{peer_id}")
+        );
+        assert_eq!(extract_peer_id(&addr), Some(peer_id));
+    }
+
+    #[test]
+    fn expands_bare_hostname_with_default_port() {
+        let addr = expand_bootstrap_host("boot.example.com").unwrap();
+        assert_eq!(addr.to_string(), "/dns4/boot.example.com/udp/9000/quic-v1");
+        assert_eq!(extract_peer_id(&addr), None);
+    }
+
+    #[test]
+    fn expands_ip_literals() {
+        let v4 = expand_bootstrap_host("203.0.113.7:9100").unwrap();
+        assert_eq!(v4.to_string(), "/ip4/203.0.113.7/udp/9100/quic-v1");
+
+        let v6 = expand_bootstrap_host("[2001:db8::1]:9100").unwrap();
+        assert_eq!(v6.to_string(), "/ip6/2001:db8::1/udp/9100/quic-v1");
+
+        let bare_v6 = expand_bootstrap_host("2001:db8::1").unwrap();
+        assert_eq!(bare_v6.to_string(), "/ip6/2001:db8::1/udp/9000/quic-v1");
+    }
+
+    #[test]
+    fn rejects_malformed_bootstrap_specs() {
+        assert!(expand_bootstrap_host("").is_err());
+        assert!(expand_bootstrap_host(":9000").is_err());
+        assert!(expand_bootstrap_host("boot.example.com:notaport").is_err());
+        assert!(expand_bootstrap_host("[2001:db8::1:9000").is_err());
+        assert!(expand_bootstrap_host("boot.example.com/p2p/not-a-peer").is_err());
+    }
+
+    #[test]
+    fn snapshot_sessions_reports_state_rtt_and_device_code() {
+        let mut app = test_app();
+        let active_peer = PeerId::random();
+        let handshaking_peer = PeerId::random();
+
+        drive_to_active(&mut app, active_peer, "session-a");
+        app.peer_device_codes
+            .insert(active_peer, "device-a".to_string());
+        app.control_keepalive
+            .get_mut(&active_peer)
+            .unwrap()
+            .last_rtt_ms = Some(42);
+        app.on_connected(handshaking_peer, Trigger::DirectConnected);
+
+        let snapshot = snapshot_sessions(&app);
+        assert_eq!(snapshot.len(), 2);
+
+        let active = snapshot.iter().find(|s| s.peer_id == active_peer).unwrap();
+        assert_eq!(active.device_code, "device-a");
+        assert_eq!(active.state, "Active");
+        assert_eq!(active.session_id.as_deref(), Some("session-a"));
+        assert_eq!(active.keepalive_rtt_ms, Some(42));
+
+        let pending = snapshot
+            .iter()
+            .find(|s| s.peer_id == handshaking_peer)
+            .unwrap();
+        assert_eq!(pending.device_code, handshaking_peer.to_string());
+        assert_eq!(pending.state, "SecureHandshake");
+        assert_eq!(pending.session_id, None);
+        assert_eq!(pending.keepalive_rtt_ms, None);
+    }
+
+    #[test]
+    fn snapshot_sessions_is_sorted_and_empty_for_fresh_app() {
+        let mut app = test_app();
+        assert!(snapshot_sessions(&app).is_empty());
+
+        for _ in 0..4 {
+            app.on_connected(PeerId::random(), Trigger::DirectConnected);
+        }
+        let ids = snapshot_sessions(&app)
+            .into_iter()
+            .map(|s| s.peer_id.to_string())
+            .collect::<Vec<_>>();
+        let mut sorted = ids.clone();
+        sorted.sort();
+        assert_eq!(ids, sorted);
+    }
+
+    #[test]
+    fn session_info_to_proto_fills_defaults() {
+        let peer_id = PeerId::random();
+        let proto = session_info_to_proto(SessionInfo {
+            peer_id,
+            device_code: "device-a".to_string(),
+            state: "Reconnecting".to_string(),
+            session_id: None,
+            keepalive_rtt_ms: None,
+            health_score: 0,
+        });
+        assert_eq!(proto.peer_id, peer_id.to_string());
+        assert_eq!(proto.session_id, "");
+        assert_eq!(proto.keepalive_rtt_ms, 0);
+    }
+
+    #[test]
+    fn session_requests_are_verified_at_the_node_clock() {
+        let signed_at = 1_700_000_000_000;
+        let requester = test_app();
+        let clock = FakeClock::new(signed_at + 5 * 60_000);
+        let mut responder = test_app_at(&clock);
+        responder.trust_on_first_use = true;
+        let responder_peer_id = responder.local_peer_id;
+        let request = |nonce: u8| {
+            build_session_request(
+                &requester,
+                responder_peer_id,
+                "s1",
+                vec![nonce; 16],
+                signed_at,
+                None,
+            )
+            .unwrap()
+        };
+
+        assert!(matches!(
+            verify_inbound_session_request(&mut responder, requester.local_peer_id, &request(1)),
+            Err(SessionAuthError::TimestampSkew { .. })
+        ));
+
+        clock.advance(-5 * 60_000);
+        verify_inbound_session_request(&mut responder, requester.local_peer_id, &request(2))
+            .unwrap();
+    }
+
+    #[test]
+    fn replay_cache_is_saved_on_an_interval() {
+        let clock = FakeClock::new(REPLAY_CACHE_SAVE_INTERVAL_MS);
+        let mut app = test_app_at(&clock);
+        let path =
+            std::env::temp_dir().join(format!("aetherlink-replay-tick-{}.json", PeerId::random()));
+        app.replay_cache_path = Some(path.clone());
+
+        handle_replay_cache_save_tick(&mut app);
+        assert!(path.exists());
+        fs::remove_file(&path).unwrap();
+
+        clock.advance(REPLAY_CACHE_SAVE_INTERVAL_MS - 1);
+        handle_replay_cache_save_tick(&mut app);
+        assert!(!path.exists());
+
+        clock.advance(1);
+        handle_replay_cache_save_tick(&mut app);
+        assert!(path.exists());
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn trust_prune_tick_forgets_stale_peers_and_persists() {
+        let clock = FakeClock::new(1_000);
+        let mut app = test_app_at(&clock);
+        let key = identity::Keypair::generate_ed25519();
+        let code = PeerId::from(key.public()).to_string();
+        app.trusted_peers = TrustedPeers::from_records(vec![TrustedPeerRecord {
+            device_code: code.clone(),
+            peer_id: code,
+            identity_pubkey_hex: key
+                .public()
+                .encode_protobuf()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect(),
+            first_seen_unix_ms: 1_000,
+            last_seen_unix_ms: 1_000,
+        }])
+        .unwrap();
+
+        // Disabled by default.
+        clock.advance(10 * TRUST_PRUNE_INTERVAL_MS);
+        handle_trust_prune_tick(&mut app);
+        assert_eq!(app.trusted_peers.len(), 1);
+
+        app.trusted_peer_max_age_ms = 5 * TRUST_PRUNE_INTERVAL_MS;
+        handle_trust_prune_tick(&mut app);
+        assert!(app.trusted_peers.is_empty());
+        let saved = load_trusted_peers(&app.trust_store_path).unwrap();
+        let _ = fs::remove_file(&app.trust_store_path);
+        assert!(saved.is_empty());
+    }
+
+    #[test]
+    fn revoking_a_device_reports_its_peers_and_persists() {
+        let mut app = test_app();
+        let key = identity::Keypair::generate_ed25519();
+        let peer_id = PeerId::from(key.public());
+        let code = peer_id.to_string();
+        app.trusted_peers = TrustedPeers::from_records(vec![TrustedPeerRecord {
+            device_code: code.clone(),
+            peer_id: code.clone(),
+            identity_pubkey_hex: key
+                .public()
+                .encode_protobuf()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect(),
+            first_seen_unix_ms: 1,
+            last_seen_unix_ms: 1,
+        }])
+        .unwrap();
+        app.connect_device_codes = vec![code.clone()];
+        app.peer_device_codes.insert(peer_id, code.clone());
+
+        assert_eq!(app.set_device_revoked(&code, true).unwrap(), vec![peer_id]);
+        assert!(app.trusted_peers.is_revoked(&code));
+        assert!(app.connect_device_codes.is_empty());
+        let saved = load_trusted_peers(&app.trust_store_path).unwrap();
+        assert!(saved.is_revoked(&code));
+        assert!(saved.is_empty());
+
+        assert!(app.set_device_revoked(&code, false).unwrap().is_empty());
+        let saved = load_trusted_peers(&app.trust_store_path).unwrap();
+        let _ = fs::remove_file(&app.trust_store_path);
+        assert!(!saved.is_revoked(&code));
+    }
+
+    #[test]
+    fn control_request_ids_differ_within_one_millisecond() {
+        let mut app = test_app();
+        let first = app.next_control_request_id("input");
+        let second = app.next_control_request_id("input");
+        assert!(first.starts_with("input-"));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn approving_an_identity_adds_it_beside_the_trusted_one() {
+        let mut app = test_app();
+        app.trust_store_path =
+            std::env::temp_dir().join(format!("aetherlink-approve-test-{}.json", PeerId::random()));
+        let home = identity::Keypair::generate_ed25519();
+        let laptop = identity::Keypair::generate_ed25519();
+        let home_id = PeerId::from(home.public());
+        let laptop_id = PeerId::from(laptop.public());
+        let code = home_id.to_string();
+        app.trusted_peers
+            .add_identity(&code, &home_id, &home.public().encode_protobuf(), 1)
+            .unwrap();
+
+        assert!(app.approve_identity(&code, &laptop_id).unwrap());
+        assert!(!app.approve_identity(&code, &laptop_id).unwrap());
+        let saved = load_trusted_peers(&app.trust_store_path).unwrap();
+        let _ = fs::remove_file(&app.trust_store_path);
+        assert_eq!(
+            saved.peer_ids(&code),
+            vec![home_id.to_string(), laptop_id.to_string()]
+        );
+
+        app.trusted_peers.revoke(&code);
+        assert!(app.approve_identity(&code, &laptop_id).is_err());
+    }
+
+    #[test]
+    fn trusted_peer_ids_drive_auto_request_and_inbound_acceptance() {
+        let mut app = test_app();
+        let key = identity::Keypair::generate_ed25519();
+        let peer_id = PeerId::from(key.public());
+        let stranger = PeerId::random();
+        app.trusted_peers = TrustedPeers::from_records(vec![TrustedPeerRecord {
+            device_code: "laptop".to_string(),
+            peer_id: peer_id.to_string(),
+            identity_pubkey_hex: key
+                .public()
+                .encode_protobuf()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect(),
+            first_seen_unix_ms: 1,
+            last_seen_unix_ms: 1,
+        }])
+        .unwrap();
+        app.auto_request = true;
+        app.connect_device_codes = vec!["laptop".to_string()];
+        assert!(app.should_auto_request_for_peer(peer_id));
+        assert!(!app.should_auto_request_for_peer(stranger));
+
+        assert!(app.accepts_inbound_from(&stranger));
+        app.known_peers_only = true;
+        assert!(app.accepts_inbound_from(&peer_id));
+        assert!(!app.accepts_inbound_from(&stranger));
+        app.bootstrap_addrs = vec![
+            format!("/ip4/203.0.113.2/udp/4001/quic-v1/p2p/{stranger}")
+                .parse()
+                .unwrap(),
+        ];
+        assert!(app.accepts_inbound_from(&stranger));
+    }
+
+    #[test]
+    fn keepalive_fires_on_virtual_interval_and_declares_loss() {
+        let clock = FakeClock::new(10_000);
+        let mut app = test_app_at(&clock);
+        let peer = PeerId::random();
+        drive_to_active(&mut app, peer, "session-a");
+        let connected = HashSet::from([peer]);
+
+        let (send, lost) = app.collect_keepalive_actions(app.now_ms(), &connected);
+        assert_eq!(send, vec![(peer, "session-a".to_string(), 1)]);
+        assert!(lost.is_empty());
+
+        // Still waiting for the Pong, but the timeout has not elapsed yet.
+        clock.advance(500);
+        let (send, lost) = app.collect_keepalive_actions(app.now_ms(), &connected);
+        assert!(send.is_empty());
+        assert!(lost.is_empty());
+
+        // Each timeout counts a miss and immediately schedules the next Ping.
+        clock.advance(700);
+        let (send, lost) = app.collect_keepalive_actions(app.now_ms(), &connected);
+        assert_eq!(send, vec![(peer, "session-a".to_string(), 2)]);
+        assert!(lost.is_empty());
+
+        clock.advance(1_200);
+        let (_, lost) = app.collect_keepalive_actions(app.now_ms(), &connected);
+        assert!(lost.is_empty());
+
+        clock.advance(1_200);
+        let (_, lost) = app.collect_keepalive_actions(app.now_ms(), &connected);
+        assert_eq!(lost, vec![peer]);
+    }
+
+    #[test]
+    fn keepalive_pong_measures_rtt_against_clock() {
+        let clock = FakeClock::new(50_000);
+        let mut app = test_app_at(&clock);
+        let peer = PeerId::random();
+        drive_to_active(&mut app, peer, "session-a");
+        let connected = HashSet::from([peer]);
+
+        let (send, _) = app.collect_keepalive_actions(app.now_ms(), &connected);
+        let (_, _, seq) = send[0].clone();
+        clock.advance(35);
+        let rtt = app.note_control_pong(
+            peer,
+            &ControlPong {
+                session_id: "session-a".to_string(),
+                seq,
+                echo_send_unix_ms: 50_000,
+                recv_unix_ms: 50_020,
+            },
+        );
+        assert_eq!(rtt, Some(35));
+    }
+
+    #[test]
+    fn pending_session_request_retries_then_fails_at_virtual_deadline() {
+        let clock = FakeClock::new(100_000);
+        let mut app = test_app_at(&clock);
+        let peer = PeerId::random();
+        app.pending_outbound_sessions.insert(
+            peer,
+            PendingOutboundSession {
+                session_id: "session-a".to_string(),
+                request_nonces: Vec::new(),
+                last_send_unix_ms: app.now_ms(),
+                attempts: 1,
+                challenge: None,
+                challenge_echoed: false,
+            },
+        );
+
+        clock.advance(1_199);
+        assert_eq!(
+            app.collect_pending_retry_actions(app.now_ms()),
+            (Vec::new(), Vec::new())
+        );
+
+        clock.advance(1);
+        assert_eq!(
+            app.collect_pending_retry_actions(app.now_ms()),
+            (vec![peer], Vec::new())
+        );
+
+        app.pending_outbound_sessions
+            .get_mut(&peer)
+            .unwrap()
+            .attempts = 3;
+        assert_eq!(
+            app.collect_pending_retry_actions(app.now_ms()),
+            (Vec::new(), vec![peer])
+        );
+    }
+
+    #[test]
+    fn discovery_dial_cooldown_follows_clock() {
+        let clock = FakeClock::new(1_000_000);
+        let mut app = test_app_at(&clock);
+        let peer = PeerId::random();
+        assert!(!app.discovery_dial_throttled(peer));
+
+        app.mark_discovery_dial_attempt(peer);
+        assert!(app.discovery_dial_throttled(peer));
+
+        clock.advance(DISCOVERY_DIAL_COOLDOWN_MS - 1);
+        assert!(app.discovery_dial_throttled(peer));
+
+        clock.advance(1);
+        assert!(!app.discovery_dial_throttled(peer));
+    }
+
+    #[test]
+    fn idle_connection_timeout_flag_is_applied() {
+        let args = Args::try_parse_from(["aetherlink-node"]).unwrap();
+        assert_eq!(
+            SwarmTuning::from_args(&args).idle_connection_timeout,
+            Duration::from_secs(300)
+        );
+
+        let args =
+            Args::try_parse_from(["aetherlink-node", "--idle-connection-timeout-secs", "45"])
+                .unwrap();
+        assert_eq!(
+            SwarmTuning::from_args(&args).idle_connection_timeout,
+            Duration::from_secs(45)
+        );
+    }
+
+    #[test]
+    fn idle_connection_timeout_outside_range_is_rejected() {
+        for value in ["0", "9", "3601"] {
+            assert!(
+                Args::try_parse_from(["aetherlink-node", "--idle-connection-timeout-secs", value])
+                    .is_err(),
+                "{value} should be rejected"
+            );
+        }
+        assert!(
+            Args::try_parse_from(["aetherlink-node", "--idle-connection-timeout-secs", "3600"])
+                .is_ok()
+        );
+    }
+
+    fn encode_base64(bytes: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut out = String::new();
+        for chunk in bytes.chunks(3) {
+            let n = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |acc, (i, b)| acc | ((*b as u32) << (16 - 8 * i)));
+            for i in 0..=chunk.len() {
+                out.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            }
+        }
+        while !out.len().is_multiple_of(4) {
+            out.push('=');
+        }
+        out
+    }
+
+    #[test]
+    fn inline_identity_key_decodes_base64_and_raw_protobuf() {
+        let key = identity::Keypair::generate_ed25519();
+        let encoded = key.to_protobuf_encoding().unwrap();
+        let expected = PeerId::from(key.public());
+
+        let from_env = format!("{}\n", encode_base64(&encoded));
+        let decoded = decode_inline_identity_key(from_env.as_bytes()).unwrap();
+        assert_eq!(PeerId::from(decoded.public()), expected);
+
+        let decoded = decode_inline_identity_key(&encoded).unwrap();
+        assert_eq!(PeerId::from(decoded.public()), expected);
+    }
+
+    #[test]
+    fn inline_identity_key_takes_precedence_over_file() {
+        let path = env::temp_dir().join(format!("aetherlink-identity-{}.key", PeerId::random()));
+        let key = identity::Keypair::generate_ed25519();
+        let inline = encode_base64(&key.to_protobuf_encoding().unwrap()).into_bytes();
+
+        let loaded =
+            load_or_create_identity_key(&path, Some((InlineIdentitySource::Env, inline))).unwrap();
+        assert_eq!(PeerId::from(loaded.public()), PeerId::from(key.public()));
+        assert!(!path.exists(), "inline keys must not be persisted");
+    }
+
+    #[test]
+    fn malformed_inline_identity_key_is_rejected() {
+        assert!(decode_inline_identity_key(b"not base64 at all!").is_err());
+        // Valid base64, but not a protobuf keypair.
+        assert!(decode_inline_identity_key(b"aGVsbG8gd29ybGQ=").is_err());
+        assert!(
+            load_or_create_identity_key(
+                Path::new("/nonexistent/device.key"),
+                Some((InlineIdentitySource::Stdin, b"%%%".to_vec())),
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn base64_decoder_handles_padding_and_url_alphabet() {
+        assert_eq!(decode_base64("aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(decode_base64("aGVsbG8").unwrap(), b"hello");
+        assert_eq!(decode_base64("-_8").unwrap(), vec![0xfb, 0xff]);
+        assert_eq!(decode_base64("+/8=").unwrap(), vec![0xfb, 0xff]);
+        assert!(decode_base64("a").is_none());
+    }
+
+    #[test]
+    fn publishable_addr_filters_loopback_and_private_ranges() {
+        let addr = |text: &str| text.parse::<Multiaddr>().unwrap();
+        let loopback = [
+            addr("/ip4/127.0.0.1/udp/9000/quic-v1"),
+            addr("/ip4/127.8.9.10/udp/9000/quic-v1"),
+            addr("/ip6/::1/udp/9000/quic-v1"),
+            addr("/ip4/0.0.0.0/udp/9000/quic-v1"),
+        ];
+        for a in &loopback {
+            assert!(!is_publishable_addr(a, false), "{a}");
+            assert!(!is_publishable_addr(a, true), "{a}");
+        }
+
+        let lan = [
+            addr("/ip4/192.168.1.20/udp/9000/quic-v1"),
+            addr("/ip4/10.0.0.5/udp/9000/quic-v1"),
+            addr("/ip4/172.16.3.4/udp/9000/quic-v1"),
+            addr("/ip4/169.254.10.1/udp/9000/quic-v1"),
+            addr("/ip6/fe80::1/udp/9000/quic-v1"),
+            addr("/ip6/fd12:3456::1/udp/9000/quic-v1"),
+        ];
+        for a in &lan {
+            assert!(!is_publishable_addr(a, false), "{a}");
+            assert!(is_publishable_addr(a, true), "{a}");
+        }
+
+        let global = [
+            addr("/ip4/203.0.113.7/udp/9000/quic-v1"),
+            addr("/ip6/2001:db8::7/udp/9000/quic-v1"),
+            addr("/dns4/node.example.org/udp/9000/quic-v1"),
+        ];
+        for a in &global {
+            assert!(is_publishable_addr(a, false), "{a}");
+        }
+    }
+
+    #[test]
+    fn every_auth_error_maps_to_a_distinct_detail_code() {
+        let errors = [
+            SessionAuthError::MissingSenderIdentity,
+            SessionAuthError::MissingResponderIdentity,
+            SessionAuthError::MissingDeviceCode,
+            SessionAuthError::MissingNonce,
+            SessionAuthError::NonceTooShort { min_bytes: 12 },
+            SessionAuthError::InvalidTargetDeviceCode {
+                expected: "a".to_string(),
+                got: "b".to_string(),
+            },
+            SessionAuthError::TimestampSkew {
+                request_unix_ms: 0,
+                now_unix_ms: 60_000,
+                allowed_skew_ms: 30_000,
+            },
+            SessionAuthError::ReplayDetected,
+            SessionAuthError::InvalidSenderPublicKey,
+            SessionAuthError::InvalidSenderPeerId,
+            SessionAuthError::PeerIdMismatch,
+            SessionAuthError::TransportPeerIdMismatch,
+            SessionAuthError::InvalidSignature,
+            SessionAuthError::SigningFailed,
+            SessionAuthError::SessionIdMismatch {
+                expected: "s1".to_string(),
+                got: "s2".to_string(),
+            },
+            SessionAuthError::MissingRequestNonceBinding,
+            SessionAuthError::RequestNonceMismatch,
+            SessionAuthError::UntrustedPeer {
+                device_code: "d".to_string(),
+            },
+            SessionAuthError::TrustedPeerMismatch {
+                device_code: "d".to_string(),
+            },
+            SessionAuthError::TrustStoreCorrupt("bad".to_string()),
+            SessionAuthError::ResumptionTicketExpired,
+            SessionAuthError::InvalidResumptionTicket("bad signature"),
+            SessionAuthError::RevokedPeer {
+                device_code: "d".to_string(),
+            },
+            SessionAuthError::Throttled { retry_after_ms: 1 },
+            SessionAuthError::MissingChallenge,
+            SessionAuthError::InvalidChallenge("bad signature"),
+            SessionAuthError::ChallengeExpired,
+        ];
+        let codes = errors
+            .iter()
+            .map(auth_error_to_detail_code)
+            .collect::<HashSet<_>>();
+        assert_eq!(codes.len(), errors.len());
+        assert!(!codes.contains(&SessionRejectDetailCode::Unspecified));
+        assert!(!codes.contains(&SessionRejectDetailCode::ProtocolVersionMismatch));
+        assert!(!codes.contains(&SessionRejectDetailCode::InboundQueueFull));
+
+        for err in &errors {
+            assert_eq!(
+                map_auth_error_to_reject(err).1,
+                auth_error_to_detail_code(err)
+            );
+        }
+        assert_eq!(
+            map_auth_error_to_reject(&SessionAuthError::ReplayDetected),
+            (
+                RejectReason::AuthFailed,
+                SessionRejectDetailCode::ReplayDetected
+            )
+        );
+        assert_eq!(
+            map_auth_error_to_reject(&SessionAuthError::UntrustedPeer {
+                device_code: "d".to_string()
+            }),
+            (
+                RejectReason::PolicyDenied,
+                SessionRejectDetailCode::UntrustedPeer
+            )
+        );
+    }
+
+    #[test]
+    fn rtt_smoothing_tracks_window_mean_and_jitter() {
+        let mut state = ControlKeepaliveState::default();
+        assert_eq!(state.smoothed_rtt_ms(), None);
+        assert_eq!(state.rtt_jitter_ms(), None);
+
+        for rtt in [40, 60, 40, 60] {
+            update_rtt(&mut state, rtt);
+        }
+        assert_eq!(state.last_rtt_ms, Some(60));
+        assert_eq!(state.smoothed_rtt_ms(), Some(50));
+        assert_eq!(state.rtt_variance_ms2(), Some(100.0));
+        assert_eq!(state.rtt_jitter_ms(), Some(10));
+
+        update_rtt(&mut state, -5);
+        assert_eq!(state.last_rtt_ms, Some(0), "negative samples clamp to zero");
+    }
+
+    #[test]
+    fn rtt_window_drops_oldest_samples() {
+        let mut state = ControlKeepaliveState::default();
+        update_rtt(&mut state, 1_000);
+        for _ in 0..KEEPALIVE_RTT_WINDOW {
+            update_rtt(&mut state, 20);
+        }
+        assert_eq!(state.recent_rtts_ms.len(), KEEPALIVE_RTT_WINDOW);
+        assert_eq!(state.smoothed_rtt_ms(), Some(20));
+        assert_eq!(state.rtt_jitter_ms(), Some(0));
+    }
+
+    #[test]
+    fn upnp_events_map_to_external_addr_changes() {
+        let addr: Multiaddr = "/ip4/198.51.100.4/udp/9000/quic-v1".parse().unwrap();
+        assert_eq!(
+            upnp_external_addr_change(&upnp::Event::NewExternalAddr(addr.clone())),
+            Some(UpnpAddrChange::Mapped(addr.clone()))
+        );
+        assert_eq!(
+            upnp_external_addr_change(&upnp::Event::ExpiredExternalAddr(addr.clone())),
+            Some(UpnpAddrChange::Expired(addr.clone()))
+        );
+        assert_eq!(
+            upnp_external_addr_change(&upnp::Event::GatewayNotFound),
+            None
+        );
+        assert_eq!(
+            upnp_external_addr_change(&upnp::Event::NonRoutableGateway),
+            None
+        );
+
+        let mut app = test_app();
+        app.note_local_addr(addr.clone());
+        app.forget_local_addr(&addr);
+        assert!(app.known_local_addrs.is_empty());
+    }
+
+    #[test]
+    fn rebootstrap_only_when_table_empty_and_interval_elapsed() {
+        assert!(should_rebootstrap(0, 1_000, 6_000, 5_000));
+        assert!(!should_rebootstrap(0, 1_000, 5_999, 5_000));
+        assert!(!should_rebootstrap(3, 1_000, 60_000, 5_000));
+        // A clock that stepped backwards must not trigger a storm of retries.
+        assert!(!should_rebootstrap(0, 10_000, 1_000, 5_000));
+    }
+
+    #[test]
+    fn health_score_ranges_for_synthetic_peers() {
+        let healthy = HealthInputs {
+            active: true,
+            smoothed_rtt_ms: Some(30),
+            jitter_ms: Some(3),
+            ..HealthInputs::default()
+        };
+        assert!(health_score(healthy) >= 95, "{}", health_score(healthy));
+
+        let relayed_and_slow = HealthInputs {
+            smoothed_rtt_ms: Some(275),
+            jitter_ms: Some(40),
+            relayed: true,
+            ..healthy
+        };
+        let score = health_score(relayed_and_slow);
+        assert!((50..=75).contains(&score), "{score}");
+
+        let lossy_and_flapping = HealthInputs {
+            loss_ratio: 0.25,
+            reconnects: 6,
+            ..relayed_and_slow
+        };
+        let score = health_score(lossy_and_flapping);
+        assert!(score <= 25, "{score}");
+
+        let worst = HealthInputs {
+            active: true,
+            smoothed_rtt_ms: Some(5_000),
+            jitter_ms: Some(1_000),
+            loss_ratio: 1.0,
+            relayed: true,
+            reconnects: 50,
+        };
+        assert_eq!(health_score(worst), 0);
+        assert_eq!(health_score(HealthInputs::default()), 0);
+    }
+
+    #[test]
+    fn connection_health_reads_app_state() {
+        let mut app = test_app();
+        let peer = PeerId::random();
+        assert_eq!(connection_health(&app, peer), 0);
+
+        drive_to_active(&mut app, peer, "session-a");
+        assert_eq!(connection_health(&app, peer), 100);
+
+        let state = app.control_keepalive.get_mut(&peer).unwrap();
+        state.pings_sent = 10;
+        state.pings_missed = 1;
+        app.relayed_peers.insert(peer);
+        assert_eq!(connection_health(&app, peer), 75);
+
+        app.on_disconnected(peer);
+        assert!(!app.relayed_peers.contains(&peer));
+        app.on_connected(peer, Trigger::DirectConnected);
+        assert_eq!(app.peer_reconnects.get(&peer), Some(&1));
+    }
+
+    #[test]
+    fn identify_gate_requires_control_protocol_and_major() {
+        let control = StreamProtocol::new(CONTROL_PROTOCOL);
+        let kad = StreamProtocol::new("/ipfs/kad/1.0.0");
+
+        assert!(is_compatible_peer(
+            &[kad.clone(), control.clone()],
+            "AetherLink-v1.0.0"
+        ));
+        assert!(is_compatible_peer(
+            std::slice::from_ref(&control),
+            "AetherLink-v1.4.2"
+        ));
+        assert!(is_compatible_peer(
+            std::slice::from_ref(&control),
+            "my-custom-build"
+        ));
+        assert!(!is_compatible_peer(
+            std::slice::from_ref(&control),
+            "AetherLink-v2.0.0"
+        ));
+        assert!(!is_compatible_peer(&[kad], "AetherLink-v1.0.0"));
+        assert!(!is_compatible_peer(&[], "AetherLink-v1.0.0"));
+        assert!(!is_compatible_peer(
+            &[StreamProtocol::new("/aetherlink/control/2.0.0")],
+            "rust-libp2p/0.56"
+        ));
+
+        assert_eq!(agent_major_version("AetherLink-v1.0.0"), Some(1));
+        assert_eq!(agent_major_version("AetherLink-vx"), None);
+    }
+
+    #[test]
+    fn relays_and_bootstrap_peers_are_exempt_from_identify_gate() {
+        let mut app = test_app();
+        let relay = PeerId::random();
+        let bootstrap = PeerId::random();
+        app.relay_reservations.add_relay(
+            relay,
+            format!("/ip4/203.0.113.1/udp/4001/quic-v1/p2p/{relay}")
+                .parse()
+                .unwrap(),
+        );
+        app.bootstrap_addrs = vec![
+            format!("/ip4/203.0.113.2/udp/4001/quic-v1/p2p/{bootstrap}")
+                .parse()
+                .unwrap(),
+        ];
+        assert!(app.is_infrastructure_peer(&relay));
+        assert!(app.is_infrastructure_peer(&bootstrap));
+        assert!(!app.is_infrastructure_peer(&PeerId::random()));
+    }
+
+    #[tokio::test]
+    async fn listen_on_all_continues_past_failures() {
+        let mut swarm = SwarmBuilder::with_new_identity()
+            .with_tokio()
+            .with_quic()
+            .with_behaviour(|_| libp2p::swarm::dummy::Behaviour)
+            .unwrap()
+            .build();
+        let addrs = [
+            "/ip4/127.0.0.1/udp/0/quic-v1".parse::<Multiaddr>().unwrap(),
+            // No TCP transport is configured, so this one is rejected up front.
+            "/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>().unwrap(),
+            "/ip4/127.0.0.1/udp/0/quic-v1".parse::<Multiaddr>().unwrap(),
+        ];
+
+        let results = listen_on_all(&mut swarm, &addrs);
+        assert_eq!(results.len(), 3);
+        assert!(results[0].1.is_ok());
+        assert!(matches!(
+            results[1].1,
+            Err(TransportError::MultiaddrNotSupported(_))
+        ));
+        assert!(results[2].1.is_ok());
+        assert_eq!(
+            results.iter().map(|(addr, _)| addr).collect::<Vec<_>>(),
+            addrs.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn data_dir_precedence() {
+        let env = DataDirEnv {
+            xdg_config_home: Some(PathBuf::from("/xdg")),
+            home: Some(PathBuf::from("/home/u")),
+        };
+        assert_eq!(
+            resolve_data_dir(Some(PathBuf::from("/explicit")), &env),
+            PathBuf::from("/explicit")
+        );
+        assert_eq!(
+            resolve_data_dir(None, &env),
+            PathBuf::from("/xdg/aetherlink")
+        );
+
+        let relative_xdg = DataDirEnv {
+            xdg_config_home: Some(PathBuf::from("relative")),
+            ..env.clone()
+        };
+        assert_eq!(
+            resolve_data_dir(None, &relative_xdg),
+            PathBuf::from("/home/u/.config/aetherlink")
+        );
+
+        let home_only = DataDirEnv {
+            xdg_config_home: None,
+            ..env
+        };
+        assert_eq!(
+            resolve_data_dir(None, &home_only),
+            PathBuf::from("/home/u/.config/aetherlink")
+        );
+        assert_eq!(
+            resolve_data_dir(None, &DataDirEnv::default()),
+            PathBuf::from(".aetherlink")
+        );
+    }
+
+    #[test]
+    fn endpoint_path_triggers() {
+        let direct: Multiaddr = "/ip4/203.0.113.7/udp/9000/quic-v1".parse().unwrap();
+        let circuit: Multiaddr = format!(
+            "/ip4/198.51.100.1/udp/4001/quic-v1/p2p/{}/p2p-circuit/p2p/{}",
+            PeerId::random(),
+            PeerId::random()
+        )
+        .parse()
+        .unwrap();
+        let local: Multiaddr = "/ip4/0.0.0.0/udp/9000/quic-v1".parse().unwrap();
+        let dialer = |address: &Multiaddr, role_override| ConnectedPoint::Dialer {
+            address: address.clone(),
+            role_override,
+            port_use: libp2p::core::transport::PortUse::Reuse,
+        };
+
+        assert_eq!(
+            trigger_for_endpoint(&dialer(&direct, Endpoint::Dialer)),
+            Trigger::DirectConnected
+        );
+        assert_eq!(
+            trigger_for_endpoint(&dialer(&circuit, Endpoint::Dialer)),
+            Trigger::RelayConnected
+        );
+        assert_eq!(
+            trigger_for_endpoint(&dialer(&direct, Endpoint::Listener)),
+            Trigger::PunchConnected
+        );
+        assert_eq!(
+            trigger_for_endpoint(&ConnectedPoint::Listener {
+                local_addr: local.clone(),
+                send_back_addr: direct.clone(),
+            }),
+            Trigger::DirectConnected
+        );
+        assert_eq!(
+            trigger_for_endpoint(&ConnectedPoint::Listener {
+                local_addr: circuit.clone(),
+                send_back_addr: "/p2p-circuit".parse().unwrap(),
+            }),
+            Trigger::RelayConnected
+        );
+    }
+
+    #[test]
+    fn relayed_connection_walks_relay_path() {
+        let mut app = test_app();
+        let relayed = PeerId::random();
+        app.on_connected(relayed, Trigger::RelayConnected);
+        assert!(app.relayed_peers.contains(&relayed));
+        assert_eq!(
+            app.sessions.get(&relayed).unwrap().state(),
+            &ConnectionState::SecureHandshake
+        );
+
+        let punched = PeerId::random();
+        app.on_connected(punched, Trigger::PunchConnected);
+        assert!(!app.relayed_peers.contains(&punched));
+        assert_eq!(
+            app.sessions.get(&punched).unwrap().state(),
+            &ConnectionState::SecureHandshake
+        );
+    }
+
+    #[test]
+    fn version_mismatch_fails_the_handshake_as_such() {
+        let mut app = test_app();
+        let peer = PeerId::random();
+        app.on_connected(peer, Trigger::DirectConnected);
+        app.on_version_mismatch(peer);
+        assert_eq!(
+            app.sessions.get(&peer).unwrap().state(),
+            &ConnectionState::Failed(FailureReason::VersionMismatch)
+        );
+    }
+
+    #[test]
+    fn effective_config_serializes_every_field() {
+        let args = Args::parse_from([
+            "aetherlink-node",
+            "--data-dir",
+            "/var/lib/aetherlink",
+            "--relay-reservation-ttl-ms",
+            "1000",
+            "--relay-max-circuits",
+            "0",
+        ]);
+        let config = EffectiveConfig::resolve(&args, Path::new("/var/lib/aetherlink"));
+        let value = serde_json::to_value(&config).unwrap();
+        let object = value.as_object().unwrap();
+
+        let expected = [
+            "listen",
+            "dial",
+            "bootstrap",
+            "bootstrap_dns",
+            "relay",
+            "agent_version",
+            "data_dir",
+            "identity_source",
+            "identity_file",
+            "trust_store_file",
+            "auth_audit_log",
+            "trust_on_first_use",
+            "trusted_peer_max_age_ms",
+            "min_nonce_bytes",
+            "known_peers_only",
+            "auto_request",
+            "session_request_timeout_ms",
+            "session_request_max_attempts",
+            "connect_device_codes",
+            "device_lookup_interval_ms",
+            "device_record_republish_ms",
+            "device_record_publish",
+            "publish_private_addrs",
+            "control_keepalive_interval_ms",
+            "control_keepalive_timeout_ms",
+            "control_keepalive_max_misses",
+            "session_auto_close_ms",
+            "idle_connection_timeout_secs",
+            "relay_reservation_ttl_ms",
+            "admin_socket",
+            "enable_upnp",
+            "relay_server",
+            "relay_max_circuits",
+            "relay_max_circuit_bytes",
+            "relay_max_circuit_duration_secs",
+            "relay_budget_bytes_per_hour",
+        ];
+        for key in expected {
+            assert!(object.contains_key(key), "missing {key}");
+        }
+        assert_eq!(object.len(), expected.len());
+
+        assert_eq!(value["identity_file"], "/var/lib/aetherlink/device.key");
+        assert_eq!(value["listen"][0], "/ip4/0.0.0.0/udp/9000/quic-v1");
+        assert_eq!(
+            value["relay_reservation_ttl_ms"],
+            MIN_RELAY_RESERVATION_TTL_MS
+        );
+        assert_eq!(value["relay_max_circuits"], 1);
+    }
+
+    #[test]
+    fn identify_regate_on_protocol_change() {
+        let control = StreamProtocol::new(CONTROL_PROTOCOL);
+        let kad = StreamProtocol::new("/ipfs/kad/1.0.0");
+        let before: HashSet<_> = [control.clone(), kad.clone()].into_iter().collect();
+        let reordered: HashSet<_> = [kad.clone(), control.clone()].into_iter().collect();
+        let after: HashSet<_> = [kad].into_iter().collect();
+        assert!(!protocols_changed(&before, &reordered));
+        assert!(protocols_changed(&before, &after));
+
+        assert_eq!(
+            identify_gate_action(true, false, true),
+            IdentifyGateAction::Keep
+        );
+        assert_eq!(
+            identify_gate_action(false, true, true),
+            IdentifyGateAction::Keep
+        );
+        assert_eq!(
+            identify_gate_action(false, false, false),
+            IdentifyGateAction::Disconnect
+        );
+        assert_eq!(
+            identify_gate_action(false, false, true),
+            IdentifyGateAction::CloseSessionAndDisconnect
+        );
+    }
+
+    #[test]
+    fn rotation_proof_validates_under_both_keys() {
+        let old = identity::Keypair::generate_ed25519();
+        let new = identity::Keypair::generate_ed25519();
+        let encoded = build_rotation_proof(&old, &new, 1_000).unwrap();
+        let proof = IdentityRotationProof::decode(encoded.as_slice()).unwrap();
+        let (old_pub, new_pub) = verify_rotation_proof(&proof).unwrap();
+        assert_eq!(old_pub, old.public());
+        assert_eq!(new_pub, new.public());
+        assert_eq!(proof.device_code, PeerId::from(old.public()).to_string());
+        assert_eq!(
+            proof.new_device_code,
+            PeerId::from(new.public()).to_string()
+        );
+
+        let mut tampered = proof.clone();
+        tampered.new_device_code = "someone-else".to_string();
+        assert!(verify_rotation_proof(&tampered).is_err());
+    }
+
+    #[test]
+    fn announced_rotation_rebinds_trust_and_retargets_discovery() {
+        let old = identity::Keypair::generate_ed25519();
+        let new = identity::Keypair::generate_ed25519();
+        let old_code = PeerId::from(old.public()).to_string();
+        let new_peer_id = PeerId::from(new.public());
+        let mut app = test_app();
+        app.trust_store_path = std::env::temp_dir().join(format!(
+            "aetherlink-rotation-test-{}.json",
+            PeerId::random()
+        ));
+        app.trusted_peers = TrustedPeers::from_records(vec![TrustedPeerRecord {
+            device_code: old_code.clone(),
+            peer_id: old_code.clone(),
+            identity_pubkey_hex: old
+                .public()
+                .encode_protobuf()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect(),
+            first_seen_unix_ms: 1,
+            last_seen_unix_ms: 1,
+        }])
+        .unwrap();
+        app.connect_device_codes = vec![old_code.clone()];
+
+        let announcement = DeviceAnnouncement {
+            version: 1,
+            device_code: old_code.clone(),
+            peer_id: new_peer_id.to_string(),
+            addrs: Vec::new(),
+            unix_ms: 0,
+            rotation_proof: Some(build_rotation_proof(&old, &new, app.now_ms()).unwrap()),
+        };
+        apply_announced_rotation(&mut app, &announcement, PeerId::random())
+            .expect_err("proof must name the announced peer");
+        apply_announced_rotation(&mut app, &announcement, new_peer_id).unwrap();
+        assert_eq!(app.connect_device_codes, vec![new_peer_id.to_string()]);
+        assert_eq!(
+            app.trusted_peers.to_records()[0].peer_id,
+            new_peer_id.to_string()
+        );
+        let _ = fs::remove_file(&app.trust_store_path);
+    }
+
+    #[test]
+    fn runtime_targets_are_deduplicated_and_enable_auto_request() {
+        let mut app = test_app();
+        app.auto_request = false;
+        assert!(app.add_connect_target(" device-b "));
+        assert!(app.add_connect_target("device-a"));
+        assert!(!app.add_connect_target("device-a"));
+        assert!(!app.add_connect_target(""));
+        let own_code = app.local_device_code.clone();
+        assert!(!app.add_connect_target(&own_code));
+        assert_eq!(app.connect_device_codes, vec!["device-a", "device-b"]);
+        assert!(app.auto_request);
+    }
+
+    #[test]
+    fn clipboard_data_requires_active_session_and_enabled_sync() {
+        let mut app = test_app();
+        let peer = PeerId::random();
+        let clip = |session_id: &str, mime: &str, data: &[u8]| ClipboardData {
+            session_id: session_id.to_string(),
+            mime: mime.to_string(),
+            data: data.to_vec(),
+        };
+        assert_eq!(
+            handle_clipboard_data(&mut app, peer, clip("s1", "text/plain", b"hi")),
+            Err("no active session")
+        );
+
+        drive_to_active(&mut app, peer, "s1");
+        app.peer_device_codes.insert(peer, "dev-a".to_string());
+        assert_eq!(
+            handle_clipboard_data(&mut app, peer, clip("s1", "text/plain", b"hi")),
+            Err("clipboard sync is disabled for this device")
+        );
+
+        app.clipboard_sync_devices.insert("dev-a".to_string());
+        assert_eq!(
+            handle_clipboard_data(&mut app, peer, clip("s2", "text/plain", b"hi")),
+            Err("session id does not match the active session")
+        );
+        assert_eq!(
+            handle_clipboard_data(&mut app, peer, clip("s1", "text/html", b"<b>")),
+            Err("clipboard type or size not allowed")
+        );
+        let oversized = vec![0_u8; clipboard::CLIPBOARD_MAX_BYTES + 1];
+        assert_eq!(
+            handle_clipboard_data(&mut app, peer, clip("s1", "image/png", &oversized)),
+            Err("clipboard type or size not allowed")
+        );
+        assert!(app.remote_clipboards.is_empty());
+
+        handle_clipboard_data(&mut app, peer, clip("s1", "text/plain", b"hi")).unwrap();
+        assert_eq!(app.remote_clipboards["dev-a"].data, b"hi");
+        assert_eq!(
+            app.active_session_for_device("dev-a"),
+            Some((peer, "s1".to_string()))
+        );
+    }
+
+    #[test]
+    fn video_chunks_are_reassembled_for_the_active_session() {
+        let mut app = test_app();
+        let peer = PeerId::random();
+        let chunk = |session_id: &str, chunk_id: u32, payload: &[u8]| VideoChunk {
+            session_id: session_id.to_string(),
+            stream_seq: u64::from(chunk_id),
+            frame_id: 7,
+            chunk_id,
+            chunk_count: 2,
+            pts_us: 1_000,
+            keyframe: true,
+            payload: payload.to_vec(),
+        };
+        assert_eq!(
+            handle_video_chunk(&mut app, peer, chunk("s1", 0, b"ab")),
+            Err("no active session")
+        );
+
+        drive_to_active(&mut app, peer, "s1");
+        app.peer_device_codes.insert(peer, "dev-a".to_string());
+        assert_eq!(
+            handle_video_chunk(&mut app, peer, chunk("s2", 0, b"ab")),
+            Err("session id does not match the active session")
+        );
+        handle_video_chunk(&mut app, peer, chunk("s1", 1, b"cd")).unwrap();
+        assert!(app.received_video.is_empty());
+        handle_video_chunk(&mut app, peer, chunk("s1", 0, b"ab")).unwrap();
+        let received = &app.received_video["dev-a"];
+        assert_eq!(received.frames.len(), 1);
+        assert_eq!(received.frames[0].data, b"abcd");
+        assert!(received.frames[0].keyframe);
+
+        app.clear_active_session(peer);
+        assert!(app.video_assemblers.is_empty());
+    }
+
+    #[test]
+    fn received_video_drops_the_oldest_frames_over_the_byte_cap() {
+        let mut received = ReceivedVideo::default();
+        for frame_id in 0..3 {
+            received.push(AssembledFrame {
+                frame_id,
+                pts_us: frame_id,
+                keyframe: false,
+                data: vec![0; MAX_BUFFERED_VIDEO_BYTES / 2],
+            });
+        }
+        assert_eq!(received.dropped, 1);
+        assert_eq!(received.frames.front().map(|f| f.frame_id), Some(1));
+        assert_eq!(received.bytes, MAX_BUFFERED_VIDEO_BYTES);
+    }
+
+    #[test]
+    fn renegotiation_clamps_the_proposal_to_host_limits() {
+        let mut app = test_app();
+        let peer = PeerId::random();
+        let request = |session_id: &str, proposed: Option<VideoProfile>| SessionRenegotiate {
+            session_id: session_id.to_string(),
+            proposed,
+        };
+        let uhd = VideoProfile {
+            width: 3840,
+            height: 2160,
+            fps: 60,
+            bitrate_kbps: 20_000,
+        };
+        assert_eq!(
+            handle_session_renegotiate(&mut app, peer, &request("s1", Some(uhd))),
+            Err("no active session")
+        );
+
+        drive_to_active(&mut app, peer, "s1");
+        assert_eq!(
+            handle_session_renegotiate(&mut app, peer, &request("s2", Some(uhd))),
+            Err("session id does not match the active session")
+        );
+        assert_eq!(
+            handle_session_renegotiate(&mut app, peer, &request("s1", None)),
+            Err("missing proposed profile")
+        );
+
+        let ack = handle_session_renegotiate(&mut app, peer, &request("s1", Some(uhd))).unwrap();
+        assert_eq!(ack.session_id, "s1");
+        assert_eq!(
+            ack.accepted,
+            Some(VideoProfile {
+                width: 1280,
+                height: 720,
+                fps: 30,
+                bitrate_kbps: 8_000,
+            })
+        );
+        assert_eq!(app.session_profiles[&peer], HOST_MAX_VIDEO_PROFILE);
+
+        app.clear_active_session(peer);
+        assert!(!app.session_profiles.contains_key(&peer));
+    }
+
+    #[test]
+    fn keyframe_requests_are_rate_limited_per_peer() {
+        let clock = FakeClock::new(10_000);
+        let mut app = test_app_at(&clock);
+        let peer = PeerId::random();
+        let other = PeerId::random();
+        let request = |session_id: &str| RequestKeyframe {
+            session_id: session_id.to_string(),
+            reason: "loss".to_string(),
+        };
+        assert_eq!(
+            handle_request_keyframe(&mut app, peer, &request("s1")),
+            Err("no active session")
+        );
+
+        drive_to_active(&mut app, peer, "s1");
+        drive_to_active(&mut app, other, "s2");
+        assert_eq!(
+            handle_request_keyframe(&mut app, peer, &request("s2")),
+            Err("session id does not match the active session")
+        );
+        assert_eq!(
+            handle_request_keyframe(&mut app, peer, &request("s1")),
+            Ok(())
+        );
+        clock.advance(KEYFRAME_REQUEST_MIN_INTERVAL_MS - 1);
+        assert_eq!(
+            handle_request_keyframe(&mut app, peer, &request("s1")),
+            Err("rate limited")
+        );
+        assert_eq!(
+            handle_request_keyframe(&mut app, other, &request("s2")),
+            Ok(())
+        );
+        clock.advance(1);
+        assert_eq!(
+            handle_request_keyframe(&mut app, peer, &request("s1")),
+            Ok(())
+        );
+
+        app.clear_active_session(peer);
+        assert!(!app.last_keyframe_request_ms.contains_key(&peer));
+    }
+
+    #[test]
+    fn admin_lookup_waits_for_its_queries_or_times_out() {
+        let mut app = test_app();
+        let (reply, _rx) = oneshot::channel();
+        let lookup = PendingAdminLookup {
+            device_codes: vec!["dev-a".to_string(), "dev-b".to_string()],
+            started_unix_ms: 1_000,
+            reply,
+        };
+        let dev_b = "dev-b".to_string();
+        let other = "dev-c".to_string();
+        assert!(!admin_lookup_done(&lookup, &HashSet::from([&dev_b]), 2_000));
+        assert!(admin_lookup_done(&lookup, &HashSet::from([&other]), 2_000));
+        assert!(admin_lookup_done(
+            &lookup,
+            &HashSet::from([&dev_b]),
+            1_000 + ADMIN_LOOKUP_TIMEOUT_MS
+        ));
+
+        app.discovered_devices.insert(
+            "dev-a".to_string(),
+            DiscoveredDevice {
+                device_code: "dev-a".to_string(),
+                peer_id: PeerId::random().to_string(),
+                last_seen_unix_ms: 1_500,
+                trusted: false,
+                addrs: vec!["/ip4/203.0.113.7/udp/9000/quic-v1".to_string()],
+                pairing_code: String::new(),
+            },
+        );
+        let found = discovered_devices_for(&app, &lookup.device_codes);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].device_code, "dev-a");
+    }
+
+    #[test]
+    fn replay_cache_survives_a_save_and_load() {
+        let path =
+            std::env::temp_dir().join(format!("aetherlink-node-replay-{}.json", PeerId::random()));
+        assert!(load_replay_cache(&path, 0).unwrap().is_empty());
+
+        let mut cache = NonceReplayCache::default();
+        cache.check_and_store(b"nonce-old-0000", 1_000).unwrap();
+        cache.check_and_store(b"nonce-new-0000", 50_000).unwrap();
+        save_replay_cache(&path, &cache).unwrap();
+
+        let mut loaded = load_replay_cache(&path, 70_000).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(
+            loaded.to_records(),
+            vec![(b"nonce-new-0000".to_vec(), 50_000)]
+        );
+        assert!(loaded.check_and_store(b"nonce-new-0000", 70_000).is_err());
+    }
+
+    #[test]
+    fn pairing_code_for_peer_uses_inlined_key() {
+        let key = identity::Keypair::generate_ed25519();
+        let peer_id = PeerId::from(key.public());
+        assert_eq!(
+            pairing_code_for_peer(&peer_id),
+            pairing_code(&key.public().encode_protobuf())
+        );
+        assert_eq!(pairing_code_for_peer(&PeerId::random()), "");
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::{HashMap, HashSet};
+
+use libp2p::{Multiaddr, PeerId, core::transport::ListenerId, multiaddr::Protocol};
+
+/// libp2p relay servers grant one-hour reservations by default.
+pub const DEFAULT_RESERVATION_TTL_MS: i64 = 3_600_000;
+pub const DEFAULT_RENEW_MARGIN_MS: i64 = 300_000;
+/// Shortest reservation lifetime accepted: a minute past the renew margin, so a fresh
+/// reservation is not due for renewal the moment it is granted.
+pub const MIN_RESERVATION_TTL_MS: i64 = DEFAULT_RENEW_MARGIN_MS + 60_000;
+pub const DEFAULT_RESERVATION_RETRY_MS: i64 = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayReservation {
+    pub relay_addr: Multiaddr,
+    pub listener_id: Option<ListenerId>,
+    pub expires_unix_ms: Option<i64>,
+    pub last_attempt_unix_ms: i64,
+}
+
+/// Reservation bookkeeping per relay server. The libp2p relay client renews live
+/// reservations on its own; this state is the watchdog that notices when a
+/// reservation lapsed or its listener went away and a fresh one is needed.
+#[derive(Debug, Clone)]
+pub struct RelayReservations {
+    pub by_relay: HashMap<PeerId, RelayReservation>,
+    pub ttl_ms: i64,
+    pub renew_margin_ms: i64,
+    pub retry_interval_ms: i64,
+}
+
+impl Default for RelayReservations {
+    fn default() -> Self {
+        Self {
+            by_relay: HashMap::new(),
+            ttl_ms: DEFAULT_RESERVATION_TTL_MS,
+            renew_margin_ms: DEFAULT_RENEW_MARGIN_MS,
+            retry_interval_ms: DEFAULT_RESERVATION_RETRY_MS,
+        }
+    }
+}
+
+impl RelayReservations {
+    pub fn add_relay(&mut self, relay_peer_id: PeerId, relay_addr: Multiaddr) {
+        self.by_relay
+            .entry(relay_peer_id)
+            .or_insert(RelayReservation {
+                relay_addr,
+                listener_id: None,
+                expires_unix_ms: None,
+                last_attempt_unix_ms: 0,
+            });
+    }
+
+    pub fn note_attempt(
+        &mut self,
+        relay_peer_id: PeerId,
+        listener_id: Option<ListenerId>,
+        now_unix_ms: i64,
+    ) {
+        if let Some(reservation) = self.by_relay.get_mut(&relay_peer_id) {
+            reservation.listener_id = listener_id;
+            reservation.last_attempt_unix_ms = now_unix_ms;
+        }
+    }
+
+    pub fn note_accepted(&mut self, relay_peer_id: PeerId, now_unix_ms: i64) -> bool {
+        let ttl_ms = self.ttl_ms;
+        match self.by_relay.get_mut(&relay_peer_id) {
+            Some(reservation) => {
+                reservation.expires_unix_ms = Some(now_unix_ms.saturating_add(ttl_ms));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Marks the reservation behind `listener_id` as lost and returns its relay.
+    pub fn note_listener_closed(&mut self, listener_id: ListenerId) -> Option<PeerId> {
+        let (relay_peer_id, reservation) = self
+            .by_relay
+            .iter_mut()
+            .find(|(_, reservation)| reservation.listener_id == Some(listener_id))?;
+        reservation.listener_id = None;
+        reservation.expires_unix_ms = None;
+        Some(*relay_peer_id)
+    }
+
+    /// Drops reservations for relays not in `keep` and returns their listeners so the
+    /// caller can close them.
+    pub fn retain_relays(&mut self, keep: &HashSet<PeerId>) -> Vec<ListenerId> {
+        let mut closed = Vec::new();
+        self.by_relay.retain(|relay_peer_id, reservation| {
+            let kept = keep.contains(relay_peer_id);
+            if !kept && let Some(listener_id) = reservation.listener_id {
+                closed.push(listener_id);
+            }
+            kept
+        });
+        closed
+    }
+
+    pub fn note_relay_disconnected(&mut self, relay_peer_id: PeerId) -> bool {
+        match self.by_relay.get_mut(&relay_peer_id) {
+            Some(reservation) => {
+                reservation.expires_unix_ms = None;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Relays whose reservation is missing, expired or within `renew_margin_ms` of expiry,
+/// excluding those already attempted within `retry_interval_ms`. Sorted for stable
+/// dial order.
+pub fn reservations_needing_renewal(state: &RelayReservations, now_ms: i64) -> Vec<PeerId> {
+    let mut due = state
+        .by_relay
+        .iter()
+        .filter(|(_, reservation)| {
+            let expiring = match reservation.expires_unix_ms {
+                Some(expires) => expires.saturating_sub(now_ms) <= state.renew_margin_ms,
+                None => true,
+            };
+            expiring
+                && now_ms.saturating_sub(reservation.last_attempt_unix_ms)
+                    >= state.retry_interval_ms
+        })
+        .map(|(relay_peer_id, _)| *relay_peer_id)
+        .collect::<Vec<_>>();
+    due.sort_by_key(|peer_id| peer_id.to_string());
+    due
+}
+
+/// Builds `<relay_addr>/p2p/<relay>/p2p-circuit/p2p/<target>`. A trailing `/p2p/...` on
+/// `relay_addr` is replaced by `relay` so callers can pass either form.
+pub fn relay_circuit_addr(relay: &PeerId, relay_addr: &Multiaddr, target: &PeerId) -> Multiaddr {
+    let mut addr = relay_addr.clone();
+    while matches!(addr.iter().last(), Some(Protocol::P2p(_))) {
+        addr.pop();
+    }
+    addr.push(Protocol::P2p(*relay));
+    addr.push(Protocol::P2pCircuit);
+    addr.push(Protocol::P2p(*target));
+    addr
+}
+
+/// Returns `(relay, target)` for a circuit address, i.e. the peer ids directly before
+/// and after `/p2p-circuit`.
+pub fn parse_circuit_addr(addr: &Multiaddr) -> Option<(PeerId, PeerId)> {
+    let protocols = addr.iter().collect::<Vec<_>>();
+    let circuit_idx = protocols
+        .iter()
+        .position(|p| matches!(p, Protocol::P2pCircuit))?;
+    let relay = match protocols.get(circuit_idx.checked_sub(1)?)? {
+        Protocol::P2p(peer_id) => *peer_id,
+        _ => return None,
+    };
+    let target = match protocols.get(circuit_idx + 1)? {
+        Protocol::P2p(peer_id) => *peer_id,
+        _ => return None,
+    };
+    Some((relay, target))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 10_000_000;
+
+    fn relay_addr(peer_id: PeerId) -> Multiaddr {
+        format!("/ip4/203.0.113.9/udp/4001/quic-v1/p2p/{peer_id}")
+            .parse()
+            .unwrap()
+    }
+
+    fn state_with(relay: PeerId, expires: Option<i64>, last_attempt: i64) -> RelayReservations {
+        let mut state = RelayReservations::default();
+        state.add_relay(relay, relay_addr(relay));
+        let reservation = state.by_relay.get_mut(&relay).unwrap();
+        reservation.expires_unix_ms = expires;
+        reservation.last_attempt_unix_ms = last_attempt;
+        state
+    }
+
+    #[test]
+    fn never_reserved_relay_needs_renewal() {
+        let relay = PeerId::random();
+        let state = state_with(relay, None, 0);
+        assert_eq!(reservations_needing_renewal(&state, NOW), vec![relay]);
+    }
+
+    #[test]
+    fn healthy_reservation_is_left_alone() {
+        let relay = PeerId::random();
+        let state = state_with(relay, Some(NOW + DEFAULT_RENEW_MARGIN_MS + 1), 0);
+        assert!(reservations_needing_renewal(&state, NOW).is_empty());
+    }
+
+    #[test]
+    fn shortest_reservation_is_not_renewed_on_grant() {
+        let relay = PeerId::random();
+        let mut state = state_with(relay, None, NOW);
+        state.ttl_ms = MIN_RESERVATION_TTL_MS;
+        assert!(state.note_accepted(relay, NOW));
+        let later = NOW + DEFAULT_RESERVATION_RETRY_MS;
+        assert!(reservations_needing_renewal(&state, later).is_empty());
+    }
+
+    #[test]
+    fn near_expiry_reservation_needs_renewal() {
+        let relay = PeerId::random();
+        let state = state_with(relay, Some(NOW + DEFAULT_RENEW_MARGIN_MS), 0);
+        assert_eq!(reservations_needing_renewal(&state, NOW), vec![relay]);
+    }
+
+    #[test]
+    fn expired_reservation_needs_renewal() {
+        let relay = PeerId::random();
+        let state = state_with(relay, Some(NOW - 1), 0);
+        assert_eq!(reservations_needing_renewal(&state, NOW), vec![relay]);
+    }
+
+    #[test]
+    fn recent_attempt_suppresses_retry() {
+        let relay = PeerId::random();
+        let state = state_with(relay, Some(NOW - 1), NOW - DEFAULT_RESERVATION_RETRY_MS + 1);
+        assert!(reservations_needing_renewal(&state, NOW).is_empty());
+
+        let state = state_with(relay, Some(NOW - 1), NOW - DEFAULT_RESERVATION_RETRY_MS);
+        assert_eq!(reservations_needing_renewal(&state, NOW), vec![relay]);
+    }
+
+    #[test]
+    fn accepted_reservation_extends_expiry_and_disconnect_clears_it() {
+        let relay = PeerId::random();
+        let mut state = state_with(relay, None, NOW);
+        assert!(state.note_accepted(relay, NOW));
+        assert_eq!(
+            state.by_relay[&relay].expires_unix_ms,
+            Some(NOW + DEFAULT_RESERVATION_TTL_MS)
+        );
+        assert!(state.note_relay_disconnected(relay));
+        assert_eq!(state.by_relay[&relay].expires_unix_ms, None);
+        assert!(!state.note_accepted(PeerId::random(), NOW));
+    }
+
+    #[test]
+    fn retain_relays_drops_unlisted_reservations() {
+        let kept = PeerId::random();
+        let dropped = PeerId::random();
+        let mut state = state_with(kept, None, 0);
+        state.add_relay(dropped, relay_addr(dropped));
+        let listener_id = ListenerId::next();
+        state.note_attempt(dropped, Some(listener_id), NOW);
+
+        assert_eq!(
+            state.retain_relays(&HashSet::from([kept])),
+            vec![listener_id]
+        );
+        assert!(state.by_relay.contains_key(&kept));
+        assert!(!state.by_relay.contains_key(&dropped));
+        assert!(state.retain_relays(&HashSet::new()).is_empty());
+        assert!(state.by_relay.is_empty());
+    }
+
+    #[test]
+    fn builds_circuit_addr_from_plain_relay_addr() {
+        let relay = PeerId::random();
+        let target = PeerId::random();
+        let base: Multiaddr = "/ip4/203.0.113.9/udp/4001/quic-v1".parse().unwrap();
+        let addr = relay_circuit_addr(&relay, &base, &target);
+        assert_eq!(
+            addr.to_string(),
+            format!("/ip4/203.0.113.9/udp/4001/quic-v1/p2p/{relay}/p2p-circuit/p2p/{target}")
+        );
+    }
+
+    #[test]
+    fn circuit_addr_does_not_duplicate_existing_relay_peer_id() {
+        let relay = PeerId::random();
+        let target = PeerId::random();
+        let addr = relay_circuit_addr(&relay, &relay_addr(relay), &target);
+        let p2p_count = addr
+            .iter()
+            .filter(|p| matches!(p, Protocol::P2p(_)))
+            .count();
+        assert_eq!(p2p_count, 2);
+        assert_eq!(parse_circuit_addr(&addr), Some((relay, target)));
+    }
+
+    #[test]
+    fn circuit_addr_round_trips_through_string() {
+        let relay = PeerId::random();
+        let target = PeerId::random();
+        let addr = relay_circuit_addr(&relay, &relay_addr(relay), &target);
+        let reparsed: Multiaddr = addr.to_string().parse().unwrap();
+        assert_eq!(parse_circuit_addr(&reparsed), Some((relay, target)));
+    }
+
+    #[test]
+    fn parse_rejects_non_circuit_and_incomplete_addrs() {
+        let relay = PeerId::random();
+        assert_eq!(parse_circuit_addr(&relay_addr(relay)), None);
+
+        let listen_only: Multiaddr = format!("{}/p2p-circuit", relay_addr(relay))
+            .parse()
+            .unwrap();
+        assert_eq!(parse_circuit_addr(&listen_only), None);
+
+        let no_relay_id: Multiaddr = format!("/p2p-circuit/p2p/{relay}").parse().unwrap();
+        assert_eq!(parse_circuit_addr(&no_relay_id), None);
+    }
+}
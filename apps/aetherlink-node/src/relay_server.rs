@@ -0,0 +1,207 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use libp2p::{Multiaddr, PeerId, relay};
+
+pub const DEFAULT_MAX_CIRCUITS: usize = 16;
+pub const DEFAULT_MAX_CIRCUIT_BYTES: u64 = 64 * 1024 * 1024;
+pub const DEFAULT_MAX_CIRCUIT_DURATION_SECS: u64 = 600;
+pub const DEFAULT_BUDGET_BYTES_PER_HOUR: u64 = 10 * 1024 * 1024 * 1024;
+const BUDGET_WINDOW: Duration = Duration::from_secs(3_600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelayLimits {
+    pub max_circuits: usize,
+    pub max_circuit_bytes: u64,
+    pub max_circuit_duration: Duration,
+    pub budget_bytes_per_window: u64,
+    pub budget_window: Duration,
+}
+
+impl Default for RelayLimits {
+    fn default() -> Self {
+        Self {
+            max_circuits: DEFAULT_MAX_CIRCUITS,
+            max_circuit_bytes: DEFAULT_MAX_CIRCUIT_BYTES,
+            max_circuit_duration: Duration::from_secs(DEFAULT_MAX_CIRCUIT_DURATION_SECS),
+            budget_bytes_per_window: DEFAULT_BUDGET_BYTES_PER_HOUR,
+            budget_window: BUDGET_WINDOW,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdmissionDecision {
+    Accept,
+    RejectTooManyCircuits,
+    RejectOverBudget,
+}
+
+/// Whether one more circuit fits. `bytes_used` is the budget already committed in the
+/// current window; a new circuit commits its full `max_circuit_bytes` cap up front.
+pub fn relay_admission(
+    active_circuits: usize,
+    bytes_used: u64,
+    limits: &RelayLimits,
+) -> AdmissionDecision {
+    if active_circuits >= limits.max_circuits {
+        return AdmissionDecision::RejectTooManyCircuits;
+    }
+    if bytes_used.saturating_add(limits.max_circuit_bytes) > limits.budget_bytes_per_window {
+        return AdmissionDecision::RejectOverBudget;
+    }
+    AdmissionDecision::Accept
+}
+
+/// Relay usage shared between the swarm event loop (which sees circuits open and
+/// close) and the rate limiters libp2p consults before granting reservations and
+/// circuits. libp2p does not report relayed byte counts, so each accepted circuit is
+/// charged its per-circuit cap, which libp2p itself enforces by closing the circuit.
+#[derive(Debug)]
+pub struct RelayBudget {
+    limits: RelayLimits,
+    active_circuits: usize,
+    bytes_committed: u64,
+    window_start: Instant,
+}
+
+impl RelayBudget {
+    pub fn new(limits: RelayLimits, now: Instant) -> Self {
+        Self {
+            limits,
+            active_circuits: 0,
+            bytes_committed: 0,
+            window_start: now,
+        }
+    }
+
+    fn roll_window(&mut self, now: Instant) {
+        if now.saturating_duration_since(self.window_start) >= self.limits.budget_window {
+            self.window_start = now;
+            self.bytes_committed = 0;
+        }
+    }
+
+    pub fn admission(&mut self, now: Instant) -> AdmissionDecision {
+        self.roll_window(now);
+        relay_admission(self.active_circuits, self.bytes_committed, &self.limits)
+    }
+
+    pub fn note_circuit_opened(&mut self, now: Instant) {
+        self.roll_window(now);
+        self.active_circuits += 1;
+        self.bytes_committed = self
+            .bytes_committed
+            .saturating_add(self.limits.max_circuit_bytes);
+    }
+
+    pub fn note_circuit_closed(&mut self) {
+        self.active_circuits = self.active_circuits.saturating_sub(1);
+    }
+
+    pub fn active_circuits(&self) -> usize {
+        self.active_circuits
+    }
+}
+
+pub type SharedRelayBudget = Arc<Mutex<RelayBudget>>;
+
+struct BudgetLimiter(SharedRelayBudget);
+
+impl relay::RateLimiter for BudgetLimiter {
+    fn try_next(&mut self, _peer: PeerId, _addr: &Multiaddr, now: Instant) -> bool {
+        let mut budget = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        budget.admission(now) == AdmissionDecision::Accept
+    }
+}
+
+/// Relay server config with the per-circuit caps enforced by libp2p and the shared
+/// budget gating both new reservations and new circuits.
+pub fn relay_server_config(limits: &RelayLimits, budget: &SharedRelayBudget) -> relay::Config {
+    let mut config = relay::Config {
+        max_circuits: limits.max_circuits,
+        max_circuit_bytes: limits.max_circuit_bytes,
+        max_circuit_duration: limits.max_circuit_duration,
+        ..Default::default()
+    };
+    config
+        .reservation_rate_limiters
+        .push(Box::new(BudgetLimiter(budget.clone())));
+    config
+        .circuit_src_rate_limiters
+        .push(Box::new(BudgetLimiter(budget.clone())));
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> RelayLimits {
+        RelayLimits {
+            max_circuits: 2,
+            max_circuit_bytes: 100,
+            max_circuit_duration: Duration::from_secs(60),
+            budget_bytes_per_window: 250,
+            budget_window: Duration::from_secs(3_600),
+        }
+    }
+
+    #[test]
+    fn admission_boundaries() {
+        let limits = limits();
+        assert_eq!(relay_admission(0, 0, &limits), AdmissionDecision::Accept);
+        assert_eq!(relay_admission(1, 150, &limits), AdmissionDecision::Accept);
+        assert_eq!(
+            relay_admission(2, 0, &limits),
+            AdmissionDecision::RejectTooManyCircuits
+        );
+        assert_eq!(
+            relay_admission(1, 151, &limits),
+            AdmissionDecision::RejectOverBudget
+        );
+        assert_eq!(
+            relay_admission(0, u64::MAX, &limits),
+            AdmissionDecision::RejectOverBudget
+        );
+    }
+
+    #[test]
+    fn budget_charges_circuits_and_resets_per_window() {
+        let start = Instant::now();
+        let mut budget = RelayBudget::new(limits(), start);
+        budget.note_circuit_opened(start);
+        budget.note_circuit_closed();
+        budget.note_circuit_opened(start);
+        budget.note_circuit_closed();
+        assert_eq!(budget.active_circuits(), 0);
+        // 200 of 250 bytes committed: a third full-cap circuit no longer fits.
+        assert_eq!(budget.admission(start), AdmissionDecision::RejectOverBudget);
+
+        let next_window = start + Duration::from_secs(3_600);
+        assert_eq!(budget.admission(next_window), AdmissionDecision::Accept);
+    }
+
+    #[test]
+    fn limiter_follows_shared_budget() {
+        use libp2p::relay::RateLimiter;
+
+        let start = Instant::now();
+        let budget = Arc::new(Mutex::new(RelayBudget::new(limits(), start)));
+        let mut limiter = BudgetLimiter(budget.clone());
+        let addr: Multiaddr = "/ip4/203.0.113.9/udp/4001/quic-v1".parse().unwrap();
+        assert!(limiter.try_next(PeerId::random(), &addr, start));
+
+        budget.lock().unwrap().note_circuit_opened(start);
+        budget.lock().unwrap().note_circuit_opened(start);
+        assert!(!limiter.try_next(PeerId::random(), &addr, start));
+
+        budget.lock().unwrap().note_circuit_closed();
+        assert!(
+            !limiter.try_next(PeerId::random(), &addr, start),
+            "still over budget"
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use aetherlink_proto::v1::DeviceIdentity;
+use libp2p::PeerId;
+
+use crate::security::{SessionAuthError, VerifiedSessionPeer};
+
+/// The handshake message an authentication decision was made about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMessage {
+    SessionRequest,
+    SessionAccept,
+}
+
+impl AuthMessage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuthMessage::SessionRequest => "session_request",
+            AuthMessage::SessionAccept => "session_accept",
+        }
+    }
+}
+
+/// A terminal authentication decision. A rejection reports the device code the
+/// message claimed and the transport peer id when known, since the signed identity
+/// may be exactly what failed to check out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthEvent {
+    Accepted {
+        message: AuthMessage,
+        peer_id: PeerId,
+        device_code: String,
+        unix_ms: i64,
+    },
+    Rejected {
+        message: AuthMessage,
+        peer_id: Option<PeerId>,
+        device_code: String,
+        unix_ms: i64,
+        error: SessionAuthError,
+    },
+}
+
+impl AuthEvent {
+    pub fn from_outcome(
+        message: AuthMessage,
+        claimed: Option<&DeviceIdentity>,
+        transport_peer_id: Option<&PeerId>,
+        unix_ms: i64,
+        outcome: &Result<VerifiedSessionPeer, SessionAuthError>,
+    ) -> Self {
+        match outcome {
+            Ok(verified) => AuthEvent::Accepted {
+                message,
+                peer_id: verified.peer_id,
+                device_code: verified.device_code.clone(),
+                unix_ms,
+            },
+            Err(error) => AuthEvent::Rejected {
+                message,
+                peer_id: transport_peer_id
+                    .copied()
+                    .or_else(|| claimed.and_then(|from| PeerId::from_bytes(&from.peer_id).ok())),
+                device_code: claimed
+                    .map(|from| from.device_code.clone())
+                    .unwrap_or_default(),
+                unix_ms,
+                error: error.clone(),
+            },
+        }
+    }
+}
+
+/// Receives every terminal decision of the session verifiers, e.g. to keep an audit
+/// trail. Recording cannot fail the handshake; a sink that can fail reports it itself.
+pub trait AuthAuditSink {
+    fn record(&mut self, event: &AuthEvent);
+}
+
+/// Auditing disabled.
+impl AuthAuditSink for () {
+    fn record(&mut self, _event: &AuthEvent) {}
+}
+
+impl<S: AuthAuditSink> AuthAuditSink for Option<S> {
+    fn record(&mut self, event: &AuthEvent) {
+        if let Some(sink) = self {
+            sink.record(event);
+        }
+    }
+}
+
+/// Keeps every event in memory.
+impl AuthAuditSink for Vec<AuthEvent> {
+    fn record(&mut self, event: &AuthEvent) {
+        self.push(event.clone());
+    }
+}
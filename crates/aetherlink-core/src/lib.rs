@@ -1,15 +1,44 @@
 #![forbid(unsafe_code)]
 
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+
+use aetherlink_proto::v1::RejectReason;
+use rand::{Rng, RngCore};
 use thiserror::Error;
 
+pub mod audit;
+pub mod pairing;
+pub mod resumption;
+pub mod rotation;
 pub mod security;
+pub mod session_key;
+pub mod timing;
+pub mod trust_store;
+pub mod version;
+pub use audit::{AuthAuditSink, AuthEvent, AuthMessage};
+pub use pairing::{inline_public_key, pairing_code, pairing_code_from_hex};
+pub use resumption::{DEFAULT_RESUMPTION_TICKET_TTL_MS, issue_ticket, verify_ticket};
+pub use rotation::{sign_identity_rotation, verify_rotation_proof};
 pub use security::{
-    DEFAULT_ALLOWED_SKEW_MS, DEFAULT_REPLAY_RETENTION_MS, MIN_NONCE_BYTES, NonceReplayCache,
-    SessionAuthError, TrustedPeerRecord, TrustedPeers, VerifiedSessionPeer, sign_session_accept,
-    sign_session_request, verify_session_accept, verify_session_request,
+    AuthThrottle, CHALLENGE_NONCE_BYTES, DEFAULT_ALLOWED_SKEW_MS, DEFAULT_AUTH_FAILURE_WINDOW_MS,
+    DEFAULT_AUTH_MAX_FAILURES, DEFAULT_CHALLENGE_TTL_MS, DEFAULT_REPLAY_MAX_ENTRIES,
+    DEFAULT_REPLAY_RETENTION_MS, MIN_NONCE_BYTES, NonceReplayCache, ROTATION_PROOF_MAX_AGE_MS,
+    SessionAuthError, TrustedPeerRecord, TrustedPeers, VerifiedSessionPeer, VerifierContext,
+    VerifierPolicy, constant_time_contains, constant_time_eq, issue_challenge, sign_session_accept,
+    sign_session_request, verify_challenge_response, verify_challenged_session_accept,
+    verify_challenged_session_request, verify_resumed_session_request, verify_session_accept,
+    verify_session_request,
+};
+pub use session_key::{SESSION_KEY_LEN, derive_session_key};
+pub use timing::{TimingProfileBuilder, TimingProfileError};
+pub use trust_store::{TRUST_STORE_VERSION, TrustStoreFile};
+pub use version::{
+    Compatibility, PROTOCOL_MAJOR, PROTOCOL_MINOR, is_compatible, local_protocol_version,
 };
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TimingProfile {
     pub discovery_timeout_ms: u64,
     pub direct_dial_budget_ms: u64,
@@ -19,8 +48,16 @@ pub struct TimingProfile {
     pub ping_interval_ms: u64,
     pub path_lost_threshold: u32,
     pub reconnect_budget_ms: u64,
+    /// Backoff waits allowed per outage, on top of the time budget.
+    pub reconnect_max_attempts: u32,
     pub reconnect_backoff_start_ms: u64,
     pub reconnect_backoff_max_ms: u64,
+    /// Route `AuthFailed` during the handshake through `Reconnecting` while reconnect
+    /// budget remains, for transient failures such as clock skew. Off by default, which
+    /// keeps authentication failures terminal.
+    pub auth_failure_retryable: bool,
+    /// Spread applied by `next_backoff_ms_with`, as a percentage of the backoff (0-100).
+    pub reconnect_backoff_jitter_pct: u32,
 }
 
 impl Default for TimingProfile {
@@ -34,23 +71,41 @@ impl Default for TimingProfile {
             ping_interval_ms: 1_000,
             path_lost_threshold: 3,
             reconnect_budget_ms: 15_000,
+            reconnect_max_attempts: u32::MAX,
             reconnect_backoff_start_ms: 200,
             reconnect_backoff_max_ms: 2_000,
+            auth_failure_retryable: false,
+            reconnect_backoff_jitter_pct: 0,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FailureReason {
     DiscoveryTimeout,
     RelayTimeout,
+    HandshakeTimeout,
     AuthFailed,
     VersionMismatch,
     RetryBudgetExhausted,
     UserAbort,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+impl FailureReason {
+    pub const ALL: [FailureReason; 7] = [
+        FailureReason::DiscoveryTimeout,
+        FailureReason::RelayTimeout,
+        FailureReason::HandshakeTimeout,
+        FailureReason::AuthFailed,
+        FailureReason::VersionMismatch,
+        FailureReason::RetryBudgetExhausted,
+        FailureReason::UserAbort,
+    ];
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ConnectionState {
     Idle,
     Discovering,
@@ -76,6 +131,7 @@ pub enum Trigger {
     RelayConnected,
     RelayTimeout,
     HandshakeOk,
+    HandshakeTimeout,
     AuthFailed,
     VersionMismatch,
     PathLost,
@@ -85,6 +141,29 @@ pub enum Trigger {
     UserHangup,
 }
 
+impl Trigger {
+    pub const ALL: [Trigger; 18] = [
+        Trigger::StartConnect,
+        Trigger::CandidatesFound,
+        Trigger::DiscoveryTimeout,
+        Trigger::DirectConnected,
+        Trigger::DirectNoSuccess,
+        Trigger::PunchConnected,
+        Trigger::PunchTimeout,
+        Trigger::RelayConnected,
+        Trigger::RelayTimeout,
+        Trigger::HandshakeOk,
+        Trigger::HandshakeTimeout,
+        Trigger::AuthFailed,
+        Trigger::VersionMismatch,
+        Trigger::PathLost,
+        Trigger::RetryBudgetAvailable,
+        Trigger::RetryBudgetExhausted,
+        Trigger::UserRetry,
+        Trigger::UserHangup,
+    ];
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TimerKind {
     Discovery,
@@ -95,6 +174,19 @@ pub enum TimerKind {
     ReconnectBackoff,
 }
 
+/// The trigger to feed back into `apply` when a timer armed by a transition fires.
+/// A fired reconnect backoff asks to retry; the budget guard decides whether it may.
+pub fn timer_expiry_trigger(kind: TimerKind) -> Trigger {
+    match kind {
+        TimerKind::Discovery => Trigger::DiscoveryTimeout,
+        TimerKind::DirectDial => Trigger::DirectNoSuccess,
+        TimerKind::HolePunch => Trigger::PunchTimeout,
+        TimerKind::RelayDial => Trigger::RelayTimeout,
+        TimerKind::Handshake => Trigger::HandshakeTimeout,
+        TimerKind::ReconnectBackoff => Trigger::RetryBudgetAvailable,
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Transition {
     pub from: ConnectionState,
@@ -109,14 +201,40 @@ pub enum StateMachineError {
         from: ConnectionState,
         trigger: Trigger,
     },
+    #[error("invalid responder transition from {from:?} with trigger {trigger:?}")]
+    InvalidResponderTransition {
+        from: ResponderState,
+        trigger: ResponderTrigger,
+    },
 }
 
-#[derive(Debug, Clone)]
+/// The resumable part of a `ConnectionStateMachine`; timing is supplied again on restore.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StateSnapshot {
+    pub state: ConnectionState,
+    pub reconnect_elapsed_ms: u64,
+    pub reconnect_attempts: u32,
+}
+
+pub const DEFAULT_HISTORY_CAPACITY: usize = 32;
+
+/// Called with every committed transition; see `ConnectionStateMachine::set_on_transition`.
+pub type TransitionObserver = Box<dyn FnMut(&Transition) + Send>;
+
 pub struct ConnectionStateMachine {
     state: ConnectionState,
     timing: TimingProfile,
     reconnect_elapsed_ms: u64,
     reconnect_attempts: u32,
+    /// When the current state was entered, if it was entered through `apply_at`.
+    state_entered_ms: Option<u64>,
+    state_durations: HashMap<ConnectionState, u64>,
+    /// Recent transitions, oldest first. Holds up to twice `history_capacity` entries and
+    /// drops the older half in one go, so `history` stays a contiguous slice.
+    history: Vec<Transition>,
+    history_capacity: usize,
+    on_transition: Option<TransitionObserver>,
 }
 
 impl Default for ConnectionStateMachine {
@@ -126,6 +244,43 @@ impl Default for ConnectionStateMachine {
             timing: TimingProfile::default(),
             reconnect_elapsed_ms: 0,
             reconnect_attempts: 0,
+            state_entered_ms: None,
+            state_durations: HashMap::new(),
+            history: Vec::new(),
+            history_capacity: DEFAULT_HISTORY_CAPACITY,
+            on_transition: None,
+        }
+    }
+}
+
+impl fmt::Debug for ConnectionStateMachine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionStateMachine")
+            .field("state", &self.state)
+            .field("timing", &self.timing)
+            .field("reconnect_elapsed_ms", &self.reconnect_elapsed_ms)
+            .field("reconnect_attempts", &self.reconnect_attempts)
+            .field("state_entered_ms", &self.state_entered_ms)
+            .field("state_durations", &self.state_durations)
+            .field("history", &self.history())
+            .field("on_transition", &self.on_transition.is_some())
+            .finish()
+    }
+}
+
+/// Clones the machine state only; the copy starts without an observer.
+impl Clone for ConnectionStateMachine {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            timing: self.timing.clone(),
+            reconnect_elapsed_ms: self.reconnect_elapsed_ms,
+            reconnect_attempts: self.reconnect_attempts,
+            state_entered_ms: self.state_entered_ms,
+            state_durations: self.state_durations.clone(),
+            history: self.history().to_vec(),
+            history_capacity: self.history_capacity,
+            on_transition: None,
         }
     }
 }
@@ -138,6 +293,83 @@ impl ConnectionStateMachine {
         }
     }
 
+    /// Like `new`, but refuses a profile that fails `TimingProfile::validate`.
+    pub fn try_new(timing: TimingProfile) -> Result<Self, TimingProfileError> {
+        timing.validate()?;
+        Ok(Self::new(timing))
+    }
+
+    pub fn from_snapshot(snapshot: StateSnapshot, timing: TimingProfile) -> Self {
+        Self {
+            state: snapshot.state,
+            timing,
+            reconnect_elapsed_ms: snapshot.reconnect_elapsed_ms,
+            reconnect_attempts: snapshot.reconnect_attempts,
+            ..Self::default()
+        }
+    }
+
+    /// Installs `observer`, replacing any previous one. It runs inside `apply` after the
+    /// new state is committed and never for rejected triggers.
+    pub fn set_on_transition(&mut self, observer: TransitionObserver) {
+        self.on_transition = Some(observer);
+    }
+
+    pub fn clear_on_transition(&mut self) {
+        self.on_transition = None;
+    }
+
+    /// The last `history_capacity` committed transitions, oldest first. Survives
+    /// `UserRetry`, so a failed connection keeps the record of how it got there.
+    pub fn history(&self) -> &[Transition] {
+        let start = self.history.len().saturating_sub(self.history_capacity);
+        &self.history[start..]
+    }
+
+    /// Sets how many transitions `history` keeps; zero turns recording off.
+    pub fn set_history_capacity(&mut self, capacity: usize) {
+        let keep = self.history().len().min(capacity);
+        let drop = self.history.len() - keep;
+        self.history.drain(..drop);
+        self.history_capacity = capacity;
+    }
+
+    /// Entering `Reconnecting` arms the next backoff; `transition` registers the wait.
+    fn reconnecting_backoff(&self) -> (ConnectionState, Option<(TimerKind, u64)>) {
+        (
+            ConnectionState::Reconnecting,
+            Some((TimerKind::ReconnectBackoff, self.next_backoff_ms())),
+        )
+    }
+
+    fn record_history(&mut self, transition: &Transition) {
+        if self.history_capacity == 0 {
+            return;
+        }
+        if self.history.len() >= self.history_capacity.saturating_mul(2) {
+            self.history.drain(..self.history_capacity);
+        }
+        self.history.push(transition.clone());
+    }
+
+    pub fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            state: self.state.clone(),
+            reconnect_elapsed_ms: self.reconnect_elapsed_ms,
+            reconnect_attempts: self.reconnect_attempts,
+        }
+    }
+
+    /// Returns to `Idle` from any state and zeroes the reconnect counters, keeping the
+    /// timing profile, observer, history and state durations. Not a transition, so the
+    /// observer is not called and nothing is added to the history.
+    pub fn reset(&mut self) {
+        self.state = ConnectionState::Idle;
+        self.reconnect_attempts = 0;
+        self.reconnect_elapsed_ms = 0;
+        self.state_entered_ms = None;
+    }
+
     pub fn state(&self) -> &ConnectionState {
         &self.state
     }
@@ -155,8 +387,11 @@ impl ConnectionStateMachine {
         self.reconnect_elapsed_ms = self.reconnect_elapsed_ms.saturating_add(backoff_ms);
     }
 
+    /// True while both the time budget and the attempt cap allow another dial. Each
+    /// registered backoff wait counts as one attempt.
     pub fn has_reconnect_budget(&self) -> bool {
         self.reconnect_elapsed_ms < self.timing.reconnect_budget_ms
+            && self.reconnect_attempts <= self.timing.reconnect_max_attempts
     }
 
     pub fn next_backoff_ms(&self) -> u64 {
@@ -168,9 +403,107 @@ impl ConnectionStateMachine {
         base.min(self.timing.reconnect_backoff_max_ms)
     }
 
+    /// `next_backoff_ms` spread uniformly by `reconnect_backoff_jitter_pct` either way, so
+    /// peers that dropped together do not retry in lockstep. Never exceeds the max.
+    pub fn next_backoff_ms_with(&self, rng: &mut impl RngCore) -> u64 {
+        let base = self.next_backoff_ms();
+        let pct = u64::from(self.timing.reconnect_backoff_jitter_pct.min(100));
+        let spread = base.saturating_mul(pct) / 100;
+        if spread == 0 {
+            return base;
+        }
+        let jittered = base - spread + rng.random_range(0..=spread.saturating_mul(2));
+        jittered.min(self.timing.reconnect_backoff_max_ms)
+    }
+
+    /// Triggers a controller may offer from the current state. While reconnecting only
+    /// the retry trigger matching the remaining budget is listed.
+    pub fn allowed_triggers(&self) -> Vec<Trigger> {
+        match &self.state {
+            ConnectionState::Idle => vec![Trigger::StartConnect],
+            ConnectionState::Discovering => vec![
+                Trigger::CandidatesFound,
+                Trigger::DiscoveryTimeout,
+                Trigger::UserHangup,
+            ],
+            ConnectionState::DialingDirect => vec![
+                Trigger::DirectConnected,
+                Trigger::DirectNoSuccess,
+                Trigger::UserHangup,
+            ],
+            ConnectionState::HolePunching => vec![
+                Trigger::PunchConnected,
+                Trigger::PunchTimeout,
+                Trigger::UserHangup,
+            ],
+            ConnectionState::RelayDialing => vec![
+                Trigger::RelayConnected,
+                Trigger::RelayTimeout,
+                Trigger::UserHangup,
+            ],
+            ConnectionState::SecureHandshake => vec![
+                Trigger::HandshakeOk,
+                Trigger::HandshakeTimeout,
+                Trigger::AuthFailed,
+                Trigger::VersionMismatch,
+                Trigger::UserHangup,
+            ],
+            ConnectionState::Active => vec![Trigger::PathLost, Trigger::UserHangup],
+            ConnectionState::Reconnecting if self.has_reconnect_budget() => vec![
+                Trigger::RetryBudgetAvailable,
+                Trigger::UserRetry,
+                Trigger::UserHangup,
+            ],
+            ConnectionState::Reconnecting => vec![
+                Trigger::RetryBudgetExhausted,
+                Trigger::UserRetry,
+                Trigger::UserHangup,
+            ],
+            ConnectionState::Failed(_) => vec![Trigger::UserRetry],
+            ConnectionState::Closed => Vec::new(),
+        }
+    }
+
+    /// Milliseconds spent in the current state so far; zero when the state was not
+    /// entered through `apply_at`.
+    pub fn time_in_state_ms(&self, now_ms: u64) -> u64 {
+        self.state_entered_ms
+            .map_or(0, |entered| now_ms.saturating_sub(entered))
+    }
+
+    /// Cumulative time per state over completed, timed stays. Re-entering a state adds
+    /// to its total; the current stay is counted once it is left.
+    pub fn state_durations(&self) -> HashMap<ConnectionState, u64> {
+        self.state_durations.clone()
+    }
+
     pub fn apply(&mut self, trigger: Trigger) -> Result<Transition, StateMachineError> {
+        self.transition(trigger, None, None)
+    }
+
+    /// Like `apply`, but a reconnect backoff is armed with `next_backoff_ms_with`, so
+    /// `reconnect_backoff_jitter_pct` spreads the wait.
+    pub fn apply_with_rng(
+        &mut self,
+        trigger: Trigger,
+        rng: &mut impl RngCore,
+    ) -> Result<Transition, StateMachineError> {
+        self.transition(trigger, None, Some(rng))
+    }
+
+    /// Like `apply`, also accounting the time spent in the state being left.
+    pub fn apply_at(
+        &mut self,
+        trigger: Trigger,
+        now_ms: u64,
+    ) -> Result<Transition, StateMachineError> {
+        self.transition(trigger, Some(now_ms), None)
+    }
+
+    /// Where `trigger` would lead from the current state, without committing anything.
+    pub fn peek(&self, trigger: &Trigger) -> Result<Transition, StateMachineError> {
         let from = self.state.clone();
-        let (to, arm_timer) = match (&self.state, &trigger) {
+        let (to, arm_timer) = match (&self.state, trigger) {
             (ConnectionState::Idle, Trigger::StartConnect) => (
                 ConnectionState::Discovering,
                 Some((TimerKind::Discovery, self.timing.discovery_timeout_ms)),
@@ -207,26 +540,29 @@ impl ConnectionStateMachine {
                 (ConnectionState::Failed(FailureReason::RelayTimeout), None)
             }
             (ConnectionState::SecureHandshake, Trigger::HandshakeOk) => {
-                self.reconnect_attempts = 0;
-                self.reconnect_elapsed_ms = 0;
                 (ConnectionState::Active, None)
             }
+            (ConnectionState::SecureHandshake, Trigger::AuthFailed)
+                if self.timing.auth_failure_retryable && self.has_reconnect_budget() =>
+            {
+                self.reconnecting_backoff()
+            }
             (ConnectionState::SecureHandshake, Trigger::AuthFailed) => {
                 (ConnectionState::Failed(FailureReason::AuthFailed), None)
             }
+            (ConnectionState::SecureHandshake, Trigger::HandshakeTimeout) => (
+                ConnectionState::Failed(FailureReason::HandshakeTimeout),
+                None,
+            ),
             (ConnectionState::SecureHandshake, Trigger::VersionMismatch) => (
                 ConnectionState::Failed(FailureReason::VersionMismatch),
                 None,
             ),
-            (ConnectionState::Active, Trigger::PathLost) => {
-                let wait = self.next_backoff_ms();
-                self.register_backoff_wait(wait);
-                (
-                    ConnectionState::Reconnecting,
-                    Some((TimerKind::ReconnectBackoff, wait)),
-                )
-            }
+            (ConnectionState::Active, Trigger::PathLost) => self.reconnecting_backoff(),
+            // UserRetry skips the rest of the backoff wait; arming the dial timer
+            // replaces the pending backoff timer.
             (ConnectionState::Reconnecting, Trigger::RetryBudgetAvailable)
+            | (ConnectionState::Reconnecting, Trigger::UserRetry)
                 if self.has_reconnect_budget() =>
             {
                 (
@@ -236,6 +572,7 @@ impl ConnectionStateMachine {
             }
             (ConnectionState::Reconnecting, Trigger::RetryBudgetExhausted)
             | (ConnectionState::Reconnecting, Trigger::RetryBudgetAvailable)
+            | (ConnectionState::Reconnecting, Trigger::UserRetry)
                 if !self.has_reconnect_budget() =>
             {
                 (
@@ -248,6 +585,7 @@ impl ConnectionStateMachine {
                 // Retry from Idle keeps old telemetry but resets active attempt.
                 None,
             ),
+            // Hangup from any in-flight state, including a Reconnecting backoff wait, closes.
             (ConnectionState::Active, Trigger::UserHangup)
             | (ConnectionState::Reconnecting, Trigger::UserHangup)
             | (ConnectionState::SecureHandshake, Trigger::UserHangup)
@@ -264,18 +602,207 @@ impl ConnectionStateMachine {
                 });
             }
         };
-
-        self.state = to.clone();
         Ok(Transition {
             from,
             to,
             arm_timer,
         })
     }
+
+    fn transition(
+        &mut self,
+        trigger: Trigger,
+        now_ms: Option<u64>,
+        rng: Option<&mut dyn RngCore>,
+    ) -> Result<Transition, StateMachineError> {
+        let mut transition = self.peek(&trigger)?;
+        if let (Some(mut rng), Some((TimerKind::ReconnectBackoff, wait))) =
+            (rng, transition.arm_timer.as_mut())
+        {
+            *wait = self.next_backoff_ms_with(&mut rng);
+        }
+
+        // Counter side effects of the decision `peek` made.
+        match (&transition.to, transition.arm_timer) {
+            (ConnectionState::Reconnecting, Some((TimerKind::ReconnectBackoff, wait))) => {
+                self.register_backoff_wait(wait);
+            }
+            (ConnectionState::Active, _) => {
+                self.reconnect_attempts = 0;
+                self.reconnect_elapsed_ms = 0;
+            }
+            _ => {}
+        }
+        if let (Some(entered), Some(now)) = (self.state_entered_ms, now_ms) {
+            *self
+                .state_durations
+                .entry(transition.from.clone())
+                .or_default() += now.saturating_sub(entered);
+        }
+        self.state_entered_ms = now_ms;
+        self.state = transition.to.clone();
+        self.record_history(&transition);
+        if let Some(observer) = self.on_transition.as_mut() {
+            observer(&transition);
+        }
+        Ok(transition)
+    }
+}
+
+/// Renders the transition table as Graphviz DOT by probing `apply` from every state
+/// with every trigger, once with reconnect budget left and once without. Edges whose
+/// outcome depends on the budget carry the guard in their label.
+pub fn state_machine_dot() -> String {
+    let timing = TimingProfile::default();
+    let mut states = vec![
+        ConnectionState::Idle,
+        ConnectionState::Discovering,
+        ConnectionState::DialingDirect,
+        ConnectionState::HolePunching,
+        ConnectionState::RelayDialing,
+        ConnectionState::SecureHandshake,
+        ConnectionState::Active,
+        ConnectionState::Reconnecting,
+    ];
+    states.extend(FailureReason::ALL.map(ConnectionState::Failed));
+    states.push(ConnectionState::Closed);
+
+    let probe = |state: &ConnectionState, trigger: &Trigger, elapsed_ms: u64| {
+        let mut sm = ConnectionStateMachine {
+            state: state.clone(),
+            reconnect_elapsed_ms: elapsed_ms,
+            ..ConnectionStateMachine::new(timing.clone())
+        };
+        sm.apply(trigger.clone()).ok()
+    };
+
+    let mut dot = String::from("digraph connection_state_machine {\n");
+    for state in &states {
+        let _ = writeln!(dot, "    \"{state:?}\";");
+    }
+    for state in &states {
+        for trigger in Trigger::ALL {
+            let with_budget = probe(state, &trigger, 0);
+            let without_budget = probe(state, &trigger, timing.reconnect_budget_ms);
+            let edges = if with_budget == without_budget {
+                vec![(with_budget, "")]
+            } else {
+                vec![
+                    (with_budget, " [budget left]"),
+                    (without_budget, " [budget exhausted]"),
+                ]
+            };
+            for (transition, guard) in edges {
+                let Some(transition) = transition else {
+                    continue;
+                };
+                let timer = transition
+                    .arm_timer
+                    .map(|(kind, _)| format!(" / arm {kind:?}"))
+                    .unwrap_or_default();
+                let _ = writeln!(
+                    dot,
+                    "    \"{:?}\" -> \"{:?}\" [label=\"{trigger:?}{guard}{timer}\"];",
+                    transition.from, transition.to
+                );
+            }
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Lifecycle of the accepting side of a session, per requesting peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResponderState {
+    Listening,
+    Verifying,
+    /// `SessionAccept` sent; waiting for the controller's first keepalive.
+    Accepted,
+    Active,
+    Rejected(RejectReason),
+    Closed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResponderTrigger {
+    RequestReceived,
+    VerifyOk,
+    VerifyFailed(RejectReason),
+    KeepaliveReceived,
+    SessionClosed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponderTransition {
+    pub from: ResponderState,
+    pub to: ResponderState,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResponderStateMachine {
+    state: ResponderState,
+}
+
+impl Default for ResponderStateMachine {
+    fn default() -> Self {
+        Self {
+            state: ResponderState::Listening,
+        }
+    }
+}
+
+impl ResponderStateMachine {
+    pub fn state(&self) -> &ResponderState {
+        &self.state
+    }
+
+    pub fn apply(
+        &mut self,
+        trigger: ResponderTrigger,
+    ) -> Result<ResponderTransition, StateMachineError> {
+        let from = self.state.clone();
+        let to = match (&self.state, &trigger) {
+            // A new request restarts verification from anywhere but mid-verification,
+            // e.g. a controller reconnecting after a rejection or a dropped session.
+            (ResponderState::Verifying, ResponderTrigger::RequestReceived) => {
+                return Err(StateMachineError::InvalidResponderTransition {
+                    from,
+                    trigger: trigger.clone(),
+                });
+            }
+            (_, ResponderTrigger::RequestReceived) => ResponderState::Verifying,
+            (ResponderState::Verifying, ResponderTrigger::VerifyOk) => ResponderState::Accepted,
+            (ResponderState::Verifying, ResponderTrigger::VerifyFailed(reason)) => {
+                ResponderState::Rejected(*reason)
+            }
+            (
+                ResponderState::Accepted | ResponderState::Active,
+                ResponderTrigger::KeepaliveReceived,
+            ) => ResponderState::Active,
+            (
+                ResponderState::Verifying | ResponderState::Accepted | ResponderState::Active,
+                ResponderTrigger::SessionClosed,
+            ) => ResponderState::Closed,
+            _ => {
+                return Err(StateMachineError::InvalidResponderTransition {
+                    from,
+                    trigger: trigger.clone(),
+                });
+            }
+        };
+        self.state = to.clone();
+        Ok(ResponderTransition { from, to })
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
     use super::*;
 
     #[test]
@@ -322,6 +849,705 @@ mod tests {
         assert_eq!(sm.reconnect_elapsed_ms(), 0);
     }
 
+    fn machine_in(state: ConnectionState, elapsed_ms: u64) -> ConnectionStateMachine {
+        ConnectionStateMachine {
+            state,
+            reconnect_elapsed_ms: elapsed_ms,
+            ..ConnectionStateMachine::default()
+        }
+    }
+
+    #[test]
+    fn allowed_triggers_match_apply_for_every_state() {
+        let budget = TimingProfile::default().reconnect_budget_ms;
+        let machines = [
+            machine_in(ConnectionState::Idle, 0),
+            machine_in(ConnectionState::Discovering, 0),
+            machine_in(ConnectionState::DialingDirect, 0),
+            machine_in(ConnectionState::HolePunching, 0),
+            machine_in(ConnectionState::RelayDialing, 0),
+            machine_in(ConnectionState::SecureHandshake, 0),
+            machine_in(ConnectionState::Active, 0),
+            machine_in(ConnectionState::Reconnecting, 0),
+            machine_in(ConnectionState::Reconnecting, budget),
+            machine_in(ConnectionState::Failed(FailureReason::AuthFailed), 0),
+            machine_in(ConnectionState::Closed, 0),
+        ];
+
+        for sm in machines {
+            let allowed = sm.allowed_triggers();
+            for trigger in Trigger::ALL {
+                let accepted = sm.clone().apply(trigger.clone()).is_ok();
+                if allowed.contains(&trigger) {
+                    assert!(accepted, "{:?} should accept {trigger:?}", sm.state());
+                } else if !(sm.state() == &ConnectionState::Reconnecting
+                    && trigger == Trigger::RetryBudgetAvailable)
+                {
+                    // apply still maps a late RetryBudgetAvailable to exhaustion.
+                    assert!(!accepted, "{:?} should reject {trigger:?}", sm.state());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn allowed_triggers_follow_reconnect_budget() {
+        let mut sm = machine_in(ConnectionState::Reconnecting, 0);
+        assert_eq!(
+            sm.allowed_triggers(),
+            vec![
+                Trigger::RetryBudgetAvailable,
+                Trigger::UserRetry,
+                Trigger::UserHangup
+            ]
+        );
+
+        sm.register_backoff_wait(TimingProfile::default().reconnect_budget_ms);
+        assert!(!sm.has_reconnect_budget());
+        assert_eq!(
+            sm.allowed_triggers(),
+            vec![
+                Trigger::RetryBudgetExhausted,
+                Trigger::UserRetry,
+                Trigger::UserHangup
+            ]
+        );
+
+        // Querying does not move the machine.
+        assert_eq!(sm.state(), &ConnectionState::Reconnecting);
+        assert_eq!(sm.reconnect_attempts(), 1);
+    }
+
+    #[test]
+    fn snapshot_restores_reconnect_counters() {
+        let timing = TimingProfile {
+            reconnect_budget_ms: 10_000,
+            ..TimingProfile::default()
+        };
+        let mut sm = ConnectionStateMachine::new(timing.clone());
+        sm.apply(Trigger::StartConnect).unwrap();
+        sm.apply(Trigger::CandidatesFound).unwrap();
+        sm.apply(Trigger::DirectConnected).unwrap();
+        sm.apply(Trigger::HandshakeOk).unwrap();
+        sm.apply(Trigger::PathLost).unwrap();
+        sm.register_backoff_wait(400);
+        assert_eq!(sm.reconnect_attempts(), 2);
+
+        let restored = ConnectionStateMachine::from_snapshot(sm.snapshot(), timing);
+        assert_eq!(restored.state(), &ConnectionState::Reconnecting);
+        assert_eq!(restored.reconnect_attempts(), 2);
+        assert_eq!(restored.reconnect_elapsed_ms(), 600);
+        assert_eq!(restored.next_backoff_ms(), sm.next_backoff_ms());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn snapshot_round_trips_through_json() {
+        let mut sm = ConnectionStateMachine::default();
+        sm.apply(Trigger::StartConnect).unwrap();
+        sm.apply(Trigger::CandidatesFound).unwrap();
+        sm.apply(Trigger::DirectConnected).unwrap();
+        sm.apply(Trigger::HandshakeOk).unwrap();
+        sm.apply(Trigger::PathLost).unwrap();
+        sm.register_backoff_wait(400);
+
+        let json = serde_json::to_string(&sm.snapshot()).unwrap();
+        let snapshot: StateSnapshot = serde_json::from_str(&json).unwrap();
+        let restored = ConnectionStateMachine::from_snapshot(snapshot, TimingProfile::default());
+        assert_eq!(restored.snapshot(), sm.snapshot());
+        assert_eq!(restored.reconnect_attempts(), 2);
+        assert_eq!(restored.reconnect_elapsed_ms(), 600);
+
+        let failed = StateSnapshot {
+            state: ConnectionState::Failed(FailureReason::RetryBudgetExhausted),
+            reconnect_elapsed_ms: 0,
+            reconnect_attempts: 0,
+        };
+        let json = serde_json::to_string(&failed).unwrap();
+        assert_eq!(
+            serde_json::from_str::<StateSnapshot>(&json).unwrap(),
+            failed
+        );
+
+        let timing = TimingProfile::default();
+        let json = serde_json::to_string(&timing).unwrap();
+        assert_eq!(
+            serde_json::from_str::<TimingProfile>(&json).unwrap(),
+            timing
+        );
+    }
+
+    #[test]
+    fn observer_sees_each_committed_transition() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut sm = ConnectionStateMachine::default();
+        let sink = Arc::clone(&seen);
+        sm.set_on_transition(Box::new(move |transition| {
+            sink.lock().unwrap().push(transition.clone());
+        }));
+
+        sm.apply(Trigger::StartConnect).unwrap();
+        sm.apply(Trigger::HandshakeOk).unwrap_err();
+        let last = sm.apply(Trigger::DiscoveryTimeout).unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert_eq!(seen[0].from, ConnectionState::Idle);
+        assert_eq!(seen[0].to, ConnectionState::Discovering);
+        assert_eq!(
+            seen[0].arm_timer,
+            Some((
+                TimerKind::Discovery,
+                TimingProfile::default().discovery_timeout_ms
+            ))
+        );
+        assert_eq!(seen[1], last);
+    }
+
+    #[test]
+    fn cleared_observer_stops_firing() {
+        let count = Arc::new(Mutex::new(0));
+        let mut sm = ConnectionStateMachine::default();
+        let sink = Arc::clone(&count);
+        sm.set_on_transition(Box::new(move |_| *sink.lock().unwrap() += 1));
+        sm.apply(Trigger::StartConnect).unwrap();
+        sm.clear_on_transition();
+        sm.apply(Trigger::CandidatesFound).unwrap();
+        assert_eq!(*count.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn timed_transitions_account_time_in_state() {
+        let mut sm = ConnectionStateMachine::default();
+        assert_eq!(sm.time_in_state_ms(500), 0);
+
+        sm.apply_at(Trigger::StartConnect, 1_000).unwrap();
+        assert_eq!(sm.time_in_state_ms(1_250), 250);
+        sm.apply_at(Trigger::CandidatesFound, 1_300).unwrap();
+        sm.apply_at(Trigger::DirectNoSuccess, 1_500).unwrap();
+        // A rejected trigger leaves the clock alone.
+        sm.apply_at(Trigger::HandshakeOk, 1_600).unwrap_err();
+        sm.apply_at(Trigger::PunchConnected, 2_400).unwrap();
+
+        let durations = sm.state_durations();
+        assert_eq!(durations[&ConnectionState::Discovering], 300);
+        assert_eq!(durations[&ConnectionState::DialingDirect], 200);
+        assert_eq!(durations[&ConnectionState::HolePunching], 900);
+        assert!(!durations.contains_key(&ConnectionState::SecureHandshake));
+        assert_eq!(sm.time_in_state_ms(2_450), 50);
+    }
+
+    #[test]
+    fn reconnecting_reentries_accumulate() {
+        let mut sm = ConnectionStateMachine::default();
+        sm.apply_at(Trigger::StartConnect, 0).unwrap();
+        sm.apply_at(Trigger::CandidatesFound, 10).unwrap();
+        sm.apply_at(Trigger::DirectConnected, 20).unwrap();
+        sm.apply_at(Trigger::HandshakeOk, 30).unwrap();
+
+        sm.apply_at(Trigger::PathLost, 1_000).unwrap();
+        sm.apply_at(Trigger::RetryBudgetAvailable, 1_200).unwrap();
+        sm.apply_at(Trigger::DirectConnected, 1_300).unwrap();
+        sm.apply_at(Trigger::HandshakeOk, 1_400).unwrap();
+        sm.apply_at(Trigger::PathLost, 2_000).unwrap();
+        sm.apply_at(Trigger::RetryBudgetAvailable, 2_350).unwrap();
+
+        let durations = sm.state_durations();
+        assert_eq!(durations[&ConnectionState::Reconnecting], 550);
+        assert_eq!(durations[&ConnectionState::Active], 970 + 600);
+    }
+
+    #[test]
+    fn untimed_apply_stops_the_clock() {
+        let mut sm = ConnectionStateMachine::default();
+        sm.apply_at(Trigger::StartConnect, 100).unwrap();
+        sm.apply(Trigger::CandidatesFound).unwrap();
+        assert_eq!(sm.time_in_state_ms(900), 0);
+        sm.apply_at(Trigger::DirectConnected, 1_000).unwrap();
+
+        // Only the stay that began with a timestamp is counted.
+        let durations = sm.state_durations();
+        assert!(!durations.contains_key(&ConnectionState::Discovering));
+        assert!(!durations.contains_key(&ConnectionState::DialingDirect));
+        assert_eq!(sm.time_in_state_ms(1_100), 100);
+    }
+
+    #[test]
+    fn history_survives_user_retry() {
+        let mut sm = ConnectionStateMachine::default();
+        sm.apply(Trigger::StartConnect).unwrap();
+        sm.apply(Trigger::DiscoveryTimeout).unwrap();
+        sm.apply(Trigger::PathLost).unwrap_err();
+        sm.apply(Trigger::UserRetry).unwrap();
+
+        let history = sm.history();
+        assert_eq!(history.len(), 3);
+        assert_eq!(
+            history[1].to,
+            ConnectionState::Failed(FailureReason::DiscoveryTimeout)
+        );
+        assert_eq!(history[2].to, ConnectionState::Idle);
+    }
+
+    #[test]
+    fn history_wraps_and_drops_the_oldest_entry() {
+        let mut sm = ConnectionStateMachine::default();
+        sm.set_history_capacity(3);
+        sm.apply(Trigger::StartConnect).unwrap();
+        sm.apply(Trigger::CandidatesFound).unwrap();
+        sm.apply(Trigger::DirectConnected).unwrap();
+        assert_eq!(sm.history()[0].from, ConnectionState::Idle);
+
+        sm.apply(Trigger::HandshakeOk).unwrap();
+        let froms: Vec<_> = sm.history().iter().map(|t| t.from.clone()).collect();
+        assert_eq!(
+            froms,
+            vec![
+                ConnectionState::Discovering,
+                ConnectionState::DialingDirect,
+                ConnectionState::SecureHandshake,
+            ]
+        );
+
+        // Long-lived machines stay bounded.
+        for _ in 0..100 {
+            sm.apply(Trigger::PathLost).unwrap();
+            sm.apply(Trigger::UserHangup).unwrap();
+            sm.state = ConnectionState::Active;
+        }
+        assert_eq!(sm.history().len(), 3);
+        assert!(sm.history.len() <= 6);
+        assert_eq!(sm.history()[2].to, ConnectionState::Closed);
+    }
+
+    #[test]
+    fn shrinking_history_keeps_the_newest_entries() {
+        let mut sm = ConnectionStateMachine::default();
+        sm.apply(Trigger::StartConnect).unwrap();
+        sm.apply(Trigger::CandidatesFound).unwrap();
+        sm.apply(Trigger::DirectConnected).unwrap();
+
+        sm.set_history_capacity(1);
+        assert_eq!(sm.history().len(), 1);
+        assert_eq!(sm.history()[0].to, ConnectionState::SecureHandshake);
+
+        sm.set_history_capacity(0);
+        sm.apply(Trigger::HandshakeOk).unwrap();
+        assert!(sm.history().is_empty());
+    }
+
+    #[test]
+    fn backoff_jitter_stays_within_spread_and_max() {
+        let mut sm = ConnectionStateMachine::new(TimingProfile {
+            reconnect_backoff_start_ms: 200,
+            reconnect_backoff_max_ms: 1_000,
+            reconnect_backoff_jitter_pct: 25,
+            ..TimingProfile::default()
+        });
+        let mut rng = StdRng::seed_from_u64(7);
+
+        let samples: Vec<u64> = (0..200)
+            .map(|_| sm.next_backoff_ms_with(&mut rng))
+            .collect();
+        assert!(samples.iter().all(|ms| (150..=250).contains(ms)));
+        assert!(samples.iter().any(|ms| *ms != 200));
+        assert_eq!(sm.next_backoff_ms(), 200);
+
+        // At the cap the upper half of the spread is clamped away.
+        sm.register_backoff_wait(0);
+        sm.register_backoff_wait(0);
+        sm.register_backoff_wait(0);
+        assert_eq!(sm.next_backoff_ms(), 1_000);
+        for _ in 0..200 {
+            let ms = sm.next_backoff_ms_with(&mut rng);
+            assert!((750..=1_000).contains(&ms));
+        }
+    }
+
+    #[test]
+    fn jittered_apply_arms_the_backoff_inside_the_spread() {
+        let timing = TimingProfile {
+            reconnect_backoff_start_ms: 200,
+            reconnect_backoff_jitter_pct: 25,
+            ..TimingProfile::default()
+        };
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut waits = Vec::new();
+        for _ in 0..50 {
+            let mut sm = handshaking_machine(timing.clone());
+            sm.apply(Trigger::HandshakeOk).unwrap();
+            let transition = sm.apply_with_rng(Trigger::PathLost, &mut rng).unwrap();
+            let Some((TimerKind::ReconnectBackoff, wait)) = transition.arm_timer else {
+                panic!("no backoff armed: {transition:?}");
+            };
+            assert!((150..=250).contains(&wait), "{wait}");
+            assert_eq!(sm.reconnect_elapsed_ms(), wait);
+            waits.push(wait);
+        }
+        assert!(waits.iter().any(|wait| *wait != 200));
+    }
+
+    #[test]
+    fn backoff_jitter_is_reproducible_and_off_by_default() {
+        let sm = ConnectionStateMachine::new(TimingProfile {
+            reconnect_backoff_jitter_pct: 50,
+            ..TimingProfile::default()
+        });
+        let first: Vec<u64> = {
+            let mut rng = StdRng::seed_from_u64(42);
+            (0..10).map(|_| sm.next_backoff_ms_with(&mut rng)).collect()
+        };
+        let mut rng = StdRng::seed_from_u64(42);
+        let second: Vec<u64> = (0..10).map(|_| sm.next_backoff_ms_with(&mut rng)).collect();
+        assert_eq!(first, second);
+
+        let plain = ConnectionStateMachine::default();
+        assert_eq!(
+            plain.next_backoff_ms_with(&mut rng),
+            plain.next_backoff_ms()
+        );
+    }
+
+    #[test]
+    fn attempt_cap_exhausts_reconnect_before_time_budget() {
+        let mut sm = ConnectionStateMachine::new(TimingProfile {
+            reconnect_max_attempts: 2,
+            ..TimingProfile::default()
+        });
+        sm.apply(Trigger::StartConnect).unwrap();
+        sm.apply(Trigger::CandidatesFound).unwrap();
+        sm.apply(Trigger::DirectConnected).unwrap();
+        sm.apply(Trigger::HandshakeOk).unwrap();
+
+        sm.apply(Trigger::PathLost).unwrap();
+        sm.register_backoff_wait(0);
+        assert_eq!(sm.reconnect_attempts(), 2);
+        assert!(sm.has_reconnect_budget());
+
+        sm.register_backoff_wait(0);
+        assert!(sm.reconnect_elapsed_ms() < TimingProfile::default().reconnect_budget_ms);
+        assert!(!sm.has_reconnect_budget());
+        sm.apply(Trigger::RetryBudgetAvailable).unwrap();
+        assert_eq!(
+            sm.state(),
+            &ConnectionState::Failed(FailureReason::RetryBudgetExhausted)
+        );
+    }
+
+    #[test]
+    fn zero_attempt_cap_fails_on_first_path_loss() {
+        let mut sm = ConnectionStateMachine::new(TimingProfile {
+            reconnect_max_attempts: 0,
+            ..TimingProfile::default()
+        });
+        sm.apply(Trigger::StartConnect).unwrap();
+        sm.apply(Trigger::CandidatesFound).unwrap();
+        sm.apply(Trigger::DirectConnected).unwrap();
+        sm.apply(Trigger::HandshakeOk).unwrap();
+        sm.apply(Trigger::PathLost).unwrap();
+
+        assert_eq!(sm.allowed_triggers()[0], Trigger::RetryBudgetExhausted);
+        sm.apply(Trigger::RetryBudgetExhausted).unwrap();
+        assert_eq!(
+            sm.state(),
+            &ConnectionState::Failed(FailureReason::RetryBudgetExhausted)
+        );
+    }
+
+    #[test]
+    fn dot_export_covers_the_transition_table() {
+        let dot = state_machine_dot();
+        assert!(dot.starts_with("digraph connection_state_machine {"));
+        assert_eq!(dot.matches(" -> ").count(), 33);
+        assert!(
+            dot.contains("\"Idle\" -> \"Discovering\" [label=\"StartConnect / arm Discovery\"];")
+        );
+        assert!(dot.contains(
+            "\"Reconnecting\" -> \"DialingDirect\" \
+             [label=\"RetryBudgetAvailable [budget left] / arm DirectDial\"];"
+        ));
+        assert!(dot.contains(
+            "\"Reconnecting\" -> \"Failed(RetryBudgetExhausted)\" \
+             [label=\"RetryBudgetAvailable [budget exhausted]\"];"
+        ));
+        assert!(dot.contains("\"Reconnecting\" -> \"Closed\" [label=\"UserHangup\"];"));
+        assert!(dot.contains("\"Closed\";"));
+        assert!(!dot.contains("\"Closed\" ->"));
+    }
+
+    fn reconnecting_machine(timing: TimingProfile) -> ConnectionStateMachine {
+        let mut sm = ConnectionStateMachine::new(timing);
+        sm.apply(Trigger::StartConnect).unwrap();
+        sm.apply(Trigger::CandidatesFound).unwrap();
+        sm.apply(Trigger::DirectConnected).unwrap();
+        sm.apply(Trigger::HandshakeOk).unwrap();
+        sm.apply(Trigger::PathLost).unwrap();
+        sm
+    }
+
+    #[test]
+    fn user_retry_skips_the_backoff_wait() {
+        let mut sm = reconnecting_machine(TimingProfile::default());
+        let transition = sm.apply(Trigger::UserRetry).unwrap();
+        assert_eq!(transition.to, ConnectionState::DialingDirect);
+        assert_eq!(
+            transition.arm_timer,
+            Some((
+                TimerKind::DirectDial,
+                TimingProfile::default().direct_dial_budget_ms
+            ))
+        );
+        // The skipped wait still counts against the budget.
+        assert_eq!(sm.reconnect_attempts(), 1);
+    }
+
+    #[test]
+    fn user_retry_without_budget_fails_instead_of_erroring() {
+        let mut sm = reconnecting_machine(TimingProfile {
+            reconnect_budget_ms: 100,
+            ..TimingProfile::default()
+        });
+        assert!(!sm.has_reconnect_budget());
+        sm.apply(Trigger::UserRetry).unwrap();
+        assert_eq!(
+            sm.state(),
+            &ConnectionState::Failed(FailureReason::RetryBudgetExhausted)
+        );
+    }
+
+    #[test]
+    fn user_hangup_while_reconnecting_closes() {
+        let mut sm = reconnecting_machine(TimingProfile::default());
+        sm.apply(Trigger::UserHangup).unwrap();
+        assert_eq!(sm.state(), &ConnectionState::Closed);
+    }
+
+    fn handshaking_machine(timing: TimingProfile) -> ConnectionStateMachine {
+        let mut sm = ConnectionStateMachine::new(timing);
+        sm.apply(Trigger::StartConnect).unwrap();
+        sm.apply(Trigger::CandidatesFound).unwrap();
+        sm.apply(Trigger::DirectConnected).unwrap();
+        sm
+    }
+
+    #[test]
+    fn auth_failure_is_terminal_by_default() {
+        let mut sm = handshaking_machine(TimingProfile::default());
+        sm.apply(Trigger::AuthFailed).unwrap();
+        assert_eq!(
+            sm.state(),
+            &ConnectionState::Failed(FailureReason::AuthFailed)
+        );
+    }
+
+    #[test]
+    fn retryable_auth_failure_reconnects_within_budget() {
+        let timing = TimingProfile {
+            auth_failure_retryable: true,
+            reconnect_max_attempts: 1,
+            ..TimingProfile::default()
+        };
+        let mut sm = handshaking_machine(timing);
+        let transition = sm.apply(Trigger::AuthFailed).unwrap();
+        assert_eq!(transition.to, ConnectionState::Reconnecting);
+        assert_eq!(
+            transition.arm_timer,
+            Some((TimerKind::ReconnectBackoff, 200))
+        );
+
+        // Second failure with the attempt cap spent keeps the auth reason.
+        sm.apply(Trigger::RetryBudgetAvailable).unwrap();
+        sm.apply(Trigger::DirectConnected).unwrap();
+        sm.register_backoff_wait(0);
+        sm.apply(Trigger::AuthFailed).unwrap();
+        assert_eq!(
+            sm.state(),
+            &ConnectionState::Failed(FailureReason::AuthFailed)
+        );
+    }
+
+    #[test]
+    fn peek_matches_apply_without_mutating() {
+        let mut sm = ConnectionStateMachine::default();
+        sm.apply(Trigger::StartConnect).unwrap();
+        sm.apply(Trigger::CandidatesFound).unwrap();
+        sm.apply(Trigger::DirectConnected).unwrap();
+        sm.apply(Trigger::HandshakeOk).unwrap();
+
+        let peeked = sm.peek(&Trigger::PathLost).unwrap();
+        assert_eq!(sm.state(), &ConnectionState::Active);
+        assert_eq!(sm.reconnect_attempts(), 0);
+        assert_eq!(sm.reconnect_elapsed_ms(), 0);
+        assert!(
+            sm.history()
+                .iter()
+                .all(|t| t.to != ConnectionState::Reconnecting)
+        );
+
+        assert_eq!(sm.apply(Trigger::PathLost).unwrap(), peeked);
+        assert_eq!(sm.reconnect_attempts(), 1);
+        assert_eq!(sm.reconnect_elapsed_ms(), 200);
+
+        // Rejections come back from peek the same way apply reports them.
+        let err = sm.peek(&Trigger::PathLost).unwrap_err();
+        assert!(matches!(
+            err,
+            StateMachineError::InvalidTransition {
+                from: ConnectionState::Reconnecting,
+                trigger: Trigger::PathLost
+            }
+        ));
+        assert_eq!(sm.reconnect_attempts(), 1);
+    }
+
+    #[test]
+    fn peek_agrees_with_apply_for_every_state_and_trigger() {
+        let budget = TimingProfile::default().reconnect_budget_ms;
+        for state in [
+            ConnectionState::Idle,
+            ConnectionState::Discovering,
+            ConnectionState::DialingDirect,
+            ConnectionState::HolePunching,
+            ConnectionState::RelayDialing,
+            ConnectionState::SecureHandshake,
+            ConnectionState::Active,
+            ConnectionState::Reconnecting,
+            ConnectionState::Failed(FailureReason::UserAbort),
+            ConnectionState::Closed,
+        ] {
+            for elapsed_ms in [0, budget] {
+                let sm = machine_in(state.clone(), elapsed_ms);
+                for trigger in Trigger::ALL {
+                    assert_eq!(sm.peek(&trigger), sm.clone().apply(trigger.clone()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_armed_timer_expires_into_an_accepted_trigger() {
+        let mut sm = ConnectionStateMachine::default();
+        let mut armed = sm.apply(Trigger::StartConnect).unwrap().arm_timer;
+        let mut fired = Vec::new();
+        // Let every timer fire until the machine lands somewhere without one.
+        while let Some((kind, _)) = armed {
+            fired.push(kind);
+            armed = sm.apply(timer_expiry_trigger(kind)).unwrap().arm_timer;
+        }
+        assert_eq!(fired, vec![TimerKind::Discovery]);
+        assert_eq!(
+            sm.state(),
+            &ConnectionState::Failed(FailureReason::DiscoveryTimeout)
+        );
+
+        let mut sm = ConnectionStateMachine::default();
+        sm.apply(Trigger::StartConnect).unwrap();
+        let mut armed = sm.apply(Trigger::CandidatesFound).unwrap().arm_timer;
+        let mut fired = Vec::new();
+        while let Some((kind, _)) = armed {
+            fired.push(kind);
+            armed = sm.apply(timer_expiry_trigger(kind)).unwrap().arm_timer;
+        }
+        assert_eq!(
+            fired,
+            vec![
+                TimerKind::DirectDial,
+                TimerKind::HolePunch,
+                TimerKind::RelayDial
+            ]
+        );
+        assert_eq!(
+            sm.state(),
+            &ConnectionState::Failed(FailureReason::RelayTimeout)
+        );
+    }
+
+    #[test]
+    fn stuck_handshake_times_out() {
+        let mut sm = handshaking_machine(TimingProfile::default());
+        let transition = sm
+            .apply(timer_expiry_trigger(TimerKind::Handshake))
+            .unwrap();
+        assert_eq!(
+            transition.to,
+            ConnectionState::Failed(FailureReason::HandshakeTimeout)
+        );
+
+        let mut sm = reconnecting_machine(TimingProfile::default());
+        sm.apply(timer_expiry_trigger(TimerKind::ReconnectBackoff))
+            .unwrap();
+        assert_eq!(sm.state(), &ConnectionState::DialingDirect);
+    }
+
+    #[test]
+    fn reset_reuses_the_machine_with_its_timing_and_history() {
+        let timing = TimingProfile {
+            reconnect_backoff_start_ms: 50,
+            ..TimingProfile::default()
+        };
+        let mut sm = reconnecting_machine(timing);
+        sm.register_backoff_wait(100);
+        let history_len = sm.history().len();
+
+        sm.reset();
+        assert_eq!(sm.state(), &ConnectionState::Idle);
+        assert_eq!(sm.reconnect_attempts(), 0);
+        assert_eq!(sm.reconnect_elapsed_ms(), 0);
+        assert_eq!(sm.next_backoff_ms(), 50);
+        assert_eq!(sm.history().len(), history_len);
+
+        sm.apply(Trigger::StartConnect).unwrap();
+        assert_eq!(sm.state(), &ConnectionState::Discovering);
+    }
+
+    #[test]
+    fn responder_walks_request_to_active_and_closed() {
+        let mut rsm = ResponderStateMachine::default();
+        assert_eq!(rsm.state(), &ResponderState::Listening);
+        rsm.apply(ResponderTrigger::RequestReceived).unwrap();
+        rsm.apply(ResponderTrigger::VerifyOk).unwrap();
+        assert_eq!(rsm.state(), &ResponderState::Accepted);
+        rsm.apply(ResponderTrigger::KeepaliveReceived).unwrap();
+        rsm.apply(ResponderTrigger::KeepaliveReceived).unwrap();
+        assert_eq!(rsm.state(), &ResponderState::Active);
+        let transition = rsm.apply(ResponderTrigger::SessionClosed).unwrap();
+        assert_eq!(transition.from, ResponderState::Active);
+        assert_eq!(transition.to, ResponderState::Closed);
+    }
+
+    #[test]
+    fn responder_rejection_keeps_the_reason_and_allows_a_new_request() {
+        let mut rsm = ResponderStateMachine::default();
+        rsm.apply(ResponderTrigger::RequestReceived).unwrap();
+        rsm.apply(ResponderTrigger::VerifyFailed(RejectReason::AuthFailed))
+            .unwrap();
+        assert_eq!(
+            rsm.state(),
+            &ResponderState::Rejected(RejectReason::AuthFailed)
+        );
+        rsm.apply(ResponderTrigger::KeepaliveReceived).unwrap_err();
+
+        rsm.apply(ResponderTrigger::RequestReceived).unwrap();
+        assert_eq!(rsm.state(), &ResponderState::Verifying);
+    }
+
+    #[test]
+    fn responder_rejects_out_of_order_triggers() {
+        let mut rsm = ResponderStateMachine::default();
+        let err = rsm.apply(ResponderTrigger::VerifyOk).unwrap_err();
+        assert_eq!(
+            err,
+            StateMachineError::InvalidResponderTransition {
+                from: ResponderState::Listening,
+                trigger: ResponderTrigger::VerifyOk
+            }
+        );
+        rsm.apply(ResponderTrigger::RequestReceived).unwrap();
+        rsm.apply(ResponderTrigger::RequestReceived).unwrap_err();
+        rsm.apply(ResponderTrigger::KeepaliveReceived).unwrap_err();
+        assert_eq!(rsm.state(), &ResponderState::Verifying);
+    }
+
     #[test]
     fn invalid_transition_is_rejected() {
         let mut sm = ConnectionStateMachine::default();
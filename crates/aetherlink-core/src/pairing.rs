@@ -0,0 +1,97 @@
+use libp2p::{PeerId, identity, multihash::Multihash};
+use sha2::{Digest, Sha256};
+
+use crate::security::{SessionAuthError, decode_hex};
+
+const PAIRING_CODE_DOMAIN: &[u8] = b"aetherlink/v1/pairing-code";
+
+/// Twelve-digit code derived from a device's protobuf-encoded identity public key,
+/// shown as `123 456 789 012`. Both devices display it so a user can compare them
+/// before trusting the pairing; a mismatch means the key on one side is not the
+/// other's. Twelve digits keep a key ground to match a displayed code out of reach.
+pub fn pairing_code(identity_pubkey: &[u8]) -> String {
+    let digest = Sha256::new()
+        .chain_update(PAIRING_CODE_DOMAIN)
+        .chain_update(identity_pubkey)
+        .finalize();
+    let mut head = [0u8; 8];
+    head.copy_from_slice(&digest[..8]);
+    let value = u64::from_be_bytes(head) % 1_000_000_000_000;
+    format!(
+        "{:03} {:03} {:03} {:03}",
+        value / 1_000_000_000,
+        value / 1_000_000 % 1_000,
+        value / 1_000 % 1_000,
+        value % 1_000
+    )
+}
+
+/// `pairing_code` for a key stored hex-encoded, as in the trust store.
+pub fn pairing_code_from_hex(identity_pubkey_hex: &str) -> Result<String, SessionAuthError> {
+    Ok(pairing_code(&decode_hex(identity_pubkey_hex)?))
+}
+
+/// The public key a peer id inlines. Ed25519 ids carry the key itself; ids that only
+/// hash a longer key cannot be mapped back to it and give `None`.
+pub fn inline_public_key(peer_id: &PeerId) -> Option<identity::PublicKey> {
+    let multihash: &Multihash<64> = peer_id.as_ref();
+    if multihash.code() != 0 {
+        return None;
+    }
+    identity::PublicKey::try_decode_protobuf(multihash.digest())
+        .ok()
+        .filter(|public| PeerId::from_public_key(public) == *peer_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_is_twelve_digits_and_stable() {
+        let key = identity::Keypair::generate_ed25519()
+            .public()
+            .encode_protobuf();
+        let code = pairing_code(&key);
+        assert_eq!(code.len(), 15);
+        assert_eq!(
+            code.split(' ').map(str::len).collect::<Vec<_>>(),
+            [3, 3, 3, 3]
+        );
+        assert!(
+            code.chars()
+                .filter(|c| *c != ' ')
+                .all(|c| c.is_ascii_digit())
+        );
+        assert_eq!(pairing_code(&key), code);
+
+        let hex: String = key.iter().map(|b| format!("{b:02x}")).collect();
+        assert_eq!(pairing_code_from_hex(&hex).unwrap(), code);
+        assert_eq!(pairing_code_from_hex(&hex.to_uppercase()).unwrap(), code);
+    }
+
+    #[test]
+    fn different_keys_get_different_codes() {
+        let a = identity::Keypair::generate_ed25519()
+            .public()
+            .encode_protobuf();
+        let b = identity::Keypair::generate_ed25519()
+            .public()
+            .encode_protobuf();
+        assert_ne!(pairing_code(&a), pairing_code(&b));
+    }
+
+    #[test]
+    fn inline_key_comes_back_only_for_ids_that_embed_it() {
+        let public = identity::Keypair::generate_ed25519().public();
+        let peer_id = PeerId::from_public_key(&public);
+        assert_eq!(inline_public_key(&peer_id), Some(public));
+        assert_eq!(inline_public_key(&PeerId::random()), None);
+    }
+
+    #[test]
+    fn malformed_hex_is_rejected() {
+        assert!(pairing_code_from_hex("abc").is_err());
+        assert!(pairing_code_from_hex("zz").is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use aetherlink_proto::{SigningPayload, signing_domain, v1::ResumptionTicket};
+use libp2p::{PeerId, identity};
+
+use crate::security::SessionAuthError;
+
+pub const DEFAULT_RESUMPTION_TICKET_TTL_MS: i64 = 300_000;
+
+/// Issues a ticket that lets `holder_peer_id` resume a session with the issuer until
+/// `now_unix_ms + ttl_ms` without going through first-use trust checks again.
+pub fn issue_ticket(
+    issuer: &identity::Keypair,
+    holder_peer_id: &PeerId,
+    holder_device_code: &str,
+    now_unix_ms: i64,
+    ttl_ms: i64,
+) -> Result<ResumptionTicket, SessionAuthError> {
+    let mut ticket = ResumptionTicket {
+        issuer_peer_id: PeerId::from(issuer.public()).to_bytes(),
+        holder_peer_id: holder_peer_id.to_bytes(),
+        holder_device_code: holder_device_code.to_string(),
+        issued_unix_ms: now_unix_ms,
+        expires_unix_ms: now_unix_ms.saturating_add(ttl_ms.max(0)),
+        signature: Vec::new(),
+    };
+    ticket.signature = issuer
+        .sign(&canonical_ticket_payload(&ticket))
+        .map_err(|_| SessionAuthError::SigningFailed)?;
+    Ok(ticket)
+}
+
+/// Checks that `ticket` was signed by `issuer`, names `holder_peer_id` and has not
+/// expired. Tickets are only ever verified by the peer that issued them.
+pub fn verify_ticket(
+    ticket: &ResumptionTicket,
+    issuer: &identity::PublicKey,
+    holder_peer_id: &PeerId,
+    now_unix_ms: i64,
+) -> Result<(), SessionAuthError> {
+    let issuer_peer_id = PeerId::from_bytes(&ticket.issuer_peer_id)
+        .map_err(|_| SessionAuthError::InvalidResumptionTicket("bad issuer peer id"))?;
+    if issuer_peer_id != PeerId::from_public_key(issuer) {
+        return Err(SessionAuthError::InvalidResumptionTicket(
+            "issued by another peer",
+        ));
+    }
+    if !issuer.verify(&canonical_ticket_payload(ticket), &ticket.signature) {
+        return Err(SessionAuthError::InvalidResumptionTicket("bad signature"));
+    }
+    let ticket_holder = PeerId::from_bytes(&ticket.holder_peer_id)
+        .map_err(|_| SessionAuthError::InvalidResumptionTicket("bad holder peer id"))?;
+    if ticket_holder != *holder_peer_id {
+        return Err(SessionAuthError::InvalidResumptionTicket(
+            "presented by another peer",
+        ));
+    }
+    if now_unix_ms < ticket.issued_unix_ms || now_unix_ms >= ticket.expires_unix_ms {
+        return Err(SessionAuthError::ResumptionTicketExpired);
+    }
+    Ok(())
+}
+
+fn ticket_layout(ticket: &ResumptionTicket) -> SigningPayload {
+    SigningPayload::new(signing_domain::RESUMPTION_TICKET)
+        .bytes(1, &ticket.issuer_peer_id)
+        .bytes(2, &ticket.holder_peer_id)
+        .string(3, &ticket.holder_device_code)
+        .i64(4, ticket.issued_unix_ms)
+        .i64(5, ticket.expires_unix_ms)
+}
+
+fn canonical_ticket_payload(ticket: &ResumptionTicket) -> Vec<u8> {
+    ticket_layout(ticket).finish()
+}
+
+/// A ticket carried inside another signed message, its issuer's signature included.
+pub(crate) fn embedded_ticket_payload(ticket: &ResumptionTicket) -> SigningPayload {
+    ticket_layout(ticket).bytes(6, &ticket.signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_ticket_verifies_for_its_holder() {
+        let issuer = identity::Keypair::generate_ed25519();
+        let holder = PeerId::random();
+        let ticket = issue_ticket(&issuer, &holder, "device-h", 1_000, 60_000).unwrap();
+        assert_eq!(ticket.expires_unix_ms, 61_000);
+        assert_eq!(
+            verify_ticket(&ticket, &issuer.public(), &holder, 30_000),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn expired_ticket_is_rejected() {
+        let issuer = identity::Keypair::generate_ed25519();
+        let holder = PeerId::random();
+        let ticket = issue_ticket(&issuer, &holder, "device-h", 1_000, 60_000).unwrap();
+        assert_eq!(
+            verify_ticket(&ticket, &issuer.public(), &holder, 61_000),
+            Err(SessionAuthError::ResumptionTicketExpired)
+        );
+        assert_eq!(
+            verify_ticket(&ticket, &issuer.public(), &holder, 999),
+            Err(SessionAuthError::ResumptionTicketExpired)
+        );
+    }
+
+    #[test]
+    fn forged_or_stolen_tickets_are_rejected() {
+        let issuer = identity::Keypair::generate_ed25519();
+        let holder = PeerId::random();
+        let ticket = issue_ticket(&issuer, &holder, "device-h", 1_000, 60_000).unwrap();
+
+        let mut extended = ticket.clone();
+        extended.expires_unix_ms = i64::MAX;
+        assert_eq!(
+            verify_ticket(&extended, &issuer.public(), &holder, 2_000),
+            Err(SessionAuthError::InvalidResumptionTicket("bad signature"))
+        );
+
+        let forger = identity::Keypair::generate_ed25519();
+        let mut forged = issue_ticket(&forger, &holder, "device-h", 1_000, 60_000).unwrap();
+        assert!(verify_ticket(&forged, &issuer.public(), &holder, 2_000).is_err());
+        forged.issuer_peer_id = PeerId::from(issuer.public()).to_bytes();
+        assert_eq!(
+            verify_ticket(&forged, &issuer.public(), &holder, 2_000),
+            Err(SessionAuthError::InvalidResumptionTicket("bad signature"))
+        );
+
+        assert_eq!(
+            verify_ticket(&ticket, &issuer.public(), &PeerId::random(), 2_000),
+            Err(SessionAuthError::InvalidResumptionTicket(
+                "presented by another peer"
+            ))
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use aetherlink_proto::{SigningPayload, signing_domain, v1::IdentityRotationProof};
+use libp2p::identity;
+
+use crate::security::SessionAuthError;
+
+/// Signs, with both the key being retired and its replacement, a statement that
+/// `device_code` now lives under `new_key` and `new_device_code`.
+pub fn sign_identity_rotation(
+    old_key: &identity::Keypair,
+    new_key: &identity::Keypair,
+    device_code: &str,
+    new_device_code: &str,
+    now_unix_ms: i64,
+) -> Result<IdentityRotationProof, SessionAuthError> {
+    let mut proof = IdentityRotationProof {
+        device_code: device_code.to_string(),
+        old_identity_pubkey: old_key.public().encode_protobuf(),
+        new_device_code: new_device_code.to_string(),
+        new_identity_pubkey: new_key.public().encode_protobuf(),
+        rotated_unix_ms: now_unix_ms,
+        signature: Vec::new(),
+        signature_by_new: Vec::new(),
+    };
+    let payload = canonical_rotation_payload(&proof);
+    proof.signature = old_key
+        .sign(&payload)
+        .map_err(|_| SessionAuthError::SigningFailed)?;
+    proof.signature_by_new = new_key
+        .sign(&payload)
+        .map_err(|_| SessionAuthError::SigningFailed)?;
+    Ok(proof)
+}
+
+/// Checks both signatures of the proof and returns the old and new public keys. The
+/// old key's signature authorizes the move and the new key's proves possession, so a
+/// new key cannot vouch for itself. Whether the old key is one we trust is up to the
+/// caller.
+pub fn verify_rotation_proof(
+    proof: &IdentityRotationProof,
+) -> Result<(identity::PublicKey, identity::PublicKey), SessionAuthError> {
+    let old = identity::PublicKey::try_decode_protobuf(&proof.old_identity_pubkey)
+        .map_err(|_| SessionAuthError::InvalidRotationProof("bad old public key"))?;
+    let new = identity::PublicKey::try_decode_protobuf(&proof.new_identity_pubkey)
+        .map_err(|_| SessionAuthError::InvalidRotationProof("bad new public key"))?;
+    if proof.new_device_code.trim().is_empty() {
+        return Err(SessionAuthError::InvalidRotationProof(
+            "missing new device code",
+        ));
+    }
+    if old == new {
+        return Err(SessionAuthError::InvalidRotationProof(
+            "new key equals old key",
+        ));
+    }
+    let payload = canonical_rotation_payload(proof);
+    if !old.verify(&payload, &proof.signature) {
+        return Err(SessionAuthError::InvalidRotationProof("bad signature"));
+    }
+    if !new.verify(&payload, &proof.signature_by_new) {
+        return Err(SessionAuthError::InvalidRotationProof(
+            "bad signature by new key",
+        ));
+    }
+    Ok((old, new))
+}
+
+fn canonical_rotation_payload(proof: &IdentityRotationProof) -> Vec<u8> {
+    SigningPayload::new(signing_domain::IDENTITY_ROTATION_PROOF)
+        .string(1, &proof.device_code)
+        .bytes(2, &proof.old_identity_pubkey)
+        .string(3, &proof.new_device_code)
+        .bytes(4, &proof.new_identity_pubkey)
+        .i64(5, proof.rotated_unix_ms)
+        .finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proof_needs_old_and_new_key_signatures() {
+        let old = identity::Keypair::generate_ed25519();
+        let new = identity::Keypair::generate_ed25519();
+        let proof = sign_identity_rotation(&old, &new, "old-code", "new-code", 1_000).unwrap();
+        let (old_pub, new_pub) = verify_rotation_proof(&proof).unwrap();
+        assert_eq!(old_pub, old.public());
+        assert_eq!(new_pub, new.public());
+
+        let mut redirected = proof.clone();
+        redirected.new_identity_pubkey = identity::Keypair::generate_ed25519()
+            .public()
+            .encode_protobuf();
+        assert_eq!(
+            verify_rotation_proof(&redirected),
+            Err(SessionAuthError::InvalidRotationProof("bad signature"))
+        );
+
+        // Only the new key signed: it cannot vouch for itself.
+        let attacker = identity::Keypair::generate_ed25519();
+        let mut claimed =
+            sign_identity_rotation(&attacker, &new, "old-code", "new-code", 1_000).unwrap();
+        claimed.old_identity_pubkey = old.public().encode_protobuf();
+        assert_eq!(
+            verify_rotation_proof(&claimed),
+            Err(SessionAuthError::InvalidRotationProof("bad signature"))
+        );
+
+        let mut unowned = proof.clone();
+        unowned.signature_by_new = proof.signature.clone();
+        assert_eq!(
+            verify_rotation_proof(&unowned),
+            Err(SessionAuthError::InvalidRotationProof(
+                "bad signature by new key"
+            ))
+        );
+
+        let mut retargeted = proof.clone();
+        retargeted.device_code = "other-code".to_string();
+        assert!(verify_rotation_proof(&retargeted).is_err());
+
+        let same = sign_identity_rotation(&old, &old, "old-code", "new-code", 1_000).unwrap();
+        assert_eq!(
+            verify_rotation_proof(&same),
+            Err(SessionAuthError::InvalidRotationProof(
+                "new key equals old key"
+            ))
+        );
+    }
+}
@@ -1,15 +1,65 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 
-use aetherlink_proto::v1::{SessionAccept, SessionRequest};
+use aetherlink_proto::{
+    SigningPayload, signing_domain,
+    v1::{
+        Challenge, DeviceIdentity, IdentityRotationProof, ProtocolVersion, SessionAccept,
+        SessionRequest,
+    },
+};
 use libp2p::{PeerId, identity};
-use prost::Message;
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use subtle::{Choice, ConstantTimeEq};
 use thiserror::Error;
 
+use crate::{
+    audit::{AuthAuditSink, AuthEvent, AuthMessage},
+    pairing::inline_public_key,
+    resumption::{embedded_ticket_payload, verify_ticket},
+    rotation::verify_rotation_proof,
+};
+
 pub const MIN_NONCE_BYTES: usize = 12;
 pub const DEFAULT_ALLOWED_SKEW_MS: i64 = 30_000;
 pub const DEFAULT_REPLAY_RETENTION_MS: i64 = 60_000;
+pub const DEFAULT_REPLAY_MAX_ENTRIES: usize = 65_536;
 pub const LAST_SEEN_PERSIST_INTERVAL_MS: i64 = 60_000;
+pub const DEFAULT_AUTH_MAX_FAILURES: usize = 5;
+pub const DEFAULT_AUTH_FAILURE_WINDOW_MS: i64 = 60_000;
+pub const DEFAULT_CHALLENGE_TTL_MS: i64 = 30_000;
+pub const CHALLENGE_NONCE_BYTES: usize = 16;
+/// Oldest identity rotation proof still applied. A peer that was offline for longer
+/// has to pair with the new identity again.
+pub const ROTATION_PROOF_MAX_AGE_MS: i64 = 7 * 24 * 60 * 60 * 1_000;
+/// Keeps challenge nonces apart from request nonces in a shared replay cache.
+const CHALLENGE_REPLAY_PREFIX: &[u8] = b"challenge:";
+
+/// How strictly session handshakes are verified. The default is the policy the
+/// handshake has always used: `MIN_NONCE_BYTES`, `DEFAULT_ALLOWED_SKEW_MS`, no
+/// trust-on-first-use and a target device code check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifierPolicy {
+    pub min_nonce_bytes: usize,
+    pub allowed_skew_ms: i64,
+    pub trust_on_first_use: bool,
+    /// Whether a request must name the expected target device code, when one is given.
+    pub require_target_match: bool,
+    /// How long an issued challenge may be echoed back.
+    pub challenge_ttl_ms: i64,
+}
+
+impl Default for VerifierPolicy {
+    fn default() -> Self {
+        Self {
+            min_nonce_bytes: MIN_NONCE_BYTES,
+            allowed_skew_ms: DEFAULT_ALLOWED_SKEW_MS,
+            trust_on_first_use: false,
+            require_target_match: true,
+            challenge_ttl_ms: DEFAULT_CHALLENGE_TTL_MS,
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct VerifiedSessionPeer {
@@ -27,14 +77,25 @@ pub struct TrustedPeerRecord {
     pub last_seen_unix_ms: i64,
 }
 
+/// Trusted identities keyed by device code. One device code may be trusted under
+/// several `(peer_id, pubkey)` pairs, e.g. the same logical device run from two
+/// machines; each pair is persisted as its own `TrustedPeerRecord`.
 #[derive(Debug, Clone, Default)]
 pub struct TrustedPeers {
-    by_device_code: HashMap<String, TrustedPeerRecord>,
+    by_device_code: HashMap<String, Vec<TrustedPeerRecord>>,
+    /// Device code each trusted peer id lives under, kept in step with
+    /// `by_device_code`. A peer id trusted under several codes maps to the smallest.
+    by_peer_id: HashMap<PeerId, String>,
+    /// Device codes refused even under trust-on-first-use until the revocation is cleared.
+    revoked: BTreeSet<String>,
 }
 
 impl TrustedPeers {
+    /// Groups records by device code; a store written before several identities per
+    /// device were allowed simply yields one pair per code. A repeated peer id keeps
+    /// its last record.
     pub fn from_records(records: Vec<TrustedPeerRecord>) -> Result<Self, SessionAuthError> {
-        let mut by_device_code = HashMap::new();
+        let mut by_device_code: HashMap<String, Vec<TrustedPeerRecord>> = HashMap::new();
         for record in records {
             if record.device_code.trim().is_empty() {
                 return Err(SessionAuthError::TrustStoreCorrupt(
@@ -43,17 +104,175 @@ impl TrustedPeers {
             }
             parse_peer_id(&record.peer_id)?;
             decode_hex(&record.identity_pubkey_hex)?;
-            by_device_code.insert(record.device_code.clone(), record);
+            let pairs = by_device_code
+                .entry(record.device_code.clone())
+                .or_default();
+            pairs.retain(|existing| existing.peer_id != record.peer_id);
+            pairs.push(record);
+        }
+        let mut trusted = Self {
+            by_device_code,
+            by_peer_id: HashMap::new(),
+            revoked: BTreeSet::new(),
+        };
+        trusted.reindex();
+        Ok(trusted)
+    }
+
+    /// The record trusting `peer_id`, for deciding about a connection before it has
+    /// said which device code it speaks for.
+    pub fn get_by_peer_id(&self, peer_id: &PeerId) -> Option<&TrustedPeerRecord> {
+        let device_code = self.by_peer_id.get(peer_id)?;
+        let peer_id = peer_id.to_string();
+        self.by_device_code
+            .get(device_code)?
+            .iter()
+            .find(|record| record.peer_id == peer_id)
+    }
+
+    fn reindex(&mut self) {
+        self.by_peer_id.clear();
+        for record in self.by_device_code.values().flatten() {
+            if let Ok(peer_id) = parse_peer_id(&record.peer_id) {
+                index_peer_id(&mut self.by_peer_id, peer_id, &record.device_code);
+            }
+        }
+    }
+
+    /// Adds persisted revocations, e.g. from the trust store file. Revoked codes drop
+    /// any record still held for them.
+    pub fn with_revoked(mut self, device_codes: impl IntoIterator<Item = String>) -> Self {
+        for device_code in device_codes {
+            let device_code = device_code.trim().to_string();
+            if !device_code.is_empty() {
+                self.revoke(&device_code);
+            }
+        }
+        self
+    }
+
+    /// Revoked device codes, sorted, for persisting next to `to_records`.
+    pub fn revoked_device_codes(&self) -> Vec<String> {
+        self.revoked.iter().cloned().collect()
+    }
+
+    pub fn is_revoked(&self, device_code: &str) -> bool {
+        self.revoked.contains(device_code)
+    }
+
+    /// Forgets `device_code` and refuses it from now on, even under trust-on-first-use.
+    /// Returns whether a trust record was removed.
+    pub fn revoke(&mut self, device_code: &str) -> bool {
+        self.revoked.insert(device_code.to_string());
+        let removed = self.by_device_code.remove(device_code).is_some();
+        if removed {
+            self.reindex();
+        }
+        removed
+    }
+
+    /// Revokes the device code trusted for `peer_id`, along with every other identity
+    /// trusted under it. Returns false when no record names that peer, since there is
+    /// then no device code to revoke.
+    pub fn revoke_peer_id(&mut self, peer_id: &PeerId) -> bool {
+        let Some(device_code) = self.by_peer_id.get(peer_id).cloned() else {
+            return false;
+        };
+        self.revoke(&device_code)
+    }
+
+    /// Lifts a revocation so the device can be trusted again. Returns whether it was
+    /// revoked.
+    pub fn clear_revocation(&mut self, device_code: &str) -> bool {
+        self.revoked.remove(device_code)
+    }
+
+    /// Trusts `peer_id` under `device_code` on an operator's approval, e.g. after they
+    /// compared pairing codes. This is the only way a device code that is already
+    /// trusted gains another identity; the verifiers never add one on their own. Fails
+    /// for revoked codes and for a key that is not `peer_id`'s. Returns whether the store
+    /// changed.
+    pub fn add_identity(
+        &mut self,
+        device_code: &str,
+        peer_id: &PeerId,
+        identity_pubkey: &[u8],
+        now_unix_ms: i64,
+    ) -> Result<bool, SessionAuthError> {
+        if self.is_revoked(device_code) {
+            return Err(SessionAuthError::RevokedPeer {
+                device_code: device_code.to_string(),
+            });
         }
-        Ok(Self { by_device_code })
+        let pubkey = identity::PublicKey::try_decode_protobuf(identity_pubkey)
+            .map_err(|_| SessionAuthError::InvalidSenderPublicKey)?;
+        if PeerId::from_public_key(&pubkey) != *peer_id {
+            return Err(SessionAuthError::PeerIdMismatch);
+        }
+        let peer_id_text = peer_id.to_string();
+        let pairs = self
+            .by_device_code
+            .entry(device_code.to_string())
+            .or_default();
+        if pairs.iter().any(|record| record.peer_id == peer_id_text) {
+            return Ok(false);
+        }
+        pairs.push(TrustedPeerRecord {
+            device_code: device_code.to_string(),
+            peer_id: peer_id_text,
+            identity_pubkey_hex: encode_hex(identity_pubkey),
+            first_seen_unix_ms: now_unix_ms,
+            last_seen_unix_ms: now_unix_ms,
+        });
+        index_peer_id(&mut self.by_peer_id, *peer_id, device_code);
+        Ok(true)
     }
 
+    /// `add_identity` for a peer id given as text, e.g. the one an operator approved
+    /// next to its pairing code. The id must inline its public key.
+    pub fn approve_peer_id(
+        &mut self,
+        device_code: &str,
+        peer_id: &str,
+        now_unix_ms: i64,
+    ) -> Result<bool, SessionAuthError> {
+        let peer_id = parse_peer_id(peer_id.trim())?;
+        let pubkey = inline_public_key(&peer_id).ok_or(SessionAuthError::InvalidSenderPublicKey)?;
+        self.add_identity(
+            device_code,
+            &peer_id,
+            &pubkey.encode_protobuf(),
+            now_unix_ms,
+        )
+    }
+
+    /// One record per trusted pair, ordered by device code and then first-seen time.
     pub fn to_records(&self) -> Vec<TrustedPeerRecord> {
-        let mut records: Vec<_> = self.by_device_code.values().cloned().collect();
-        records.sort_by(|a, b| a.device_code.cmp(&b.device_code));
+        let mut records: Vec<_> = self.by_device_code.values().flatten().cloned().collect();
+        records.sort_by(|a, b| {
+            (&a.device_code, a.first_seen_unix_ms, &a.peer_id).cmp(&(
+                &b.device_code,
+                b.first_seen_unix_ms,
+                &b.peer_id,
+            ))
+        });
         records
     }
 
+    /// Peer ids trusted under `device_code`, oldest first.
+    pub fn peer_ids(&self, device_code: &str) -> Vec<String> {
+        let mut pairs: Vec<_> = self
+            .by_device_code
+            .get(device_code)
+            .into_iter()
+            .flatten()
+            .collect();
+        pairs.sort_by_key(|record| record.first_seen_unix_ms);
+        pairs.iter().map(|record| record.peer_id.clone()).collect()
+    }
+
+    /// Number of trusted device codes; a code trusted under several identities counts
+    /// once.
     pub fn len(&self) -> usize {
         self.by_device_code.len()
     }
@@ -62,6 +281,104 @@ impl TrustedPeers {
         self.by_device_code.is_empty()
     }
 
+    /// Forgets identities not seen within `max_age_ms` and returns how many were
+    /// removed; a non-zero count means the store changed and should be persisted.
+    /// `max_age_ms` is raised to `LAST_SEEN_PERSIST_INTERVAL_MS`, since
+    /// `last_seen_unix_ms` only moves that often and a shorter age would prune peers
+    /// that are still connecting. Peers in `connected` are kept whatever their age:
+    /// `last_seen_unix_ms` only moves when a session is authenticated, so a session
+    /// that outlives the max age would otherwise lose its own peer's trust.
+    pub fn prune_expired(
+        &mut self,
+        now_unix_ms: i64,
+        max_age_ms: i64,
+        connected: &HashSet<PeerId>,
+    ) -> usize {
+        let max_age_ms = max_age_ms.max(LAST_SEEN_PERSIST_INTERVAL_MS);
+        let connected: HashSet<String> = connected.iter().map(ToString::to_string).collect();
+        let mut removed = 0;
+        self.by_device_code.retain(|_, pairs| {
+            let before = pairs.len();
+            pairs.retain(|record| {
+                connected.contains(&record.peer_id)
+                    || now_unix_ms.saturating_sub(record.last_seen_unix_ms) <= max_age_ms
+            });
+            removed += before - pairs.len();
+            !pairs.is_empty()
+        });
+        if removed > 0 {
+            self.reindex();
+        }
+        removed
+    }
+
+    /// Moves the trusted identity the proof's old key names from `proof.device_code` to
+    /// the new identity and device code in the proof, keeping its first-seen time. The
+    /// proof must be signed by a key that device code currently pins, name the new key's
+    /// own peer id as the new device code, and be at most `ROTATION_PROOF_MAX_AGE_MS` old;
+    /// other identities trusted under the old code stay where they are. Returns the
+    /// device code the identity now lives under.
+    pub fn rotate_identity(
+        &mut self,
+        proof: &IdentityRotationProof,
+        now_unix_ms: i64,
+    ) -> Result<String, SessionAuthError> {
+        let (_, new_pubkey) = verify_rotation_proof(proof)?;
+        // Otherwise a key trusted as one device could sign itself in as any other.
+        if proof.new_device_code != PeerId::from_public_key(&new_pubkey).to_string() {
+            return Err(SessionAuthError::InvalidRotationProof(
+                "new device code is not the new key's peer id",
+            ));
+        }
+        let age_ms = now_unix_ms.saturating_sub(proof.rotated_unix_ms);
+        if age_ms < -DEFAULT_ALLOWED_SKEW_MS {
+            return Err(SessionAuthError::InvalidRotationProof(
+                "proof is dated in the future",
+            ));
+        }
+        if age_ms > ROTATION_PROOF_MAX_AGE_MS {
+            return Err(SessionAuthError::InvalidRotationProof("proof is too old"));
+        }
+        if self.is_revoked(&proof.new_device_code) {
+            return Err(SessionAuthError::RevokedPeer {
+                device_code: proof.new_device_code.clone(),
+            });
+        }
+        let Some(pairs) = self.by_device_code.get_mut(&proof.device_code) else {
+            return Err(SessionAuthError::UntrustedPeer {
+                device_code: proof.device_code.clone(),
+            });
+        };
+        let old_pubkey_hex = encode_hex(&proof.old_identity_pubkey);
+        let Some(index) = pairs
+            .iter()
+            .position(|record| record.identity_pubkey_hex == old_pubkey_hex)
+        else {
+            return Err(SessionAuthError::InvalidRotationProof(
+                "old key is not the trusted key",
+            ));
+        };
+        let first_seen_unix_ms = pairs.remove(index).first_seen_unix_ms;
+        if pairs.is_empty() {
+            self.by_device_code.remove(&proof.device_code);
+        }
+        let peer_id = PeerId::from_public_key(&new_pubkey).to_string();
+        let pairs = self
+            .by_device_code
+            .entry(proof.new_device_code.clone())
+            .or_default();
+        pairs.retain(|record| record.peer_id != peer_id);
+        pairs.push(TrustedPeerRecord {
+            device_code: proof.new_device_code.clone(),
+            peer_id,
+            identity_pubkey_hex: encode_hex(&proof.new_identity_pubkey),
+            first_seen_unix_ms,
+            last_seen_unix_ms: now_unix_ms,
+        });
+        self.reindex();
+        Ok(proof.new_device_code.clone())
+    }
+
     fn ensure_trusted(
         &mut self,
         device_code: &str,
@@ -70,62 +387,220 @@ impl TrustedPeers {
         now_unix_ms: i64,
         trust_on_first_use: bool,
     ) -> Result<bool, SessionAuthError> {
+        if self.is_revoked(device_code) {
+            return Err(SessionAuthError::RevokedPeer {
+                device_code: device_code.to_string(),
+            });
+        }
         let current_peer_id = peer_id.to_string();
         let current_pubkey_hex = encode_hex(identity_pubkey);
 
-        if let Some(existing) = self.by_device_code.get_mut(device_code) {
-            if existing.peer_id != current_peer_id
-                || existing.identity_pubkey_hex != current_pubkey_hex
-            {
-                return Err(SessionAuthError::TrustedPeerMismatch {
-                    device_code: device_code.to_string(),
-                });
-            }
-            let changed = now_unix_ms.saturating_sub(existing.last_seen_unix_ms)
-                >= LAST_SEEN_PERSIST_INTERVAL_MS;
-            if changed {
-                existing.last_seen_unix_ms = now_unix_ms;
+        if let Some(pairs) = self.by_device_code.get_mut(device_code) {
+            if let Some(existing) = pairs.iter_mut().find(|record| {
+                record.peer_id == current_peer_id
+                    && record.identity_pubkey_hex == current_pubkey_hex
+            }) {
+                let changed = now_unix_ms.saturating_sub(existing.last_seen_unix_ms)
+                    >= LAST_SEEN_PERSIST_INTERVAL_MS;
+                if changed {
+                    existing.last_seen_unix_ms = now_unix_ms;
+                }
+                return Ok(changed);
             }
-            return Ok(changed);
-        }
-
-        if !trust_on_first_use {
+            // First use covers device codes only: another identity under a code we
+            // already trust is a mismatch until `add_identity` approves it.
+            return Err(SessionAuthError::TrustedPeerMismatch {
+                device_code: device_code.to_string(),
+            });
+        } else if !trust_on_first_use {
             return Err(SessionAuthError::UntrustedPeer {
                 device_code: device_code.to_string(),
             });
         }
 
-        self.by_device_code.insert(
-            device_code.to_string(),
-            TrustedPeerRecord {
+        self.by_device_code
+            .entry(device_code.to_string())
+            .or_default()
+            .push(TrustedPeerRecord {
                 device_code: device_code.to_string(),
                 peer_id: current_peer_id,
                 identity_pubkey_hex: current_pubkey_hex,
                 first_seen_unix_ms: now_unix_ms,
                 last_seen_unix_ms: now_unix_ms,
-            },
-        );
+            });
+        index_peer_id(&mut self.by_peer_id, *peer_id, device_code);
         Ok(true)
     }
 }
 
+fn index_peer_id(by_peer_id: &mut HashMap<PeerId, String>, peer_id: PeerId, device_code: &str) {
+    match by_peer_id.get_mut(&peer_id) {
+        Some(indexed) if indexed.as_str() <= device_code => {}
+        Some(indexed) => *indexed = device_code.to_string(),
+        None => {
+            by_peer_id.insert(peer_id, device_code.to_string());
+        }
+    }
+}
+
+/// Recent authentication failures per peer. A peer that fails `max_failures` times
+/// within `window_ms` is refused until the oldest of those failures ages out, and a
+/// successful authentication clears its record.
+#[derive(Debug, Clone)]
+pub struct AuthThrottle {
+    max_failures: usize,
+    window_ms: i64,
+    /// The last `max_failures` failure times per peer, oldest first.
+    failures: HashMap<PeerId, VecDeque<i64>>,
+}
+
+impl Default for AuthThrottle {
+    fn default() -> Self {
+        Self::new(DEFAULT_AUTH_MAX_FAILURES, DEFAULT_AUTH_FAILURE_WINDOW_MS)
+    }
+}
+
+impl AuthThrottle {
+    pub fn new(max_failures: usize, window_ms: i64) -> Self {
+        Self {
+            max_failures: max_failures.max(1),
+            window_ms: window_ms.max(0),
+            failures: HashMap::new(),
+        }
+    }
+
+    /// Refuses `peer_id` with `SessionAuthError::Throttled` while it is locked out.
+    /// Cheap enough to call before any signature work.
+    pub fn check(&self, peer_id: &PeerId, now_unix_ms: i64) -> Result<(), SessionAuthError> {
+        let Some(failures) = self.failures.get(peer_id) else {
+            return Ok(());
+        };
+        if failures.len() < self.max_failures {
+            return Ok(());
+        }
+        let oldest = failures.front().copied().unwrap_or_default();
+        let retry_after_ms = oldest.saturating_add(self.window_ms) - now_unix_ms;
+        if retry_after_ms > 0 {
+            return Err(SessionAuthError::Throttled { retry_after_ms });
+        }
+        Ok(())
+    }
+
+    pub fn record_failure(&mut self, peer_id: PeerId, now_unix_ms: i64) {
+        let failures = self.failures.entry(peer_id).or_default();
+        failures.push_back(now_unix_ms);
+        while failures.len() > self.max_failures {
+            failures.pop_front();
+        }
+    }
+
+    pub fn record_success(&mut self, peer_id: &PeerId) {
+        self.failures.remove(peer_id);
+    }
+
+    /// Forgets peers whose latest failure is outside the window.
+    pub fn prune_expired(&mut self, now_unix_ms: i64) {
+        let window_ms = self.window_ms;
+        self.failures.retain(|_, failures| {
+            failures
+                .back()
+                .is_some_and(|&at| now_unix_ms.saturating_sub(at) < window_ms)
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.failures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Nonces seen within the retention window, bounded by both age and count.
 #[derive(Debug, Clone)]
 pub struct NonceReplayCache {
     retention_ms: i64,
+    max_entries: usize,
     seen: HashMap<Vec<u8>, i64>,
+    /// `seen` ordered by timestamp, oldest first.
+    by_age: BTreeSet<(i64, Vec<u8>)>,
 }
 
 impl Default for NonceReplayCache {
     fn default() -> Self {
-        Self::new(DEFAULT_REPLAY_RETENTION_MS)
+        Self::new(DEFAULT_REPLAY_RETENTION_MS, DEFAULT_REPLAY_MAX_ENTRIES)
     }
 }
 
 impl NonceReplayCache {
-    pub fn new(retention_ms: i64) -> Self {
+    /// Once `max_entries` nonces are held, storing another evicts the oldest even if it
+    /// is still within retention: under a flood, memory stays bounded at the cost of
+    /// forgetting the oldest nonces early.
+    pub fn new(retention_ms: i64, max_entries: usize) -> Self {
         Self {
             retention_ms: retention_ms.max(1),
+            max_entries: max_entries.max(1),
             seen: HashMap::new(),
+            by_age: BTreeSet::new(),
+        }
+    }
+
+    /// Rebuilds a cache saved with `to_records`, dropping nonces already past retention
+    /// at `now_unix_ms` and keeping the newest `max_entries`.
+    pub fn from_records(
+        retention_ms: i64,
+        max_entries: usize,
+        records: Vec<(Vec<u8>, i64)>,
+        now_unix_ms: i64,
+    ) -> Self {
+        let mut cache = Self::new(retention_ms, max_entries);
+        for (nonce, seen_unix_ms) in records {
+            if cache.seen.contains_key(&nonce) {
+                continue;
+            }
+            cache.by_age.insert((seen_unix_ms, nonce.clone()));
+            cache.seen.insert(nonce, seen_unix_ms);
+        }
+        cache.evict_expired(now_unix_ms);
+        while cache.seen.len() > cache.max_entries {
+            cache.evict_oldest();
+        }
+        cache
+    }
+
+    /// Stored nonces with the time they were seen, oldest first.
+    pub fn to_records(&self) -> Vec<(Vec<u8>, i64)> {
+        self.by_age
+            .iter()
+            .map(|(seen_unix_ms, nonce)| (nonce.clone(), *seen_unix_ms))
+            .collect()
+    }
+
+    pub fn retention_ms(&self) -> i64 {
+        self.retention_ms
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    /// Fails with `ReplayDetected` if `nonce` was stored within retention, without
+    /// recording it. Verifiers call this before authenticating a message and
+    /// `check_and_store` only once it authenticated, so forged messages cannot fill the
+    /// cache or burn a nonce a genuine peer is about to use.
+    pub fn check(&self, nonce: &[u8], now_unix_ms: i64) -> Result<(), SessionAuthError> {
+        match self.seen.get(nonce) {
+            Some(&seen_unix_ms)
+                if now_unix_ms.saturating_sub(seen_unix_ms) <= self.retention_ms =>
+            {
+                Err(SessionAuthError::ReplayDetected)
+            }
+            _ => Ok(()),
         }
     }
 
@@ -138,19 +613,27 @@ impl NonceReplayCache {
         if self.seen.contains_key(nonce) {
             return Err(SessionAuthError::ReplayDetected);
         }
+        while self.seen.len() >= self.max_entries {
+            self.evict_oldest();
+        }
         self.seen.insert(nonce.to_vec(), now_unix_ms);
+        self.by_age.insert((now_unix_ms, nonce.to_vec()));
         Ok(())
     }
 
     fn evict_expired(&mut self, now_unix_ms: i64) {
-        let retention = self.retention_ms;
-        self.seen.retain(|_, seen_unix_ms| {
-            if *seen_unix_ms > now_unix_ms {
-                true
-            } else {
-                now_unix_ms - *seen_unix_ms <= retention
-            }
-        });
+        // Entries stamped in the future never count as expired.
+        while let Some((seen_unix_ms, _)) = self.by_age.first()
+            && now_unix_ms.saturating_sub(*seen_unix_ms) > self.retention_ms
+        {
+            self.evict_oldest();
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some((_, nonce)) = self.by_age.pop_first() {
+            self.seen.remove(&nonce);
+        }
     }
 }
 
@@ -198,10 +681,45 @@ pub enum SessionAuthError {
     RequestNonceMismatch,
     #[error("peer is not trusted and trust-on-first-use is disabled: {device_code}")]
     UntrustedPeer { device_code: String },
-    #[error("trusted peer mismatch for device code {device_code}")]
+    #[error(
+        "trusted peer mismatch for device code {device_code}; a rotated device must announce an identity rotation proof signed by its trusted key, otherwise revoke and re-pair it"
+    )]
     TrustedPeerMismatch { device_code: String },
+    #[error("device code {device_code} has been revoked")]
+    RevokedPeer { device_code: String },
     #[error("trust store is corrupted: {0}")]
     TrustStoreCorrupt(String),
+    #[error("resumption ticket expired")]
+    ResumptionTicketExpired,
+    #[error("invalid resumption ticket: {0}")]
+    InvalidResumptionTicket(&'static str),
+    #[error("invalid identity rotation proof: {0}")]
+    InvalidRotationProof(&'static str),
+    #[error("too many authentication failures; retry after {retry_after_ms} ms")]
+    Throttled { retry_after_ms: i64 },
+    #[error("missing challenge in SessionRequest")]
+    MissingChallenge,
+    #[error("invalid challenge: {0}")]
+    InvalidChallenge(&'static str),
+    #[error("challenge expired")]
+    ChallengeExpired,
+}
+
+/// Compares two byte strings in time that depends only on their lengths, for nonces
+/// and other values whose contents should not leak through timing.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// Whether any candidate equals `needle`. Every candidate is compared, so timing does
+/// not reveal which one matched.
+pub fn constant_time_contains<T: AsRef<[u8]>>(candidates: &[T], needle: &[u8]) -> bool {
+    candidates
+        .iter()
+        .fold(Choice::from(0), |found, candidate| {
+            found | candidate.as_ref().ct_eq(needle)
+        })
+        .into()
 }
 
 pub fn sign_session_request(
@@ -228,80 +746,79 @@ pub fn sign_session_accept(
     Ok(())
 }
 
-#[allow(clippy::too_many_arguments)]
+/// The state the session verifiers read and update: the replay cache, the trust store
+/// and the sink every terminal decision is reported to.
+pub struct VerifierContext<'a> {
+    pub replay_cache: &'a mut NonceReplayCache,
+    pub trusted_peers: &'a mut TrustedPeers,
+    pub audit: &'a mut dyn AuthAuditSink,
+}
+
+impl<'a> VerifierContext<'a> {
+    pub fn new(
+        replay_cache: &'a mut NonceReplayCache,
+        trusted_peers: &'a mut TrustedPeers,
+        audit: &'a mut dyn AuthAuditSink,
+    ) -> Self {
+        Self {
+            replay_cache,
+            trusted_peers,
+            audit,
+        }
+    }
+}
+
+/// Verifies a SessionRequest and reports the decision to `ctx.audit`.
 pub fn verify_session_request(
     request: &SessionRequest,
     transport_peer_id: Option<&PeerId>,
     expected_target_device_code: Option<&str>,
     now_unix_ms: i64,
-    allowed_skew_ms: i64,
-    replay_cache: &mut NonceReplayCache,
-    trusted_peers: &mut TrustedPeers,
-    trust_on_first_use: bool,
+    policy: &VerifierPolicy,
+    ctx: &mut VerifierContext<'_>,
 ) -> Result<VerifiedSessionPeer, SessionAuthError> {
-    let from = request
-        .from
-        .as_ref()
-        .ok_or(SessionAuthError::MissingSenderIdentity)?;
-
-    if from.device_code.trim().is_empty() {
-        return Err(SessionAuthError::MissingDeviceCode);
-    }
-
-    if let Some(expected_target) = expected_target_device_code
-        && request.target_device_code != expected_target
-    {
-        return Err(SessionAuthError::InvalidTargetDeviceCode {
-            expected: expected_target.to_string(),
-            got: request.target_device_code.clone(),
-        });
-    }
-
-    if request.nonce.is_empty() {
-        return Err(SessionAuthError::MissingNonce);
-    }
-    if request.nonce.len() < MIN_NONCE_BYTES {
-        return Err(SessionAuthError::NonceTooShort {
-            min_bytes: MIN_NONCE_BYTES,
-        });
-    }
-
-    if (now_unix_ms - request.unix_ms).abs() > allowed_skew_ms {
-        return Err(SessionAuthError::TimestampSkew {
-            request_unix_ms: request.unix_ms,
-            now_unix_ms,
-            allowed_skew_ms,
-        });
-    }
-    replay_cache.check_and_store(&request.nonce, now_unix_ms)?;
-
-    let sender_public_key = identity::PublicKey::try_decode_protobuf(&from.identity_pubkey)
-        .map_err(|_| SessionAuthError::InvalidSenderPublicKey)?;
-
-    let signing_payload = canonical_session_request_payload(request);
-    if !sender_public_key.verify(&signing_payload, &request.signature) {
-        return Err(SessionAuthError::InvalidSignature);
-    }
-
-    let claimed_peer_id =
-        PeerId::from_bytes(&from.peer_id).map_err(|_| SessionAuthError::InvalidSenderPeerId)?;
-    let derived_peer_id = PeerId::from_public_key(&sender_public_key);
-    if claimed_peer_id != derived_peer_id {
-        return Err(SessionAuthError::PeerIdMismatch);
-    }
+    let outcome = check_session_request(
+        request,
+        transport_peer_id,
+        expected_target_device_code,
+        now_unix_ms,
+        policy,
+        ctx,
+    );
+    ctx.audit.record(&AuthEvent::from_outcome(
+        AuthMessage::SessionRequest,
+        request.from.as_ref(),
+        transport_peer_id,
+        now_unix_ms,
+        &outcome,
+    ));
+    outcome
+}
 
-    if let Some(bound_peer_id) = transport_peer_id
-        && *bound_peer_id != derived_peer_id
-    {
-        return Err(SessionAuthError::TransportPeerIdMismatch);
-    }
+fn check_session_request(
+    request: &SessionRequest,
+    transport_peer_id: Option<&PeerId>,
+    expected_target_device_code: Option<&str>,
+    now_unix_ms: i64,
+    policy: &VerifierPolicy,
+    ctx: &mut VerifierContext<'_>,
+) -> Result<VerifiedSessionPeer, SessionAuthError> {
+    let (derived_peer_id, from) = verify_signed_session_request(
+        request,
+        transport_peer_id,
+        expected_target_device_code,
+        now_unix_ms,
+        policy,
+        true,
+        ctx.replay_cache,
+    )?;
 
-    let trust_store_changed = trusted_peers.ensure_trusted(
+    let trust_store_changed = ctx.trusted_peers.ensure_trusted(
         &from.device_code,
         &derived_peer_id,
         &from.identity_pubkey,
         now_unix_ms,
-        trust_on_first_use,
+        policy.trust_on_first_use,
     )?;
 
     Ok(VerifiedSessionPeer {
@@ -311,35 +828,352 @@ pub fn verify_session_request(
     })
 }
 
-#[allow(clippy::too_many_arguments)]
-pub fn verify_session_accept(
-    accept: &SessionAccept,
+/// Verifies a SessionRequest that echoes a challenge issued by `issuer`, the local
+/// key. The challenge stands in for the timestamp check, so the requester's clock does
+/// not matter; everything else is checked exactly as in `verify_session_request`.
+pub fn verify_challenged_session_request(
+    request: &SessionRequest,
     transport_peer_id: Option<&PeerId>,
-    expected_session_id: Option<&str>,
-    expected_request_nonce: Option<&[u8]>,
+    expected_target_device_code: Option<&str>,
     now_unix_ms: i64,
-    allowed_skew_ms: i64,
-    replay_cache: &mut NonceReplayCache,
-    trusted_peers: &mut TrustedPeers,
-    trust_on_first_use: bool,
+    policy: &VerifierPolicy,
+    issuer: &identity::PublicKey,
+    ctx: &mut VerifierContext<'_>,
 ) -> Result<VerifiedSessionPeer, SessionAuthError> {
-    if let Some(expected) = expected_session_id
-        && accept.session_id != expected
-    {
-        return Err(SessionAuthError::SessionIdMismatch {
-            expected: expected.to_string(),
-            got: accept.session_id.clone(),
-        });
-    }
+    let outcome = check_challenged_session_request(
+        request,
+        transport_peer_id,
+        expected_target_device_code,
+        now_unix_ms,
+        policy,
+        issuer,
+        ctx,
+    );
+    ctx.audit.record(&AuthEvent::from_outcome(
+        AuthMessage::SessionRequest,
+        request.from.as_ref(),
+        transport_peer_id,
+        now_unix_ms,
+        &outcome,
+    ));
+    outcome
+}
 
-    if let Some(expected_nonce) = expected_request_nonce {
-        if accept.request_nonce.is_empty() {
-            return Err(SessionAuthError::MissingRequestNonceBinding);
-        }
-        if accept.request_nonce != expected_nonce {
-            return Err(SessionAuthError::RequestNonceMismatch);
-        }
-    }
+fn check_challenged_session_request(
+    request: &SessionRequest,
+    transport_peer_id: Option<&PeerId>,
+    expected_target_device_code: Option<&str>,
+    now_unix_ms: i64,
+    policy: &VerifierPolicy,
+    issuer: &identity::PublicKey,
+    ctx: &mut VerifierContext<'_>,
+) -> Result<VerifiedSessionPeer, SessionAuthError> {
+    let (derived_peer_id, from) = verify_signed_session_request(
+        request,
+        transport_peer_id,
+        expected_target_device_code,
+        now_unix_ms,
+        policy,
+        false,
+        ctx.replay_cache,
+    )?;
+    verify_challenge_response(
+        request,
+        issuer,
+        now_unix_ms,
+        policy.challenge_ttl_ms,
+        ctx.replay_cache,
+    )?;
+
+    let trust_store_changed = ctx.trusted_peers.ensure_trusted(
+        &from.device_code,
+        &derived_peer_id,
+        &from.identity_pubkey,
+        now_unix_ms,
+        policy.trust_on_first_use,
+    )?;
+
+    Ok(VerifiedSessionPeer {
+        peer_id: derived_peer_id,
+        device_code: from.device_code.clone(),
+        trust_store_changed,
+    })
+}
+
+/// Issues a challenge for a peer to echo in its next SessionRequest.
+pub fn issue_challenge(
+    keypair: &identity::Keypair,
+    now_unix_ms: i64,
+) -> Result<Challenge, SessionAuthError> {
+    let mut nonce = vec![0u8; CHALLENGE_NONCE_BYTES];
+    rand::rng().fill_bytes(&mut nonce);
+    let mut challenge = Challenge {
+        nonce,
+        unix_ms: now_unix_ms,
+        signature: Vec::new(),
+    };
+    challenge.signature = keypair
+        .sign(&canonical_challenge_payload(&challenge))
+        .map_err(|_| SessionAuthError::SigningFailed)?;
+    Ok(challenge)
+}
+
+/// Checks that `request` echoes a challenge `issuer` signed within `ttl_ms` and marks
+/// it used, so each challenge admits one request. The ttl is capped at the replay
+/// cache's retention, since a challenge must not outlive the record of its use.
+pub fn verify_challenge_response(
+    request: &SessionRequest,
+    issuer: &identity::PublicKey,
+    now_unix_ms: i64,
+    ttl_ms: i64,
+    replay_cache: &mut NonceReplayCache,
+) -> Result<(), SessionAuthError> {
+    let challenge = request
+        .challenge
+        .as_ref()
+        .ok_or(SessionAuthError::MissingChallenge)?;
+    if challenge.nonce.len() < CHALLENGE_NONCE_BYTES {
+        return Err(SessionAuthError::InvalidChallenge("nonce too short"));
+    }
+    if !issuer.verify(
+        &canonical_challenge_payload(challenge),
+        &challenge.signature,
+    ) {
+        return Err(SessionAuthError::InvalidChallenge("bad signature"));
+    }
+    let age_ms = now_unix_ms.saturating_sub(challenge.unix_ms);
+    if !(0..=ttl_ms.min(replay_cache.retention_ms())).contains(&age_ms) {
+        return Err(SessionAuthError::ChallengeExpired);
+    }
+    let key = [CHALLENGE_REPLAY_PREFIX, challenge.nonce.as_slice()].concat();
+    replay_cache.check_and_store(&key, now_unix_ms)
+}
+
+/// Verifies a SessionRequest that carries a resumption ticket issued by `issuer`. The
+/// ticket replaces the trust-store lookup, so `policy.trust_on_first_use` is unused,
+/// but a device revoked since the ticket was issued is still refused; everything else
+/// (freshness, replay, request signature, transport binding) is checked exactly as in
+/// `verify_session_request`.
+/// Ticket problems are reported before the nonce is recorded, so the caller can fall
+/// back to full verification of the same request. For the same reason nothing is
+/// reported to `ctx.audit`: the caller records the outcome it acts on.
+pub fn verify_resumed_session_request(
+    request: &SessionRequest,
+    transport_peer_id: Option<&PeerId>,
+    expected_target_device_code: Option<&str>,
+    now_unix_ms: i64,
+    policy: &VerifierPolicy,
+    issuer: &identity::PublicKey,
+    ctx: &mut VerifierContext<'_>,
+) -> Result<VerifiedSessionPeer, SessionAuthError> {
+    let ticket = request
+        .resumption_ticket
+        .as_ref()
+        .ok_or(SessionAuthError::InvalidResumptionTicket("missing ticket"))?;
+    let from = request
+        .from
+        .as_ref()
+        .ok_or(SessionAuthError::MissingSenderIdentity)?;
+    let claimed_peer_id =
+        PeerId::from_bytes(&from.peer_id).map_err(|_| SessionAuthError::InvalidSenderPeerId)?;
+    verify_ticket(ticket, issuer, &claimed_peer_id, now_unix_ms)?;
+    if ticket.holder_device_code != from.device_code {
+        return Err(SessionAuthError::InvalidResumptionTicket(
+            "device code does not match holder",
+        ));
+    }
+    if ctx.trusted_peers.is_revoked(&from.device_code) {
+        return Err(SessionAuthError::RevokedPeer {
+            device_code: from.device_code.clone(),
+        });
+    }
+
+    let (derived_peer_id, from) = verify_signed_session_request(
+        request,
+        transport_peer_id,
+        expected_target_device_code,
+        now_unix_ms,
+        policy,
+        true,
+        ctx.replay_cache,
+    )?;
+
+    Ok(VerifiedSessionPeer {
+        peer_id: derived_peer_id,
+        device_code: from.device_code.clone(),
+        trust_store_changed: false,
+    })
+}
+
+/// Everything `verify_session_request` checks short of the trust policy: identity
+/// presence, target, nonce, freshness, replay, signature and peer id bindings. The
+/// timestamp check is skipped only when a challenge proves freshness instead.
+fn verify_signed_session_request<'a>(
+    request: &'a SessionRequest,
+    transport_peer_id: Option<&PeerId>,
+    expected_target_device_code: Option<&str>,
+    now_unix_ms: i64,
+    policy: &VerifierPolicy,
+    check_skew: bool,
+    replay_cache: &mut NonceReplayCache,
+) -> Result<(PeerId, &'a DeviceIdentity), SessionAuthError> {
+    let from = request
+        .from
+        .as_ref()
+        .ok_or(SessionAuthError::MissingSenderIdentity)?;
+
+    if from.device_code.trim().is_empty() {
+        return Err(SessionAuthError::MissingDeviceCode);
+    }
+
+    if policy.require_target_match
+        && let Some(expected_target) = expected_target_device_code
+        && request.target_device_code != expected_target
+    {
+        return Err(SessionAuthError::InvalidTargetDeviceCode {
+            expected: expected_target.to_string(),
+            got: request.target_device_code.clone(),
+        });
+    }
+
+    if request.nonce.is_empty() {
+        return Err(SessionAuthError::MissingNonce);
+    }
+    if request.nonce.len() < policy.min_nonce_bytes {
+        return Err(SessionAuthError::NonceTooShort {
+            min_bytes: policy.min_nonce_bytes,
+        });
+    }
+
+    if check_skew && (now_unix_ms - request.unix_ms).abs() > policy.allowed_skew_ms {
+        return Err(SessionAuthError::TimestampSkew {
+            request_unix_ms: request.unix_ms,
+            now_unix_ms,
+            allowed_skew_ms: policy.allowed_skew_ms,
+        });
+    }
+    replay_cache.check(&request.nonce, now_unix_ms)?;
+
+    let sender_public_key = identity::PublicKey::try_decode_protobuf(&from.identity_pubkey)
+        .map_err(|_| SessionAuthError::InvalidSenderPublicKey)?;
+
+    let signing_payload = canonical_session_request_payload(request);
+    if !sender_public_key.verify(&signing_payload, &request.signature) {
+        return Err(SessionAuthError::InvalidSignature);
+    }
+
+    let claimed_peer_id =
+        PeerId::from_bytes(&from.peer_id).map_err(|_| SessionAuthError::InvalidSenderPeerId)?;
+    let derived_peer_id = PeerId::from_public_key(&sender_public_key);
+    if claimed_peer_id != derived_peer_id {
+        return Err(SessionAuthError::PeerIdMismatch);
+    }
+
+    if let Some(bound_peer_id) = transport_peer_id
+        && *bound_peer_id != derived_peer_id
+    {
+        return Err(SessionAuthError::TransportPeerIdMismatch);
+    }
+    replay_cache.check_and_store(&request.nonce, now_unix_ms)?;
+
+    Ok((derived_peer_id, from))
+}
+
+/// The controller-side counterpart of `verify_session_request`: checks the session id
+/// and, when given, that the accept echoes one of the request nonces we sent (each
+/// retry of a request carries a fresh one), then the responder's identity, nonce,
+/// freshness, replay, signature, peer id bindings and trust exactly as the responder
+/// checks a request. The decision is reported to `ctx.audit`.
+pub fn verify_session_accept(
+    accept: &SessionAccept,
+    transport_peer_id: Option<&PeerId>,
+    expected_session_id: Option<&str>,
+    expected_request_nonces: Option<&[Vec<u8>]>,
+    now_unix_ms: i64,
+    policy: &VerifierPolicy,
+    ctx: &mut VerifierContext<'_>,
+) -> Result<VerifiedSessionPeer, SessionAuthError> {
+    let outcome = check_session_accept(
+        accept,
+        transport_peer_id,
+        expected_session_id,
+        expected_request_nonces,
+        now_unix_ms,
+        policy,
+        true,
+        ctx,
+    );
+    ctx.audit.record(&AuthEvent::from_outcome(
+        AuthMessage::SessionAccept,
+        accept.from.as_ref(),
+        transport_peer_id,
+        now_unix_ms,
+        &outcome,
+    ));
+    outcome
+}
+
+/// Verifies a SessionAccept answering a request that echoed the responder's
+/// challenge. Having proved our clock wrong once, we let the binding to one of our
+/// fresh `request_nonces` stand in for the timestamp check; everything else is checked
+/// exactly as in `verify_session_accept`.
+pub fn verify_challenged_session_accept(
+    accept: &SessionAccept,
+    transport_peer_id: Option<&PeerId>,
+    expected_session_id: Option<&str>,
+    request_nonces: &[Vec<u8>],
+    now_unix_ms: i64,
+    policy: &VerifierPolicy,
+    ctx: &mut VerifierContext<'_>,
+) -> Result<VerifiedSessionPeer, SessionAuthError> {
+    let outcome = check_session_accept(
+        accept,
+        transport_peer_id,
+        expected_session_id,
+        Some(request_nonces),
+        now_unix_ms,
+        policy,
+        false,
+        ctx,
+    );
+    ctx.audit.record(&AuthEvent::from_outcome(
+        AuthMessage::SessionAccept,
+        accept.from.as_ref(),
+        transport_peer_id,
+        now_unix_ms,
+        &outcome,
+    ));
+    outcome
+}
+
+#[allow(clippy::too_many_arguments)]
+fn check_session_accept(
+    accept: &SessionAccept,
+    transport_peer_id: Option<&PeerId>,
+    expected_session_id: Option<&str>,
+    expected_request_nonces: Option<&[Vec<u8>]>,
+    now_unix_ms: i64,
+    policy: &VerifierPolicy,
+    check_skew: bool,
+    ctx: &mut VerifierContext<'_>,
+) -> Result<VerifiedSessionPeer, SessionAuthError> {
+    if let Some(expected) = expected_session_id
+        && accept.session_id != expected
+    {
+        return Err(SessionAuthError::SessionIdMismatch {
+            expected: expected.to_string(),
+            got: accept.session_id.clone(),
+        });
+    }
+
+    if let Some(expected_nonces) = expected_request_nonces {
+        if accept.request_nonce.is_empty() {
+            return Err(SessionAuthError::MissingRequestNonceBinding);
+        }
+        if !constant_time_contains(expected_nonces, &accept.request_nonce) {
+            return Err(SessionAuthError::RequestNonceMismatch);
+        }
+    }
 
     let from = accept
         .from
@@ -351,20 +1185,20 @@ pub fn verify_session_accept(
     if accept.nonce.is_empty() {
         return Err(SessionAuthError::MissingNonce);
     }
-    if accept.nonce.len() < MIN_NONCE_BYTES {
+    if accept.nonce.len() < policy.min_nonce_bytes {
         return Err(SessionAuthError::NonceTooShort {
-            min_bytes: MIN_NONCE_BYTES,
+            min_bytes: policy.min_nonce_bytes,
         });
     }
 
-    if (now_unix_ms - accept.unix_ms).abs() > allowed_skew_ms {
+    if check_skew && (now_unix_ms - accept.unix_ms).abs() > policy.allowed_skew_ms {
         return Err(SessionAuthError::TimestampSkew {
             request_unix_ms: accept.unix_ms,
             now_unix_ms,
-            allowed_skew_ms,
+            allowed_skew_ms: policy.allowed_skew_ms,
         });
     }
-    replay_cache.check_and_store(&accept.nonce, now_unix_ms)?;
+    ctx.replay_cache.check(&accept.nonce, now_unix_ms)?;
 
     let sender_public_key = identity::PublicKey::try_decode_protobuf(&from.identity_pubkey)
         .map_err(|_| SessionAuthError::InvalidSenderPublicKey)?;
@@ -384,13 +1218,15 @@ pub fn verify_session_accept(
     {
         return Err(SessionAuthError::TransportPeerIdMismatch);
     }
+    ctx.replay_cache
+        .check_and_store(&accept.nonce, now_unix_ms)?;
 
-    let trust_store_changed = trusted_peers.ensure_trusted(
+    let trust_store_changed = ctx.trusted_peers.ensure_trusted(
         &from.device_code,
         &derived_peer_id,
         &from.identity_pubkey,
         now_unix_ms,
-        trust_on_first_use,
+        policy.trust_on_first_use,
     )?;
 
     Ok(VerifiedSessionPeer {
@@ -401,15 +1237,86 @@ pub fn verify_session_accept(
 }
 
 fn canonical_session_request_payload(request: &SessionRequest) -> Vec<u8> {
-    let mut stripped = request.clone();
-    stripped.signature.clear();
-    stripped.encode_to_vec()
+    SigningPayload::new(signing_domain::SESSION_REQUEST)
+        .string(1, &request.session_id)
+        .message(2, request.from.as_ref().map(identity_payload))
+        .i32(3, request.requested_role)
+        .string(4, &request.target_device_code)
+        .i32s(5, &request.supported_video_codecs)
+        .bool(6, request.allow_relay)
+        .u32(7, request.preferred_max_fps)
+        .u32(8, request.preferred_max_width)
+        .u32(9, request.preferred_max_height)
+        .bytes(10, &request.nonce)
+        .i64(11, request.unix_ms)
+        .message(13, request.version.as_ref().map(version_payload))
+        .strings(14, &request.feature_bits)
+        .message(
+            15,
+            request
+                .resumption_ticket
+                .as_ref()
+                .map(embedded_ticket_payload),
+        )
+        .message(
+            16,
+            request.challenge.as_ref().map(embedded_challenge_payload),
+        )
+        .finish()
+}
+
+fn challenge_layout(challenge: &Challenge) -> SigningPayload {
+    SigningPayload::new(signing_domain::CHALLENGE)
+        .bytes(1, &challenge.nonce)
+        .i64(2, challenge.unix_ms)
+}
+
+fn canonical_challenge_payload(challenge: &Challenge) -> Vec<u8> {
+    challenge_layout(challenge).finish()
+}
+
+/// A challenge echoed in a request, its issuer's signature included.
+fn embedded_challenge_payload(challenge: &Challenge) -> SigningPayload {
+    challenge_layout(challenge).bytes(3, &challenge.signature)
 }
 
 fn canonical_session_accept_payload(accept: &SessionAccept) -> Vec<u8> {
-    let mut stripped = accept.clone();
-    stripped.signature.clear();
-    stripped.encode_to_vec()
+    SigningPayload::new(signing_domain::SESSION_ACCEPT)
+        .string(1, &accept.session_id)
+        .i32(2, accept.selected_codec)
+        .u32(3, accept.selected_fps)
+        .u32(4, accept.selected_width)
+        .u32(5, accept.selected_height)
+        .bool(6, accept.using_relay)
+        .string(7, &accept.path_id)
+        .message(8, accept.from.as_ref().map(identity_payload))
+        .bytes(9, &accept.nonce)
+        .i64(10, accept.unix_ms)
+        .bytes(12, &accept.request_nonce)
+        .strings(13, &accept.accepted_feature_bits)
+        .message(
+            14,
+            accept
+                .resumption_ticket
+                .as_ref()
+                .map(embedded_ticket_payload),
+        )
+        .message(15, accept.version.as_ref().map(version_payload))
+        .finish()
+}
+
+fn identity_payload(identity: &DeviceIdentity) -> SigningPayload {
+    SigningPayload::new("aetherlink.v1.DeviceIdentity")
+        .bytes(1, &identity.peer_id)
+        .bytes(2, &identity.identity_pubkey)
+        .string(3, &identity.device_code)
+}
+
+fn version_payload(version: &ProtocolVersion) -> SigningPayload {
+    SigningPayload::new("aetherlink.v1.ProtocolVersion")
+        .u32(1, version.major)
+        .u32(2, version.minor)
+        .u32(3, version.patch)
 }
 
 fn parse_peer_id(text: &str) -> Result<PeerId, SessionAuthError> {
@@ -426,7 +1333,7 @@ fn encode_hex(bytes: &[u8]) -> String {
     out
 }
 
-fn decode_hex(hex: &str) -> Result<Vec<u8>, SessionAuthError> {
+pub(crate) fn decode_hex(hex: &str) -> Result<Vec<u8>, SessionAuthError> {
     if !hex.len().is_multiple_of(2) {
         return Err(SessionAuthError::TrustStoreCorrupt(
             "hex string has odd length".to_string(),
@@ -468,6 +1375,13 @@ mod tests {
         DeviceIdentity, ProtocolVersion, SessionAccept, SessionRole, VideoCodec,
     };
 
+    fn tofu_policy() -> VerifierPolicy {
+        VerifierPolicy {
+            trust_on_first_use: true,
+            ..VerifierPolicy::default()
+        }
+    }
+
     fn make_signed_request(
         keypair: &identity::Keypair,
         target_device_code: &str,
@@ -497,6 +1411,8 @@ mod tests {
                 patch: 0,
             }),
             feature_bits: Vec::new(),
+            resumption_ticket: None,
+            challenge: None,
         };
         sign_session_request(&mut req, keypair).unwrap();
         req
@@ -527,6 +1443,8 @@ mod tests {
             signature: Vec::new(),
             request_nonce: request_nonce.to_vec(),
             accepted_feature_bits: Vec::new(),
+            resumption_ticket: None,
+            version: None,
         };
         sign_session_accept(&mut accept, keypair).unwrap();
         accept
@@ -545,10 +1463,8 @@ mod tests {
             Some(&peer_id),
             Some("target-a"),
             1_000_100,
-            DEFAULT_ALLOWED_SKEW_MS,
-            &mut replay,
-            &mut trust,
-            true,
+            &tofu_policy(),
+            &mut VerifierContext::new(&mut replay, &mut trust, &mut ()),
         )
         .unwrap();
         assert_eq!(verified.peer_id, peer_id);
@@ -563,10 +1479,8 @@ mod tests {
             Some(&peer_id),
             Some("target-a"),
             1_000_250,
-            DEFAULT_ALLOWED_SKEW_MS,
-            &mut replay,
-            &mut trust,
-            true,
+            &tofu_policy(),
+            &mut VerifierContext::new(&mut replay, &mut trust, &mut ()),
         )
         .unwrap();
         assert!(!verified.trust_store_changed);
@@ -585,10 +1499,8 @@ mod tests {
             Some(&peer_id),
             Some("target-a"),
             1_000_100,
-            DEFAULT_ALLOWED_SKEW_MS,
-            &mut replay,
-            &mut trust,
-            true,
+            &tofu_policy(),
+            &mut VerifierContext::new(&mut replay, &mut trust, &mut ()),
         )
         .unwrap();
 
@@ -597,94 +1509,1182 @@ mod tests {
             Some(&peer_id),
             Some("target-a"),
             1_000_200,
-            DEFAULT_ALLOWED_SKEW_MS,
-            &mut replay,
-            &mut trust,
-            true,
+            &tofu_policy(),
+            &mut VerifierContext::new(&mut replay, &mut trust, &mut ()),
         )
         .unwrap_err();
         assert_eq!(err, SessionAuthError::ReplayDetected);
     }
 
     #[test]
-    fn untrusted_peer_rejected_when_tofu_disabled() {
+    fn forged_messages_do_not_burn_nonces() {
         let key = identity::Keypair::generate_ed25519();
         let peer_id = PeerId::from(key.public());
         let req = make_signed_request(&key, "target-a", b"0123456789abcdef", 1_000_000);
+        let mut forged = req.clone();
+        forged.signature[0] ^= 0xff;
         let mut replay = NonceReplayCache::default();
         let mut trust = TrustedPeers::default();
 
         let err = verify_session_request(
-            &req,
+            &forged,
             Some(&peer_id),
             Some("target-a"),
             1_000_100,
-            DEFAULT_ALLOWED_SKEW_MS,
-            &mut replay,
-            &mut trust,
-            false,
+            &tofu_policy(),
+            &mut VerifierContext::new(&mut replay, &mut trust, &mut ()),
         )
         .unwrap_err();
-        assert!(matches!(err, SessionAuthError::UntrustedPeer { .. }));
-    }
+        assert_eq!(err, SessionAuthError::InvalidSignature);
+        let err = verify_session_request(
+            &req,
+            Some(&PeerId::random()),
+            Some("target-a"),
+            1_000_100,
+            &tofu_policy(),
+            &mut VerifierContext::new(&mut replay, &mut trust, &mut ()),
+        )
+        .unwrap_err();
+        assert_eq!(err, SessionAuthError::TransportPeerIdMismatch);
+        assert!(replay.is_empty());
+
+        verify_session_request(
+            &req,
+            Some(&peer_id),
+            Some("target-a"),
+            1_000_200,
+            &tofu_policy(),
+            &mut VerifierContext::new(&mut replay, &mut trust, &mut ()),
+        )
+        .unwrap();
+        assert_eq!(
+            replay.check(b"0123456789abcdef", 1_000_300),
+            Err(SessionAuthError::ReplayDetected)
+        );
 
-    #[test]
-    fn session_accept_signature_and_binding_verified() {
-        let key = identity::Keypair::generate_ed25519();
-        let peer_id = PeerId::from(key.public());
-        let req_nonce = b"reqnonce01234567";
         let accept = make_signed_accept(
             &key,
-            "session-test",
-            req_nonce,
+            "s",
+            b"reqnonce01234567",
             b"resnonce01234567",
             1_000_000,
         );
+        let mut forged_accept = accept.clone();
+        forged_accept.signature[0] ^= 0xff;
+        let accept_with =
+            |accept: &SessionAccept, replay: &mut NonceReplayCache, trust: &mut TrustedPeers| {
+                verify_session_accept(
+                    accept,
+                    Some(&peer_id),
+                    Some("s"),
+                    None,
+                    1_000_100,
+                    &tofu_policy(),
+                    &mut VerifierContext::new(replay, trust, &mut ()),
+                )
+            };
+        assert_eq!(
+            accept_with(&forged_accept, &mut replay, &mut trust),
+            Err(SessionAuthError::InvalidSignature)
+        );
+        accept_with(&accept, &mut replay, &mut trust).unwrap();
+    }
 
-        let mut replay = NonceReplayCache::default();
-        let mut trust = TrustedPeers::default();
-        let verified = verify_session_accept(
-            &accept,
+    #[test]
+    fn signatures_survive_unknown_fields_and_stay_in_their_domain() {
+        use prost::Message;
+
+        let key = identity::Keypair::generate_ed25519();
+        let peer_id = PeerId::from(key.public());
+        let req = make_signed_request(&key, "target-a", b"0123456789abcdef", 1_000_000);
+        // A newer peer's request carrying a field this version does not know (99).
+        let mut wire = req.encode_to_vec();
+        wire.extend_from_slice(&[0x98, 0x06, 0x01]);
+        let decoded = SessionRequest::decode(wire.as_slice()).unwrap();
+        verify_session_request(
+            &decoded,
             Some(&peer_id),
-            Some("session-test"),
-            Some(req_nonce),
+            Some("target-a"),
             1_000_100,
-            DEFAULT_ALLOWED_SKEW_MS,
-            &mut replay,
-            &mut trust,
-            true,
+            &tofu_policy(),
+            &mut VerifierContext::new(
+                &mut NonceReplayCache::default(),
+                &mut TrustedPeers::default(),
+                &mut (),
+            ),
         )
         .unwrap();
-        assert_eq!(verified.peer_id, peer_id);
-        assert!(verified.trust_store_changed);
+
+        // The same key signing an accept over the same bytes cannot pass as a request.
+        let accept = make_signed_accept(&key, "session-test", b"0123456789abcdef", b"n", 1);
+        assert!(
+            !key.public()
+                .verify(&canonical_session_request_payload(&req), &accept.signature)
+        );
+        let challenge = issue_challenge(&key, 1_000_000).unwrap();
+        assert!(!key.public().verify(
+            &canonical_session_request_payload(&req),
+            &challenge.signature
+        ));
     }
 
     #[test]
-    fn session_accept_request_nonce_mismatch_rejected() {
+    fn verifiers_report_each_decision_to_the_audit_sink() {
         let key = identity::Keypair::generate_ed25519();
         let peer_id = PeerId::from(key.public());
-        let accept = make_signed_accept(
-            &key,
-            "session-test",
-            b"reqnonce01234567",
-            b"resnonce01234567",
-            1_000_000,
-        );
-
+        let device_code = peer_id.to_string();
+        let req = make_signed_request(&key, "target-a", b"0123456789abcdef", 1_000_000);
         let mut replay = NonceReplayCache::default();
         let mut trust = TrustedPeers::default();
-        let err = verify_session_accept(
-            &accept,
+        let mut audit: Vec<AuthEvent> = Vec::new();
+
+        for now in [1_000_100, 1_000_200] {
+            let _ = verify_session_request(
+                &req,
+                Some(&peer_id),
+                Some("target-a"),
+                now,
+                &tofu_policy(),
+                &mut VerifierContext::new(&mut replay, &mut trust, &mut audit),
+            );
+        }
+        let mut forged = make_signed_request(&key, "target-a", b"fedcba9876543210", 1_000_000);
+        forged.signature[0] ^= 0xff;
+        let _ = verify_session_request(
+            &forged,
             Some(&peer_id),
-            Some("session-test"),
-            Some(b"differentnonce123"),
-            1_000_100,
-            DEFAULT_ALLOWED_SKEW_MS,
-            &mut replay,
-            &mut trust,
-            true,
-        )
+            Some("target-a"),
+            1_000_300,
+            &tofu_policy(),
+            &mut VerifierContext::new(&mut replay, &mut trust, &mut audit),
+        );
+
+        assert_eq!(
+            audit,
+            vec![
+                AuthEvent::Accepted {
+                    message: AuthMessage::SessionRequest,
+                    peer_id,
+                    device_code: device_code.clone(),
+                    unix_ms: 1_000_100,
+                },
+                AuthEvent::Rejected {
+                    message: AuthMessage::SessionRequest,
+                    peer_id: Some(peer_id),
+                    device_code: device_code.clone(),
+                    unix_ms: 1_000_200,
+                    error: SessionAuthError::ReplayDetected,
+                },
+                AuthEvent::Rejected {
+                    message: AuthMessage::SessionRequest,
+                    peer_id: Some(peer_id),
+                    device_code,
+                    unix_ms: 1_000_300,
+                    error: SessionAuthError::InvalidSignature,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn verifier_policy_tightens_and_relaxes_checks() {
+        let key = identity::Keypair::generate_ed25519();
+        let peer_id = PeerId::from(key.public());
+        let req = make_signed_request(&key, "target-a", b"0123456789abcdef", 1_000_000);
+        let mut replay = NonceReplayCache::default();
+        let mut trust = TrustedPeers::default();
+
+        let strict = VerifierPolicy {
+            min_nonce_bytes: 17,
+            ..tofu_policy()
+        };
+        assert_eq!(
+            verify_session_request(
+                &req,
+                Some(&peer_id),
+                Some("target-a"),
+                1_000_100,
+                &strict,
+                &mut VerifierContext::new(&mut replay, &mut trust, &mut ())
+            ),
+            Err(SessionAuthError::NonceTooShort { min_bytes: 17 })
+        );
+
+        let tight_clock = VerifierPolicy {
+            allowed_skew_ms: 50,
+            ..tofu_policy()
+        };
+        assert!(matches!(
+            verify_session_request(
+                &req,
+                Some(&peer_id),
+                Some("target-a"),
+                1_000_100,
+                &tight_clock,
+                &mut VerifierContext::new(&mut replay, &mut trust, &mut ())
+            ),
+            Err(SessionAuthError::TimestampSkew {
+                allowed_skew_ms: 50,
+                ..
+            })
+        ));
+
+        let any_target = VerifierPolicy {
+            require_target_match: false,
+            ..tofu_policy()
+        };
+        verify_session_request(
+            &req,
+            Some(&peer_id),
+            Some("target-b"),
+            1_000_100,
+            &any_target,
+            &mut VerifierContext::new(&mut replay, &mut trust, &mut ()),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn challenge_replaces_clock_check_and_is_single_use() {
+        let responder = identity::Keypair::generate_ed25519();
+        let key = identity::Keypair::generate_ed25519();
+        let peer_id = PeerId::from(key.public());
+        let mut replay = NonceReplayCache::default();
+        let mut trust = TrustedPeers::default();
+        let challenged = |challenge: Option<Challenge>, nonce: &[u8]| {
+            // The requester's clock is hours off; only the challenge vouches for it.
+            let mut req = make_signed_request(&key, "target-a", nonce, 1_000);
+            req.challenge = challenge;
+            sign_session_request(&mut req, &key).unwrap();
+            req
+        };
+
+        let challenge = issue_challenge(&responder, 10_000_000).unwrap();
+        let req = challenged(Some(challenge.clone()), b"0123456789abcdef");
+        assert!(matches!(
+            verify_session_request(
+                &req,
+                Some(&peer_id),
+                Some("target-a"),
+                10_000_100,
+                &tofu_policy(),
+                &mut VerifierContext::new(&mut replay, &mut trust, &mut ())
+            ),
+            Err(SessionAuthError::TimestampSkew { .. })
+        ));
+        let verified = verify_challenged_session_request(
+            &req,
+            Some(&peer_id),
+            Some("target-a"),
+            10_000_100,
+            &tofu_policy(),
+            &responder.public(),
+            &mut VerifierContext::new(&mut NonceReplayCache::default(), &mut trust, &mut ()),
+        )
+        .unwrap();
+        assert_eq!(verified.peer_id, peer_id);
+
+        // Echoing the same challenge again, even with a fresh request nonce, fails.
+        let mut replay = NonceReplayCache::default();
+        let req = challenged(Some(challenge.clone()), b"fedcba9876543210");
+        verify_challenge_response(&req, &responder.public(), 10_000_200, 30_000, &mut replay)
+            .unwrap();
+        assert_eq!(
+            verify_challenge_response(&req, &responder.public(), 10_000_300, 30_000, &mut replay),
+            Err(SessionAuthError::ReplayDetected)
+        );
+
+        let stale = issue_challenge(&responder, 10_000_000).unwrap();
+        let req = challenged(Some(stale), b"0123456789abcdef");
+        assert_eq!(
+            verify_challenge_response(&req, &responder.public(), 10_040_000, 30_000, &mut replay),
+            Err(SessionAuthError::ChallengeExpired)
+        );
+
+        let req = challenged(None, b"0123456789abcdef");
+        assert_eq!(
+            verify_challenge_response(&req, &responder.public(), 10_000_100, 30_000, &mut replay),
+            Err(SessionAuthError::MissingChallenge)
+        );
+
+        let forged = issue_challenge(&key, 10_000_000).unwrap();
+        let req = challenged(Some(forged), b"0123456789abcdef");
+        assert_eq!(
+            verify_challenge_response(&req, &responder.public(), 10_000_100, 30_000, &mut replay),
+            Err(SessionAuthError::InvalidChallenge("bad signature"))
+        );
+
+        let mut tampered = issue_challenge(&responder, 10_000_000).unwrap();
+        tampered.nonce[0] ^= 0xff;
+        let req = challenged(Some(tampered), b"0123456789abcdef");
+        assert_eq!(
+            verify_challenge_response(&req, &responder.public(), 10_000_100, 30_000, &mut replay),
+            Err(SessionAuthError::InvalidChallenge("bad signature"))
+        );
+    }
+
+    #[test]
+    fn untrusted_peer_rejected_when_tofu_disabled() {
+        let key = identity::Keypair::generate_ed25519();
+        let peer_id = PeerId::from(key.public());
+        let req = make_signed_request(&key, "target-a", b"0123456789abcdef", 1_000_000);
+        let mut replay = NonceReplayCache::default();
+        let mut trust = TrustedPeers::default();
+
+        let err = verify_session_request(
+            &req,
+            Some(&peer_id),
+            Some("target-a"),
+            1_000_100,
+            &VerifierPolicy::default(),
+            &mut VerifierContext::new(&mut replay, &mut trust, &mut ()),
+        )
+        .unwrap_err();
+        assert!(matches!(err, SessionAuthError::UntrustedPeer { .. }));
+    }
+
+    #[test]
+    fn session_accept_signature_and_binding_verified() {
+        let key = identity::Keypair::generate_ed25519();
+        let peer_id = PeerId::from(key.public());
+        let req_nonce = b"reqnonce01234567";
+        let accept = make_signed_accept(
+            &key,
+            "session-test",
+            req_nonce,
+            b"resnonce01234567",
+            1_000_000,
+        );
+
+        let mut replay = NonceReplayCache::default();
+        let mut trust = TrustedPeers::default();
+        let verified = verify_session_accept(
+            &accept,
+            Some(&peer_id),
+            Some("session-test"),
+            Some(&[req_nonce.to_vec()]),
+            1_000_100,
+            &tofu_policy(),
+            &mut VerifierContext::new(&mut replay, &mut trust, &mut ()),
+        )
+        .unwrap();
+        assert_eq!(verified.peer_id, peer_id);
+        assert!(verified.trust_store_changed);
+    }
+
+    #[test]
+    fn session_accept_sign_and_verify_success_with_tofu() {
+        let key = identity::Keypair::generate_ed25519();
+        let peer_id = PeerId::from(key.public());
+        let mut replay = NonceReplayCache::default();
+        let mut trust = TrustedPeers::default();
+        let verify = |accept: &SessionAccept,
+                      req_nonce: &[u8],
+                      now: i64,
+                      policy: &VerifierPolicy,
+                      replay: &mut NonceReplayCache,
+                      trust: &mut TrustedPeers| {
+            verify_session_accept(
+                accept,
+                Some(&peer_id),
+                Some("session-test"),
+                Some(&[req_nonce.to_vec()]),
+                now,
+                policy,
+                &mut VerifierContext::new(replay, trust, &mut ()),
+            )
+        };
+
+        let first = make_signed_accept(
+            &key,
+            "session-test",
+            b"reqnonce01234567",
+            b"resnonce01234567",
+            1_000_000,
+        );
+        assert_eq!(
+            verify(
+                &first,
+                b"reqnonce01234567",
+                1_000_100,
+                &VerifierPolicy::default(),
+                &mut replay,
+                &mut trust,
+            ),
+            Err(SessionAuthError::UntrustedPeer {
+                device_code: peer_id.to_string(),
+            })
+        );
+        let mut replay = NonceReplayCache::default();
+        let verified = verify(
+            &first,
+            b"reqnonce01234567",
+            1_000_100,
+            &tofu_policy(),
+            &mut replay,
+            &mut trust,
+        )
+        .unwrap();
+        assert!(verified.trust_store_changed);
+        assert_eq!(trust.len(), 1);
+
+        let second = make_signed_accept(
+            &key,
+            "session-test",
+            b"reqnonce76543210",
+            b"resnonce76543210",
+            1_000_200,
+        );
+        let verified = verify(
+            &second,
+            b"reqnonce76543210",
+            1_000_250,
+            &VerifierPolicy::default(),
+            &mut replay,
+            &mut trust,
+        )
+        .unwrap();
+        assert_eq!(verified.peer_id, peer_id);
+        assert!(!verified.trust_store_changed);
+
+        assert_eq!(
+            verify(
+                &second,
+                b"reqnonce76543210",
+                1_000_300,
+                &VerifierPolicy::default(),
+                &mut replay,
+                &mut trust,
+            ),
+            Err(SessionAuthError::ReplayDetected)
+        );
+
+        let mut unbound =
+            make_signed_accept(&key, "session-test", b"", b"resnonceabcdefgh", 1_000_300);
+        assert_eq!(
+            verify(
+                &unbound,
+                b"reqnonce76543210",
+                1_000_300,
+                &VerifierPolicy::default(),
+                &mut replay,
+                &mut trust,
+            ),
+            Err(SessionAuthError::MissingRequestNonceBinding)
+        );
+
+        // Fields are covered by the signature; only `signature` is stripped.
+        unbound.request_nonce = b"reqnonce76543210".to_vec();
+        assert_eq!(
+            verify(
+                &unbound,
+                b"reqnonce76543210",
+                1_000_300,
+                &VerifierPolicy::default(),
+                &mut replay,
+                &mut trust,
+            ),
+            Err(SessionAuthError::InvalidSignature)
+        );
+
+        // Binding to a request nonce does not excuse a stale accept; only a request
+        // that echoed the responder's challenge does.
+        let late = make_signed_accept(
+            &key,
+            "session-test",
+            b"reqnonce01234567",
+            b"resnonce00000000",
+            1_000_000,
+        );
+        let late_now = 1_000_000 + DEFAULT_ALLOWED_SKEW_MS + 1;
+        assert!(matches!(
+            verify_session_accept(
+                &late,
+                Some(&peer_id),
+                Some("session-test"),
+                None,
+                late_now,
+                &VerifierPolicy::default(),
+                &mut VerifierContext::new(&mut replay, &mut trust, &mut ()),
+            ),
+            Err(SessionAuthError::TimestampSkew { .. })
+        ));
+        assert!(matches!(
+            verify(
+                &late,
+                b"reqnonce01234567",
+                late_now,
+                &VerifierPolicy::default(),
+                &mut replay,
+                &mut trust,
+            ),
+            Err(SessionAuthError::TimestampSkew { .. })
+        ));
+        verify_challenged_session_accept(
+            &late,
+            Some(&peer_id),
+            Some("session-test"),
+            &[b"reqnonce01234567".to_vec()],
+            late_now,
+            &VerifierPolicy::default(),
+            &mut VerifierContext::new(&mut replay, &mut trust, &mut ()),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn session_accept_request_nonce_mismatch_rejected() {
+        let key = identity::Keypair::generate_ed25519();
+        let peer_id = PeerId::from(key.public());
+        let accept = make_signed_accept(
+            &key,
+            "session-test",
+            b"reqnonce01234567",
+            b"resnonce01234567",
+            1_000_000,
+        );
+
+        let mut replay = NonceReplayCache::default();
+        let mut trust = TrustedPeers::default();
+        let mut audit: Vec<AuthEvent> = Vec::new();
+        let err = verify_session_accept(
+            &accept,
+            Some(&peer_id),
+            Some("session-test"),
+            Some(&[b"differentnonce123".to_vec()]),
+            1_000_100,
+            &tofu_policy(),
+            &mut VerifierContext::new(&mut replay, &mut trust, &mut audit),
+        )
         .unwrap_err();
         assert_eq!(err, SessionAuthError::RequestNonceMismatch);
+        assert!(matches!(
+            audit.as_slice(),
+            [AuthEvent::Rejected {
+                message: AuthMessage::SessionAccept,
+                error: SessionAuthError::RequestNonceMismatch,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn resumed_request_skips_trust_policy_with_valid_ticket() {
+        let issuer = identity::Keypair::generate_ed25519();
+        let key = identity::Keypair::generate_ed25519();
+        let peer_id = PeerId::from(key.public());
+        let mut req = make_signed_request(&key, "target-a", b"0123456789abcdef", 1_000_000);
+        req.resumption_ticket = Some(
+            crate::resumption::issue_ticket(
+                &issuer,
+                &peer_id,
+                &peer_id.to_string(),
+                990_000,
+                60_000,
+            )
+            .unwrap(),
+        );
+        sign_session_request(&mut req, &key).unwrap();
+        let mut replay = NonceReplayCache::default();
+        let mut trust = TrustedPeers::default();
+
+        let verified = verify_resumed_session_request(
+            &req,
+            Some(&peer_id),
+            Some("target-a"),
+            1_000_100,
+            &VerifierPolicy::default(),
+            &issuer.public(),
+            &mut VerifierContext::new(&mut replay, &mut trust, &mut ()),
+        )
+        .unwrap();
+        assert_eq!(verified.peer_id, peer_id);
+        assert!(!verified.trust_store_changed);
+    }
+
+    #[test]
+    fn revoked_holder_with_a_valid_ticket_is_refused() {
+        let issuer = identity::Keypair::generate_ed25519();
+        let key = identity::Keypair::generate_ed25519();
+        let peer_id = PeerId::from(key.public());
+        let mut req = make_signed_request(&key, "target-a", b"0123456789abcdef", 1_000_000);
+        req.resumption_ticket = Some(
+            crate::resumption::issue_ticket(
+                &issuer,
+                &peer_id,
+                &peer_id.to_string(),
+                990_000,
+                60_000,
+            )
+            .unwrap(),
+        );
+        sign_session_request(&mut req, &key).unwrap();
+        let mut replay = NonceReplayCache::default();
+        let mut trust = TrustedPeers::default();
+        trust.revoke(&peer_id.to_string());
+
+        let err = verify_resumed_session_request(
+            &req,
+            Some(&peer_id),
+            Some("target-a"),
+            1_000_100,
+            &VerifierPolicy::default(),
+            &issuer.public(),
+            &mut VerifierContext::new(&mut replay, &mut trust, &mut ()),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            SessionAuthError::RevokedPeer {
+                device_code: peer_id.to_string()
+            }
+        );
+        assert!(replay.is_empty());
+    }
+
+    #[test]
+    fn rejected_ticket_leaves_nonce_for_full_verification() {
+        let issuer = identity::Keypair::generate_ed25519();
+        let key = identity::Keypair::generate_ed25519();
+        let peer_id = PeerId::from(key.public());
+        let mut req = make_signed_request(&key, "target-a", b"0123456789abcdef", 1_000_000);
+        req.resumption_ticket = Some(
+            crate::resumption::issue_ticket(&issuer, &peer_id, &peer_id.to_string(), 0, 1_000)
+                .unwrap(),
+        );
+        sign_session_request(&mut req, &key).unwrap();
+        let mut replay = NonceReplayCache::default();
+        let mut trust = TrustedPeers::default();
+
+        let err = verify_resumed_session_request(
+            &req,
+            Some(&peer_id),
+            Some("target-a"),
+            1_000_100,
+            &VerifierPolicy::default(),
+            &issuer.public(),
+            &mut VerifierContext::new(&mut replay, &mut trust, &mut ()),
+        )
+        .unwrap_err();
+        assert_eq!(err, SessionAuthError::ResumptionTicketExpired);
+
+        verify_session_request(
+            &req,
+            Some(&peer_id),
+            Some("target-a"),
+            1_000_100,
+            &tofu_policy(),
+            &mut VerifierContext::new(&mut replay, &mut trust, &mut ()),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn rotate_identity_rebinds_trusted_device() {
+        let old = identity::Keypair::generate_ed25519();
+        let new = identity::Keypair::generate_ed25519();
+        let old_code = PeerId::from(old.public()).to_string();
+        let new_code = PeerId::from(new.public()).to_string();
+        let mut trust = TrustedPeers::default();
+        trust
+            .ensure_trusted(
+                &old_code,
+                &PeerId::from(old.public()),
+                &old.public().encode_protobuf(),
+                1_000,
+                true,
+            )
+            .unwrap();
+
+        let stranger = identity::Keypair::generate_ed25519();
+        let unrelated =
+            crate::rotation::sign_identity_rotation(&stranger, &new, &old_code, &new_code, 2_000)
+                .unwrap();
+        assert_eq!(
+            trust.rotate_identity(&unrelated, 2_000),
+            Err(SessionAuthError::InvalidRotationProof(
+                "old key is not the trusted key"
+            ))
+        );
+
+        let proof =
+            crate::rotation::sign_identity_rotation(&old, &new, &old_code, &new_code, 2_000)
+                .unwrap();
+        assert_eq!(trust.rotate_identity(&proof, 2_000), Ok(new_code.clone()));
+        let records = trust.to_records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].device_code, new_code);
+        assert_eq!(records[0].peer_id, PeerId::from(new.public()).to_string());
+        assert_eq!(records[0].first_seen_unix_ms, 1_000);
+
+        assert!(matches!(
+            trust.rotate_identity(&proof, 3_000),
+            Err(SessionAuthError::UntrustedPeer { .. })
+        ));
+    }
+
+    #[test]
+    fn rotate_identity_refuses_foreign_codes_and_stale_proofs() {
+        let old = identity::Keypair::generate_ed25519();
+        let new = identity::Keypair::generate_ed25519();
+        let old_code = PeerId::from(old.public()).to_string();
+        let new_code = PeerId::from(new.public()).to_string();
+        let mut trust = TrustedPeers::default();
+        trust
+            .ensure_trusted(
+                &old_code,
+                &PeerId::from(old.public()),
+                &old.public().encode_protobuf(),
+                1_000,
+                true,
+            )
+            .unwrap();
+
+        // A trusted key cannot move itself under another device's code.
+        let victim_code = PeerId::random().to_string();
+        let hijack =
+            crate::rotation::sign_identity_rotation(&old, &new, &old_code, &victim_code, 2_000)
+                .unwrap();
+        assert_eq!(
+            trust.rotate_identity(&hijack, 2_000),
+            Err(SessionAuthError::InvalidRotationProof(
+                "new device code is not the new key's peer id"
+            ))
+        );
+
+        let proof =
+            crate::rotation::sign_identity_rotation(&old, &new, &old_code, &new_code, 2_000)
+                .unwrap();
+        assert_eq!(
+            trust.rotate_identity(&proof, 2_000 + ROTATION_PROOF_MAX_AGE_MS + 1),
+            Err(SessionAuthError::InvalidRotationProof("proof is too old"))
+        );
+        assert_eq!(
+            trust.rotate_identity(&proof, 2_000 - DEFAULT_ALLOWED_SKEW_MS - 1),
+            Err(SessionAuthError::InvalidRotationProof(
+                "proof is dated in the future"
+            ))
+        );
+        assert_eq!(trust.peer_ids(&old_code), vec![old_code.clone()]);
+    }
+
+    #[test]
+    fn peer_id_index_follows_every_trust_change() {
+        let old = identity::Keypair::generate_ed25519();
+        let new = identity::Keypair::generate_ed25519();
+        let other = identity::Keypair::generate_ed25519();
+        let old_peer_id = PeerId::from(old.public());
+        let new_peer_id = PeerId::from(new.public());
+        let other_peer_id = PeerId::from(other.public());
+        let mut trust = TrustedPeers::default();
+        assert!(trust.get_by_peer_id(&old_peer_id).is_none());
+
+        trust
+            .ensure_trusted(
+                "device-a",
+                &old_peer_id,
+                &old.public().encode_protobuf(),
+                1_000,
+                true,
+            )
+            .unwrap();
+        trust
+            .ensure_trusted(
+                "device-b",
+                &other_peer_id,
+                &other.public().encode_protobuf(),
+                1_000,
+                true,
+            )
+            .unwrap();
+        let record = trust.get_by_peer_id(&old_peer_id).unwrap();
+        assert_eq!(record.device_code, "device-a");
+        assert_eq!(record.peer_id, old_peer_id.to_string());
+
+        let proof = crate::rotation::sign_identity_rotation(
+            &old,
+            &new,
+            "device-a",
+            &new_peer_id.to_string(),
+            2_000,
+        )
+        .unwrap();
+        trust.rotate_identity(&proof, 2_000).unwrap();
+        assert!(trust.get_by_peer_id(&old_peer_id).is_none());
+        assert_eq!(
+            trust.get_by_peer_id(&new_peer_id).unwrap().device_code,
+            new_peer_id.to_string()
+        );
+
+        let reloaded = TrustedPeers::from_records(trust.to_records()).unwrap();
+        assert_eq!(
+            reloaded.get_by_peer_id(&other_peer_id).unwrap().device_code,
+            "device-b"
+        );
+
+        assert!(trust.revoke_peer_id(&new_peer_id));
+        assert!(trust.get_by_peer_id(&new_peer_id).is_none());
+        assert!(trust.get_by_peer_id(&other_peer_id).is_some());
+
+        assert_eq!(
+            trust.prune_expired(100_000, LAST_SEEN_PERSIST_INTERVAL_MS, &HashSet::new()),
+            1
+        );
+        assert!(trust.get_by_peer_id(&other_peer_id).is_none());
+    }
+
+    #[test]
+    fn constant_time_helpers_match_plain_equality() {
+        assert!(constant_time_eq(b"nonce-a", b"nonce-a"));
+        assert!(!constant_time_eq(b"nonce-a", b"nonce-b"));
+        assert!(!constant_time_eq(b"nonce", b"nonce-a"));
+        assert!(constant_time_eq(b"", b""));
+
+        let attempts = vec![b"first".to_vec(), b"second".to_vec(), b"third".to_vec()];
+        assert!(constant_time_contains(&attempts, b"first"));
+        assert!(constant_time_contains(&attempts, b"third"));
+        assert!(!constant_time_contains(&attempts, b"fourth"));
+        assert!(!constant_time_contains::<Vec<u8>>(&[], b"first"));
+    }
+
+    #[test]
+    fn auth_throttle_locks_out_after_repeated_failures() {
+        let peer = PeerId::random();
+        let other = PeerId::random();
+        let mut throttle = AuthThrottle::new(3, 1_000);
+
+        throttle.record_failure(peer, 0);
+        throttle.record_failure(peer, 100);
+        assert_eq!(throttle.check(&peer, 200), Ok(()));
+        throttle.record_failure(peer, 200);
+        assert_eq!(
+            throttle.check(&peer, 300),
+            Err(SessionAuthError::Throttled {
+                retry_after_ms: 700
+            })
+        );
+        assert_eq!(throttle.check(&other, 300), Ok(()));
+
+        // The oldest failure ages out of the window and one more attempt is allowed.
+        assert_eq!(throttle.check(&peer, 1_000), Ok(()));
+        throttle.record_failure(peer, 1_000);
+        assert_eq!(
+            throttle.check(&peer, 1_050),
+            Err(SessionAuthError::Throttled { retry_after_ms: 50 })
+        );
+
+        throttle.record_success(&peer);
+        assert_eq!(throttle.check(&peer, 1_050), Ok(()));
+        assert!(throttle.is_empty());
+
+        throttle.record_failure(peer, 2_000);
+        throttle.record_failure(other, 2_500);
+        throttle.prune_expired(3_100);
+        assert_eq!(throttle.len(), 1);
+        throttle.prune_expired(3_500);
+        assert!(throttle.is_empty());
+    }
+
+    #[test]
+    fn replay_cache_evicts_by_age() {
+        let mut replay = NonceReplayCache::new(1_000, 16);
+        replay.check_and_store(b"a", 0).unwrap();
+        replay.check_and_store(b"b", 600).unwrap();
+        assert_eq!(replay.len(), 2);
+
+        assert_eq!(
+            replay.check_and_store(b"a", 1_000),
+            Err(SessionAuthError::ReplayDetected)
+        );
+        replay.check_and_store(b"c", 1_001).unwrap();
+        assert_eq!(replay.len(), 2);
+        replay.check_and_store(b"a", 1_002).unwrap();
+    }
+
+    #[test]
+    fn full_replay_cache_evicts_the_oldest_live_entry() {
+        let mut replay = NonceReplayCache::new(DEFAULT_REPLAY_RETENTION_MS, 3);
+        replay.check_and_store(b"n2", 200).unwrap();
+        replay.check_and_store(b"n1", 100).unwrap();
+        replay.check_and_store(b"n3", 300).unwrap();
+
+        replay.check_and_store(b"n4", 400).unwrap();
+        assert_eq!(replay.len(), 3);
+        // n1 carried the oldest timestamp, so it made room; the rest still replay.
+        replay.check_and_store(b"n1", 500).unwrap();
+        for nonce in [b"n3", b"n4"] {
+            assert_eq!(
+                replay.check_and_store(nonce, 500),
+                Err(SessionAuthError::ReplayDetected)
+            );
+        }
+        assert_eq!(replay.len(), 3);
+    }
+
+    #[test]
+    fn replay_cache_stays_bounded_under_a_flood() {
+        let mut replay = NonceReplayCache::new(DEFAULT_REPLAY_RETENTION_MS, 64);
+        for i in 0..10_000_u32 {
+            replay
+                .check_and_store(&i.to_be_bytes(), 1_000 + i64::from(i))
+                .unwrap();
+        }
+        assert_eq!(replay.len(), 64);
+        assert_eq!(replay.by_age.len(), 64);
+    }
+
+    #[test]
+    fn restored_replay_cache_rejects_a_replay_within_retention() {
+        let key = identity::Keypair::generate_ed25519();
+        let peer_id = PeerId::from(key.public());
+        let req = make_signed_request(&key, "target-a", b"0123456789abcdef", 1_000_000);
+        let mut replay = NonceReplayCache::default();
+        let mut trust = TrustedPeers::default();
+        verify_session_request(
+            &req,
+            Some(&peer_id),
+            Some("target-a"),
+            1_000_100,
+            &tofu_policy(),
+            &mut VerifierContext::new(&mut replay, &mut trust, &mut ()),
+        )
+        .unwrap();
+
+        // Simulated restart: the cache goes through its records and back.
+        let mut restored = NonceReplayCache::from_records(
+            DEFAULT_REPLAY_RETENTION_MS,
+            DEFAULT_REPLAY_MAX_ENTRIES,
+            replay.to_records(),
+            1_000_500,
+        );
+        assert_eq!(restored.len(), 1);
+        let err = verify_session_request(
+            &req,
+            Some(&peer_id),
+            Some("target-a"),
+            1_000_600,
+            &tofu_policy(),
+            &mut VerifierContext::new(&mut restored, &mut trust, &mut ()),
+        )
+        .unwrap_err();
+        assert_eq!(err, SessionAuthError::ReplayDetected);
+    }
+
+    #[test]
+    fn replay_cache_load_drops_expired_and_excess_records() {
+        let records = vec![
+            (b"old".to_vec(), 0),
+            (b"mid".to_vec(), 5_000),
+            (b"new".to_vec(), 9_000),
+            (b"newest".to_vec(), 9_500),
+        ];
+        let cache = NonceReplayCache::from_records(6_000, 2, records, 10_000);
+        assert_eq!(
+            cache.to_records(),
+            vec![(b"new".to_vec(), 9_000), (b"newest".to_vec(), 9_500)]
+        );
+
+        let empty = NonceReplayCache::from_records(1_000, 8, Vec::new(), 0);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn device_code_can_be_trusted_under_several_identities() {
+        let home = identity::Keypair::generate_ed25519();
+        let laptop = identity::Keypair::generate_ed25519();
+        let home_id = PeerId::from(home.public());
+        let laptop_id = PeerId::from(laptop.public());
+        let code = "device-a";
+
+        // A single-peer record as written before several identities were allowed.
+        let v1 = format!(
+            r#"[{{"device_code":"{code}","peer_id":"{home_id}","identity_pubkey_hex":"{}","first_seen_unix_ms":1000,"last_seen_unix_ms":1000}}]"#,
+            encode_hex(&home.public().encode_protobuf())
+        );
+        let records: Vec<TrustedPeerRecord> = serde_json::from_str(&v1).unwrap();
+        let mut trust = TrustedPeers::from_records(records).unwrap();
+        assert_eq!(trust.peer_ids(code), vec![home_id.to_string()]);
+
+        // First use does not stretch to a second identity; only approval adds one.
+        let laptop_pubkey = laptop.public().encode_protobuf();
+        for trust_on_first_use in [false, true] {
+            assert!(matches!(
+                trust.ensure_trusted(code, &laptop_id, &laptop_pubkey, 2_000, trust_on_first_use),
+                Err(SessionAuthError::TrustedPeerMismatch { .. })
+            ));
+        }
+        assert_eq!(
+            trust.add_identity(code, &laptop_id, &home.public().encode_protobuf(), 2_000),
+            Err(SessionAuthError::PeerIdMismatch)
+        );
+        assert_eq!(
+            trust.add_identity(code, &laptop_id, &laptop_pubkey, 2_000),
+            Ok(true)
+        );
+        assert_eq!(
+            trust.add_identity(code, &laptop_id, &laptop_pubkey, 2_000),
+            Ok(false)
+        );
+        assert_eq!(
+            trust.approve_peer_id(code, &laptop_id.to_string(), 2_000),
+            Ok(false)
+        );
+        assert_eq!(
+            trust.approve_peer_id(code, &PeerId::random().to_string(), 2_000),
+            Err(SessionAuthError::InvalidSenderPublicKey)
+        );
+        assert_eq!(
+            trust.peer_ids(code),
+            vec![home_id.to_string(), laptop_id.to_string()]
+        );
+        assert_eq!(trust.len(), 1);
+        for (peer_id, key) in [(&home_id, &home), (&laptop_id, &laptop)] {
+            assert!(
+                trust
+                    .ensure_trusted(code, peer_id, &key.public().encode_protobuf(), 2_500, false)
+                    .is_ok()
+            );
+        }
+        // A known peer id cannot bring a different key along, even under TOFU.
+        assert!(matches!(
+            trust.ensure_trusted(
+                code,
+                &laptop_id,
+                &home.public().encode_protobuf(),
+                2_500,
+                true
+            ),
+            Err(SessionAuthError::TrustedPeerMismatch { .. })
+        ));
+
+        let records = trust.to_records();
+        assert_eq!(records.len(), 2);
+        let mut reloaded = TrustedPeers::from_records(records.clone()).unwrap();
+        assert_eq!(reloaded.to_records(), records);
+
+        // Pruning drops the stale identity and keeps the device.
+        assert_eq!(
+            reloaded.prune_expired(2_000 + LAST_SEEN_PERSIST_INTERVAL_MS, 0, &HashSet::new()),
+            1
+        );
+        assert_eq!(reloaded.peer_ids(code), vec![laptop_id.to_string()]);
+    }
+
+    #[test]
+    fn prune_expired_drops_only_stale_peers() {
+        let day_ms = 86_400_000;
+        let stale = identity::Keypair::generate_ed25519();
+        let active = identity::Keypair::generate_ed25519();
+        let mut trust = TrustedPeers::default();
+        for (key, code) in [(&stale, "stale"), (&active, "active")] {
+            trust
+                .ensure_trusted(
+                    code,
+                    &PeerId::from(key.public()),
+                    &key.public().encode_protobuf(),
+                    0,
+                    true,
+                )
+                .unwrap();
+        }
+
+        // The active peer keeps connecting, which bumps its last-seen time.
+        let changed = trust
+            .ensure_trusted(
+                "active",
+                &PeerId::from(active.public()),
+                &active.public().encode_protobuf(),
+                20 * day_ms,
+                false,
+            )
+            .unwrap();
+        assert!(changed);
+
+        let none = HashSet::new();
+        assert_eq!(trust.prune_expired(10 * day_ms, 30 * day_ms, &none), 0);
+        // A peer still in session stays, however long ago it authenticated.
+        let connected = HashSet::from([PeerId::from(stale.public())]);
+        assert_eq!(trust.prune_expired(31 * day_ms, 30 * day_ms, &connected), 0);
+        assert_eq!(trust.prune_expired(31 * day_ms, 30 * day_ms, &none), 1);
+        let codes: Vec<_> = trust
+            .to_records()
+            .into_iter()
+            .map(|r| r.device_code)
+            .collect();
+        assert_eq!(codes, vec!["active".to_string()]);
+    }
+
+    #[test]
+    fn prune_expired_never_uses_an_age_below_the_last_seen_interval() {
+        let key = identity::Keypair::generate_ed25519();
+        let mut trust = TrustedPeers::default();
+        trust
+            .ensure_trusted(
+                "dev",
+                &PeerId::from(key.public()),
+                &key.public().encode_protobuf(),
+                0,
+                true,
+            )
+            .unwrap();
+        let none = HashSet::new();
+        assert_eq!(
+            trust.prune_expired(LAST_SEEN_PERSIST_INTERVAL_MS, 1, &none),
+            0
+        );
+        assert_eq!(
+            trust.prune_expired(LAST_SEEN_PERSIST_INTERVAL_MS + 1, 1, &none),
+            1
+        );
+        assert!(trust.is_empty());
+    }
+
+    #[test]
+    fn revoked_device_is_refused_even_with_tofu() {
+        let key = identity::Keypair::generate_ed25519();
+        let peer_id = PeerId::from(key.public());
+        let pubkey = key.public().encode_protobuf();
+        let mut trust = TrustedPeers::default();
+        trust
+            .ensure_trusted("dev", &peer_id, &pubkey, 0, true)
+            .unwrap();
+
+        assert!(trust.revoke_peer_id(&peer_id));
+        assert!(trust.is_empty());
+        assert_eq!(
+            trust.ensure_trusted("dev", &peer_id, &pubkey, 1, true),
+            Err(SessionAuthError::RevokedPeer {
+                device_code: "dev".to_string()
+            })
+        );
+        // Nothing left to remove, but the revocation still holds.
+        assert!(!trust.revoke("dev"));
+        assert!(!trust.revoke_peer_id(&peer_id));
+        assert_eq!(trust.revoked_device_codes(), vec!["dev".to_string()]);
+
+        assert!(trust.clear_revocation("dev"));
+        assert!(
+            trust
+                .ensure_trusted("dev", &peer_id, &pubkey, 2, true)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn persisted_revocations_survive_a_reload() {
+        let key = identity::Keypair::generate_ed25519();
+        let peer_id = PeerId::from(key.public());
+        let mut trust = TrustedPeers::default();
+        trust
+            .ensure_trusted("dev", &peer_id, &key.public().encode_protobuf(), 0, true)
+            .unwrap();
+        let records = trust.to_records();
+
+        // A record saved before the revocation is dropped again on load.
+        let reloaded = TrustedPeers::from_records(records)
+            .unwrap()
+            .with_revoked(vec!["dev".to_string(), "  ".to_string()]);
+        assert!(reloaded.is_empty());
+        assert_eq!(reloaded.revoked_device_codes(), vec!["dev".to_string()]);
     }
 }
@@ -0,0 +1,87 @@
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+pub const SESSION_KEY_LEN: usize = 32;
+
+const SESSION_KEY_SALT: &[u8] = b"aetherlink/v1/session-key";
+const SESSION_KEY_INFO: &[u8] = b"aetherlink/v1/data-plane";
+
+/// Derives the data-plane key for one session from the handshake transcript.
+///
+/// `local_pub`/`remote_pub` are protobuf-encoded identity public keys and are ordered
+/// before hashing, so requester and acceptor get the same key. The nonces keep their
+/// roles: `req_nonce` is always the SessionRequest nonce, `accept_nonce` the
+/// SessionAccept nonce. Secrecy rests on the nonces, which only travel over the
+/// encrypted control channel.
+pub fn derive_session_key(
+    local_pub: &[u8],
+    remote_pub: &[u8],
+    req_nonce: &[u8],
+    accept_nonce: &[u8],
+) -> [u8; SESSION_KEY_LEN] {
+    let (first_pub, second_pub) = if local_pub <= remote_pub {
+        (local_pub, remote_pub)
+    } else {
+        (remote_pub, local_pub)
+    };
+
+    let mut ikm = Vec::with_capacity(
+        16 + first_pub.len() + second_pub.len() + req_nonce.len() + accept_nonce.len(),
+    );
+    for part in [first_pub, second_pub, req_nonce, accept_nonce] {
+        // Length prefixes keep (ab, c) and (a, bc) from hashing to the same input.
+        ikm.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        ikm.extend_from_slice(part);
+    }
+
+    let mut key = [0u8; SESSION_KEY_LEN];
+    Hkdf::<Sha256>::new(Some(SESSION_KEY_SALT), &ikm)
+        .expand(SESSION_KEY_INFO, &mut key)
+        .expect("32 bytes is within the HKDF-SHA256 output limit");
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::identity;
+
+    #[test]
+    fn both_peers_derive_the_same_key() {
+        let requester = identity::Keypair::generate_ed25519()
+            .public()
+            .encode_protobuf();
+        let acceptor = identity::Keypair::generate_ed25519()
+            .public()
+            .encode_protobuf();
+        let req_nonce = [1u8; 16];
+        let accept_nonce = [2u8; 16];
+
+        let at_requester = derive_session_key(&requester, &acceptor, &req_nonce, &accept_nonce);
+        let at_acceptor = derive_session_key(&acceptor, &requester, &req_nonce, &accept_nonce);
+        assert_eq!(at_requester, at_acceptor);
+        assert_ne!(at_requester, [0u8; SESSION_KEY_LEN]);
+    }
+
+    #[test]
+    fn different_nonces_produce_different_keys() {
+        let a = identity::Keypair::generate_ed25519()
+            .public()
+            .encode_protobuf();
+        let b = identity::Keypair::generate_ed25519()
+            .public()
+            .encode_protobuf();
+        let base = derive_session_key(&a, &b, &[1u8; 16], &[2u8; 16]);
+
+        assert_ne!(base, derive_session_key(&a, &b, &[3u8; 16], &[2u8; 16]));
+        assert_ne!(base, derive_session_key(&a, &b, &[1u8; 16], &[3u8; 16]));
+        // Nonce roles are not interchangeable.
+        assert_ne!(base, derive_session_key(&a, &b, &[2u8; 16], &[1u8; 16]));
+    }
+
+    #[test]
+    fn field_boundaries_are_unambiguous() {
+        let key = derive_session_key(b"pub-a", b"pub-b", b"ab", b"c");
+        assert_ne!(key, derive_session_key(b"pub-a", b"pub-b", b"a", b"bc"));
+    }
+}
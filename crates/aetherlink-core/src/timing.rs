@@ -0,0 +1,209 @@
+use thiserror::Error;
+
+use crate::TimingProfile;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum TimingProfileError {
+    #[error("{field} must be non-zero")]
+    Zero { field: &'static str },
+    #[error("reconnect backoff start {start_ms} ms exceeds the {max_ms} ms maximum")]
+    BackoffStartAboveMax { start_ms: u64, max_ms: u64 },
+    #[error("reconnect backoff jitter of {0}% is over 100%")]
+    JitterOutOfRange(u32),
+}
+
+impl TimingProfile {
+    pub fn builder() -> TimingProfileBuilder {
+        TimingProfileBuilder::default()
+    }
+
+    /// Checks the invariants the state machine relies on: every timeout, interval and
+    /// budget is non-zero, the backoff starts at or below its maximum and the jitter
+    /// stays within 100%.
+    pub fn validate(&self) -> Result<(), TimingProfileError> {
+        let non_zero = [
+            ("discovery_timeout_ms", self.discovery_timeout_ms),
+            ("direct_dial_budget_ms", self.direct_dial_budget_ms),
+            ("punch_budget_ms", self.punch_budget_ms),
+            ("relay_dial_timeout_ms", self.relay_dial_timeout_ms),
+            ("handshake_timeout_ms", self.handshake_timeout_ms),
+            ("ping_interval_ms", self.ping_interval_ms),
+            ("path_lost_threshold", u64::from(self.path_lost_threshold)),
+            ("reconnect_budget_ms", self.reconnect_budget_ms),
+            (
+                "reconnect_backoff_start_ms",
+                self.reconnect_backoff_start_ms,
+            ),
+        ];
+        if let Some((field, _)) = non_zero.into_iter().find(|(_, value)| *value == 0) {
+            return Err(TimingProfileError::Zero { field });
+        }
+        if self.reconnect_backoff_start_ms > self.reconnect_backoff_max_ms {
+            return Err(TimingProfileError::BackoffStartAboveMax {
+                start_ms: self.reconnect_backoff_start_ms,
+                max_ms: self.reconnect_backoff_max_ms,
+            });
+        }
+        if self.reconnect_backoff_jitter_pct > 100 {
+            return Err(TimingProfileError::JitterOutOfRange(
+                self.reconnect_backoff_jitter_pct,
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Builds a `TimingProfile` from the defaults, validating it in `build`.
+#[derive(Debug, Clone, Default)]
+pub struct TimingProfileBuilder {
+    profile: TimingProfile,
+}
+
+impl TimingProfileBuilder {
+    pub fn discovery_timeout_ms(mut self, ms: u64) -> Self {
+        self.profile.discovery_timeout_ms = ms;
+        self
+    }
+
+    pub fn direct_dial_budget_ms(mut self, ms: u64) -> Self {
+        self.profile.direct_dial_budget_ms = ms;
+        self
+    }
+
+    pub fn punch_budget_ms(mut self, ms: u64) -> Self {
+        self.profile.punch_budget_ms = ms;
+        self
+    }
+
+    pub fn relay_dial_timeout_ms(mut self, ms: u64) -> Self {
+        self.profile.relay_dial_timeout_ms = ms;
+        self
+    }
+
+    pub fn handshake_timeout_ms(mut self, ms: u64) -> Self {
+        self.profile.handshake_timeout_ms = ms;
+        self
+    }
+
+    pub fn ping_interval_ms(mut self, ms: u64) -> Self {
+        self.profile.ping_interval_ms = ms;
+        self
+    }
+
+    pub fn path_lost_threshold(mut self, misses: u32) -> Self {
+        self.profile.path_lost_threshold = misses;
+        self
+    }
+
+    pub fn reconnect_budget_ms(mut self, ms: u64) -> Self {
+        self.profile.reconnect_budget_ms = ms;
+        self
+    }
+
+    pub fn reconnect_max_attempts(mut self, attempts: u32) -> Self {
+        self.profile.reconnect_max_attempts = attempts;
+        self
+    }
+
+    pub fn reconnect_backoff_ms(mut self, start_ms: u64, max_ms: u64) -> Self {
+        self.profile.reconnect_backoff_start_ms = start_ms;
+        self.profile.reconnect_backoff_max_ms = max_ms;
+        self
+    }
+
+    pub fn reconnect_backoff_jitter_pct(mut self, pct: u32) -> Self {
+        self.profile.reconnect_backoff_jitter_pct = pct;
+        self
+    }
+
+    pub fn auth_failure_retryable(mut self, retryable: bool) -> Self {
+        self.profile.auth_failure_retryable = retryable;
+        self
+    }
+
+    pub fn build(self) -> Result<TimingProfile, TimingProfileError> {
+        self.profile.validate()?;
+        Ok(self.profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TimingProfileBuilder, TimingProfileError};
+    use crate::{ConnectionStateMachine, TimingProfile};
+
+    #[test]
+    fn defaults_are_valid_and_match_the_builder() {
+        assert_eq!(TimingProfile::default().validate(), Ok(()));
+        assert_eq!(
+            TimingProfile::builder().build(),
+            Ok(TimingProfile::default())
+        );
+    }
+
+    #[test]
+    fn builder_sets_fields() {
+        let profile = TimingProfileBuilder::default()
+            .ping_interval_ms(500)
+            .reconnect_backoff_ms(100, 400)
+            .reconnect_max_attempts(5)
+            .auth_failure_retryable(true)
+            .build()
+            .unwrap();
+        assert_eq!(profile.ping_interval_ms, 500);
+        assert_eq!(profile.reconnect_backoff_start_ms, 100);
+        assert_eq!(profile.reconnect_backoff_max_ms, 400);
+        assert_eq!(profile.reconnect_max_attempts, 5);
+        assert!(profile.auth_failure_retryable);
+        assert_eq!(
+            profile.discovery_timeout_ms,
+            TimingProfile::default().discovery_timeout_ms
+        );
+    }
+
+    #[test]
+    fn invalid_profiles_are_rejected() {
+        assert_eq!(
+            TimingProfile::builder().ping_interval_ms(0).build(),
+            Err(TimingProfileError::Zero {
+                field: "ping_interval_ms"
+            })
+        );
+        assert_eq!(
+            TimingProfile::builder().path_lost_threshold(0).build(),
+            Err(TimingProfileError::Zero {
+                field: "path_lost_threshold"
+            })
+        );
+        assert_eq!(
+            TimingProfile::builder()
+                .reconnect_backoff_ms(3_000, 2_000)
+                .build(),
+            Err(TimingProfileError::BackoffStartAboveMax {
+                start_ms: 3_000,
+                max_ms: 2_000
+            })
+        );
+        assert_eq!(
+            TimingProfile::builder()
+                .reconnect_backoff_jitter_pct(150)
+                .build(),
+            Err(TimingProfileError::JitterOutOfRange(150))
+        );
+    }
+
+    #[test]
+    fn try_new_rejects_an_invalid_profile() {
+        let bad = TimingProfile {
+            handshake_timeout_ms: 0,
+            ..TimingProfile::default()
+        };
+        assert_eq!(
+            ConnectionStateMachine::try_new(bad).unwrap_err(),
+            TimingProfileError::Zero {
+                field: "handshake_timeout_ms"
+            }
+        );
+        assert!(ConnectionStateMachine::try_new(TimingProfile::default()).is_ok());
+    }
+}
@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+
+use crate::security::{SessionAuthError, TrustedPeerRecord, TrustedPeers};
+
+/// Layout written by `TrustedPeers::to_store`. Version 1 held at most one record per
+/// device code; version 2 allows several, one per trusted identity.
+pub const TRUST_STORE_VERSION: u32 = 2;
+
+/// The trust store as persisted by the node and read by the daemon.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrustStoreFile {
+    pub version: u32,
+    pub peers: Vec<TrustedPeerRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub revoked: Vec<String>,
+}
+
+impl Default for TrustStoreFile {
+    fn default() -> Self {
+        Self {
+            version: TRUST_STORE_VERSION,
+            peers: Vec::new(),
+            revoked: Vec::new(),
+        }
+    }
+}
+
+impl TrustedPeers {
+    /// Loads a persisted trust store, upgrading older layouts. A version newer than
+    /// this build understands is refused rather than loaded with peers missing.
+    pub fn from_store(file: TrustStoreFile) -> Result<Self, SessionAuthError> {
+        let records = match file.version {
+            1 => migrate_v1(file.peers),
+            TRUST_STORE_VERSION => file.peers,
+            version => {
+                return Err(SessionAuthError::TrustStoreCorrupt(format!(
+                    "unsupported trust store version {version} (this build reads up to {TRUST_STORE_VERSION})"
+                )));
+            }
+        };
+        Ok(Self::from_records(records)?.with_revoked(file.revoked))
+    }
+
+    pub fn to_store(&self) -> TrustStoreFile {
+        TrustStoreFile {
+            version: TRUST_STORE_VERSION,
+            peers: self.to_records(),
+            revoked: self.revoked_device_codes(),
+        }
+    }
+}
+
+/// Version 1 pinned one identity per device code, so a repeated code is a later
+/// record replacing an earlier one rather than a second identity.
+fn migrate_v1(records: Vec<TrustedPeerRecord>) -> Vec<TrustedPeerRecord> {
+    let mut migrated: Vec<TrustedPeerRecord> = Vec::with_capacity(records.len());
+    for record in records {
+        migrated.retain(|existing| existing.device_code != record.device_code);
+        migrated.push(record);
+    }
+    migrated
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::{PeerId, identity};
+
+    use super::*;
+
+    fn record(device_code: &str, key: &identity::Keypair, seen_unix_ms: i64) -> TrustedPeerRecord {
+        TrustedPeerRecord {
+            device_code: device_code.to_string(),
+            peer_id: PeerId::from(key.public()).to_string(),
+            identity_pubkey_hex: key
+                .public()
+                .encode_protobuf()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect(),
+            first_seen_unix_ms: seen_unix_ms,
+            last_seen_unix_ms: seen_unix_ms,
+        }
+    }
+
+    #[test]
+    fn v1_store_is_upgraded_on_load() {
+        let old = identity::Keypair::generate_ed25519();
+        let new = identity::Keypair::generate_ed25519();
+        let v1 = format!(
+            r#"{{"version":1,"peers":[{},{}]}}"#,
+            serde_json::to_string(&record("device-a", &old, 1)).unwrap(),
+            serde_json::to_string(&record("device-a", &new, 2)).unwrap(),
+        );
+        let file: TrustStoreFile = serde_json::from_str(&v1).unwrap();
+        let trusted = TrustedPeers::from_store(file).unwrap();
+        assert_eq!(
+            trusted.peer_ids("device-a"),
+            vec![PeerId::from(new.public()).to_string()]
+        );
+
+        let stored = trusted.to_store();
+        assert_eq!(stored.version, TRUST_STORE_VERSION);
+        assert_eq!(
+            TrustedPeers::from_store(stored.clone()).unwrap().to_store(),
+            stored
+        );
+    }
+
+    #[test]
+    fn current_store_keeps_every_identity_and_revocation() {
+        let home = identity::Keypair::generate_ed25519();
+        let laptop = identity::Keypair::generate_ed25519();
+        let file = TrustStoreFile {
+            version: TRUST_STORE_VERSION,
+            peers: vec![record("device-a", &home, 1), record("device-a", &laptop, 2)],
+            revoked: vec!["device-b".to_string()],
+        };
+        let trusted = TrustedPeers::from_store(file.clone()).unwrap();
+        assert_eq!(trusted.peer_ids("device-a").len(), 2);
+        assert!(trusted.is_revoked("device-b"));
+        assert_eq!(trusted.to_store(), file);
+    }
+
+    #[test]
+    fn unknown_versions_are_refused() {
+        let key = identity::Keypair::generate_ed25519();
+        for version in [0, TRUST_STORE_VERSION + 1] {
+            let file = TrustStoreFile {
+                version,
+                peers: vec![record("device-a", &key, 1)],
+                revoked: Vec::new(),
+            };
+            assert!(matches!(
+                TrustedPeers::from_store(file),
+                Err(SessionAuthError::TrustStoreCorrupt(_))
+            ));
+        }
+    }
+}
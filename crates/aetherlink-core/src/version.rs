@@ -0,0 +1,92 @@
+use aetherlink_proto::v1::ProtocolVersion;
+
+pub const PROTOCOL_MAJOR: u32 = 1;
+pub const PROTOCOL_MINOR: u32 = 0;
+pub const PROTOCOL_PATCH: u32 = 0;
+
+/// How a peer's protocol version relates to ours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compatibility {
+    /// Same major and the peer's minor is not newer than ours.
+    Compatible,
+    /// Same major, but the peer speaks a newer minor; it must fall back to our feature
+    /// set, so the session can go ahead.
+    MinorAhead,
+    /// Different major: the wire format or semantics may differ, so refuse.
+    Incompatible,
+}
+
+impl Compatibility {
+    pub fn is_usable(self) -> bool {
+        self != Compatibility::Incompatible
+    }
+}
+
+/// The version this build speaks, as sent in `SessionRequest` and `SessionAccept`.
+pub fn local_protocol_version() -> ProtocolVersion {
+    ProtocolVersion {
+        major: PROTOCOL_MAJOR,
+        minor: PROTOCOL_MINOR,
+        patch: PROTOCOL_PATCH,
+    }
+}
+
+/// Semver-style rule shared by the session request and accept paths: majors must
+/// match, a newer remote minor is tolerated and patch levels are ignored.
+pub fn is_compatible(local: &ProtocolVersion, remote: &ProtocolVersion) -> Compatibility {
+    if local.major != remote.major {
+        Compatibility::Incompatible
+    } else if remote.minor > local.minor {
+        Compatibility::MinorAhead
+    } else {
+        Compatibility::Compatible
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(major: u32, minor: u32, patch: u32) -> ProtocolVersion {
+        ProtocolVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    #[test]
+    fn same_major_is_usable_whatever_the_minor() {
+        let local = version(1, 2, 0);
+        assert_eq!(
+            is_compatible(&local, &version(1, 2, 7)),
+            Compatibility::Compatible
+        );
+        assert_eq!(
+            is_compatible(&local, &version(1, 0, 0)),
+            Compatibility::Compatible
+        );
+        assert_eq!(
+            is_compatible(&local, &version(1, 3, 0)),
+            Compatibility::MinorAhead
+        );
+        assert!(Compatibility::MinorAhead.is_usable());
+    }
+
+    #[test]
+    fn different_major_is_incompatible() {
+        let local = version(1, 0, 0);
+        for remote in [version(0, 0, 0), version(2, 0, 0), version(2, 5, 1)] {
+            let compatibility = is_compatible(&local, &remote);
+            assert_eq!(compatibility, Compatibility::Incompatible);
+            assert!(!compatibility.is_usable());
+        }
+    }
+
+    #[test]
+    fn local_version_is_compatible_with_itself() {
+        let local = local_protocol_version();
+        assert_eq!(local.major, PROTOCOL_MAJOR);
+        assert_eq!(is_compatible(&local, &local), Compatibility::Compatible);
+    }
+}
@@ -1,6 +1,6 @@
 #![forbid(unsafe_code)]
 
-use aetherlink_proto::v1::{InputEvent, input_event};
+use aetherlink_proto::v1::{InputEvent, KeyEvent, MouseEvent, TouchEvent, WheelEvent, input_event};
 use thiserror::Error;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -74,6 +74,52 @@ pub fn normalize_input_event(event: &InputEvent) -> Result<InputCommand, InputEr
     }
 }
 
+/// The wire form of a normalized command; fields the command does not carry are left
+/// at their defaults.
+impl From<InputCommand> for input_event::Payload {
+    fn from(command: InputCommand) -> Self {
+        match command {
+            InputCommand::Mouse { x, y, buttons_mask } => Self::Mouse(MouseEvent {
+                x,
+                y,
+                buttons_mask,
+                ..MouseEvent::default()
+            }),
+            InputCommand::Wheel { delta_x, delta_y } => {
+                Self::Wheel(WheelEvent { delta_x, delta_y })
+            }
+            InputCommand::Key {
+                key_code,
+                down,
+                up,
+                repeat,
+                modifiers_mask,
+            } => Self::Key(KeyEvent {
+                key_code,
+                down,
+                up,
+                repeat,
+                modifiers_mask,
+            }),
+            InputCommand::Touch {
+                pointer_id,
+                x,
+                y,
+                down,
+                move_,
+                up,
+            } => Self::Touch(TouchEvent {
+                pointer_id,
+                x,
+                y,
+                down,
+                r#move: move_,
+                up,
+            }),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
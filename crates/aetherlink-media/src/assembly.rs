@@ -0,0 +1,209 @@
+use std::collections::BTreeMap;
+
+use crate::MediaError;
+
+/// Incomplete frames kept by default; the oldest is dropped to make room.
+pub const DEFAULT_MAX_PENDING_FRAMES: usize = 8;
+/// Largest number of chunks one frame may be split into.
+pub const MAX_CHUNKS_PER_FRAME: u32 = 1024;
+/// Largest frame reassembled; a frame growing past it is dropped.
+pub const MAX_ASSEMBLED_FRAME_BYTES: usize = 8 * 1024 * 1024;
+
+/// One chunk of an encoded frame as it arrives from the data plane.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameChunk {
+    pub frame_id: u64,
+    pub chunk_id: u32,
+    pub chunk_count: u32,
+    pub pts_us: u64,
+    pub keyframe: bool,
+    pub payload: Vec<u8>,
+}
+
+/// An encoded frame put back together from all of its chunks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembledFrame {
+    pub frame_id: u64,
+    pub pts_us: u64,
+    pub keyframe: bool,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct PendingFrame {
+    chunk_count: u32,
+    pts_us: u64,
+    keyframe: bool,
+    bytes: usize,
+    chunks: BTreeMap<u32, Vec<u8>>,
+}
+
+/// Reassembles chunked frames. Chunks may arrive in any order and repeats are
+/// ignored; a frame is handed out once every chunk is in. At most `max_pending`
+/// incomplete frames are held, the lowest frame id going first, so a lost chunk
+/// costs one frame rather than memory.
+#[derive(Debug)]
+pub struct FrameAssembler {
+    max_pending: usize,
+    pending: BTreeMap<u64, PendingFrame>,
+    /// Frames at or below this id were completed or dropped; late chunks are ignored.
+    done_through: Option<u64>,
+}
+
+impl Default for FrameAssembler {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_PENDING_FRAMES)
+    }
+}
+
+impl FrameAssembler {
+    pub fn new(max_pending: usize) -> Self {
+        Self {
+            max_pending: max_pending.max(1),
+            pending: BTreeMap::new(),
+            done_through: None,
+        }
+    }
+
+    /// Adds `chunk` and returns its frame when this chunk completed it.
+    pub fn push(&mut self, chunk: FrameChunk) -> Result<Option<AssembledFrame>, MediaError> {
+        if chunk.chunk_count == 0
+            || chunk.chunk_count > MAX_CHUNKS_PER_FRAME
+            || chunk.chunk_id >= chunk.chunk_count
+        {
+            return Err(MediaError::InvalidChunk);
+        }
+        if self.done_through.is_some_and(|done| chunk.frame_id <= done) {
+            return Ok(None);
+        }
+        let pending = self
+            .pending
+            .entry(chunk.frame_id)
+            .or_insert_with(|| PendingFrame {
+                chunk_count: chunk.chunk_count,
+                pts_us: chunk.pts_us,
+                keyframe: chunk.keyframe,
+                bytes: 0,
+                chunks: BTreeMap::new(),
+            });
+        if pending.chunk_count != chunk.chunk_count {
+            return Err(MediaError::InvalidChunk);
+        }
+        pending.keyframe |= chunk.keyframe;
+        if !pending.chunks.contains_key(&chunk.chunk_id) {
+            pending.bytes += chunk.payload.len();
+            if pending.bytes > MAX_ASSEMBLED_FRAME_BYTES {
+                self.pending.remove(&chunk.frame_id);
+                self.finish_through(chunk.frame_id);
+                return Err(MediaError::FrameTooLarge);
+            }
+            pending.chunks.insert(chunk.chunk_id, chunk.payload);
+        }
+
+        let complete = pending.chunks.len() == pending.chunk_count as usize;
+        if complete && let Some(frame) = self.pending.remove(&chunk.frame_id) {
+            self.finish_through(chunk.frame_id);
+            return Ok(Some(AssembledFrame {
+                frame_id: chunk.frame_id,
+                pts_us: frame.pts_us,
+                keyframe: frame.keyframe,
+                data: frame.chunks.into_values().flatten().collect(),
+            }));
+        }
+        while self.pending.len() > self.max_pending {
+            if let Some((frame_id, _)) = self.pending.pop_first() {
+                self.finish_through(frame_id);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Incomplete frames currently held.
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Marks frames up to `frame_id` as done and drops any older incomplete ones,
+    /// which can no longer be played in order.
+    fn finish_through(&mut self, frame_id: u64) {
+        self.done_through = Some(
+            self.done_through
+                .map_or(frame_id, |done| done.max(frame_id)),
+        );
+        self.pending.retain(|&id, _| id > frame_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(frame_id: u64, chunk_id: u32, chunk_count: u32, payload: &[u8]) -> FrameChunk {
+        FrameChunk {
+            frame_id,
+            chunk_id,
+            chunk_count,
+            pts_us: frame_id * 33_333,
+            keyframe: frame_id == 0,
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn chunks_in_any_order_make_one_frame() {
+        let mut assembler = FrameAssembler::default();
+        assert_eq!(assembler.push(chunk(0, 2, 3, b"ef")).unwrap(), None);
+        assert_eq!(assembler.push(chunk(0, 0, 3, b"ab")).unwrap(), None);
+        assert_eq!(assembler.push(chunk(0, 0, 3, b"xx")).unwrap(), None);
+        let frame = assembler.push(chunk(0, 1, 3, b"cd")).unwrap().unwrap();
+        assert_eq!(frame.data, b"abcdef");
+        assert!(frame.keyframe);
+        assert_eq!(assembler.pending_len(), 0);
+
+        // A late repeat of a finished frame does not start it again.
+        assert_eq!(assembler.push(chunk(0, 0, 3, b"ab")).unwrap(), None);
+        assert_eq!(assembler.pending_len(), 0);
+    }
+
+    #[test]
+    fn malformed_chunks_are_refused() {
+        let mut assembler = FrameAssembler::default();
+        assert_eq!(
+            assembler.push(chunk(1, 0, 0, b"a")),
+            Err(MediaError::InvalidChunk)
+        );
+        assert_eq!(
+            assembler.push(chunk(1, 2, 2, b"a")),
+            Err(MediaError::InvalidChunk)
+        );
+        assembler.push(chunk(1, 0, 2, b"a")).unwrap();
+        assert_eq!(
+            assembler.push(chunk(1, 1, 3, b"b")),
+            Err(MediaError::InvalidChunk)
+        );
+
+        let big = vec![0_u8; MAX_ASSEMBLED_FRAME_BYTES / 2 + 1];
+        assembler.push(chunk(2, 0, 3, &big)).unwrap();
+        assert_eq!(
+            assembler.push(chunk(2, 1, 3, &big)),
+            Err(MediaError::FrameTooLarge)
+        );
+        assert_eq!(assembler.push(chunk(2, 2, 3, b"c")).unwrap(), None);
+    }
+
+    #[test]
+    fn incomplete_frames_are_bounded_and_superseded() {
+        let mut assembler = FrameAssembler::new(2);
+        for frame_id in 1..=3 {
+            assembler.push(chunk(frame_id, 0, 2, b"a")).unwrap();
+        }
+        assert_eq!(assembler.pending_len(), 2);
+        // Frame 1 was dropped to make room, so its last chunk no longer completes it.
+        assert_eq!(assembler.push(chunk(1, 1, 2, b"b")).unwrap(), None);
+
+        // Completing frame 3 gives up on frame 2, which can no longer play in order.
+        let frame = assembler.push(chunk(3, 1, 2, b"b")).unwrap().unwrap();
+        assert_eq!(frame.frame_id, 3);
+        assert_eq!(assembler.pending_len(), 0);
+    }
+}
@@ -1,5 +1,14 @@
 #![forbid(unsafe_code)]
 
+pub mod assembly;
+pub mod recording;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+pub use assembly::{AssembledFrame, FrameAssembler, FrameChunk};
+pub use recording::{RecordingState, RecordingWriter, VideoCodec, recording_header};
+#[cfg(any(test, feature = "sim"))]
+pub use sim::{LinkPhase, NetworkSim};
+
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -47,6 +56,16 @@ impl Default for BitrateLimits {
 pub enum MediaError {
     #[error("invalid bitrate limits")]
     InvalidLimits,
+    #[error("recording already started")]
+    RecordingAlreadyStarted,
+    #[error("recording is not active")]
+    RecordingNotActive,
+    #[error("frame exceeds the recording frame size limit")]
+    FrameTooLarge,
+    #[error("frame chunk has an invalid index or count")]
+    InvalidChunk,
+    #[error("recording io error: {0}")]
+    Io(String),
 }
 
 pub fn adaptive_bitrate_step(
@@ -66,6 +85,51 @@ pub fn adaptive_bitrate_step(
     Ok(next.clamp(limits.floor_kbps, limits.ceil_kbps))
 }
 
+/// Fits `profile` inside `max`. Resolution is scaled down keeping the aspect ratio and
+/// rounded to even dimensions, as encoders require; fps and bitrate are capped.
+pub fn clamp_profile(profile: VideoProfile, max: VideoProfile) -> VideoProfile {
+    let (mut width, mut height) = (profile.width, profile.height);
+    if width > max.width || height > max.height {
+        // Whichever side overshoots more sets the scale.
+        if u64::from(width) * u64::from(max.height) > u64::from(height) * u64::from(max.width) {
+            height = (u64::from(height) * u64::from(max.width) / u64::from(width)) as u32;
+            width = max.width;
+        } else {
+            width = (u64::from(width) * u64::from(max.height) / u64::from(height)) as u32;
+            height = max.height;
+        }
+    }
+    VideoProfile {
+        width: (width & !1).max(2),
+        height: (height & !1).max(2),
+        fps: profile.fps.min(max.fps),
+        bitrate_kbps: profile.bitrate_kbps.min(max.bitrate_kbps),
+    }
+}
+
+/// Profile a host answers with when its peer proposes `proposed` mid-session. Zero
+/// fields carry no preference and take the host's maximum.
+pub fn agree_profile(proposed: VideoProfile, host_max: VideoProfile) -> VideoProfile {
+    let or_max = |value: u32, max: u32| if value == 0 { max } else { value };
+    let proposed = if proposed.width == 0 || proposed.height == 0 {
+        VideoProfile {
+            width: host_max.width,
+            height: host_max.height,
+            ..proposed
+        }
+    } else {
+        proposed
+    };
+    clamp_profile(
+        VideoProfile {
+            fps: or_max(proposed.fps, host_max.fps),
+            bitrate_kbps: or_max(proposed.bitrate_kbps, host_max.bitrate_kbps),
+            ..proposed
+        },
+        host_max,
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -84,6 +148,51 @@ mod tests {
         assert!(next < 3_000);
     }
 
+    fn profile(width: u32, height: u32, fps: u32, bitrate_kbps: u32) -> VideoProfile {
+        VideoProfile {
+            width,
+            height,
+            fps,
+            bitrate_kbps,
+        }
+    }
+
+    #[test]
+    fn proposal_within_host_limits_is_kept() {
+        let host_max = profile(1920, 1080, 60, 8_000);
+        let proposed = profile(1280, 720, 30, 2_500);
+        assert_eq!(agree_profile(proposed, host_max), proposed);
+    }
+
+    #[test]
+    fn oversized_proposal_is_scaled_to_host_limits() {
+        let host_max = profile(1280, 720, 30, 4_000);
+        assert_eq!(
+            agree_profile(profile(3840, 2160, 60, 20_000), host_max),
+            profile(1280, 720, 30, 4_000)
+        );
+        // 4:3 keeps its aspect ratio, limited by height.
+        assert_eq!(
+            agree_profile(profile(1600, 1200, 30, 2_000), host_max),
+            profile(960, 720, 30, 2_000)
+        );
+        // Ultra-wide is limited by width, and odd results are rounded down to even.
+        assert_eq!(
+            agree_profile(profile(3440, 1440, 30, 2_000), host_max),
+            profile(1280, 534, 30, 2_000)
+        );
+    }
+
+    #[test]
+    fn zero_fields_take_host_maximum() {
+        let host_max = profile(1280, 720, 30, 4_000);
+        assert_eq!(agree_profile(profile(0, 0, 0, 0), host_max), host_max);
+        assert_eq!(
+            agree_profile(profile(640, 360, 0, 1_000), host_max),
+            profile(640, 360, 30, 1_000)
+        );
+    }
+
     #[test]
     fn increases_bitrate_on_good_network() {
         let next = adaptive_bitrate_step(
@@ -0,0 +1,202 @@
+use std::io::Write;
+
+use crate::{MediaError, VideoProfile};
+
+/// First bytes of every recording file.
+pub const RECORDING_MAGIC: &[u8; 4] = b"ALRC";
+pub const RECORDING_FORMAT_VERSION: u16 = 1;
+/// Magic, version, codec fourcc and the four profile fields.
+pub const RECORDING_HEADER_LEN: usize = 4 + 2 + 4 + 4 * 4;
+const FRAME_FLAG_KEYFRAME: u8 = 0x01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoCodec {
+    H264,
+    H265,
+    Vp9,
+    Av1,
+}
+
+impl VideoCodec {
+    pub fn fourcc(self) -> [u8; 4] {
+        match self {
+            Self::H264 => *b"avc1",
+            Self::H265 => *b"hvc1",
+            Self::Vp9 => *b"vp09",
+            Self::Av1 => *b"av01",
+        }
+    }
+}
+
+/// Fixed-size file header: magic, format version, codec fourcc, then width, height,
+/// fps and bitrate. Integers are big-endian.
+pub fn recording_header(profile: &VideoProfile, codec: VideoCodec) -> Vec<u8> {
+    let mut header = Vec::with_capacity(RECORDING_HEADER_LEN);
+    header.extend_from_slice(RECORDING_MAGIC);
+    header.extend_from_slice(&RECORDING_FORMAT_VERSION.to_be_bytes());
+    header.extend_from_slice(&codec.fourcc());
+    header.extend_from_slice(&profile.width.to_be_bytes());
+    header.extend_from_slice(&profile.height.to_be_bytes());
+    header.extend_from_slice(&profile.fps.to_be_bytes());
+    header.extend_from_slice(&profile.bitrate_kbps.to_be_bytes());
+    header
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordingState {
+    Idle,
+    Recording,
+    Stopped,
+}
+
+/// Writes a recording as the header followed by length-prefixed frames, each framed
+/// as `timestamp_us: u64`, `flags: u8`, `len: u32` and the encoded frame bytes.
+#[derive(Debug)]
+pub struct RecordingWriter<W: Write> {
+    sink: W,
+    state: RecordingState,
+    frames_written: u64,
+    bytes_written: u64,
+}
+
+impl<W: Write> RecordingWriter<W> {
+    pub fn new(sink: W) -> Self {
+        Self {
+            sink,
+            state: RecordingState::Idle,
+            frames_written: 0,
+            bytes_written: 0,
+        }
+    }
+
+    pub fn start(&mut self, profile: &VideoProfile, codec: VideoCodec) -> Result<(), MediaError> {
+        if self.state != RecordingState::Idle {
+            return Err(MediaError::RecordingAlreadyStarted);
+        }
+        let header = recording_header(profile, codec);
+        self.write_all(&header)?;
+        self.state = RecordingState::Recording;
+        Ok(())
+    }
+
+    pub fn write_frame(
+        &mut self,
+        timestamp_us: u64,
+        keyframe: bool,
+        data: &[u8],
+    ) -> Result<(), MediaError> {
+        if self.state != RecordingState::Recording {
+            return Err(MediaError::RecordingNotActive);
+        }
+        let len = u32::try_from(data.len()).map_err(|_| MediaError::FrameTooLarge)?;
+        let flags = if keyframe { FRAME_FLAG_KEYFRAME } else { 0 };
+        self.write_all(&timestamp_us.to_be_bytes())?;
+        self.write_all(&[flags])?;
+        self.write_all(&len.to_be_bytes())?;
+        self.write_all(data)?;
+        self.frames_written += 1;
+        Ok(())
+    }
+
+    /// Flushes and finishes the recording; no frames are accepted afterwards.
+    pub fn stop(&mut self) -> Result<(), MediaError> {
+        if self.state != RecordingState::Recording {
+            return Err(MediaError::RecordingNotActive);
+        }
+        self.state = RecordingState::Stopped;
+        self.sink
+            .flush()
+            .map_err(|err| MediaError::Io(err.to_string()))
+    }
+
+    pub fn state(&self) -> RecordingState {
+        self.state
+    }
+
+    pub fn frames_written(&self) -> u64 {
+        self.frames_written
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    pub fn into_inner(self) -> W {
+        self.sink
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), MediaError> {
+        self.sink
+            .write_all(bytes)
+            .map_err(|err| MediaError::Io(err.to_string()))?;
+        self.bytes_written += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_encodes_codec_and_profile() {
+        let profile = VideoProfile {
+            width: 1920,
+            height: 1080,
+            fps: 60,
+            bitrate_kbps: 8_000,
+        };
+        let header = recording_header(&profile, VideoCodec::H265);
+        assert_eq!(header.len(), RECORDING_HEADER_LEN);
+        assert_eq!(&header[0..4], b"ALRC");
+        assert_eq!(&header[4..6], &[0, 1]);
+        assert_eq!(&header[6..10], b"hvc1");
+        assert_eq!(&header[10..14], &1920_u32.to_be_bytes());
+        assert_eq!(&header[14..18], &1080_u32.to_be_bytes());
+        assert_eq!(&header[18..22], &60_u32.to_be_bytes());
+        assert_eq!(&header[22..26], &8_000_u32.to_be_bytes());
+    }
+
+    #[test]
+    fn frames_require_a_started_recording() {
+        let mut writer = RecordingWriter::new(Vec::new());
+        assert_eq!(writer.state(), RecordingState::Idle);
+        assert_eq!(
+            writer.write_frame(0, true, b"frame"),
+            Err(MediaError::RecordingNotActive)
+        );
+        assert_eq!(writer.stop(), Err(MediaError::RecordingNotActive));
+
+        writer
+            .start(&VideoProfile::default(), VideoCodec::H264)
+            .unwrap();
+        assert_eq!(writer.state(), RecordingState::Recording);
+        assert_eq!(
+            writer.start(&VideoProfile::default(), VideoCodec::H264),
+            Err(MediaError::RecordingAlreadyStarted)
+        );
+        writer.write_frame(1_000, true, b"key").unwrap();
+        writer.write_frame(34_333, false, b"delta!").unwrap();
+        writer.stop().unwrap();
+        assert_eq!(writer.state(), RecordingState::Stopped);
+        assert_eq!(
+            writer.write_frame(70_000, false, b"late"),
+            Err(MediaError::RecordingNotActive)
+        );
+        assert_eq!(
+            writer.start(&VideoProfile::default(), VideoCodec::H264),
+            Err(MediaError::RecordingAlreadyStarted)
+        );
+
+        assert_eq!(writer.frames_written(), 2);
+        let bytes = writer.bytes_written();
+        let out = writer.into_inner();
+        assert_eq!(out.len() as u64, bytes);
+        assert_eq!(out.len(), RECORDING_HEADER_LEN + (8 + 1 + 4) * 2 + 3 + 6);
+        let first = &out[RECORDING_HEADER_LEN..];
+        assert_eq!(&first[0..8], &1_000_u64.to_be_bytes());
+        assert_eq!(first[8], FRAME_FLAG_KEYFRAME);
+        assert_eq!(&first[9..13], &3_u32.to_be_bytes());
+        assert_eq!(&first[13..16], b"key");
+    }
+}
@@ -0,0 +1,184 @@
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::NetworkFeedback;
+
+/// Link conditions held for a number of feedback samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkPhase {
+    pub samples: u32,
+    /// Bitrate the link carries without queueing or loss.
+    pub capacity_kbps: u32,
+    /// Round-trip time when the link is not congested.
+    pub base_rtt_ms: u32,
+    /// Loss when the link is not congested, in 1/10000.
+    pub base_loss_x10000: u32,
+}
+
+/// Deterministic link model for exercising rate control. Each `step` takes the bitrate
+/// the sender is pushing and returns the `NetworkFeedback` a receiver would report:
+/// within capacity the phase's base RTT and loss; above it, the overshoot share
+/// (`1 - capacity / sent`) is lost and queueing adds up to a second of RTT. Noise, if
+/// enabled, comes from a seeded RNG so runs repeat exactly.
+#[derive(Debug, Clone)]
+pub struct NetworkSim {
+    script: Vec<LinkPhase>,
+    phase: usize,
+    sample_in_phase: u32,
+    noise: Option<SimNoise>,
+}
+
+#[derive(Debug, Clone)]
+struct SimNoise {
+    rng: StdRng,
+    rtt_jitter_ms: u32,
+    loss_jitter_x10000: u32,
+}
+
+impl NetworkSim {
+    pub fn new(script: Vec<LinkPhase>) -> Self {
+        Self {
+            script,
+            phase: 0,
+            sample_in_phase: 0,
+            noise: None,
+        }
+    }
+
+    /// Adds uniform noise of up to `rtt_jitter_ms` and `loss_jitter_x10000` either way
+    /// to every sample, drawn from an RNG seeded with `seed`.
+    pub fn with_noise(mut self, seed: u64, rtt_jitter_ms: u32, loss_jitter_x10000: u32) -> Self {
+        self.noise = Some(SimNoise {
+            rng: StdRng::seed_from_u64(seed),
+            rtt_jitter_ms,
+            loss_jitter_x10000,
+        });
+        self
+    }
+
+    /// The phase the next `step` will sample, or `None` once the script has run out.
+    pub fn current_phase(&self) -> Option<&LinkPhase> {
+        let mut sample_in_phase = self.sample_in_phase;
+        for phase in &self.script[self.phase..] {
+            if sample_in_phase < phase.samples {
+                return Some(phase);
+            }
+            sample_in_phase = 0;
+        }
+        None
+    }
+
+    /// Feedback for one interval at `send_kbps`; `None` once the script has run out.
+    pub fn step(&mut self, send_kbps: u32) -> Option<NetworkFeedback> {
+        while self.phase < self.script.len()
+            && self.sample_in_phase >= self.script[self.phase].samples
+        {
+            self.phase += 1;
+            self.sample_in_phase = 0;
+        }
+        let link = *self.script.get(self.phase)?;
+        self.sample_in_phase += 1;
+
+        let overshoot = if send_kbps > link.capacity_kbps {
+            1.0 - f64::from(link.capacity_kbps) / f64::from(send_kbps)
+        } else {
+            0.0
+        };
+        let mut rtt_ms = f64::from(link.base_rtt_ms) + overshoot * 1_000.0;
+        let mut loss_x10000 = f64::from(link.base_loss_x10000) + overshoot * 10_000.0;
+        if let Some(noise) = &mut self.noise {
+            rtt_ms += jitter(&mut noise.rng, noise.rtt_jitter_ms);
+            loss_x10000 += jitter(&mut noise.rng, noise.loss_jitter_x10000);
+        }
+        Some(NetworkFeedback {
+            rtt_ms: rtt_ms.max(0.0) as u32,
+            packet_loss_x10000: loss_x10000.clamp(0.0, 10_000.0) as u32,
+        })
+    }
+}
+
+fn jitter(rng: &mut StdRng, amplitude: u32) -> f64 {
+    if amplitude == 0 {
+        return 0.0;
+    }
+    let amplitude = i64::from(amplitude);
+    rng.random_range(-amplitude..=amplitude) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitrateLimits, adaptive_bitrate_step};
+
+    fn phase(samples: u32, capacity_kbps: u32) -> LinkPhase {
+        LinkPhase {
+            samples,
+            capacity_kbps,
+            base_rtt_ms: 40,
+            base_loss_x10000: 0,
+        }
+    }
+
+    #[test]
+    fn congestion_shows_up_as_loss_and_delay() {
+        let mut sim = NetworkSim::new(vec![phase(2, 2_000)]);
+        let idle = sim.step(1_000).unwrap();
+        assert_eq!(idle.rtt_ms, 40);
+        assert_eq!(idle.packet_loss_x10000, 0);
+        let overloaded = sim.step(4_000).unwrap();
+        assert_eq!(overloaded.packet_loss_x10000, 5_000);
+        assert_eq!(overloaded.rtt_ms, 540);
+        assert_eq!(sim.step(1_000), None);
+    }
+
+    #[test]
+    fn current_phase_follows_step_across_boundaries() {
+        let mut sim = NetworkSim::new(vec![phase(1, 1_000), phase(0, 2_000), phase(2, 3_000)]);
+        let mut seen = Vec::new();
+        while let Some(capacity_kbps) = sim.current_phase().map(|phase| phase.capacity_kbps) {
+            sim.step(500).unwrap();
+            seen.push(capacity_kbps);
+        }
+        assert_eq!(seen, vec![1_000, 3_000, 3_000]);
+        assert_eq!(sim.step(500), None);
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_samples() {
+        let script = vec![phase(50, 2_000)];
+        let run = |seed| {
+            let mut sim = NetworkSim::new(script.clone()).with_noise(seed, 10, 50);
+            (0..50)
+                .map(|_| sim.step(2_100).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    fn abr_converges_after_a_step_change_in_bandwidth() {
+        let mut sim = NetworkSim::new(vec![phase(80, 4_000), phase(80, 1_500), phase(80, 4_000)])
+            .with_noise(42, 10, 50);
+        let mut bitrate_kbps = 2_500;
+        let mut history: Vec<(u32, u32)> = Vec::new();
+        while let Some(capacity_kbps) = sim.current_phase().map(|phase| phase.capacity_kbps) {
+            let feedback = sim.step(bitrate_kbps).unwrap();
+            bitrate_kbps =
+                adaptive_bitrate_step(bitrate_kbps, feedback, BitrateLimits::default()).unwrap();
+            history.push((capacity_kbps, bitrate_kbps));
+        }
+        assert_eq!(history.len(), 240);
+
+        // The last 30 samples of every phase sit close to that phase's capacity.
+        for settled in [&history[50..80], &history[130..160], &history[210..240]] {
+            for &(capacity_kbps, bitrate_kbps) in settled {
+                let low = capacity_kbps * 90 / 100;
+                let high = capacity_kbps * 115 / 100;
+                assert!(
+                    (low..=high).contains(&bitrate_kbps),
+                    "{bitrate_kbps} kbps not within [{low}, {high}] for a {capacity_kbps} kbps link"
+                );
+            }
+        }
+    }
+}
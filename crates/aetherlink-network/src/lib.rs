@@ -1,16 +1,45 @@
 #![forbid(unsafe_code)]
 
+use std::{cmp::Ordering, net::IpAddr};
+
 use aetherlink_core::TimingProfile;
+use aetherlink_media::NetworkFeedback;
 use aetherlink_proto::v1::{PathType, SessionErrorCode};
+use multiaddr::{Multiaddr, Protocol};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+/// Score of a path with no measured RTT or loss.
+pub const PATH_SCORE_MAX: u32 = 1_000_000;
+/// Loss, in `packet_loss_x10000` units, that costs as much score as 1 ms of RTT: a
+/// percent of loss weighs like 20 ms of extra round trip.
+const LOSS_X10000_PER_RTT_MS: u32 = 5;
+
+/// RFC 8305's recommended wait between staggered connection attempts.
+pub const DEFAULT_CONNECTION_ATTEMPT_DELAY_MS: u64 = 250;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CandidateKind {
     DirectIpv6,
     DirectLan,
     ServerReflexive,
     Relay,
+    /// A public IPv4 address reachable without NAT traversal. Appended last so
+    /// serialized kinds keep their meaning.
+    DirectIpv4,
+}
+
+impl CandidateKind {
+    /// The dial phase that tries a candidate of this kind.
+    pub fn dial_phase(self) -> DialPhase {
+        match self {
+            CandidateKind::DirectIpv6 | CandidateKind::DirectLan | CandidateKind::DirectIpv4 => {
+                DialPhase::Direct
+            }
+            CandidateKind::ServerReflexive => DialPhase::HolePunch,
+            CandidateKind::Relay => DialPhase::Relay,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -34,47 +63,243 @@ pub struct DialPlan {
     pub timeout_ms: u64,
 }
 
+/// When each dial phase starts, relative to the start of the race, and whether the
+/// relay phase runs at all. The default is the schedule `plan_dial_race` has always
+/// used: direct at once, hole punching after 200 ms, relay after 1.6 s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DialRaceOptions {
+    pub direct_start_ms: u64,
+    pub hole_punch_start_ms: u64,
+    pub relay_start_ms: u64,
+    /// Off when the session does not allow relaying, e.g. `SessionRequest.allow_relay`
+    /// is false.
+    pub include_relay: bool,
+}
+
+impl Default for DialRaceOptions {
+    fn default() -> Self {
+        Self {
+            direct_start_ms: 0,
+            hole_punch_start_ms: 200,
+            relay_start_ms: 1_600,
+            include_relay: true,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Error, PartialEq, Eq)]
 pub enum PlannerError {
     #[error("candidate list is empty")]
     EmptyCandidates,
+    #[error("candidate address is not dialable: {0}")]
+    InvalidAddress(String),
 }
 
 pub fn plan_dial_race(timing: &TimingProfile) -> [DialPlan; 3] {
-    [
+    let plan = plan_dial_race_with(timing, DialRaceOptions::default());
+    [plan[0], plan[1], plan[2]]
+}
+
+/// The dial race under `opts`, in phase order; each phase's timeout comes from `timing`.
+pub fn plan_dial_race_with(timing: &TimingProfile, opts: DialRaceOptions) -> Vec<DialPlan> {
+    let mut plan = vec![
         DialPlan {
             phase: DialPhase::Direct,
-            start_after_ms: 0,
+            start_after_ms: opts.direct_start_ms,
             timeout_ms: timing.direct_dial_budget_ms,
         },
         DialPlan {
             phase: DialPhase::HolePunch,
-            start_after_ms: 200,
+            start_after_ms: opts.hole_punch_start_ms,
             timeout_ms: timing.punch_budget_ms,
         },
-        DialPlan {
+    ];
+    if opts.include_relay {
+        plan.push(DialPlan {
             phase: DialPhase::Relay,
-            start_after_ms: 1600,
+            start_after_ms: opts.relay_start_ms,
             timeout_ms: timing.relay_dial_timeout_ms,
-        },
-    ]
+        });
+    }
+    plan
+}
+
+/// The default dial race reduced to the phases some candidate needs, so a missing
+/// relay or server-reflexive candidate does not cost a phase timeout. Start times
+/// are shifted so the first remaining phase starts at once.
+pub fn plan_dial_race_for(
+    candidates: &[Candidate],
+    timing: &TimingProfile,
+) -> Result<Vec<DialPlan>, PlannerError> {
+    if candidates.is_empty() {
+        return Err(PlannerError::EmptyCandidates);
+    }
+    let mut plan = plan_dial_race_with(timing, DialRaceOptions::default());
+    plan.retain(|stage| {
+        candidates
+            .iter()
+            .any(|candidate| candidate.kind.dial_phase() == stage.phase)
+    });
+    let first_start_ms = plan.first().map_or(0, |stage| stage.start_after_ms);
+    for stage in &mut plan {
+        stage.start_after_ms -= first_start_ms;
+    }
+    Ok(plan)
+}
+
+/// Derives a candidate's kind from the address itself: a relay circuit is `Relay`,
+/// a loopback or private-range IP (RFC1918, link-local, IPv6 ULA) is `DirectLan`, and
+/// any other IPv6 or IPv4 address is `DirectIpv6` or `DirectIpv4`. DNS names count as
+/// public, IPv6 for `/dns6` and IPv4 otherwise. `ServerReflexive` is never returned:
+/// an observed address looks like any other public one.
+pub fn classify_multiaddr(addr: &Multiaddr) -> CandidateKind {
+    if addr
+        .iter()
+        .any(|protocol| matches!(protocol, Protocol::P2pCircuit))
+    {
+        return CandidateKind::Relay;
+    }
+    let host = addr.iter().find_map(|protocol| match protocol {
+        Protocol::Ip4(ip) => Some(Some(IpAddr::V4(ip))),
+        Protocol::Ip6(ip) => Some(Some(IpAddr::V6(ip))),
+        Protocol::Dns6(_) => Some(None),
+        _ => None,
+    });
+    match host {
+        Some(Some(ip)) if is_lan_ip(ip) => CandidateKind::DirectLan,
+        Some(Some(IpAddr::V6(_)) | None) => CandidateKind::DirectIpv6,
+        Some(Some(IpAddr::V4(_))) | None => CandidateKind::DirectIpv4,
+    }
+}
+
+/// A typed candidate for `addr`, refusing addresses with nothing to dial (no IP, DNS
+/// name or relay circuit) and unspecified IPs (`0.0.0.0`, `::`), which only make
+/// sense as listen addresses.
+pub fn candidate_from_multiaddr(
+    addr: &Multiaddr,
+    priority: u32,
+) -> Result<Candidate, PlannerError> {
+    let dialable = addr.iter().any(|protocol| {
+        matches!(
+            protocol,
+            Protocol::Ip4(_)
+                | Protocol::Ip6(_)
+                | Protocol::Dns(_)
+                | Protocol::Dns4(_)
+                | Protocol::Dns6(_)
+                | Protocol::Dnsaddr(_)
+                | Protocol::P2pCircuit
+        )
+    });
+    let unspecified = addr.iter().any(|protocol| match protocol {
+        Protocol::Ip4(ip) => ip.is_unspecified(),
+        Protocol::Ip6(ip) => ip.is_unspecified(),
+        _ => false,
+    });
+    if !dialable || unspecified {
+        return Err(PlannerError::InvalidAddress(addr.to_string()));
+    }
+    Ok(Candidate {
+        address: addr.to_string(),
+        priority,
+        kind: classify_multiaddr(addr),
+    })
+}
+
+fn is_lan_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+        IpAddr::V6(ip) => ip.is_loopback() || ip.is_unicast_link_local() || ip.is_unique_local(),
+    }
 }
 
 pub fn select_primary_candidate(candidates: &[Candidate]) -> Result<&Candidate, PlannerError> {
     candidates
         .iter()
-        .max_by_key(|item| {
-            let kind_weight = match item.kind {
-                CandidateKind::DirectIpv6 => 40_000_u64,
-                CandidateKind::DirectLan => 30_000_u64,
-                CandidateKind::ServerReflexive => 20_000_u64,
-                CandidateKind::Relay => 10_000_u64,
-            };
-            kind_weight.saturating_add(item.priority as u64)
-        })
+        .max_by_key(|item| candidate_score(item))
         .ok_or(PlannerError::EmptyCandidates)
 }
 
+/// Staggers the direct candidates happy-eyeballs style (RFC 8305): best first within
+/// each address family, alternating IPv6 and IPv4 starting with IPv6, each attempt
+/// `attempt_delay_ms` after the previous one, so a black-holed family costs one delay
+/// rather than the whole direct dial budget. With only one family present there is
+/// nothing to fall back to and every attempt starts at once.
+pub fn interleave_direct_dials(
+    candidates: &[Candidate],
+    attempt_delay_ms: u64,
+) -> Vec<(Candidate, u64)> {
+    let mut ipv6 = Vec::new();
+    let mut ipv4 = Vec::new();
+    for candidate in candidates {
+        if candidate.kind.dial_phase() != DialPhase::Direct {
+            continue;
+        }
+        if is_ipv6_candidate(candidate) {
+            ipv6.push(candidate);
+        } else {
+            ipv4.push(candidate);
+        }
+    }
+    for family in [&mut ipv6, &mut ipv4] {
+        family.sort_by_key(|candidate| std::cmp::Reverse(candidate_score(candidate)));
+    }
+
+    let delay_ms = if ipv6.is_empty() || ipv4.is_empty() {
+        0
+    } else {
+        attempt_delay_ms
+    };
+    let mut ipv6 = ipv6.into_iter();
+    let mut ipv4 = ipv4.into_iter();
+    let mut schedule = Vec::new();
+    loop {
+        let next_ipv6 = ipv6.next();
+        let next_ipv4 = ipv4.next();
+        if next_ipv6.is_none() && next_ipv4.is_none() {
+            break;
+        }
+        for candidate in [next_ipv6, next_ipv4].into_iter().flatten() {
+            let start_after_ms = delay_ms.saturating_mul(schedule.len() as u64);
+            schedule.push((candidate.clone(), start_after_ms));
+        }
+    }
+    schedule
+}
+
+fn candidate_score(candidate: &Candidate) -> u64 {
+    let kind_weight = match candidate.kind {
+        CandidateKind::DirectIpv6 => 40_000_u64,
+        CandidateKind::DirectLan => 30_000_u64,
+        CandidateKind::DirectIpv4 => 25_000_u64,
+        CandidateKind::ServerReflexive => 20_000_u64,
+        CandidateKind::Relay => 10_000_u64,
+    };
+    kind_weight.saturating_add(candidate.priority as u64)
+}
+
+/// LAN candidates may be either family, so their address decides.
+fn is_ipv6_candidate(candidate: &Candidate) -> bool {
+    match candidate.kind {
+        CandidateKind::DirectIpv6 => true,
+        CandidateKind::DirectIpv4 => false,
+        _ => candidate.address.starts_with("/ip6/"),
+    }
+}
+
+/// Ranks a live path for migration: `PATH_SCORE_MAX` less the RTT and a loss penalty,
+/// so the score never rises as either gets worse. Loss above 100% counts as 100%.
+pub fn path_score(feedback: NetworkFeedback) -> u32 {
+    let loss_penalty = feedback.packet_loss_x10000.min(10_000) / LOSS_X10000_PER_RTT_MS;
+    PATH_SCORE_MAX.saturating_sub(feedback.rtt_ms.saturating_add(loss_penalty))
+}
+
+/// `Greater` when path `a` scores better than `b`, e.g. a newly formed direct path
+/// worth switching to from the current relay.
+pub fn better_path(a: NetworkFeedback, b: NetworkFeedback) -> Ordering {
+    path_score(a).cmp(&path_score(b))
+}
+
 pub fn path_type_to_proto(path: DialPhase) -> PathType {
     match path {
         DialPhase::Direct => PathType::Direct,
@@ -95,6 +320,22 @@ pub fn classify_failure_to_error_code(code: &str) -> SessionErrorCode {
     }
 }
 
+/// The stable string for `code` reported to the daemon and UI; the inverse of
+/// `classify_failure_to_error_code` for every failure class. `Unspecified` is not a
+/// failure, so its string still classifies as `Internal`.
+pub fn error_code_to_str(code: SessionErrorCode) -> &'static str {
+    match code {
+        SessionErrorCode::Unspecified => "unspecified",
+        SessionErrorCode::DiscoveryTimeout => "discovery_timeout",
+        SessionErrorCode::DialTimeout => "dial_timeout",
+        SessionErrorCode::HolePunchFailed => "punch_failed",
+        SessionErrorCode::RelayUnavailable => "relay_unavailable",
+        SessionErrorCode::AuthFailed => "auth_failed",
+        SessionErrorCode::PermissionDenied => "permission_denied",
+        SessionErrorCode::Internal => "internal",
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -117,11 +358,302 @@ mod tests {
         assert!(best.address.contains("/ip6/"));
     }
 
+    #[test]
+    fn direct_ipv4_ranks_between_ipv6_and_server_reflexive() {
+        let reflexive = Candidate {
+            address: "/ip4/198.51.100.7/udp/40123/quic-v1".to_string(),
+            priority: 100,
+            kind: CandidateKind::ServerReflexive,
+        };
+        let ipv4 = Candidate {
+            address: "/ip4/203.0.113.5/udp/9000/quic-v1".to_string(),
+            priority: 100,
+            kind: CandidateKind::DirectIpv4,
+        };
+        let ipv6 = Candidate {
+            address: "/ip6/2001:db8::5/udp/9000/quic-v1".to_string(),
+            priority: 100,
+            kind: CandidateKind::DirectIpv6,
+        };
+
+        let candidates = [reflexive.clone(), ipv4.clone()];
+        assert_eq!(select_primary_candidate(&candidates).unwrap(), &ipv4);
+        let candidates = [ipv4, ipv6.clone(), reflexive];
+        assert_eq!(select_primary_candidate(&candidates).unwrap(), &ipv6);
+    }
+
+    #[test]
+    fn direct_dials_alternate_families_starting_with_ipv6() {
+        let candidate = |address: &str, priority, kind| Candidate {
+            address: address.to_string(),
+            priority,
+            kind,
+        };
+        let v4_public = candidate(
+            "/ip4/203.0.113.5/udp/9000/quic-v1",
+            0,
+            CandidateKind::DirectIpv4,
+        );
+        let v4_lan = candidate(
+            "/ip4/192.168.1.5/udp/9000/quic-v1",
+            0,
+            CandidateKind::DirectLan,
+        );
+        let v6_best = candidate(
+            "/ip6/2001:db8::5/udp/9000/quic-v1",
+            9,
+            CandidateKind::DirectIpv6,
+        );
+        let v6_lan = candidate("/ip6/fe80::5/udp/9000/quic-v1", 0, CandidateKind::DirectLan);
+        let relay = candidate("relay://x", 0, CandidateKind::Relay);
+
+        let schedule = interleave_direct_dials(
+            &[
+                v4_public.clone(),
+                relay,
+                v6_lan.clone(),
+                v4_lan.clone(),
+                v6_best.clone(),
+            ],
+            DEFAULT_CONNECTION_ATTEMPT_DELAY_MS,
+        );
+        assert_eq!(
+            schedule,
+            vec![(v6_best, 0), (v4_lan, 250), (v6_lan, 500), (v4_public, 750)]
+        );
+    }
+
+    #[test]
+    fn single_family_direct_dials_start_at_once() {
+        let v4 = |address: &str| Candidate {
+            address: address.to_string(),
+            priority: 0,
+            kind: CandidateKind::DirectIpv4,
+        };
+        let schedule = interleave_direct_dials(
+            &[
+                v4("/ip4/203.0.113.5/udp/9000/quic-v1"),
+                v4("/ip4/203.0.113.6/udp/9000/quic-v1"),
+            ],
+            DEFAULT_CONNECTION_ATTEMPT_DELAY_MS,
+        );
+        assert_eq!(schedule.len(), 2);
+        assert!(
+            schedule
+                .iter()
+                .all(|(_, start_after_ms)| *start_after_ms == 0)
+        );
+        assert!(interleave_direct_dials(&[], DEFAULT_CONNECTION_ATTEMPT_DELAY_MS).is_empty());
+    }
+
+    #[test]
+    fn multiaddrs_classify_by_structure() {
+        let kind = |addr: &str| classify_multiaddr(&addr.parse().unwrap());
+        assert_eq!(
+            kind("/ip6/2001:db8::5/udp/9000/quic-v1"),
+            CandidateKind::DirectIpv6
+        );
+        assert_eq!(
+            kind("/ip6/fd00::5/udp/9000/quic-v1"),
+            CandidateKind::DirectLan
+        );
+        assert_eq!(
+            kind("/ip6/fe80::5/udp/9000/quic-v1"),
+            CandidateKind::DirectLan
+        );
+        assert_eq!(
+            kind("/ip4/203.0.113.5/udp/9000/quic-v1"),
+            CandidateKind::DirectIpv4
+        );
+        assert_eq!(
+            kind("/ip4/192.168.1.5/udp/9000/quic-v1"),
+            CandidateKind::DirectLan
+        );
+        assert_eq!(kind("/ip4/127.0.0.1/tcp/9000"), CandidateKind::DirectLan);
+        assert_eq!(kind("/dns4/example.com/tcp/443"), CandidateKind::DirectIpv4);
+        assert_eq!(kind("/dns6/example.com/tcp/443"), CandidateKind::DirectIpv6);
+        assert_eq!(
+            kind(
+                "/ip4/203.0.113.1/udp/4001/quic-v1/p2p/12D3KooWAHoEkEqnKzM5PXFygh2movVBCSX3k8tDsT2cneU68Gyt/p2p-circuit"
+            ),
+            CandidateKind::Relay
+        );
+    }
+
+    #[test]
+    fn candidates_from_multiaddrs_are_typed_and_checked() {
+        let addr: Multiaddr = "/ip6/2001:db8::5/udp/9000/quic-v1".parse().unwrap();
+        assert_eq!(
+            candidate_from_multiaddr(&addr, 7),
+            Ok(Candidate {
+                address: "/ip6/2001:db8::5/udp/9000/quic-v1".to_string(),
+                priority: 7,
+                kind: CandidateKind::DirectIpv6,
+            })
+        );
+        let no_host: Multiaddr = "/udp/9000/quic-v1".parse().unwrap();
+        assert_eq!(
+            candidate_from_multiaddr(&no_host, 0),
+            Err(PlannerError::InvalidAddress(
+                "/udp/9000/quic-v1".to_string()
+            ))
+        );
+        for unspecified in ["/ip4/0.0.0.0/udp/9000/quic-v1", "/ip6/::/udp/9000/quic-v1"] {
+            assert_eq!(
+                candidate_from_multiaddr(&unspecified.parse().unwrap(), 0),
+                Err(PlannerError::InvalidAddress(unspecified.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn error_codes_round_trip_through_their_strings() {
+        let codes: Vec<SessionErrorCode> = (0..=SessionErrorCode::Internal as i32)
+            .filter_map(|value| SessionErrorCode::try_from(value).ok())
+            .collect();
+        assert_eq!(codes.len(), 8);
+        for code in codes {
+            if code == SessionErrorCode::Unspecified {
+                continue;
+            }
+            assert_eq!(
+                classify_failure_to_error_code(error_code_to_str(code)),
+                code
+            );
+        }
+        assert_eq!(
+            classify_failure_to_error_code("something_new"),
+            SessionErrorCode::Internal
+        );
+        assert_eq!(
+            classify_failure_to_error_code(error_code_to_str(SessionErrorCode::Unspecified)),
+            SessionErrorCode::Internal
+        );
+    }
+
+    #[test]
+    fn path_score_prefers_lower_rtt_and_loss() {
+        let feedback = |rtt_ms, packet_loss_x10000| NetworkFeedback {
+            rtt_ms,
+            packet_loss_x10000,
+        };
+        assert_eq!(path_score(feedback(0, 0)), PATH_SCORE_MAX);
+        assert!(path_score(feedback(20, 0)) > path_score(feedback(40, 0)));
+        assert!(path_score(feedback(20, 0)) > path_score(feedback(20, 100)));
+        assert_eq!(path_score(feedback(20, 100)), path_score(feedback(40, 0)));
+        assert_eq!(
+            path_score(feedback(20, 50_000)),
+            path_score(feedback(20, 10_000))
+        );
+        assert_eq!(path_score(feedback(u32::MAX, 10_000)), 0);
+
+        let direct = feedback(18, 20);
+        let relay = feedback(65, 0);
+        assert_eq!(better_path(direct, relay), Ordering::Greater);
+        assert_eq!(better_path(relay, direct), Ordering::Less);
+        assert_eq!(better_path(relay, relay), Ordering::Equal);
+    }
+
     #[test]
     fn dial_plan_has_expected_stages() {
         let plan = plan_dial_race(&TimingProfile::default());
         assert_eq!(plan[0].phase, DialPhase::Direct);
         assert_eq!(plan[1].phase, DialPhase::HolePunch);
         assert_eq!(plan[2].phase, DialPhase::Relay);
+        assert_eq!(plan.map(|stage| stage.start_after_ms), [0, 200, 1_600]);
+    }
+
+    #[test]
+    fn dial_race_for_candidates_skips_phases_without_candidates() {
+        let timing = TimingProfile::default();
+        let candidate = |kind| Candidate {
+            address: String::new(),
+            priority: 0,
+            kind,
+        };
+        let phases = |candidates: &[Candidate]| {
+            plan_dial_race_for(candidates, &timing)
+                .unwrap()
+                .iter()
+                .map(|stage| stage.phase)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            phases(&[candidate(CandidateKind::DirectIpv4)]),
+            vec![DialPhase::Direct]
+        );
+        assert_eq!(
+            phases(&[
+                candidate(CandidateKind::Relay),
+                candidate(CandidateKind::DirectLan)
+            ]),
+            vec![DialPhase::Direct, DialPhase::Relay]
+        );
+        assert_eq!(
+            phases(&[candidate(CandidateKind::ServerReflexive)]),
+            vec![DialPhase::HolePunch]
+        );
+        let starts = |candidates: &[Candidate]| {
+            plan_dial_race_for(candidates, &timing)
+                .unwrap()
+                .iter()
+                .map(|stage| stage.start_after_ms)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(starts(&[candidate(CandidateKind::Relay)]), vec![0]);
+        assert_eq!(
+            starts(&[
+                candidate(CandidateKind::Relay),
+                candidate(CandidateKind::ServerReflexive)
+            ]),
+            vec![0, 1_400]
+        );
+        assert_eq!(
+            phases(&[
+                candidate(CandidateKind::Relay),
+                candidate(CandidateKind::ServerReflexive),
+                candidate(CandidateKind::DirectIpv6),
+            ]),
+            plan_dial_race(&timing).map(|stage| stage.phase).to_vec()
+        );
+        assert_eq!(
+            plan_dial_race_for(&[], &timing),
+            Err(PlannerError::EmptyCandidates)
+        );
+    }
+
+    #[test]
+    fn dial_race_options_tune_offsets_and_drop_relay() {
+        let timing = TimingProfile::default();
+        let plan = plan_dial_race_with(
+            &timing,
+            DialRaceOptions {
+                hole_punch_start_ms: 50,
+                relay_start_ms: 800,
+                ..DialRaceOptions::default()
+            },
+        );
+        assert_eq!(
+            plan.iter()
+                .map(|stage| (stage.phase, stage.start_after_ms))
+                .collect::<Vec<_>>(),
+            vec![
+                (DialPhase::Direct, 0),
+                (DialPhase::HolePunch, 50),
+                (DialPhase::Relay, 800),
+            ]
+        );
+        assert_eq!(plan[2].timeout_ms, timing.relay_dial_timeout_ms);
+
+        let plan = plan_dial_race_with(
+            &timing,
+            DialRaceOptions {
+                include_relay: false,
+                ..DialRaceOptions::default()
+            },
+        );
+        assert_eq!(plan.len(), 2);
+        assert!(plan.iter().all(|stage| stage.phase != DialPhase::Relay));
     }
 }
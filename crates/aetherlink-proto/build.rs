@@ -4,12 +4,21 @@ fn main() {
     let control_proto = PathBuf::from("../../proto/aetherlink/v1/control.proto");
     let ipc_proto = PathBuf::from("../../proto/aetherlink/v1/ipc.proto");
     let proto_root = PathBuf::from("../../proto");
+    let descriptor_set = PathBuf::from(std::env::var("OUT_DIR").expect("OUT_DIR is set by cargo"))
+        .join("aetherlink_descriptor.bin");
 
     println!("cargo:rerun-if-changed={}", control_proto.display());
     println!("cargo:rerun-if-changed={}", ipc_proto.display());
 
     prost_build::Config::new()
         .protoc_arg("--experimental_allow_proto3_optional")
+        .file_descriptor_set_path(&descriptor_set)
+        .type_attribute(
+            ".",
+            "#[cfg_attr(feature = \"serde\", derive(serde::Serialize, serde::Deserialize))]",
+        )
+        // Missing JSON fields take their proto3 default, as they would on the wire.
+        .message_attribute(".", "#[cfg_attr(feature = \"serde\", serde(default))]")
         .compile_protos(&[control_proto, ipc_proto], &[proto_root])
         .expect("failed to compile protobuf definitions");
 }
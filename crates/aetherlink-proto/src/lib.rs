@@ -1,5 +1,448 @@
 #![forbid(unsafe_code)]
 
+use prost::Message;
+use thiserror::Error;
+
 pub mod v1 {
     include!(concat!(env!("OUT_DIR"), "/aetherlink.v1.rs"));
 }
+
+/// Room a `ControlEnvelope` needs around its largest payload: request and session ids,
+/// MIME type, sequence number and field tags.
+pub const CONTROL_ENVELOPE_OVERHEAD_BYTES: usize = 16 * 1024;
+
+/// Largest encoded `ControlEnvelope` accepted from a peer: a 1 MiB clipboard payload
+/// plus `CONTROL_ENVELOPE_OVERHEAD_BYTES`.
+pub const MAX_CONTROL_ENVELOPE_BYTES: usize = 1024 * 1024 + CONTROL_ENVELOPE_OVERHEAD_BYTES;
+
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum DecodeError {
+    #[error("control envelope is {len} bytes, over the {max} byte limit")]
+    TooLarge { len: usize, max: usize },
+    #[error("malformed control envelope: {0}")]
+    Malformed(#[from] prost::DecodeError),
+}
+
+/// Decodes untrusted control-channel bytes. Oversized input is refused before any
+/// parsing; everything else either decodes or fails with `Malformed`, never panics.
+pub fn try_decode_control(bytes: &[u8]) -> Result<v1::ControlEnvelope, DecodeError> {
+    if bytes.len() > MAX_CONTROL_ENVELOPE_BYTES {
+        return Err(DecodeError::TooLarge {
+            len: bytes.len(),
+            max: MAX_CONTROL_ENVELOPE_BYTES,
+        });
+    }
+    Ok(v1::ControlEnvelope::decode(bytes)?)
+}
+
+/// Domain tags that open every signed payload, one per signed message type, so a
+/// signature over one kind of message never verifies as another.
+pub mod signing_domain {
+    pub const SESSION_REQUEST: &str = "aetherlink.v1.SessionRequest";
+    pub const SESSION_ACCEPT: &str = "aetherlink.v1.SessionAccept";
+    pub const CHALLENGE: &str = "aetherlink.v1.Challenge";
+    pub const RESUMPTION_TICKET: &str = "aetherlink.v1.ResumptionTicket";
+    pub const IDENTITY_ROTATION_PROOF: &str = "aetherlink.v1.IdentityRotationProof";
+}
+
+/// Bytes a signature covers, laid out explicitly rather than by re-encoding the
+/// decoded message: the length-prefixed domain tag, then each signed field as its
+/// big-endian field number, length and value, in the order the caller writes them.
+/// Every listed field is written, defaults included. Fields a peer does not know are
+/// dropped on decode, so a field only becomes signed once the layout lists it; until
+/// then older and newer peers still compute the same bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigningPayload(Vec<u8>);
+
+impl SigningPayload {
+    pub fn new(domain: &str) -> Self {
+        let mut buf = Vec::with_capacity(128);
+        put_len_prefixed(&mut buf, domain.as_bytes());
+        Self(buf)
+    }
+
+    pub fn bytes(mut self, field: u32, value: &[u8]) -> Self {
+        self.0.extend_from_slice(&field.to_be_bytes());
+        put_len_prefixed(&mut self.0, value);
+        self
+    }
+
+    pub fn string(self, field: u32, value: &str) -> Self {
+        self.bytes(field, value.as_bytes())
+    }
+
+    pub fn u32(self, field: u32, value: u32) -> Self {
+        self.bytes(field, &value.to_be_bytes())
+    }
+
+    pub fn i32(self, field: u32, value: i32) -> Self {
+        self.bytes(field, &value.to_be_bytes())
+    }
+
+    pub fn i64(self, field: u32, value: i64) -> Self {
+        self.bytes(field, &value.to_be_bytes())
+    }
+
+    pub fn bool(self, field: u32, value: bool) -> Self {
+        self.bytes(field, &[u8::from(value)])
+    }
+
+    /// A repeated string field: each value length-prefixed, in order.
+    pub fn strings(self, field: u32, values: &[String]) -> Self {
+        let mut inner = Vec::new();
+        for value in values {
+            put_len_prefixed(&mut inner, value.as_bytes());
+        }
+        self.bytes(field, &inner)
+    }
+
+    /// A repeated enum field, in order.
+    pub fn i32s(self, field: u32, values: &[i32]) -> Self {
+        let inner: Vec<u8> = values
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect();
+        self.bytes(field, &inner)
+    }
+
+    /// An optional nested message, given as its own payload. Absent and present-but-empty
+    /// encode differently.
+    pub fn message(self, field: u32, nested: Option<SigningPayload>) -> Self {
+        match nested {
+            None => self.bytes(field, &[0]),
+            Some(nested) => {
+                let mut inner = Vec::with_capacity(1 + nested.0.len());
+                inner.push(1);
+                inner.extend_from_slice(&nested.0);
+                self.bytes(field, &inner)
+            }
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+fn put_len_prefixed(buf: &mut Vec<u8>, value: &[u8]) {
+    let len = u32::try_from(value.len()).expect("signed field under 4 GiB");
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(value);
+}
+
+/// Whether `env` is an IPC liveness probe to be answered with [`ipc_pong_for`].
+pub fn is_ipc_ping(env: &v1::IpcEnvelope) -> bool {
+    matches!(env.payload, Some(v1::ipc_envelope::Payload::Ping(_)))
+}
+
+/// The `IpcPong` answering `ping`: same `request_id`, nonce and send time echoed.
+/// `None` when `ping` is not a ping.
+pub fn ipc_pong_for(ping: &v1::IpcEnvelope, seq: u64) -> Option<v1::IpcEnvelope> {
+    let Some(v1::ipc_envelope::Payload::Ping(probe)) = &ping.payload else {
+        return None;
+    };
+    Some(v1::IpcEnvelope {
+        seq,
+        request_id: ping.request_id.clone(),
+        payload: Some(v1::ipc_envelope::Payload::Pong(v1::IpcPong {
+            nonce: probe.nonce,
+            echo_send_unix_ms: probe.send_unix_ms,
+        })),
+    })
+}
+
+/// Encoded `google.protobuf.FileDescriptorSet` for `control.proto` and `ipc.proto`,
+/// for tooling that works from descriptors rather than the generated Rust types.
+pub const FILE_DESCRIPTOR_SET: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/aetherlink_descriptor.bin"));
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::v1::{ClipboardData, ControlEnvelope, FileChunk, control_envelope};
+    use super::v1::{DeviceIdentity, ProtocolVersion, SessionRequest};
+    use super::v1::{IpcEnvelope, IpcPing, IpcPong, ipc_envelope};
+    use super::{
+        DecodeError, FILE_DESCRIPTOR_SET, MAX_CONTROL_ENVELOPE_BYTES, SigningPayload, ipc_pong_for,
+        is_ipc_ping, signing_domain, try_decode_control,
+    };
+
+    fn round_trip(message: control_envelope::Message) {
+        let envelope = ControlEnvelope {
+            seq: 7,
+            request_id: "req-7".to_string(),
+            message: Some(message),
+        };
+        let decoded = ControlEnvelope::decode(envelope.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded, envelope);
+    }
+
+    fn signed_request() -> SessionRequest {
+        SessionRequest {
+            session_id: "s".to_string(),
+            from: Some(DeviceIdentity {
+                peer_id: vec![1],
+                identity_pubkey: vec![2],
+                device_code: "d".to_string(),
+            }),
+            nonce: vec![3, 4],
+            unix_ms: 5,
+            version: Some(ProtocolVersion {
+                major: 1,
+                minor: 0,
+                patch: 0,
+            }),
+            feature_bits: vec!["f".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn signing_payload_layout_is_pinned() {
+        let payload = SigningPayload::new("d")
+            .string(1, "s")
+            .i64(2, -1)
+            .bool(3, true)
+            .strings(4, &["f".to_string()])
+            .message(5, None)
+            .message(6, Some(SigningPayload::new("n").bytes(1, &[7])))
+            .finish();
+        assert_eq!(
+            payload,
+            [
+                0, 0, 0, 1, b'd', // domain
+                0, 0, 0, 1, 0, 0, 0, 1, b's', // 1: "s"
+                0, 0, 0, 2, 0, 0, 0, 8, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+                0xff, // 2: -1
+                0, 0, 0, 3, 0, 0, 0, 1, 1, // 3: true
+                0, 0, 0, 4, 0, 0, 0, 5, 0, 0, 0, 1, b'f', // 4: ["f"]
+                0, 0, 0, 5, 0, 0, 0, 1, 0, // 5: absent
+                0, 0, 0, 6, 0, 0, 0, 15, 1, // 6: present,
+                0, 0, 0, 1, b'n', 0, 0, 0, 1, 0, 0, 0, 1, 7, // nested payload
+            ]
+        );
+    }
+
+    #[test]
+    fn signing_payload_separates_domains_and_empty_fields() {
+        let signed = |domain: &str| SigningPayload::new(domain).string(1, "s").finish();
+        assert_ne!(
+            signed(signing_domain::SESSION_REQUEST),
+            signed(signing_domain::SESSION_ACCEPT)
+        );
+        assert_ne!(
+            SigningPayload::new("d").message(1, None),
+            SigningPayload::new("d").message(1, Some(SigningPayload::new("")))
+        );
+        assert_ne!(
+            SigningPayload::new("d").string(1, "ab").string(2, ""),
+            SigningPayload::new("d").string(1, "a").string(2, "b")
+        );
+    }
+
+    #[test]
+    fn control_decode_accepts_valid_envelopes() {
+        let envelope = ControlEnvelope {
+            seq: 1,
+            request_id: "req-1".to_string(),
+            message: Some(control_envelope::Message::ClipboardData(ClipboardData {
+                session_id: "s".to_string(),
+                mime: "text/plain".to_string(),
+                data: b"hi".to_vec(),
+            })),
+        };
+        assert_eq!(
+            try_decode_control(&envelope.encode_to_vec()).unwrap(),
+            envelope
+        );
+        assert_eq!(try_decode_control(&[]).unwrap(), ControlEnvelope::default());
+    }
+
+    #[test]
+    fn control_decode_rejects_truncated_input() {
+        let bytes = signed_envelope_bytes();
+        for len in 1..bytes.len() {
+            // Truncation either fails cleanly or, at a field boundary, yields a prefix.
+            let _ = try_decode_control(&bytes[..len]);
+        }
+        assert!(matches!(
+            try_decode_control(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn control_decode_rejects_oversized_input() {
+        let bytes = vec![0_u8; MAX_CONTROL_ENVELOPE_BYTES + 1];
+        assert_eq!(
+            try_decode_control(&bytes),
+            Err(DecodeError::TooLarge {
+                len: MAX_CONTROL_ENVELOPE_BYTES + 1,
+                max: MAX_CONTROL_ENVELOPE_BYTES,
+            })
+        );
+    }
+
+    #[test]
+    fn control_decode_survives_garbage() {
+        // Fixed-seed xorshift so failures reproduce.
+        let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+        for round in 0..2_000 {
+            let len = round % 257;
+            let bytes: Vec<u8> = (0..len)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect();
+            let _ = try_decode_control(&bytes);
+        }
+        assert!(try_decode_control(&[0xff; 16]).is_err());
+        // Length prefix claiming far more bytes than present.
+        assert!(try_decode_control(&[0x12, 0xff, 0xff, 0xff, 0x0f, b'x']).is_err());
+    }
+
+    fn signed_envelope_bytes() -> Vec<u8> {
+        ControlEnvelope {
+            seq: 9,
+            request_id: "req-9".to_string(),
+            message: Some(control_envelope::Message::SessionRequest(signed_request())),
+        }
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn ipc_ping_round_trips_to_a_matching_pong() {
+        let ping = IpcEnvelope {
+            seq: 3,
+            request_id: "ping-1".to_string(),
+            payload: Some(ipc_envelope::Payload::Ping(IpcPing {
+                nonce: 42,
+                send_unix_ms: 1_700_000_000_000,
+            })),
+        };
+        let decoded = IpcEnvelope::decode(ping.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded, ping);
+        assert!(is_ipc_ping(&decoded));
+
+        let pong = ipc_pong_for(&decoded, 9).unwrap();
+        let decoded = IpcEnvelope::decode(pong.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded.seq, 9);
+        assert_eq!(decoded.request_id, "ping-1");
+        assert_eq!(
+            decoded.payload,
+            Some(ipc_envelope::Payload::Pong(IpcPong {
+                nonce: 42,
+                echo_send_unix_ms: 1_700_000_000_000,
+            }))
+        );
+        assert!(!is_ipc_ping(&decoded));
+        assert_eq!(ipc_pong_for(&decoded, 10), None);
+    }
+
+    #[test]
+    fn descriptor_set_describes_both_protos() {
+        let set = prost_types::FileDescriptorSet::decode(FILE_DESCRIPTOR_SET).unwrap();
+        let files: Vec<_> = set.file.iter().map(|file| file.name()).collect();
+        assert!(files.contains(&"aetherlink/v1/control.proto"));
+        assert!(files.contains(&"aetherlink/v1/ipc.proto"));
+
+        let control = set
+            .file
+            .iter()
+            .find(|file| file.name() == "aetherlink/v1/control.proto")
+            .unwrap();
+        assert_eq!(control.package(), "aetherlink.v1");
+        assert!(
+            control
+                .message_type
+                .iter()
+                .any(|message| message.name() == "ControlEnvelope")
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn session_stats_round_trips_through_json() {
+        use super::v1::SessionStats;
+
+        let stats = SessionStats {
+            session_id: "session-1".to_string(),
+            rtt_ms: 42,
+            tx_bitrate_kbps: 2_500,
+            rx_bitrate_kbps: 1_200,
+            packet_loss_x10000: 125,
+            encode_latency_ms: 8,
+            decode_latency_ms: 5,
+            using_relay: true,
+        };
+        let json = serde_json::to_string(&stats).unwrap();
+        assert_eq!(serde_json::from_str::<SessionStats>(&json).unwrap(), stats);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn session_request_round_trips_through_json() {
+        use super::v1::{DeviceIdentity, ProtocolVersion, SessionRequest, SessionRole};
+
+        let request = SessionRequest {
+            session_id: "session-1".to_string(),
+            requested_role: SessionRole::Controller as i32,
+            from: Some(DeviceIdentity {
+                peer_id: vec![0x00, 0x24, 0x08],
+                identity_pubkey: vec![0x08, 0x01, 0x12],
+                device_code: "desk".to_string(),
+            }),
+            nonce: vec![7; 16],
+            unix_ms: 1_700_000_000_000,
+            version: Some(ProtocolVersion {
+                major: 1,
+                minor: 0,
+                patch: 0,
+            }),
+            feature_bits: vec!["file.transfer.v1".to_string()],
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(
+            serde_json::from_str::<SessionRequest>(&json).unwrap(),
+            request
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn device_announcement_reads_records_without_rotation_proof() {
+        use super::v1::DeviceAnnouncement;
+
+        let json = r#"{"version":1,"device_code":"desk","peer_id":"12D3KooW","addrs":["/ip4/203.0.113.7/udp/9000/quic-v1"],"unix_ms":5}"#;
+        let announcement: DeviceAnnouncement = serde_json::from_str(json).unwrap();
+        assert_eq!(announcement.device_code, "desk");
+        assert_eq!(announcement.rotation_proof, None);
+    }
+
+    #[test]
+    fn file_chunk_round_trips() {
+        round_trip(control_envelope::Message::FileChunk(FileChunk {
+            session_id: "session-1".to_string(),
+            transfer_id: "transfer-1".to_string(),
+            offset: 65_536,
+            payload: vec![0xab; 1_024],
+            eof: false,
+            total_size: 1_048_576,
+            sha256: vec![0x11; 32],
+        }));
+    }
+
+    #[test]
+    fn clipboard_data_round_trips() {
+        round_trip(control_envelope::Message::ClipboardData(ClipboardData {
+            session_id: "session-1".to_string(),
+            mime: "text/plain".to_string(),
+            data: "hello".as_bytes().to_vec(),
+        }));
+    }
+}